@@ -5,7 +5,7 @@ extern crate rustc_serialize;
 extern crate test;
 
 use std::iter::repeat;
-use cbor::{Decoder, DirectDecoder, Encoder};
+use cbor::{Cbor, CborBytes, Decoder, DirectDecoder, Encoder};
 use rustc_serialize::{Decodable, Encodable};
 use rustc_serialize::json::{self, Json, ToJson};
 
@@ -127,6 +127,39 @@ fn read_medium_cbor(b: &mut test::Bencher) {
     });
 }
 
+#[bench]
+fn read_text_cbor(b: &mut test::Bencher) {
+    let items = (0..2_000).map(|i| {
+        format!("text number {}, with a few more words: {}",
+                i, repeat('x').take(i % 200).collect::<String>())
+    }).collect::<Vec<_>>();
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[items]).unwrap();
+    let bytes = enc.as_bytes();
+
+    b.bytes = bytes.len() as u64;
+    b.iter(|| {
+        let mut dec = Decoder::from_bytes(bytes.to_vec());
+        dec.items().next().unwrap().unwrap();
+    });
+}
+
+#[bench]
+fn read_medium_cbor_slice(b: &mut test::Bencher) {
+    let data = ("hello, world".to_string(),
+                true, (), vec![1, 1000, 100_000, 10_000_000], 3.14);
+    let items = repeat(data).take(10_000).collect::<Vec<_>>();
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[items]).unwrap();
+    let bytes = enc.as_bytes();
+
+    b.bytes = bytes.len() as u64;
+    b.iter(|| {
+        let mut dec = Decoder::from_slice(bytes);
+        dec.items().next().unwrap().unwrap();
+    });
+}
+
 #[bench]
 fn read_medium_json(b: &mut test::Bencher) {
     let data = ("hello, world".to_string(),
@@ -232,3 +265,81 @@ fn decode_medium_json(b: &mut test::Bencher) {
             json::decode(&string).unwrap();
     });
 }
+
+// Corpora of different shapes of data, for comparing the encoder and the
+// decoders on each. They are fixtures in `benches/data`, so every run (and
+// every version of the crate) measures the same bytes:
+//
+// * records: 1,000 small structs with text, integer, float and array
+//   fields.
+// * nested: 100 integers, each nested 100 arrays deep.
+// * bytes: 100 byte strings of 1 KB each.
+// * text: 1,000 text strings of up to 150 bytes, some of them non-ASCII.
+
+const RECORDS: &'static [u8] = include_bytes!("data/records.cbor");
+const NESTED: &'static [u8] = include_bytes!("data/nested.cbor");
+const BYTES: &'static [u8] = include_bytes!("data/bytes.cbor");
+const TEXT: &'static [u8] = include_bytes!("data/text.cbor");
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Record {
+    id: u64,
+    name: String,
+    active: bool,
+    score: f64,
+    tags: Vec<String>,
+}
+
+fn bench_encode<T: Encodable>(b: &mut test::Bencher, data: &T) {
+    b.bytes = cbor_encode(data).len() as u64;
+    b.iter(|| {
+        cbor_encode(data);
+    });
+}
+
+fn bench_decode<T: Decodable>(b: &mut test::Bencher, bytes: &[u8]) {
+    b.bytes = bytes.len() as u64;
+    b.iter(|| {
+        let _: T = cbor::from_slice_exact(bytes).unwrap();
+    });
+}
+
+#[bench]
+fn encode_records_corpus(b: &mut test::Bencher) {
+    bench_encode(b, &cbor::from_slice_exact::<Vec<Record>>(RECORDS).unwrap());
+}
+
+#[bench]
+fn decode_records_corpus(b: &mut test::Bencher) {
+    bench_decode::<Vec<Record>>(b, RECORDS);
+}
+
+#[bench]
+fn encode_nested_corpus(b: &mut test::Bencher) {
+    bench_encode(b, &cbor::from_slice_exact::<Vec<Cbor>>(NESTED).unwrap());
+}
+
+#[bench]
+fn decode_nested_corpus(b: &mut test::Bencher) {
+    bench_decode::<Vec<Cbor>>(b, NESTED);
+}
+
+#[bench]
+fn encode_bytes_corpus(b: &mut test::Bencher) {
+    bench_encode(b, &cbor::from_slice_exact::<Vec<CborBytes>>(BYTES).unwrap());
+}
+
+#[bench]
+fn decode_bytes_corpus(b: &mut test::Bencher) {
+    bench_decode::<Vec<CborBytes>>(b, BYTES);
+}
+
+#[bench]
+fn encode_text_corpus(b: &mut test::Bencher) {
+    bench_encode(b, &cbor::from_slice_exact::<Vec<String>>(TEXT).unwrap());
+}
+
+#[bench]
+fn decode_text_corpus(b: &mut test::Bencher) {
+    bench_decode::<Vec<String>>(b, TEXT);
+}
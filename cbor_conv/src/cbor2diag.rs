@@ -0,0 +1,30 @@
+#![feature(exit_status, io)]
+
+extern crate cbor;
+
+use std::env::set_exit_status;
+use std::io::{self, Write};
+
+use cbor::Decoder;
+
+macro_rules! err {
+    ($($arg:tt)*) => ({ let _ = writeln!(&mut io::stderr(), $($arg)*); });
+}
+
+fn main() {
+    macro_rules! ordie {
+        ($e:expr) => (
+            match $e {
+                Ok(v) => v,
+                Err(err) => { err!("{}", err); set_exit_status(1); return; }
+            }
+        );
+    }
+    // Unlike `cbor2json`, this prints every top-level data item, since
+    // diagnostic notation can't otherwise show a CBOR sequence.
+    let mut dec = Decoder::from_reader(io::stdin());
+    for result in dec.items() {
+        let cbor = ordie!(result);
+        println!("{:#}", cbor);
+    }
+}
@@ -0,0 +1,24 @@
+#![feature(exit_status, io)]
+
+extern crate cbor;
+
+use std::env::set_exit_status;
+use std::io::{self, Read, Write};
+
+macro_rules! err {
+    ($($arg:tt)*) => ({ let _ = writeln!(&mut io::stderr(), $($arg)*); });
+}
+
+fn main() {
+    macro_rules! ordie {
+        ($e:expr) => (
+            match $e {
+                Ok(v) => v,
+                Err(err) => { err!("{}", err); set_exit_status(1); return; }
+            }
+        );
+    }
+    let mut bytes = vec![];
+    ordie!(io::stdin().read_to_end(&mut bytes));
+    ordie!(io::stdout().write_all(cbor::annotate(&bytes).as_bytes()));
+}
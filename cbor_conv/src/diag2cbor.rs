@@ -0,0 +1,29 @@
+#![feature(exit_status, io)]
+
+extern crate cbor;
+
+use std::env::set_exit_status;
+use std::io::{self, Read, Write};
+
+use cbor::{Cbor, Encoder};
+
+macro_rules! err {
+    ($($arg:tt)*) => ({ let _ = writeln!(&mut io::stderr(), $($arg)*); });
+}
+
+fn main() {
+    macro_rules! ordie {
+        ($e:expr) => (
+            match $e {
+                Ok(v) => v,
+                Err(err) => { err!("{}", err); set_exit_status(1); return; }
+            }
+        );
+    }
+    let mut diag = String::new();
+    ordie!(io::stdin().read_to_string(&mut diag));
+    let cbor = ordie!(Cbor::from_diagnostic(&diag));
+    let mut enc = Encoder::from_writer(io::stdout());
+    ordie!(enc.encode(&[cbor]));
+    ordie!(enc.flush());
+}
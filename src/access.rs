@@ -0,0 +1,316 @@
+use std::mem::transmute;
+use std::ops::{Index, IndexMut};
+use std::sync::{ONCE_INIT, Once};
+
+use {Cbor, CborMap, CborSigned, CborUnsigned};
+
+impl Cbor {
+    /// Returns the value of `key` if this is a map that has it.
+    pub fn get(&self, key: &str) -> Option<&Cbor> {
+        match *self {
+            Cbor::Map(ref map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the item at index `i` if this is an array that has it.
+    pub fn get_at(&self, i: usize) -> Option<&Cbor> {
+        match *self {
+            Cbor::Array(ref items) => items.get(i),
+            _ => None,
+        }
+    }
+
+    /// Looks up a data item nested in this one by a path like `/a/b/0`,
+    /// in the style of a JSON Pointer (RFC 6901).
+    ///
+    /// Each segment of the path is a key of a map or an index of an array.
+    /// As in a JSON Pointer, `~1` stands for `/` and `~0` for `~` in a
+    /// segment. The empty path refers to this data item. `None` is
+    /// returned if the path doesn't start with `/` or doesn't lead to
+    /// anything.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Cbor;
+    ///
+    /// let item = Cbor::from_diagnostic(
+    ///     r#"{"sensors": [{"id": "a/1", "temps": [21, 22]}]}"#).unwrap();
+    /// assert_eq!(item.pointer("/sensors/0/temps/1").and_then(|v| v.as_u64()),
+    ///            Some(22));
+    /// assert_eq!(item["sensors"][0]["id"].get_text(), Some("a/1"));
+    /// assert_eq!(item.pointer("/sensors/1"), None);
+    /// ```
+    pub fn pointer(&self, path: &str) -> Option<&Cbor> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        if !path.starts_with('/') {
+            return None;
+        }
+        let mut item = self;
+        for seg in path[1..].split('/') {
+            let seg = seg.replace("~1", "/").replace("~0", "~");
+            let next = match *item {
+                Cbor::Map(ref map) => map.get(&seg),
+                Cbor::Array(ref items) => {
+                    seg.parse::<usize>().ok().and_then(|i| items.get(i))
+                }
+                _ => None,
+            };
+            item = match next {
+                Some(next) => next,
+                None => return None,
+            };
+        }
+        Some(item)
+    }
+
+    /// Returns a mutable reference to the value of `key` if this is a map
+    /// that has it.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Cbor> {
+        match *self {
+            Cbor::Map(ref mut map) => map.get_mut(key),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the item at index `i` if this is an
+    /// array that has it.
+    pub fn get_at_mut(&mut self, i: usize) -> Option<&mut Cbor> {
+        match *self {
+            Cbor::Array(ref mut items) => items.get_mut(i),
+            _ => None,
+        }
+    }
+
+    /// Like `pointer`, but returns a mutable reference.
+    pub fn pointer_mut(&mut self, path: &str) -> Option<&mut Cbor> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        if !path.starts_with('/') {
+            return None;
+        }
+        let mut item = self;
+        for seg in path[1..].split('/') {
+            let seg = seg.replace("~1", "/").replace("~0", "~");
+            let next = match *item {
+                Cbor::Map(ref mut map) => map.get_mut(&seg),
+                Cbor::Array(ref mut items) => match seg.parse::<usize>() {
+                    Ok(i) => items.get_mut(i),
+                    Err(_) => None,
+                },
+                _ => None,
+            };
+            item = match next {
+                Some(next) => next,
+                None => return None,
+            };
+        }
+        Some(item)
+    }
+
+    /// Adds an item to the end of an array.
+    ///
+    /// This panics if the data item isn't an array.
+    pub fn push(&mut self, v: Cbor) {
+        match *self {
+            Cbor::Array(ref mut items) => items.push(v),
+            _ => panic!("cannot push onto {}", self),
+        }
+    }
+
+    /// Adds an entry to a map (see `CborMap::insert`), and returns the old
+    /// value of `key` if there was one.
+    ///
+    /// This panics if the data item isn't a map.
+    pub fn insert<K: Into<String>>(&mut self, key: K, v: Cbor)
+                                  -> Option<Cbor> {
+        match *self {
+            Cbor::Map(ref mut map) => map.insert(key, v),
+            _ => panic!("cannot insert into {}", self),
+        }
+    }
+
+    /// Returns true if this is a null.
+    pub fn is_null(&self) -> bool {
+        match *self {
+            Cbor::Null => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the value of a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Cbor::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an integer that fits in a `u64`, regardless of
+    /// the size it was encoded with.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Cbor::Unsigned(v) => Some(match v {
+                CborUnsigned::UInt8(v) => v as u64,
+                CborUnsigned::UInt16(v) => v as u64,
+                CborUnsigned::UInt32(v) => v as u64,
+                CborUnsigned::UInt64(v) => v,
+            }),
+            Cbor::Wide(v) if !v.negative => Some(v.arg),
+            Cbor::Signed(_) => {
+                self.as_i64().and_then(|n| {
+                    if n >= 0 { Some(n as u64) } else { None }
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an integer that fits in an `i64`, regardless of
+    /// the size it was encoded with.
+    pub fn as_i64(&self) -> Option<i64> {
+        let max = ::std::i64::MAX as u64;
+        match *self {
+            Cbor::Signed(v) => Some(match v {
+                CborSigned::Int8(v) => v as i64,
+                CborSigned::Int16(v) => v as i64,
+                CborSigned::Int32(v) => v as i64,
+                CborSigned::Int64(v) => v,
+            }),
+            Cbor::Wide(v) if v.negative => {
+                if v.arg <= max { Some(-1 - v.arg as i64) } else { None }
+            }
+            _ => {
+                self.as_u64().and_then(|n| {
+                    if n <= max { Some(n as i64) } else { None }
+                })
+            }
+        }
+    }
+
+    /// Returns the value of a float of any precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Cbor::Float(v) => v.to_f64().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the contents of a Unicode string.
+    pub fn get_text(&self) -> Option<&str> {
+        match *self {
+            Cbor::Unicode(ref s) => Some(&s[..]),
+            _ => None,
+        }
+    }
+
+    /// Returns the contents of a byte string.
+    pub fn get_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Cbor::Bytes(ref bytes) => Some(&bytes.0[..]),
+            _ => None,
+        }
+    }
+
+    /// Returns the items of an array.
+    pub fn as_array(&self) -> Option<&[Cbor]> {
+        match *self {
+            Cbor::Array(ref items) => Some(&items[..]),
+            _ => None,
+        }
+    }
+
+    /// Returns the items of an array, which can be changed.
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Cbor>> {
+        match *self {
+            Cbor::Array(ref mut items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries of a map.
+    pub fn as_map(&self) -> Option<&CborMap> {
+        match *self {
+            Cbor::Map(ref map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries of a map, which can be changed.
+    pub fn as_map_mut(&mut self) -> Option<&mut CborMap> {
+        match *self {
+            Cbor::Map(ref mut map) => Some(map),
+            _ => None,
+        }
+    }
+}
+
+/// Returns what indexing returns for a key or index that isn't there.
+///
+/// A static can't hold a `Cbor`, since it has a destructor, so the null is
+/// allocated the first time it's needed and never freed.
+fn null() -> &'static Cbor {
+    static INIT: Once = ONCE_INIT;
+    static mut NULL: *const Cbor = 0 as *const Cbor;
+    unsafe {
+        INIT.call_once(|| NULL = transmute(Box::new(Cbor::Null)));
+        &*NULL
+    }
+}
+
+/// Looks up the value of a key in a map.
+///
+/// `Cbor::Null` is returned if the data item isn't a map or doesn't have
+/// the key, so lookups can be chained (e.g., `item["a"]["b"][0]`) without
+/// checking each step. Use `Cbor::get` or `Cbor::pointer` to tell a
+/// missing key from a null value.
+impl<'a> Index<&'a str> for Cbor {
+    type Output = Cbor;
+
+    fn index(&self, key: &str) -> &Cbor {
+        self.get(key).unwrap_or(null())
+    }
+}
+
+/// Looks up the value of a key in a map to change it.
+///
+/// Unlike `Index`, this panics if the data item isn't a map or doesn't
+/// have the key. Use `Cbor::insert` to add a key.
+impl<'a> IndexMut<&'a str> for Cbor {
+    fn index_mut(&mut self, key: &str) -> &mut Cbor {
+        if self.get(key).is_none() {
+            panic!("cannot look up key {:?} in {}", key, self);
+        }
+        self.get_mut(key).unwrap()
+    }
+}
+
+/// Looks up an item of an array.
+///
+/// `Cbor::Null` is returned if the data item isn't an array or the index
+/// is out of bounds. Use `Cbor::get_at` or `Cbor::pointer` to tell a
+/// missing item from a null value.
+impl Index<usize> for Cbor {
+    type Output = Cbor;
+
+    fn index(&self, i: usize) -> &Cbor {
+        self.get_at(i).unwrap_or(null())
+    }
+}
+
+/// Looks up an item of an array to change it.
+///
+/// Unlike `Index`, this panics if the data item isn't an array or the
+/// index is out of bounds.
+impl IndexMut<usize> for Cbor {
+    fn index_mut(&mut self, i: usize) -> &mut Cbor {
+        match *self {
+            Cbor::Array(ref mut items) => &mut items[i],
+            _ => panic!("cannot look up index {} in {}", i, self),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json::Json;
+
+use lexer::{Lexer, Token};
+use {Cbor, CborFloat};
+
+/// Returns an annotated hex dump of CBOR data.
+///
+/// Every data item head gets its own line, which shows its byte range, the
+/// raw bytes in hex (indented by nesting depth) and what they mean. String
+/// contents are shown on the same line as their head. This works on any
+/// bytes, including a sequence of top-level data items. If the data is
+/// malformed, the dump stops with a line describing the error, which makes
+/// this useful for figuring out what's wrong with a payload.
+///
+/// # Example
+///
+/// ```rust
+/// let dump = cbor::annotate(&[0x82, 0x01, 0x61, b'a']);
+/// assert_eq!(dump, "\
+/// 0..1  82         # array(2)
+/// 1..2     01      # unsigned(1)
+/// 2..4     61 61   # text(1) \"a\"
+/// ");
+/// ```
+pub fn annotate(bytes: &[u8]) -> String {
+    let mut lines = vec![];
+    let mut error = None;
+    // Each entry is the number of data items left to read in an open
+    // container, or `None` if the container is terminated by a break.
+    let mut open: Vec<Option<u64>> = vec![];
+    let mut lex = Lexer::new(bytes);
+    while let Some(result) = lex.next() {
+        let (at, tok) = match result {
+            Ok(v) => v,
+            Err(err) => { error = Some(err); break; }
+        };
+        let end = lex.offset();
+        let depth = match tok {
+            // A break belongs to the container it closes.
+            Token::Break if !open.is_empty() => open.len() - 1,
+            _ => open.len(),
+        };
+        let hex = bytes[at..end].iter()
+                                .map(|b| [*b].to_hex())
+                                .collect::<Vec<_>>()
+                                .join(" ");
+        lines.push((format!("{}..{}", at, end), depth, hex, describe(&tok)));
+
+        let pushed = match tok {
+            Token::BeginArray(Some(n)) if n > 0 => Some(Some(n as u64)),
+            // Saturating is fine, since no map that large fits in memory.
+            Token::BeginMap(Some(n)) if n > 0 => {
+                Some(Some((n as u64).saturating_mul(2)))
+            }
+            Token::BeginArray(None) | Token::BeginMap(None)
+            | Token::BeginBytes | Token::BeginText => Some(None),
+            Token::Tag(_) => Some(Some(1)),
+            _ => None,
+        };
+        if let Some(remaining) = pushed {
+            open.push(remaining);
+            continue;
+        }
+        if let Token::Break = tok {
+            if let Some(None) = open.pop() {
+            } else {
+                // The lexer doesn't check nesting, so report it here.
+                lines.push((format!("{}", at), 0, String::new(),
+                            "error: unexpected break".to_string()));
+                return render(lines);
+            }
+        }
+        // A data item was completed, which may complete its parents too.
+        loop {
+            let finished = match open.last_mut() {
+                None | Some(&mut None) => false,
+                Some(&mut Some(ref mut n)) => { *n -= 1; *n == 0 }
+            };
+            if !finished {
+                break;
+            }
+            open.pop();
+        }
+    }
+    if let Some(err) = error {
+        lines.push((format!("{}", lex.offset()), 0, String::new(),
+                    format!("error: {}", err)));
+    } else if !open.is_empty() {
+        lines.push((format!("{}", bytes.len()), 0, String::new(),
+                    "error: unexpected end of data".to_string()));
+    }
+    render(lines)
+}
+
+fn render(lines: Vec<(String, usize, String, String)>) -> String {
+    let range_width = lines.iter().map(|l| l.0.len()).max().unwrap_or(0);
+    let hex_width = lines.iter()
+                         .map(|l| 3 * l.1 + l.2.len())
+                         .max()
+                         .unwrap_or(0);
+    let mut out = String::new();
+    for (range, depth, hex, desc) in lines {
+        let hex = format!("{}{}", pad(3 * depth), hex);
+        out.push_str(&format!("{}{}  {}{}   # {}\n",
+                              range, pad(range_width - range.len()),
+                              hex, pad(hex_width - hex.len()), desc));
+    }
+    out
+}
+
+fn pad(n: usize) -> String {
+    ::std::iter::repeat(' ').take(n).collect()
+}
+
+fn describe(tok: &Token) -> String {
+    match *tok {
+        Token::UInt(n) => format!("unsigned({})", n),
+        Token::NegInt(n) if n == ::std::u64::MAX => {
+            "negative(-18446744073709551616)".to_string()
+        }
+        Token::NegInt(n) => format!("negative(-{})", n + 1),
+        Token::Bytes(b) => format!("bytes({})", b.len()),
+        Token::Text(b) => match ::std::str::from_utf8(b) {
+            Ok(s) => format!("text({}) {}", b.len(),
+                             Json::String(s.to_string())),
+            Err(_) => format!("text({}) (invalid UTF-8)", b.len()),
+        },
+        Token::BeginBytes => "bytes(*)".to_string(),
+        Token::BeginText => "text(*)".to_string(),
+        Token::BeginArray(Some(n)) => format!("array({})", n),
+        Token::BeginArray(None) => "array(*)".to_string(),
+        Token::BeginMap(Some(n)) => format!("map({})", n),
+        Token::BeginMap(None) => "map(*)".to_string(),
+        Token::Tag(n) => format!("tag({})", n),
+        Token::Bool(v) => format!("{}", v),
+        Token::Null => "null".to_string(),
+        Token::Undefined => "undefined".to_string(),
+        Token::Simple(v) => format!("simple({})", v),
+        Token::Float(v) => {
+            format!("float({})", Cbor::Float(CborFloat::Float64(v)))
+        }
+        Token::Break => "break".to_string(),
+    }
+}
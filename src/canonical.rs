@@ -0,0 +1,127 @@
+use std::cmp::Ordering;
+use std::slice;
+
+use rustc_serialize::{Encodable, Encoder as RustcEncoder};
+
+/// Compares two map keys by their encoded bytes.
+///
+/// This is the order that deterministically encoded CBOR (RFC 8949,
+/// section 4.2.1) requires for map keys: bytewise lexicographic order of
+/// their encodings. For Unicode string keys, this means that shorter keys
+/// sort first and keys of the same length are compared bytewise, which can
+/// be done without encoding either key.
+///
+/// # Example
+///
+/// ```rust
+/// use std::cmp::Ordering;
+///
+/// assert_eq!(cbor::cmp_canonical_keys("z", "aa"), Ordering::Less);
+/// assert_eq!(cbor::cmp_canonical_keys("ab", "aa"), Ordering::Greater);
+/// ```
+pub fn cmp_canonical_keys(a: &str, b: &str) -> Ordering {
+    // The head of an encoded string only depends on its length and grows
+    // with it, so comparing lengths is the same as comparing heads.
+    match a.len().cmp(&b.len()) {
+        Ordering::Equal => a.as_bytes().cmp(b.as_bytes()),
+        ord => ord,
+    }
+}
+
+/// A map whose entries are always encoded in canonical key order.
+///
+/// Encoding a `HashMap` writes its entries in whatever order it happens to
+/// iterate in. This map keeps its entries sorted according to
+/// `cmp_canonical_keys` instead, so that building a map by hand (e.g., for
+/// data that will be signed) always produces the same bytes.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{CanonicalMap, Encoder};
+///
+/// let map = CanonicalMap::new().entry("bb", 1).entry("a", 2).entry("c", 3);
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[map]).unwrap();
+/// assert_eq!(enc.as_bytes(), &[
+///     0xa3, 0x61, b'a', 0x02, 0x61, b'c', 0x03, 0x62, b'b', b'b', 0x01,
+/// ][..]);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanonicalMap<V> {
+    entries: Vec<(String, V)>,
+}
+
+impl<V> CanonicalMap<V> {
+    /// Create a new empty map.
+    pub fn new() -> CanonicalMap<V> {
+        CanonicalMap { entries: vec![] }
+    }
+
+    /// Add an entry to this map, replacing any existing entry with the same
+    /// key, and return the map.
+    pub fn entry<K: Into<String>>(mut self, key: K, value: V)
+                                 -> CanonicalMap<V> {
+        self.insert(key, value);
+        self
+    }
+
+    /// Add an entry to this map. If an entry with the same key already
+    /// exists, then its value is replaced and returned.
+    pub fn insert<K: Into<String>>(&mut self, key: K, value: V) -> Option<V> {
+        let key = key.into();
+        match self.find(&key) {
+            Ok(i) => Some(::std::mem::replace(&mut self.entries[i].1, value)),
+            Err(i) => { self.entries.insert(i, (key, value)); None }
+        }
+    }
+
+    /// Returns the value associated with `key`, if it exists.
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.find(key).ok().map(|i| &self.entries[i].1)
+    }
+
+    /// Returns the number of entries in this map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if this map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the entries of this map in canonical order.
+    pub fn iter(&self) -> CanonicalIter<V> {
+        CanonicalIter { it: self.entries.iter() }
+    }
+
+    fn find(&self, key: &str) -> Result<usize, usize> {
+        self.entries.binary_search_by(|e| cmp_canonical_keys(&e.0, key))
+    }
+}
+
+impl<V: Encodable> Encodable for CanonicalMap<V> {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        e.emit_map(self.entries.len(), |e| {
+            for (i, &(ref k, ref v)) in self.entries.iter().enumerate() {
+                try!(e.emit_map_elt_key(i, |e| k.encode(e)));
+                try!(e.emit_map_elt_val(i, |e| v.encode(e)));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An iterator over the entries of a `CanonicalMap` in canonical order.
+pub struct CanonicalIter<'a, V: 'a> {
+    it: slice::Iter<'a, (String, V)>,
+}
+
+impl<'a, V> Iterator for CanonicalIter<'a, V> {
+    type Item = (&'a str, &'a V);
+
+    fn next(&mut self) -> Option<(&'a str, &'a V)> {
+        self.it.next().map(|&(ref k, ref v)| (&**k, v))
+    }
+}
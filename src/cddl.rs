@@ -0,0 +1,894 @@
+//! Validation of data items against CDDL schemas (RFC 8610).
+//!
+//! A `Schema` is parsed from CDDL text, and its first rule is the type that
+//! data items are validated against. When a data item doesn't match, the
+//! error is a `CborError::AtPath` whose path points at the first value
+//! that doesn't match, e.g., `["items"][2]["id"]`.
+//!
+//! Only a subset of CDDL is supported:
+//!
+//! * The types of the standard prelude, like `uint`, `tstr`, `bstr`,
+//!   `float`, `bool`, `nil`, `any` and `tdate`.
+//! * Integer, float and text string literals, and integer ranges (`1..10`
+//!   and `1...10`).
+//! * Type choices (`int / tstr`), arrays, maps and tags (`#6.32(tstr)`).
+//! * Occurrence indicators (`?`, `*`, `+` and `n*m`) and member keys
+//!   (`name: type`, `"name": type` and `type => type`).
+//! * Groups that are spliced into arrays and maps by name, without an
+//!   occurrence indicator.
+//!
+//! Anything else (e.g., control operators, group choices, sockets and
+//! generics) is a syntax error. Since `Cbor` maps always have text string
+//! keys, map entries with any other key never match.
+//!
+//! This module is only available with the `cddl` feature.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cbor::Cbor;
+//! use cbor::cddl::Schema;
+//!
+//! let schema = Schema::parse(r#"
+//!     message = {
+//!         id: uint,
+//!         ? tags: [* tstr],
+//!         body: tstr / bstr,
+//!     }
+//! "#).unwrap();
+//!
+//! let ok: Cbor = r#"{"id": 1, "body": "hi"}"#.parse().unwrap();
+//! assert!(schema.validate(&ok).is_ok());
+//!
+//! let bad: Cbor = r#"{"id": 1, "tags": ["a", 2], "body": "hi"}"#
+//!     .parse().unwrap();
+//! let err = schema.validate(&bad).unwrap_err();
+//! assert_eq!(err.to_string(), "Error at byte offset 0 ([\"tags\"][1]): \
+//!                              Data item doesn't match the schema: \
+//!                              expected tstr, found 2");
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str;
+
+use lexer::item_len;
+use {Cbor, CborError, CborResult, Decoder, ReadError};
+
+/// The maximum number of nested data items and rule references followed
+/// while validating.
+const MAX_DEPTH: usize = 128;
+
+/// A parsed CDDL schema.
+#[derive(Clone, Debug)]
+pub struct Schema {
+    rules: Vec<(String, Ty)>,
+    index: HashMap<String, usize>,
+}
+
+/// A type, or a group when used inside of an array or a map.
+#[derive(Clone, Debug)]
+enum Ty {
+    Any,
+    UInt,
+    NInt,
+    Int,
+    Float,
+    Bytes,
+    Text,
+    Bool,
+    Nil,
+    Undefined,
+    IntLit(Int),
+    FloatLit(f64),
+    TextLit(String),
+    BoolLit(bool),
+    Range { min: Int, max: Int, inclusive: bool },
+    Ref(String),
+    Choice(Vec<Ty>),
+    Group(Vec<Entry>),
+    Array(Vec<Entry>),
+    Map(Vec<Entry>),
+    Tag(u64, Box<Ty>),
+}
+
+/// An entry of a group, which matches between `min` and `max` data items
+/// (or map entries).
+#[derive(Clone, Debug)]
+struct Entry {
+    min: u64,
+    max: u64,
+    key: Option<Ty>,
+    value: Ty,
+}
+
+/// An integer that may be below `i64::MIN`. Negative integers come first
+/// in the derived order.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Int {
+    /// The negative integer `-1 - n`, stored as `!n`.
+    Neg(u64),
+    Pos(u64),
+}
+
+impl Int {
+    fn neg(arg: u64) -> Int {
+        Int::Neg(!arg)
+    }
+
+    fn of(v: &Cbor) -> Option<Int> {
+        match *v {
+            Cbor::Unsigned(n) => n.to_u64().ok().map(Int::Pos),
+            Cbor::Signed(n) => n.to_i64().ok().map(|n| if n < 0 {
+                Int::neg((-1 - n) as u64)
+            } else {
+                Int::Pos(n as u64)
+            }),
+            Cbor::Wide(w) if w.negative => Some(Int::neg(w.arg)),
+            Cbor::Wide(w) => Some(Int::Pos(w.arg)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Int {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Int::Pos(n) => write!(f, "{}", n),
+            Int::Neg(n) if !n == ::std::u64::MAX => {
+                write!(f, "-18446744073709551616")
+            }
+            Int::Neg(n) => write!(f, "-{}", !n + 1),
+        }
+    }
+}
+
+impl Schema {
+    /// Parses a schema from CDDL text.
+    ///
+    /// If the text isn't valid CDDL, uses a feature that isn't supported
+    /// or refers to a rule that isn't defined, then a `ReadError::Syntax`
+    /// is returned with the byte offset of the error.
+    pub fn parse(s: &str) -> CborResult<Schema> {
+        let mut p = Parser { s: s.as_bytes(), pos: 0 };
+        let mut schema = Schema { rules: vec![], index: HashMap::new() };
+        let mut refs = vec![];
+        p.ws();
+        while p.pos < p.s.len() {
+            let at = p.pos;
+            let (name, ty) = try!(p.rule());
+            if schema.index.contains_key(&name) {
+                return Err(p.err_at(
+                    &format!("rule '{}' is defined twice", name), at));
+            }
+            ty.refs(&mut refs, at);
+            schema.index.insert(name.clone(), schema.rules.len());
+            schema.rules.push((name, ty));
+            p.ws();
+        }
+        if schema.rules.is_empty() {
+            return Err(p.err("expected a rule"));
+        }
+        for (name, at) in refs {
+            if !schema.index.contains_key(&name) && prelude(&name).is_none() {
+                return Err(p.err_at(
+                    &format!("rule '{}' is not defined", name), at));
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Checks that `v` matches the first rule of the schema.
+    ///
+    /// If it doesn't, then a `ReadError::SchemaMismatch` is returned with
+    /// the path to the value that doesn't match. The byte offset of the
+    /// error is always `0`.
+    pub fn validate(&self, v: &Cbor) -> CborResult<()> {
+        self.validate_at(v, 0)
+    }
+
+    /// Checks that every data item in `bytes` matches the first rule of the
+    /// schema.
+    ///
+    /// The byte offset of an error is the offset of the top-level data item
+    /// that doesn't match (or of the error, if the data isn't well formed).
+    pub fn validate_bytes(&self, bytes: &[u8]) -> CborResult<()> {
+        let mut offset = 0;
+        for item in Decoder::from_slice(bytes).items() {
+            try!(self.validate_at(&try!(item), offset));
+            // The item was decoded, so it is complete.
+            offset += try!(item_len(&bytes[offset..])).unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn validate_at(&self, v: &Cbor, offset: usize) -> CborResult<()> {
+        self.check(&self.rules[0].1, v, 0).map_err(|err| {
+            CborError::AtPath {
+                kind: ReadError::SchemaMismatch(err.msg),
+                offset: offset,
+                path: err.path.iter().map(|p| p.to_string()).collect(),
+            }
+        })
+    }
+
+    fn check(&self, ty: &Ty, v: &Cbor, depth: usize)
+            -> Result<(), Violation> {
+        if depth > MAX_DEPTH {
+            return Err(Violation::new("the schema nests too deeply".into()));
+        }
+        let ok = match *ty {
+            Ty::Any => true,
+            Ty::UInt => match Int::of(v) {
+                Some(Int::Pos(_)) => true,
+                _ => false,
+            },
+            Ty::NInt => match Int::of(v) {
+                Some(Int::Neg(_)) => true,
+                _ => false,
+            },
+            Ty::Int => Int::of(v).is_some(),
+            Ty::Float => match *v { Cbor::Float(_) => true, _ => false },
+            Ty::Bytes => match *v { Cbor::Bytes(_) => true, _ => false },
+            Ty::Text => match *v { Cbor::Unicode(_) => true, _ => false },
+            Ty::Bool => match *v { Cbor::Bool(_) => true, _ => false },
+            Ty::Nil => match *v { Cbor::Null => true, _ => false },
+            Ty::Undefined => match *v {
+                Cbor::Undefined => true,
+                _ => false,
+            },
+            Ty::IntLit(n) => Int::of(v) == Some(n),
+            Ty::FloatLit(f) => match *v {
+                Cbor::Float(x) => x.to_f64().ok() == Some(f),
+                _ => false,
+            },
+            Ty::TextLit(ref s) => match *v {
+                Cbor::Unicode(ref t) => t == s,
+                _ => false,
+            },
+            Ty::BoolLit(b) => match *v {
+                Cbor::Bool(x) => x == b,
+                _ => false,
+            },
+            Ty::Range { min, max, inclusive } => match Int::of(v) {
+                Some(n) => n >= min && (n < max || inclusive && n == max),
+                None => false,
+            },
+            Ty::Ref(ref name) => {
+                return match self.index.get(name) {
+                    Some(&i) => self.check(&self.rules[i].1, v, depth + 1),
+                    None => match prelude(name) {
+                        Some(ty) => self.check(&ty, v, depth + 1),
+                        None => Err(Violation::new(
+                            format!("rule '{}' is not defined", name))),
+                    },
+                };
+            }
+            Ty::Choice(ref alts) => {
+                // Report the alternative that got the furthest into `v`,
+                // unless none of them got anywhere.
+                let mut best: Option<Violation> = None;
+                for alt in alts {
+                    match self.check(alt, v, depth + 1) {
+                        Ok(()) => return Ok(()),
+                        Err(err) => {
+                            let better = match best {
+                                None => true,
+                                Some(ref b) => err.path.len() > b.path.len(),
+                            };
+                            if better {
+                                best = Some(err);
+                            }
+                        }
+                    }
+                }
+                if let Some(err) = best {
+                    if !err.path.is_empty() {
+                        return Err(err);
+                    }
+                }
+                false
+            }
+            Ty::Group(_) => false,
+            Ty::Array(ref entries) => match *v {
+                Cbor::Array(ref items) => {
+                    return self.check_array(entries, items, depth);
+                }
+                _ => false,
+            },
+            Ty::Map(ref entries) => match *v {
+                Cbor::Map(ref map) => {
+                    let pairs: Vec<(&String, &Cbor)> = map.iter().collect();
+                    return self.check_map(entries, &pairs, depth);
+                }
+                _ => false,
+            },
+            Ty::Tag(tag, ref inner) => match *v {
+                Cbor::Tag(ref t) if t.tag == tag => {
+                    return self.check(inner, &t.data, depth + 1);
+                }
+                _ => false,
+            },
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(Violation::new(format!("expected {}, found {}",
+                                       describe(ty), found(v))))
+        }
+    }
+
+    fn check_array(&self, entries: &[Entry], items: &[Cbor], depth: usize)
+                  -> Result<(), Violation> {
+        let mut flat = vec![];
+        try!(self.flatten(entries, &mut flat, depth));
+        let mut i = 0;
+        // The error for the item that stopped the last entry, if any.
+        let mut last_err = None;
+        for e in flat {
+            // Keys in arrays are only there for documentation.
+            let mut n = 0;
+            last_err = None;
+            while n < e.max && i < items.len() {
+                match self.check(&e.value, &items[i], depth + 1) {
+                    Ok(()) => { n += 1; i += 1; }
+                    Err(err) => {
+                        last_err = Some(err.within(PathElem::Index(i)));
+                        break;
+                    }
+                }
+            }
+            if n < e.min {
+                return Err(last_err.unwrap_or_else(|| Violation::new(
+                    format!("expected {}, found the end of the array",
+                            describe(&e.value)))));
+            }
+        }
+        if i < items.len() {
+            return Err(last_err.unwrap_or_else(|| {
+                Violation::new(format!("unexpected item {}",
+                                       found(&items[i])))
+                    .within(PathElem::Index(i))
+            }));
+        }
+        Ok(())
+    }
+
+    fn check_map(&self, entries: &[Entry], pairs: &[(&String, &Cbor)],
+                 depth: usize) -> Result<(), Violation> {
+        let mut flat = vec![];
+        try!(self.flatten(entries, &mut flat, depth));
+        let mut used = vec![false; pairs.len()];
+        for e in flat {
+            let key = match e.key {
+                Some(ref key) => key,
+                None => {
+                    return Err(Violation::new(format!(
+                        "map entry {} has no key", describe(&e.value))));
+                }
+            };
+            let mut n = 0;
+            for (j, &(k, v)) in pairs.iter().enumerate() {
+                if used[j] || n >= e.max || !self.key_matches(key, k, depth) {
+                    continue;
+                }
+                try!(self.check(&e.value, v, depth + 1).map_err(|err| {
+                    err.within(PathElem::Key(k.clone()))
+                }));
+                used[j] = true;
+                n += 1;
+            }
+            if n < e.min {
+                return Err(Violation::new(
+                    format!("missing entry {}", describe(key))));
+            }
+        }
+        match used.iter().position(|&u| !u) {
+            None => Ok(()),
+            Some(j) => {
+                Err(Violation::new("unexpected entry".into())
+                        .within(PathElem::Key(pairs[j].0.clone())))
+            }
+        }
+    }
+
+    fn key_matches(&self, key: &Ty, k: &str, depth: usize) -> bool {
+        match *key {
+            Ty::TextLit(ref s) => s == k,
+            _ => {
+                let k = Cbor::Unicode(k.to_string());
+                self.check(key, &k, depth + 1).is_ok()
+            }
+        }
+    }
+
+    /// Appends `entries` to `out`, replacing references to groups with
+    /// the entries of the group.
+    fn flatten(&self, entries: &[Entry], out: &mut Vec<Entry>, depth: usize)
+              -> Result<(), Violation> {
+        if depth > MAX_DEPTH {
+            return Err(Violation::new("the schema nests too deeply".into()));
+        }
+        for e in entries {
+            let group = match (e.key.is_none(), e.min, e.max) {
+                (true, 1, 1) => self.group(&e.value),
+                _ => None,
+            };
+            match group {
+                Some(inner) => try!(self.flatten(inner, out, depth + 1)),
+                None => out.push(e.clone()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of `ty` if it is (or refers to) a group.
+    fn group<'a>(&'a self, mut ty: &'a Ty) -> Option<&'a [Entry]> {
+        // Rules can refer to each other in a cycle, so give up eventually.
+        for _ in 0..self.rules.len() + 1 {
+            match *ty {
+                Ty::Group(ref entries) => return Some(entries),
+                Ty::Ref(ref name) => match self.index.get(name) {
+                    Some(&i) => ty = &self.rules[i].1,
+                    None => return None,
+                },
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+
+impl Ty {
+    /// Collects the rule names referred to by this type, along with `at`
+    /// (the offset of the rule it is part of).
+    fn refs(&self, out: &mut Vec<(String, usize)>, at: usize) {
+        match *self {
+            Ty::Ref(ref name) => out.push((name.clone(), at)),
+            Ty::Choice(ref alts) => for alt in alts { alt.refs(out, at) },
+            Ty::Group(ref entries) | Ty::Array(ref entries)
+            | Ty::Map(ref entries) => {
+                for e in entries {
+                    if let Some(ref key) = e.key {
+                        key.refs(out, at);
+                    }
+                    e.value.refs(out, at);
+                }
+            }
+            Ty::Tag(_, ref inner) => inner.refs(out, at),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the type that a name in the standard prelude stands for.
+fn prelude(name: &str) -> Option<Ty> {
+    Some(match name {
+        "any" => Ty::Any,
+        "uint" => Ty::UInt,
+        "nint" => Ty::NInt,
+        "int" => Ty::Int,
+        "float" | "float16" | "float32" | "float64" | "float16-32"
+        | "float32-64" => Ty::Float,
+        "number" => Ty::Choice(vec![Ty::Int, Ty::Float]),
+        "bstr" | "bytes" => Ty::Bytes,
+        "tstr" | "text" => Ty::Text,
+        "bool" => Ty::Bool,
+        "true" => Ty::BoolLit(true),
+        "false" => Ty::BoolLit(false),
+        "nil" | "null" => Ty::Nil,
+        "undefined" => Ty::Undefined,
+        "tdate" => Ty::Tag(0, Box::new(Ty::Text)),
+        "time" => Ty::Tag(1, Box::new(Ty::Choice(vec![Ty::Int, Ty::Float]))),
+        "uri" => Ty::Tag(32, Box::new(Ty::Text)),
+        _ => return None,
+    })
+}
+
+fn describe(ty: &Ty) -> String {
+    match *ty {
+        Ty::Any => "any".into(),
+        Ty::UInt => "uint".into(),
+        Ty::NInt => "nint".into(),
+        Ty::Int => "int".into(),
+        Ty::Float => "float".into(),
+        Ty::Bytes => "bstr".into(),
+        Ty::Text => "tstr".into(),
+        Ty::Bool => "bool".into(),
+        Ty::Nil => "nil".into(),
+        Ty::Undefined => "undefined".into(),
+        Ty::IntLit(n) => n.to_string(),
+        Ty::FloatLit(f) => format!("{:?}", f),
+        Ty::TextLit(ref s) => format!("{:?}", s),
+        Ty::BoolLit(b) => b.to_string(),
+        Ty::Range { min, max, inclusive } => {
+            format!("{}{}{}", min, if inclusive { ".." } else { "..." }, max)
+        }
+        Ty::Ref(ref name) => name.clone(),
+        Ty::Choice(ref alts) => {
+            alts.iter().map(describe).collect::<Vec<_>>().join(" / ")
+        }
+        Ty::Group(_) => "a group".into(),
+        Ty::Array(_) => "an array".into(),
+        Ty::Map(_) => "a map".into(),
+        Ty::Tag(tag, ref inner) => format!("#6.{}({})", tag, describe(inner)),
+    }
+}
+
+/// Describes a data item that doesn't match.
+fn found(v: &Cbor) -> String {
+    match *v {
+        Cbor::Array(_) => "an array".into(),
+        Cbor::Map(_) => "a map".into(),
+        Cbor::Tag(ref t) => format!("tag {}", t.tag),
+        _ => v.to_string(),
+    }
+}
+
+/// Why a data item doesn't match, and where.
+struct Violation {
+    path: Vec<PathElem>,
+    msg: String,
+}
+
+impl Violation {
+    fn new(msg: String) -> Violation {
+        Violation { path: vec![], msg: msg }
+    }
+
+    /// Puts this violation inside of the array item or map entry `elem`.
+    fn within(mut self, elem: PathElem) -> Violation {
+        self.path.insert(0, elem);
+        self
+    }
+}
+
+enum PathElem {
+    Index(usize),
+    Key(String),
+}
+
+impl fmt::Display for PathElem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathElem::Index(i) => write!(f, "[{}]", i),
+            PathElem::Key(ref k) => write!(f, "[{:?}]", k),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn err(&self, msg: &str) -> CborError {
+        self.err_at(msg, self.pos)
+    }
+
+    fn err_at(&self, msg: &str, offset: usize) -> CborError {
+        CborError::AtOffset {
+            kind: ReadError::Syntax(msg.to_string()),
+            offset: offset,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, lit: &str) -> bool {
+        if self.s[self.pos..].starts_with(lit.as_bytes()) {
+            self.pos += lit.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, lit: &str) -> CborResult<()> {
+        self.ws();
+        if self.eat(lit) {
+            Ok(())
+        } else {
+            Err(self.err(&format!("expected '{}'", lit)))
+        }
+    }
+
+    /// Skips whitespace and comments, which run from `;` to the end of the
+    /// line.
+    fn ws(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') => {
+                    self.pos += 1;
+                }
+                Some(b';') => {
+                    let rest = &self.s[self.pos..];
+                    match rest.iter().position(|&b| b == b'\n') {
+                        Some(i) => self.pos += i + 1,
+                        None => self.pos = self.s.len(),
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn rule(&mut self) -> CborResult<(String, Ty)> {
+        let name = match self.ident() {
+            Some(name) => name,
+            None => return Err(self.err("expected a rule name")),
+        };
+        self.ws();
+        if self.peek() == Some(b'<') {
+            return Err(self.err("generic rules are not supported"));
+        }
+        if self.eat("/=") || self.eat("//=") {
+            return Err(self.err("choice assignments are not supported"));
+        }
+        try!(self.expect("="));
+        let ty = try!(self.ty());
+        Ok((name, ty))
+    }
+
+    fn ty(&mut self) -> CborResult<Ty> {
+        let first = try!(self.ty1());
+        self.choices(first)
+    }
+
+    /// Parses the rest of a type choice whose first alternative is
+    /// `first`.
+    fn choices(&mut self, first: Ty) -> CborResult<Ty> {
+        let mut alts = vec![first];
+        loop {
+            self.ws();
+            if self.eat("//") {
+                return Err(self.err_at("group choices are not supported",
+                                       self.pos - 2));
+            }
+            if !self.eat("/") {
+                break;
+            }
+            alts.push(try!(self.ty1()));
+        }
+        if alts.len() == 1 {
+            Ok(alts.pop().unwrap())
+        } else {
+            Ok(Ty::Choice(alts))
+        }
+    }
+
+    fn ty1(&mut self) -> CborResult<Ty> {
+        let min = try!(self.ty2());
+        self.ws();
+        let at = self.pos;
+        let inclusive = if self.eat("...") {
+            false
+        } else if self.eat("..") {
+            true
+        } else {
+            if self.peek() == Some(b'.') {
+                return Err(self.err("control operators are not supported"));
+            }
+            return Ok(min);
+        };
+        self.ws();
+        let max = try!(self.ty2());
+        match (min, max) {
+            (Ty::IntLit(min), Ty::IntLit(max)) => {
+                Ok(Ty::Range { min: min, max: max, inclusive: inclusive })
+            }
+            _ => Err(self.err_at("only integer ranges are supported", at)),
+        }
+    }
+
+    fn ty2(&mut self) -> CborResult<Ty> {
+        self.ws();
+        match self.peek() {
+            None => Err(self.err("unexpected end of input")),
+            Some(b'{') => { self.pos += 1; self.group(b'}').map(Ty::Map) }
+            Some(b'[') => { self.pos += 1; self.group(b']').map(Ty::Array) }
+            Some(b'(') => {
+                self.pos += 1;
+                let mut entries = try!(self.group(b')'));
+                // A group of one plain type is just that type.
+                if entries.len() == 1 && entries[0].key.is_none()
+                   && entries[0].min == 1 && entries[0].max == 1 {
+                    return Ok(entries.pop().unwrap().value);
+                }
+                Ok(Ty::Group(entries))
+            }
+            Some(b'#') => {
+                if !self.eat("#6.") {
+                    return Err(self.err("only tags (#6.n) are supported"));
+                }
+                let tag = match self.uint() {
+                    Some(tag) => tag,
+                    None => return Err(self.err("expected a tag number")),
+                };
+                if !self.eat("(") {
+                    return Err(self.err("expected '('"));
+                }
+                let inner = try!(self.ty());
+                try!(self.expect(")"));
+                Ok(Ty::Tag(tag, Box::new(inner)))
+            }
+            Some(b'"') => self.text().map(Ty::TextLit),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            _ => match self.ident() {
+                Some(name) => {
+                    if self.peek() == Some(b'<') {
+                        return Err(self.err("generics are not supported"));
+                    }
+                    Ok(Ty::Ref(name))
+                }
+                None => Err(self.err("expected a type")),
+            },
+        }
+    }
+
+    /// Parses the entries of a group up to `close`.
+    fn group(&mut self, close: u8) -> CborResult<Vec<Entry>> {
+        let mut entries = vec![];
+        loop {
+            self.ws();
+            if self.peek() == Some(close) {
+                self.pos += 1;
+                return Ok(entries);
+            }
+            entries.push(try!(self.entry()));
+            self.ws();
+            self.eat(",");
+        }
+    }
+
+    fn entry(&mut self) -> CborResult<Entry> {
+        let (min, max) = self.occurrence();
+        self.ws();
+        // A bare word followed by a colon is a text string key.
+        let start = self.pos;
+        if let Some(name) = self.ident() {
+            self.ws();
+            if self.peek() == Some(b':') {
+                self.pos += 1;
+                let value = try!(self.ty());
+                return Ok(Entry {
+                    min: min,
+                    max: max,
+                    key: Some(Ty::TextLit(name)),
+                    value: value,
+                });
+            }
+            self.pos = start;
+        }
+        let first = try!(self.ty1());
+        self.ws();
+        let is_value = match first {
+            Ty::IntLit(_) | Ty::FloatLit(_) | Ty::TextLit(_) => true,
+            _ => false,
+        };
+        let key = if self.eat("=>") || (is_value && self.eat(":")) {
+            Some(first)
+        } else {
+            let value = try!(self.choices(first));
+            return Ok(Entry { min: min, max: max, key: None, value: value });
+        };
+        let value = try!(self.ty());
+        Ok(Entry { min: min, max: max, key: key, value: value })
+    }
+
+    /// Parses an optional occurrence indicator, returning the minimum and
+    /// maximum number of occurrences.
+    fn occurrence(&mut self) -> (u64, u64) {
+        let max = ::std::u64::MAX;
+        self.ws();
+        if self.eat("?") {
+            return (0, 1);
+        }
+        if self.eat("+") {
+            return (1, max);
+        }
+        let start = self.pos;
+        let min = self.uint();
+        if !self.eat("*") {
+            // The number was a value, not an occurrence.
+            self.pos = start;
+            return (1, 1);
+        }
+        (min.unwrap_or(0), self.uint().unwrap_or(max))
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        let start = self.pos;
+        match self.peek() {
+            Some(b'a'...b'z') | Some(b'A'...b'Z') | Some(b'@') | Some(b'_')
+            | Some(b'$') => self.pos += 1,
+            _ => return None,
+        }
+        loop {
+            match self.peek() {
+                Some(b'a'...b'z') | Some(b'A'...b'Z') | Some(b'0'...b'9')
+                | Some(b'@') | Some(b'_') | Some(b'$') => self.pos += 1,
+                // `-` and `.` can't end a name.
+                Some(b'-') | Some(b'.') => match self.s.get(self.pos + 1) {
+                    Some(&b) if (b as char).is_alphanumeric() => {
+                        self.pos += 2;
+                    }
+                    _ => break,
+                },
+                _ => break,
+            }
+        }
+        // The characters are all ASCII.
+        Some(str::from_utf8(&self.s[start..self.pos]).unwrap().to_string())
+    }
+
+    fn uint(&mut self) -> Option<u64> {
+        let start = self.pos;
+        while let Some(b'0'...b'9') = self.peek() {
+            self.pos += 1;
+        }
+        str::from_utf8(&self.s[start..self.pos]).unwrap().parse().ok()
+    }
+
+    fn number(&mut self) -> CborResult<Ty> {
+        let start = self.pos;
+        let negative = self.eat("-");
+        let hex = self.eat("0x");
+        while let Some(b) = self.peek() {
+            match b {
+                b'0'...b'9' | b'a'...b'f' | b'A'...b'F' if hex => {}
+                b'0'...b'9' | b'e' | b'E' | b'+' => {}
+                // The sign of an exponent.
+                b'-' if self.pos > start
+                        && self.s[self.pos - 1] | 0x20 == b'e' => {}
+                // Not the start of a range.
+                b'.' if self.s.get(self.pos + 1) != Some(&b'.') => {}
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        let s = str::from_utf8(&self.s[start..self.pos]).unwrap();
+        let digits = &s[if negative { 1 } else { 0 }
+                        + if hex { 2 } else { 0 }..];
+        let arg = if hex {
+            u64::from_str_radix(digits, 16).ok()
+        } else {
+            digits.parse::<u64>().ok()
+        };
+        match arg {
+            Some(n) if !negative => Ok(Ty::IntLit(Int::Pos(n))),
+            Some(0) => Ok(Ty::IntLit(Int::Pos(0))),
+            Some(n) => Ok(Ty::IntLit(Int::neg(n - 1))),
+            None if hex => Err(self.err_at("invalid number", start)),
+            None => match s.parse::<f64>() {
+                Ok(f) => Ok(Ty::FloatLit(f)),
+                Err(_) => Err(self.err_at("invalid number", start)),
+            },
+        }
+    }
+
+    fn text(&mut self) -> CborResult<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut buf = vec![];
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(self.err_at("unterminated string", start));
+                }
+                Some(b'"') => { self.pos += 1; break; }
+                Some(b'\\') if self.pos + 1 < self.s.len() => {
+                    buf.push(self.s[self.pos + 1]);
+                    self.pos += 2;
+                }
+                Some(b) => { buf.push(b); self.pos += 1; }
+            }
+        }
+        // The input is a `&str` and escapes only take ASCII characters, so
+        // this is valid UTF-8.
+        Ok(String::from_utf8(buf).unwrap())
+    }
+}
@@ -0,0 +1,273 @@
+//! Data items protected by a checksum, for detecting corruption in CBOR
+//! stored on disk.
+//!
+//! Each data item is written in an envelope: a tagged array of the encoded
+//! data item, as a byte string, and its checksum, as a big-endian byte
+//! string of length 4:
+//!
+//! ```text
+//! 4411971([h'<data item>', h'<crc>'])
+//! 5789768([h'<data item>', h'<xxh32>'])
+//! ```
+//!
+//! The checksum is either the CRC-32 (as used by gzip and Ethernet) of the
+//! encoded data item, which is the default, or its xxHash (XXH32), which is
+//! much faster to compute for large data items (see `Checksum`). The tag
+//! number says which: it is "CRC" or "XXH" in ASCII. The tags aren't
+//! registered with IANA, but a log of envelopes is still a sequence of CBOR
+//! data items, so generic CBOR tools can read it. Reading an envelope
+//! checks the checksum, so a bit flipped by the disk is reported as
+//! `ReadError::ChecksumMismatch` instead of being decoded into a wrong
+//! value.
+//!
+//! Like the `framed` module, `write_item` and `read_item` work on encoded
+//! data items, and `write_message` and `read_message` encode and decode
+//! them as well. `write_item_with` and `write_message_with` choose the
+//! checksum, and the readers accept either kind of envelope.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cbor::checksum;
+//!
+//! let mut log = vec![];
+//! checksum::write_message(&mut log, &(1, "a")).unwrap();
+//! checksum::write_message(&mut log, &(2, "b")).unwrap();
+//!
+//! // Flip a bit in the second data item.
+//! let at = log.len() - 6;
+//! log[at] ^= 1;
+//!
+//! let mut rdr = &log[..];
+//! let first: Option<(u8, String)> =
+//!     checksum::read_message(&mut rdr).unwrap();
+//! assert_eq!(first, Some((1, "a".to_string())));
+//! assert!(checksum::read_message::<_, (u8, String)>(&mut rdr).is_err());
+//! ```
+
+use std::io::{Read, Write};
+
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
+use rustc_serialize::{Decodable, Encodable};
+
+use framed::{self, Framing};
+use streaming::shift_offset;
+use {
+    CborResult, CborWide, Encoder, ReadError, eof, errat, from_slice_exact,
+    write_head,
+};
+
+/// The tag of an envelope with a CRC-32 ("CRC" in ASCII).
+pub const TAG: u64 = 0x43_52_43;
+
+/// The tag of an envelope with an xxHash ("XXH" in ASCII).
+pub const TAG_XXH32: u64 = 0x58_58_48;
+
+/// The length of the head of the tag and of the array of two items in it.
+const HEAD_LEN: usize = 6;
+
+/// The checksum of an envelope.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Checksum {
+    /// The CRC-32 (see `crc32`), in an envelope with the tag `TAG`.
+    Crc32,
+    /// The xxHash (see `xxh32`), in an envelope with the tag `TAG_XXH32`.
+    XxHash32,
+}
+
+impl Checksum {
+    /// Returns the tag of envelopes with this checksum.
+    pub fn tag(self) -> u64 {
+        match self {
+            Checksum::Crc32 => TAG,
+            Checksum::XxHash32 => TAG_XXH32,
+        }
+    }
+
+    /// Computes this checksum of `bytes`.
+    pub fn compute(self, bytes: &[u8]) -> u32 {
+        match self {
+            Checksum::Crc32 => crc32(bytes),
+            Checksum::XxHash32 => xxh32(bytes),
+        }
+    }
+
+    /// Returns the head of the tag and of the array of two items in it.
+    fn head(self) -> Vec<u8> {
+        let mut head = vec![];
+        write_head(&mut head, 6, self.tag());
+        head.push(0x82);
+        head
+    }
+}
+
+/// Writes an encoded data item to `wtr` in an envelope with its CRC-32.
+pub fn write_item<W: Write>(wtr: &mut W, item: &[u8]) -> CborResult<()> {
+    write_item_with(wtr, Checksum::Crc32, item)
+}
+
+/// Writes an encoded data item to `wtr` in an envelope with its
+/// `checksum`.
+pub fn write_item_with<W: Write>(wtr: &mut W, checksum: Checksum,
+                                 item: &[u8]) -> CborResult<()> {
+    let mut sum = [0x44, 0, 0, 0, 0];
+    <BigEndian as ByteOrder>::write_u32(&mut sum[1..],
+                                        checksum.compute(item));
+    try!(wtr.write_all(&checksum.head()));
+    try!(framed::write_frame(wtr, Framing::ByteString, item));
+    try!(wtr.write_all(&sum));
+    Ok(())
+}
+
+/// Reads the next envelope that was written with `write_item` or
+/// `write_item_with` from `rdr`, checks its checksum, and returns the
+/// encoded data item in it.
+///
+/// `None` is returned if `rdr` is at EOF before the envelope starts. Byte
+/// offsets in errors are relative to the start of the envelope. Nothing
+/// past the envelope is read from `rdr`.
+pub fn read_item<R: Read>(rdr: &mut R) -> CborResult<Option<Vec<u8>>> {
+    let mut head = vec![];
+    try!(rdr.by_ref().take(HEAD_LEN as u64).read_to_end(&mut head));
+    if head.is_empty() {
+        return Ok(None);
+    } else if head.len() < HEAD_LEN {
+        return Err(eof(HEAD_LEN - head.len(), head.len()));
+    }
+    let checksum = if head == Checksum::Crc32.head() {
+        Checksum::Crc32
+    } else if head == Checksum::XxHash32.head() {
+        Checksum::XxHash32
+    } else {
+        return Err(errat(ReadError::Other(format!(
+            "Expected a checksummed data item (tag {} or {}), but got {:?}.",
+            TAG, TAG_XXH32, head)), 0));
+    };
+    let max = ::std::u64::MAX;
+    let item = match framed::read_frame(rdr, Framing::ByteString, max) {
+        Ok(Some(item)) => item,
+        Ok(None) => return Err(eof(1, HEAD_LEN)),
+        Err(err) => return Err(shift_offset(err, HEAD_LEN)),
+    };
+    let len = item.len() as u64;
+    let at = HEAD_LEN + 1 + CborWide::width_of(len) as usize + item.len();
+    let mut crc = vec![];
+    try!(rdr.by_ref().take(5).read_to_end(&mut crc));
+    if crc.len() < 5 {
+        return Err(eof(5 - crc.len(), at + crc.len()));
+    } else if crc[0] != 0x44 {
+        return Err(errat(ReadError::Other(format!(
+            "Expected a 4 byte checksum, but got a data item that starts \
+             with {:#x}.", crc[0])), at));
+    }
+    let expected = <BigEndian as ByteOrder>::read_u32(&crc[1..]);
+    let got = checksum.compute(&item);
+    if expected != got {
+        let err = ReadError::ChecksumMismatch {
+            expected: expected,
+            got: got,
+        };
+        return Err(errat(err, at));
+    }
+    Ok(Some(item))
+}
+
+/// Encodes `v` and writes it to `wtr` in an envelope with its CRC-32.
+pub fn write_message<W, T>(wtr: &mut W, v: &T) -> CborResult<()>
+        where W: Write, T: Encodable {
+    write_message_with(wtr, Checksum::Crc32, v)
+}
+
+/// Encodes `v` and writes it to `wtr` in an envelope with its `checksum`.
+pub fn write_message_with<W, T>(wtr: &mut W, checksum: Checksum, v: &T)
+                               -> CborResult<()>
+        where W: Write, T: Encodable {
+    let mut enc = Encoder::from_memory();
+    try!(enc.encode(&[v]));
+    write_item_with(wtr, checksum, enc.as_bytes())
+}
+
+/// Reads the next envelope from `rdr` (see `read_item`) and decodes the
+/// data item in it.
+///
+/// `None` is returned if `rdr` is at EOF before the envelope starts. Byte
+/// offsets in decoding errors are relative to the start of the data item.
+pub fn read_message<R, T>(rdr: &mut R) -> CborResult<Option<T>>
+        where R: Read, T: Decodable {
+    match try!(read_item(rdr)) {
+        None => Ok(None),
+        Some(item) => from_slice_exact(&item).map(Some),
+    }
+}
+
+/// Computes the CRC-32 of `bytes`, with the reflected polynomial
+/// `0xedb88320`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+const PRIME32_1: u32 = 2654435761;
+const PRIME32_2: u32 = 2246822519;
+const PRIME32_3: u32 = 3266489917;
+const PRIME32_4: u32 = 668265263;
+const PRIME32_5: u32 = 374761393;
+
+/// Computes the 32 bit xxHash (XXH32) of `bytes`, with a seed of 0.
+pub fn xxh32(bytes: &[u8]) -> u32 {
+    let mut rest = bytes;
+    let mut h = if bytes.len() >= 16 {
+        let mut v = [
+            PRIME32_1.wrapping_add(PRIME32_2),
+            PRIME32_2,
+            0,
+            0u32.wrapping_sub(PRIME32_1),
+        ];
+        while rest.len() >= 16 {
+            for i in 0..4 {
+                let lane = &rest[4 * i..];
+                let lane = <LittleEndian as ByteOrder>::read_u32(lane);
+                v[i] = xxh32_round(v[i], lane);
+            }
+            rest = &rest[16..];
+        }
+        v[0].rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18))
+    } else {
+        PRIME32_5
+    };
+    h = h.wrapping_add(bytes.len() as u32);
+    while rest.len() >= 4 {
+        let word = <LittleEndian as ByteOrder>::read_u32(rest);
+        h = h.wrapping_add(word.wrapping_mul(PRIME32_3));
+        h = h.rotate_left(17).wrapping_mul(PRIME32_4);
+        rest = &rest[4..];
+    }
+    for &b in rest {
+        h = h.wrapping_add((b as u32).wrapping_mul(PRIME32_5));
+        h = h.rotate_left(11).wrapping_mul(PRIME32_1);
+    }
+    h ^= h >> 15;
+    h = h.wrapping_mul(PRIME32_2);
+    h ^= h >> 13;
+    h = h.wrapping_mul(PRIME32_3);
+    h ^ (h >> 16)
+}
+
+fn xxh32_round(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME32_2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME32_1)
+}
@@ -0,0 +1,595 @@
+//! Signed, MACed and encrypted messages in the COSE format (RFC 9052).
+//!
+//! A COSE message is an array of a header map that is protected by the
+//! signature (or MAC, or encryption), a header map that isn't, and the
+//! payload with its signature. The protected headers are kept as the
+//! bytes they were encoded to, since verifying a message has to see
+//! exactly the bytes its sender signed.
+//!
+//! Header maps are keyed by integer labels (e.g., `ALG` and `KID`), so
+//! they are a `HeaderMap`, which holds any keys (see `decode_any_map`).
+//!
+//! This module builds the messages and the structures that are signed
+//! (`Sig_structure` and friends), but it does no cryptography itself. That
+//! is left to implementations of `Signer`, `Verifier`, `Mac` and `Cipher`
+//! (e.g., on top of a crypto library), which are handed the bytes to sign,
+//! MAC or encrypt.
+//!
+//! # Example
+//!
+//! This "signature" is only an illustration: it provides no security at
+//! all.
+//!
+//! ```rust
+//! use cbor::{CborError, CborResult, ReadError, ToCbor};
+//! use cbor::cose::{self, CoseSign1, HeaderMap, Headers, Signer, Verifier};
+//!
+//! struct Sum;
+//!
+//! impl Signer for Sum {
+//!     fn sign(&self, data: &[u8]) -> CborResult<Vec<u8>> {
+//!         Ok(vec![data.iter().fold(0u8, |a, &b| a.wrapping_add(b))])
+//!     }
+//! }
+//!
+//! impl Verifier for Sum {
+//!     fn verify(&self, data: &[u8], signature: &[u8]) -> CborResult<()> {
+//!         if try!(self.sign(data)) == signature {
+//!             Ok(())
+//!         } else {
+//!             let err = ReadError::Other("bad signature".to_string());
+//!             Err(CborError::Decode(err))
+//!         }
+//!     }
+//! }
+//!
+//! let mut protected = HeaderMap::new();
+//! protected.insert(cose::ALG.to_cbor(), (-7i64).to_cbor());
+//! let headers = Headers::new(&protected, HeaderMap::new()).unwrap();
+//! let msg = CoseSign1::sign(headers, b"hello".to_vec(), b"", &Sum)
+//!                     .unwrap();
+//!
+//! let bytes = msg.to_bytes().unwrap();
+//! let msg = CoseSign1::from_bytes(&bytes).unwrap();
+//! assert!(msg.verify(b"", &Sum).is_ok());
+//! assert_eq!(msg.payload, Some(b"hello".to_vec()));
+//! assert_eq!(msg.headers.protected().unwrap(), protected);
+//! ```
+
+use std::collections::BTreeMap;
+
+use streaming::shift_offset;
+use {
+    Cbor, CborBytes, CborError, CborResult, Encoder, ReadError, SliceDecoder,
+    Type, decode_any_map, encode_any_map, errat, write_head,
+};
+
+/// The tag of a `COSE_Sign` message.
+pub const TAG_SIGN: u64 = 98;
+/// The tag of a `COSE_Sign1` message.
+pub const TAG_SIGN1: u64 = 18;
+/// The tag of a `COSE_Encrypt0` message.
+pub const TAG_ENCRYPT0: u64 = 16;
+/// The tag of a `COSE_Mac0` message.
+pub const TAG_MAC0: u64 = 17;
+
+/// The header label of the cryptographic algorithm.
+pub const ALG: i64 = 1;
+/// The header label of the critical headers.
+pub const CRIT: i64 = 2;
+/// The header label of the content type of the payload.
+pub const CONTENT_TYPE: i64 = 3;
+/// The header label of the key identifier.
+pub const KID: i64 = 4;
+/// The header label of the full initialization vector.
+pub const IV: i64 = 5;
+/// The header label of the partial initialization vector.
+pub const PARTIAL_IV: i64 = 6;
+
+/// A map of COSE headers, keyed by their labels.
+pub type HeaderMap = BTreeMap<Cbor, Cbor>;
+
+/// The protected and unprotected headers of a message or a signature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Headers {
+    protected: Vec<u8>,
+    /// The headers that aren't covered by the signature.
+    pub unprotected: HeaderMap,
+}
+
+impl Headers {
+    /// Creates headers from the maps given. The protected headers are
+    /// encoded right away, with their entries in the order of their keys.
+    pub fn new(protected: &HeaderMap, unprotected: HeaderMap)
+              -> CborResult<Headers> {
+        // An empty map is protected as an empty byte string.
+        let protected = if protected.is_empty() {
+            vec![]
+        } else {
+            try!(encode_any_map(protected))
+        };
+        Ok(Headers { protected: protected, unprotected: unprotected })
+    }
+
+    /// Returns the protected headers as they were encoded.
+    pub fn protected_bytes(&self) -> &[u8] {
+        &self.protected
+    }
+
+    /// Decodes the protected headers.
+    pub fn protected(&self) -> CborResult<HeaderMap> {
+        if self.protected.is_empty() {
+            Ok(HeaderMap::new())
+        } else {
+            decode_any_map(&self.protected)
+        }
+    }
+
+    /// Writes the protected and unprotected headers, which are the first
+    /// two items of every COSE message.
+    fn write(&self, out: &mut Vec<u8>) -> CborResult<()> {
+        out.extend(try!(encode(&CborBytes(self.protected.clone()))));
+        out.extend(try!(encode_any_map(&self.unprotected)));
+        Ok(())
+    }
+
+    fn read(dec: &mut SliceDecoder, bytes: &[u8]) -> CborResult<Headers> {
+        let protected = try!(dec.read_bytes()).to_vec();
+        if !protected.is_empty() {
+            let at = dec.offset() - protected.len();
+            try!(decode_any_map(&protected).map_err(|err| {
+                shift_offset(err, at)
+            }));
+        }
+        let start = dec.offset();
+        if try!(dec.peek_type()) != Type::Map {
+            return Err(other("Expected a map of unprotected headers.",
+                             start));
+        }
+        try!(dec.skip());
+        let unprotected = try!(decode_any_map(&bytes[start..dec.offset()])
+                                   .map_err(|err| shift_offset(err, start)));
+        Ok(Headers { protected: protected, unprotected: unprotected })
+    }
+}
+
+/// Signs the bytes of a `Sig_structure`.
+pub trait Signer {
+    /// Returns the signature of `data`.
+    fn sign(&self, data: &[u8]) -> CborResult<Vec<u8>>;
+}
+
+/// Verifies the signature of the bytes of a `Sig_structure`.
+pub trait Verifier {
+    /// Returns an error if `signature` isn't a valid signature of `data`.
+    fn verify(&self, data: &[u8], signature: &[u8]) -> CborResult<()>;
+}
+
+/// Computes the tag of the bytes of a `MAC_structure`.
+pub trait Mac {
+    /// Returns the tag of `data`.
+    fn tag(&self, data: &[u8]) -> CborResult<Vec<u8>>;
+}
+
+/// Encrypts and decrypts with an AEAD cipher.
+///
+/// The cipher is given the headers of the message, so it can take the
+/// nonce from the `IV` header, and the bytes of the `Enc_structure` as its
+/// associated data.
+pub trait Cipher {
+    /// Returns the ciphertext of `plaintext`.
+    fn encrypt(&self, headers: &Headers, aad: &[u8], plaintext: &[u8])
+              -> CborResult<Vec<u8>>;
+
+    /// Returns the plaintext of `ciphertext`, or an error if it (or `aad`)
+    /// was tampered with.
+    fn decrypt(&self, headers: &Headers, aad: &[u8], ciphertext: &[u8])
+              -> CborResult<Vec<u8>>;
+}
+
+/// A message with a single signature (`COSE_Sign1`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoseSign1 {
+    /// The headers of the message.
+    pub headers: Headers,
+    /// The payload, or `None` if it is detached (i.e., sent separately).
+    pub payload: Option<Vec<u8>>,
+    /// The signature.
+    pub signature: Vec<u8>,
+}
+
+impl CoseSign1 {
+    /// Signs `payload` with `signer`. `external_aad` is data that is
+    /// signed but not sent (it is usually empty).
+    pub fn sign<S: Signer>(headers: Headers, payload: Vec<u8>,
+                           external_aad: &[u8], signer: &S)
+                          -> CborResult<CoseSign1> {
+        let data = try!(sig_structure("Signature1", &headers.protected, None,
+                                      external_aad, &payload));
+        Ok(CoseSign1 {
+            signature: try!(signer.sign(&data)),
+            headers: headers,
+            payload: Some(payload),
+        })
+    }
+
+    /// Verifies the signature with `verifier`. The payload must not be
+    /// detached (see `verify_detached`).
+    pub fn verify<V: Verifier>(&self, external_aad: &[u8], verifier: &V)
+                              -> CborResult<()> {
+        match self.payload {
+            Some(ref payload) => {
+                self.verify_detached(payload, external_aad, verifier)
+            }
+            None => Err(CborError::Decode(ReadError::Other(
+                "The payload of the message is detached.".to_string()))),
+        }
+    }
+
+    /// Verifies the signature of the detached `payload` with `verifier`.
+    pub fn verify_detached<V: Verifier>(&self, payload: &[u8],
+                                        external_aad: &[u8], verifier: &V)
+                                       -> CborResult<()> {
+        let data = try!(sig_structure("Signature1", &self.headers.protected,
+                                      None, external_aad, payload));
+        verifier.verify(&data, &self.signature)
+    }
+
+    /// Encodes the message, with its tag.
+    pub fn to_bytes(&self) -> CborResult<Vec<u8>> {
+        let mut out = vec![0xc0 | TAG_SIGN1 as u8, 0x84];
+        try!(self.headers.write(&mut out));
+        out.extend(try!(encode_payload(&self.payload)));
+        out.extend(try!(encode(&CborBytes(self.signature.clone()))));
+        Ok(out)
+    }
+
+    /// Decodes a message from `bytes`, which must be exactly one data
+    /// item. Its tag may be omitted.
+    pub fn from_bytes(bytes: &[u8]) -> CborResult<CoseSign1> {
+        let mut dec = try!(read_start(bytes, TAG_SIGN1, 4));
+        let msg = CoseSign1 {
+            headers: try!(Headers::read(&mut dec, bytes)),
+            payload: try!(read_payload(&mut dec)),
+            signature: try!(dec.read_bytes()).to_vec(),
+        };
+        try!(read_end(&dec, bytes));
+        Ok(msg)
+    }
+}
+
+/// A message with any number of signatures (`COSE_Sign`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoseSign {
+    /// The headers of the message.
+    pub headers: Headers,
+    /// The payload, or `None` if it is detached (i.e., sent separately).
+    pub payload: Option<Vec<u8>>,
+    /// The signatures.
+    pub signatures: Vec<CoseSignature>,
+}
+
+/// One of the signatures of a `CoseSign` message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoseSignature {
+    /// The headers of the signature (e.g., the key of its signer).
+    pub headers: Headers,
+    /// The signature.
+    pub signature: Vec<u8>,
+}
+
+impl CoseSign {
+    /// Creates a message with no signatures yet.
+    pub fn new(headers: Headers, payload: Vec<u8>) -> CoseSign {
+        CoseSign {
+            headers: headers,
+            payload: Some(payload),
+            signatures: vec![],
+        }
+    }
+
+    /// Signs the payload with `signer`, and adds the signature with its
+    /// `headers` to the message.
+    pub fn add_signature<S: Signer>(&mut self, headers: Headers,
+                                    external_aad: &[u8], signer: &S)
+                                   -> CborResult<()> {
+        let signature = {
+            let payload = match self.payload {
+                Some(ref payload) => payload,
+                None => return Err(CborError::Decode(ReadError::Other(
+                    "The payload of the message is detached.".to_string()))),
+            };
+            let data = try!(sig_structure(
+                "Signature", &self.headers.protected,
+                Some(&headers.protected), external_aad, payload));
+            try!(signer.sign(&data))
+        };
+        self.signatures.push(CoseSignature {
+            headers: headers,
+            signature: signature,
+        });
+        Ok(())
+    }
+
+    /// Verifies the signature at `index` with `verifier`.
+    ///
+    /// # Panics
+    ///
+    /// If there is no signature at `index`.
+    pub fn verify<V: Verifier>(&self, index: usize, external_aad: &[u8],
+                               verifier: &V) -> CborResult<()> {
+        let sig = &self.signatures[index];
+        let payload = match self.payload {
+            Some(ref payload) => payload,
+            None => return Err(CborError::Decode(ReadError::Other(
+                "The payload of the message is detached.".to_string()))),
+        };
+        let data = try!(sig_structure(
+            "Signature", &self.headers.protected,
+            Some(&sig.headers.protected), external_aad, payload));
+        verifier.verify(&data, &sig.signature)
+    }
+
+    /// Encodes the message, with its tag.
+    pub fn to_bytes(&self) -> CborResult<Vec<u8>> {
+        let mut out = vec![0xd8, TAG_SIGN as u8, 0x84];
+        try!(self.headers.write(&mut out));
+        out.extend(try!(encode_payload(&self.payload)));
+        write_head(&mut out, 4, self.signatures.len() as u64);
+        for sig in &self.signatures {
+            out.push(0x83);
+            try!(sig.headers.write(&mut out));
+            out.extend(try!(encode(&CborBytes(sig.signature.clone()))));
+        }
+        Ok(out)
+    }
+
+    /// Decodes a message from `bytes`, which must be exactly one data
+    /// item. Its tag may be omitted.
+    pub fn from_bytes(bytes: &[u8]) -> CborResult<CoseSign> {
+        let mut dec = try!(read_start(bytes, TAG_SIGN, 4));
+        let headers = try!(Headers::read(&mut dec, bytes));
+        let payload = try!(read_payload(&mut dec));
+        let at = dec.offset();
+        let len = match try!(dec.read_array()) {
+            Some(len) => len,
+            None => return Err(other(
+                "Expected a definite length array of signatures.", at)),
+        };
+        let mut signatures = vec![];
+        for _ in 0..len {
+            let at = dec.offset();
+            if try!(dec.read_array()) != Some(3) {
+                return Err(other("Expected a COSE_Signature.", at));
+            }
+            signatures.push(CoseSignature {
+                headers: try!(Headers::read(&mut dec, bytes)),
+                signature: try!(dec.read_bytes()).to_vec(),
+            });
+        }
+        try!(read_end(&dec, bytes));
+        Ok(CoseSign {
+            headers: headers,
+            payload: payload,
+            signatures: signatures,
+        })
+    }
+}
+
+/// An encrypted message whose recipient already knows the key
+/// (`COSE_Encrypt0`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoseEncrypt0 {
+    /// The headers of the message, e.g., the `IV` used by the cipher.
+    pub headers: Headers,
+    /// The ciphertext, or `None` if it is detached (i.e., sent
+    /// separately).
+    pub ciphertext: Option<Vec<u8>>,
+}
+
+impl CoseEncrypt0 {
+    /// Encrypts `plaintext` with `cipher`. `external_aad` is data that is
+    /// authenticated but not sent (it is usually empty).
+    pub fn encrypt<C: Cipher>(headers: Headers, plaintext: &[u8],
+                              external_aad: &[u8], cipher: &C)
+                             -> CborResult<CoseEncrypt0> {
+        let aad = try!(encode(&("Encrypt0",
+                                CborBytes(headers.protected.clone()),
+                                CborBytes(external_aad.to_vec()))));
+        let ciphertext = try!(cipher.encrypt(&headers, &aad, plaintext));
+        Ok(CoseEncrypt0 { headers: headers, ciphertext: Some(ciphertext) })
+    }
+
+    /// Decrypts the ciphertext with `cipher`. The ciphertext must not be
+    /// detached.
+    pub fn decrypt<C: Cipher>(&self, external_aad: &[u8], cipher: &C)
+                             -> CborResult<Vec<u8>> {
+        let ciphertext = match self.ciphertext {
+            Some(ref ciphertext) => ciphertext,
+            None => return Err(CborError::Decode(ReadError::Other(
+                "The ciphertext of the message is detached.".to_string()))),
+        };
+        let aad = try!(encode(&("Encrypt0",
+                                CborBytes(self.headers.protected.clone()),
+                                CborBytes(external_aad.to_vec()))));
+        cipher.decrypt(&self.headers, &aad, ciphertext)
+    }
+
+    /// Encodes the message, with its tag.
+    pub fn to_bytes(&self) -> CborResult<Vec<u8>> {
+        let mut out = vec![0xc0 | TAG_ENCRYPT0 as u8, 0x83];
+        try!(self.headers.write(&mut out));
+        out.extend(try!(encode_payload(&self.ciphertext)));
+        Ok(out)
+    }
+
+    /// Decodes a message from `bytes`, which must be exactly one data
+    /// item. Its tag may be omitted.
+    pub fn from_bytes(bytes: &[u8]) -> CborResult<CoseEncrypt0> {
+        let mut dec = try!(read_start(bytes, TAG_ENCRYPT0, 3));
+        let msg = CoseEncrypt0 {
+            headers: try!(Headers::read(&mut dec, bytes)),
+            ciphertext: try!(read_payload(&mut dec)),
+        };
+        try!(read_end(&dec, bytes));
+        Ok(msg)
+    }
+}
+
+/// A message with a MAC whose recipient already knows the key
+/// (`COSE_Mac0`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoseMac0 {
+    /// The headers of the message.
+    pub headers: Headers,
+    /// The payload, or `None` if it is detached (i.e., sent separately).
+    pub payload: Option<Vec<u8>>,
+    /// The tag computed by the MAC.
+    pub tag: Vec<u8>,
+}
+
+impl CoseMac0 {
+    /// Computes the tag of `payload` with `mac`. `external_aad` is data
+    /// that is authenticated but not sent (it is usually empty).
+    pub fn create<M: Mac>(headers: Headers, payload: Vec<u8>,
+                          external_aad: &[u8], mac: &M)
+                         -> CborResult<CoseMac0> {
+        let data = try!(mac_structure(&headers.protected, external_aad,
+                                      &payload));
+        Ok(CoseMac0 {
+            tag: try!(mac.tag(&data)),
+            headers: headers,
+            payload: Some(payload),
+        })
+    }
+
+    /// Checks the tag with `mac`. The payload must not be detached.
+    ///
+    /// The tags are compared in constant time, so the time this takes
+    /// doesn't tell how much of a forged tag was right.
+    pub fn verify<M: Mac>(&self, external_aad: &[u8], mac: &M)
+                         -> CborResult<()> {
+        let payload = match self.payload {
+            Some(ref payload) => payload,
+            None => return Err(CborError::Decode(ReadError::Other(
+                "The payload of the message is detached.".to_string()))),
+        };
+        let data = try!(mac_structure(&self.headers.protected, external_aad,
+                                      payload));
+        let tag = try!(mac.tag(&data));
+        let diff = tag.iter().zip(&self.tag).fold(0, |d, (a, b)| d | (*a ^ *b));
+        if tag.len() != self.tag.len() || diff != 0 {
+            return Err(CborError::Decode(ReadError::Other(
+                "The tag of the message doesn't match.".to_string())));
+        }
+        Ok(())
+    }
+
+    /// Encodes the message, with its tag.
+    pub fn to_bytes(&self) -> CborResult<Vec<u8>> {
+        let mut out = vec![0xc0 | TAG_MAC0 as u8, 0x84];
+        try!(self.headers.write(&mut out));
+        out.extend(try!(encode_payload(&self.payload)));
+        out.extend(try!(encode(&CborBytes(self.tag.clone()))));
+        Ok(out)
+    }
+
+    /// Decodes a message from `bytes`, which must be exactly one data
+    /// item. Its tag may be omitted.
+    pub fn from_bytes(bytes: &[u8]) -> CborResult<CoseMac0> {
+        let mut dec = try!(read_start(bytes, TAG_MAC0, 4));
+        let msg = CoseMac0 {
+            headers: try!(Headers::read(&mut dec, bytes)),
+            payload: try!(read_payload(&mut dec)),
+            tag: try!(dec.read_bytes()).to_vec(),
+        };
+        try!(read_end(&dec, bytes));
+        Ok(msg)
+    }
+}
+
+/// Returns the bytes of a `Sig_structure`, which are what is signed.
+/// `sign_protected` is only given for the signatures of `COSE_Sign`.
+pub fn sig_structure(context: &str, body_protected: &[u8],
+                     sign_protected: Option<&[u8]>, external_aad: &[u8],
+                     payload: &[u8]) -> CborResult<Vec<u8>> {
+    let body = CborBytes(body_protected.to_vec());
+    let aad = CborBytes(external_aad.to_vec());
+    let payload = CborBytes(payload.to_vec());
+    match sign_protected {
+        Some(sign) => {
+            let sign = CborBytes(sign.to_vec());
+            encode(&(context, body, sign, aad, payload))
+        }
+        None => encode(&(context, body, aad, payload)),
+    }
+}
+
+/// Returns the bytes of the `MAC_structure` of a `COSE_Mac0` message,
+/// which are what the MAC is computed over.
+pub fn mac_structure(protected: &[u8], external_aad: &[u8], payload: &[u8])
+                    -> CborResult<Vec<u8>> {
+    encode(&("MAC0", CborBytes(protected.to_vec()),
+             CborBytes(external_aad.to_vec()), CborBytes(payload.to_vec())))
+}
+
+/// Reads the start of a message: its tag, which may be omitted, and the
+/// head of an array of `len` items.
+fn read_start(bytes: &[u8], tag: u64, len: usize)
+             -> CborResult<SliceDecoder> {
+    let mut dec = SliceDecoder::new(bytes);
+    if try!(dec.peek_type()) == Type::Tag {
+        let at = dec.offset();
+        let got = try!(dec.read_tag());
+        if got != tag {
+            return Err(other(&format!(
+                "Expected a COSE message with tag {}, but got tag {}.",
+                tag, got), at));
+        }
+    }
+    let at = dec.offset();
+    if try!(dec.read_array()) != Some(len) {
+        return Err(other(&format!(
+            "Expected a COSE message (an array of {} items).", len), at));
+    }
+    Ok(dec)
+}
+
+/// Returns an error if anything follows the message.
+fn read_end(dec: &SliceDecoder, bytes: &[u8]) -> CborResult<()> {
+    if dec.is_empty() {
+        return Ok(());
+    }
+    Err(CborError::AtOffset {
+        kind: ReadError::TrailingBytes {
+            remaining: bytes.len() - dec.offset(),
+        },
+        offset: dec.offset(),
+    })
+}
+
+/// Reads a payload, which is `null` if it is detached.
+fn read_payload(dec: &mut SliceDecoder) -> CborResult<Option<Vec<u8>>> {
+    if try!(dec.read_null()) {
+        Ok(None)
+    } else {
+        Ok(Some(try!(dec.read_bytes()).to_vec()))
+    }
+}
+
+fn encode_payload(payload: &Option<Vec<u8>>) -> CborResult<Vec<u8>> {
+    match *payload {
+        Some(ref payload) => encode(&CborBytes(payload.clone())),
+        None => encode(&()),
+    }
+}
+
+fn encode<T: ::rustc_serialize::Encodable>(v: &T) -> CborResult<Vec<u8>> {
+    let mut enc = Encoder::from_memory();
+    try!(enc.encode(&[v]));
+    Ok(enc.into_bytes())
+}
+
+fn other(msg: &str, offset: usize) -> CborError {
+    errat(ReadError::Other(msg.to_string()), offset)
+}
@@ -0,0 +1,157 @@
+//! Claims sets of CBOR Web Tokens (CWT, RFC 8392).
+//!
+//! A CWT is a COSE message (see the `cose` module) whose payload is a
+//! claims set: a map from integer claim keys (e.g., 1 for the issuer and
+//! 4 for the expiration time) to their values. `CwtClaims` holds the
+//! standard claims as fields, and any other claims as they were read.
+//!
+//! Times are NumericDates, i.e., seconds since the Unix epoch. RFC 8392
+//! writes them without tag 1 (epoch-based date/time), but a tagged time is
+//! accepted too, since some encoders add it. NumericDates may also be
+//! floats with fractional seconds, which are truncated toward zero.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cbor::cwt::CwtClaims;
+//!
+//! let mut claims = CwtClaims::new();
+//! claims.iss = Some("coap://as.example.com".to_string());
+//! claims.exp = Some(1444064944);
+//! let bytes = claims.to_bytes().unwrap();
+//! // {1: "coap://as.example.com", 4: 1444064944}
+//! assert_eq!(&bytes[..3], &[0xa2, 0x01, 0x75][..]);
+//!
+//! // The claims set is the payload of a COSE message, e.g., `CoseSign1`.
+//! assert_eq!(CwtClaims::from_bytes(&bytes).unwrap(), claims);
+//! ```
+
+use std::collections::BTreeMap;
+
+use {
+    Cbor, CborBytes, CborError, CborResult, ReadError, ToCbor, Type,
+    decode_any_map, encode_any_map,
+};
+
+/// The tag of a CWT, which may wrap the COSE message of the token.
+pub const TAG: u64 = 61;
+
+/// The claim key of the issuer.
+pub const ISS: i64 = 1;
+/// The claim key of the subject.
+pub const SUB: i64 = 2;
+/// The claim key of the audience.
+pub const AUD: i64 = 3;
+/// The claim key of the expiration time.
+pub const EXP: i64 = 4;
+/// The claim key of the time before which the token isn't valid.
+pub const NBF: i64 = 5;
+/// The claim key of the time the token was issued at.
+pub const IAT: i64 = 6;
+/// The claim key of the unique identifier of the token.
+pub const CTI: i64 = 7;
+
+/// The claims set of a CWT.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CwtClaims {
+    /// The issuer (`iss`).
+    pub iss: Option<String>,
+    /// The subject (`sub`).
+    pub sub: Option<String>,
+    /// The audience (`aud`).
+    pub aud: Option<String>,
+    /// The expiration time (`exp`).
+    pub exp: Option<i64>,
+    /// The time before which the token isn't valid (`nbf`).
+    pub nbf: Option<i64>,
+    /// The time the token was issued at (`iat`).
+    pub iat: Option<i64>,
+    /// The unique identifier of the token (`cti`).
+    pub cti: Option<Vec<u8>>,
+    /// Any other claims, keyed by their claim keys (or names).
+    pub extra: BTreeMap<Cbor, Cbor>,
+}
+
+impl CwtClaims {
+    /// Creates a claims set with no claims.
+    pub fn new() -> CwtClaims {
+        CwtClaims::default()
+    }
+
+    /// Encodes the claims set as a map, with its entries in the order of
+    /// their keys. A standard claim replaces an entry of `extra` with the
+    /// same key.
+    pub fn to_bytes(&self) -> CborResult<Vec<u8>> {
+        let mut map = self.extra.clone();
+        let texts = [(ISS, &self.iss), (SUB, &self.sub), (AUD, &self.aud)];
+        for &(key, v) in &texts {
+            if let Some(ref v) = *v {
+                map.insert(key.to_cbor(), v.to_cbor());
+            }
+        }
+        let times = [(EXP, self.exp), (NBF, self.nbf), (IAT, self.iat)];
+        for &(key, v) in &times {
+            if let Some(v) = v {
+                map.insert(key.to_cbor(), v.to_cbor());
+            }
+        }
+        if let Some(ref cti) = self.cti {
+            map.insert(CTI.to_cbor(), Cbor::Bytes(CborBytes(cti.clone())));
+        }
+        encode_any_map(&map)
+    }
+
+    /// Decodes a claims set from `bytes`, which must be exactly one map.
+    ///
+    /// Claims with other keys are kept in `extra`. A standard claim of the
+    /// wrong type is a `ReadError::TypeMismatch` error.
+    pub fn from_bytes(bytes: &[u8]) -> CborResult<CwtClaims> {
+        let mut claims = CwtClaims::new();
+        for (k, v) in try!(decode_any_map(bytes)) {
+            match k.as_i64() {
+                Some(ISS) => claims.iss = Some(try!(text(v))),
+                Some(SUB) => claims.sub = Some(try!(text(v))),
+                Some(AUD) => claims.aud = Some(try!(text(v))),
+                Some(EXP) => claims.exp = Some(try!(numeric_date(v))),
+                Some(NBF) => claims.nbf = Some(try!(numeric_date(v))),
+                Some(IAT) => claims.iat = Some(try!(numeric_date(v))),
+                Some(CTI) => match v {
+                    Cbor::Bytes(bytes) => claims.cti = Some(bytes.0),
+                    v => return Err(mismatch(Type::Bytes, &v)),
+                },
+                _ => {
+                    claims.extra.insert(k, v);
+                }
+            }
+        }
+        Ok(claims)
+    }
+}
+
+fn text(v: Cbor) -> CborResult<String> {
+    match v {
+        Cbor::Unicode(s) => Ok(s),
+        v => Err(mismatch(Type::Unicode, &v)),
+    }
+}
+
+fn numeric_date(v: Cbor) -> CborResult<i64> {
+    let v = match v {
+        Cbor::Tag(tag) => {
+            if tag.tag == 1 { *tag.data } else { Cbor::Tag(tag) }
+        }
+        v => v,
+    };
+    if let Some(secs) = v.as_f64() {
+        // This is false for NaN, as well as for times that don't fit.
+        let secs = secs.trunc();
+        if secs >= -9223372036854775808.0 && secs < 9223372036854775808.0 {
+            return Ok(secs as i64);
+        }
+    }
+    v.as_i64().ok_or_else(|| mismatch(Type::Int64, &v))
+}
+
+fn mismatch(expected: Type, got: &Cbor) -> CborError {
+    CborError::Decode(ReadError::mismatch(expected, got))
+}
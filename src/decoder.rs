@@ -1,30 +1,571 @@
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
 use std::convert::Into;
-use std::collections::hash_map::HashMap;
 use std::io::{self, Read};
-use std::mem::transmute;
+use std::marker::PhantomData;
 
-use byteorder::{ReadBytesExt, BigEndian};
-use rustc_serialize::Decodable;
+use byteorder::{ByteOrder, BigEndian};
+use rustc_serialize::{Decodable, Decoder as RustcDecoder};
 
-use rustc_decoder::CborDecoder;
+use canonical::cmp_canonical_keys;
+use lexer::f16_to_f64;
+use rustc_decoder::{CborDecoder, Options};
 use {
-    Cbor, CborUnsigned, CborSigned, CborFloat, CborBytes, CborTag, Type,
-    CborResult, CborError, ReadError,
+    Cbor, CborUnsigned, CborSigned, CborFloat, CborBytes, CborSimple, CborTag,
+    CborMap, CborWide, Type,
+    CborResult, CborError, ReadError, Narrowing, TagPolicy, OptionFormat,
+    DuplicateKeyPolicy, Budget, SliceDecoder, DEFAULT_MAX_DEPTH,
+    stringref_min_len,
 };
 
+/// The most items to allocate room for before reading an array or map.
+/// Lengths come from the data, so they can't be trusted until the items
+/// have actually been read.
+const MAX_PREALLOC: usize = 1024;
+
 /// Read CBOR data items into Rust values from the underlying reader `R`.
+///
+/// When reading untrusted data, consider setting `max_size`, `max_items`
+/// and `duplicate_keys`, as well as `max_total_bytes` and
+/// `max_total_items`. By default, there are no limits on sizes, so a few
+/// bytes of input can claim a length of gigabytes. Nesting is limited to a
+/// depth of 128 by default (see `max_depth`).
 pub struct Decoder<R> {
     rdr: CborReader<R>,
+    opts: Options,
+    faithful_integers: bool,
+    strict_nans: bool,
+    strict_minimal: bool,
+    dag_cbor: bool,
+    stringrefs: bool,
+    // The strings of each open stringref namespace, by index.
+    strings: Vec<Vec<Cbor>>,
+    shared_values: bool,
+    // The values shared in the current top-level data item, by index. A
+    // value is `None` while it is being read.
+    shared: Vec<Option<Cbor>>,
+    // The names of struct fields that are written with integer keys.
+    field_keys: HashMap<i64, String>,
+    limits: Limits,
+    // The number of arrays, maps and tags enclosing the current data item.
+    depth: usize,
+    // The number of data items read so far, for `max_total_items`.
+    items_read: u64,
+}
+
+/// Limits on the data items read by a `Decoder`.
+#[derive(Clone, Copy, Debug)]
+struct Limits {
+    max_depth: usize,
+    max_size: u64,
+    max_items: u64,
+    duplicate_keys: DuplicateKeyPolicy,
+    max_total_items: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_size: ::std::u64::MAX,
+            max_items: ::std::u64::MAX,
+            duplicate_keys: DuplicateKeyPolicy::Last,
+            max_total_items: ::std::u64::MAX,
+        }
+    }
 }
 
 impl<R: io::Read> Decoder<R> {
     /// Create a new CBOR decoder from the underlying reader.
-    pub fn from_reader(rdr: R) -> Decoder<io::BufReader<R>> {
-        Decoder { rdr: CborReader::new(io::BufReader::new(rdr)) }
+    ///
+    /// The decoder reads ahead into a buffer of its own, so there is no
+    /// need to wrap `rdr` in an `io::BufReader`.
+    pub fn from_reader(rdr: R) -> Decoder<R> {
+        Decoder {
+            rdr: CborReader::new(rdr),
+            opts: Options::default(),
+            faithful_integers: false,
+            strict_nans: false,
+            strict_minimal: false,
+            dag_cbor: false,
+            stringrefs: false,
+            strings: vec![],
+            shared_values: false,
+            shared: vec![],
+            field_keys: HashMap::new(),
+            limits: Limits::default(),
+            depth: 0,
+            items_read: 0,
+        }
     }
 }
 
 impl<R: io::Read> Decoder<R> {
+    /// Set how numbers are decoded into Rust types that can't represent
+    /// them, e.g., a `300` into a `u8`. The default is `Narrowing::Error`.
+    ///
+    /// This only affects `decode`. The values yielded by `items` are never
+    /// narrowed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Decoder, Encoder, Narrowing};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[300]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes())
+    ///                       .narrowing(Narrowing::Saturate);
+    /// let n: u8 = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(n, 255);
+    /// ```
+    pub fn narrowing(mut self, narrowing: Narrowing) -> Decoder<R> {
+        self.opts.narrowing = narrowing;
+        self
+    }
+
+    /// When enabled, `undefined` can't be decoded into Rust values.
+    ///
+    /// By default, `undefined` (which is produced by e.g. JavaScript
+    /// encoders) is decoded the same as `null`, i.e., as `()` or `None`.
+    /// When this is enabled, doing so returns a `TypeMismatch` error.
+    ///
+    /// This only affects `decode`. The values yielded by `items` always
+    /// include `Cbor::Undefined`.
+    pub fn strict_undefined(mut self, yes: bool) -> Decoder<R> {
+        self.opts.strict_undefined = yes;
+        self
+    }
+
+    /// When enabled, a text string that holds a number, like `"42"`, can be
+    /// decoded into an integer or a float.
+    ///
+    /// Tools that convert JSON to CBOR sometimes write large numbers as
+    /// strings. By default, decoding one into a number returns a
+    /// `TypeMismatch` error. (Integers can always be decoded into floats,
+    /// with or without this.)
+    ///
+    /// This only affects `decode`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Decoder, Encoder};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[("18446744073709551615", "-1.5")]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes())
+    ///                       .coerce_numbers(true);
+    /// let (n, f): (u64, f64) = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!((n, f), (::std::u64::MAX, -1.5));
+    /// ```
+    pub fn coerce_numbers(mut self, yes: bool) -> Decoder<R> {
+        self.opts.coerce_numbers = yes;
+        self
+    }
+
+    /// When enabled, a byte string can be decoded into a `String` and a
+    /// text string into a sequence of `u8`s (e.g., a `Vec<u8>`).
+    ///
+    /// Some encoders mix up the two for the same field. By default, doing
+    /// either returns a `TypeMismatch` error. A byte string decoded into a
+    /// `String` must still be valid UTF-8.
+    ///
+    /// This only affects `decode`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborBytes, Decoder, Encoder};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[CborBytes(b"hi".to_vec())]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes())
+    ///                       .coerce_strings(true);
+    /// let s: String = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(s, "hi");
+    /// ```
+    pub fn coerce_strings(mut self, yes: bool) -> Decoder<R> {
+        self.opts.coerce_strings = yes;
+        self
+    }
+
+    /// Set how `Option`s are read. This must match the format they were
+    /// written with (see `Encoder::option_format`). The default is
+    /// `OptionFormat::Null`.
+    ///
+    /// With `OptionFormat::Array`, a value that is neither `null` nor an
+    /// array of one item can't be decoded into an `Option`.
+    ///
+    /// This only affects `decode`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Decoder, Encoder, OptionFormat};
+    ///
+    /// let format = OptionFormat::Array;
+    /// let mut enc = Encoder::from_memory().option_format(format);
+    /// enc.encode(&[Some(None::<u8>)]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes())
+    ///                       .option_format(format);
+    /// let v: Option<Option<u8>> = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(v, Some(None));
+    /// ```
+    pub fn option_format(mut self, format: OptionFormat) -> Decoder<R> {
+        self.opts.options = format;
+        self
+    }
+
+    /// Set how tagged data items are decoded into Rust values.
+    ///
+    /// By default, tags are preserved: a tag is decoded as its tag number
+    /// followed by its data item. Use `TagPolicy::Ignore` to decode tagged
+    /// data from other encoders into types that don't know about tags.
+    ///
+    /// This only affects `decode`. The values yielded by `items` always
+    /// include `Cbor::Tag`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborTagEncode, Decoder, Encoder, TagPolicy};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[CborTagEncode::new(0, &"2013-03-21T20:04:00Z")]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes())
+    ///                       .tag_policy(TagPolicy::Ignore);
+    /// let date: String = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(date, "2013-03-21T20:04:00Z");
+    /// ```
+    pub fn tag_policy(mut self, tags: TagPolicy) -> Decoder<R> {
+        self.opts.tags = tags;
+        self
+    }
+
+    /// When enabled, integers that were encoded with more bytes than needed
+    /// are read as `Cbor::Wide`, which is encoded with the same number of
+    /// bytes again.
+    ///
+    /// By default, such integers are read as `Cbor::Unsigned` or
+    /// `Cbor::Signed` and lose their width. That only matters when data
+    /// must be re-encoded byte for byte, e.g., to check a signature over it.
+    ///
+    /// `Cbor::Wide` is decoded into Rust values like any other integer.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Cbor, CborWide, Decoder, Encoder};
+    ///
+    /// // 5, encoded with a two byte argument
+    /// let bytes = vec![0x19, 0x00, 0x05];
+    /// let mut dec = Decoder::from_bytes(&bytes[..]).faithful_integers(true);
+    /// let item = dec.items().next().unwrap().unwrap();
+    /// assert_eq!(item, Cbor::Wide(CborWide {
+    ///     negative: false, arg: 5, width: 2,
+    /// }));
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[item]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &bytes[..]);
+    /// ```
+    pub fn faithful_integers(mut self, yes: bool) -> Decoder<R> {
+        self.faithful_integers = yes;
+        self
+    }
+
+    /// When enabled, a NaN is only accepted in its canonical encoding,
+    /// the half precision float `f9 7e 00`.
+    ///
+    /// Any other NaN (e.g., one with a payload, a sign or more precision)
+    /// returns a `ReadError::NonCanonicalNan` error. This is useful for
+    /// checking data that must have exactly one encoding, like data that
+    /// is hashed (see `Encoder::canonical_nans`).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborError, Decoder, ReadError};
+    ///
+    /// // [NaN, NaN as a single precision float]
+    /// let bytes = vec![0x82, 0xf9, 0x7e, 0x00, 0xfa, 0x7f, 0xc0, 0x00, 0x00];
+    /// let mut dec = Decoder::from_bytes(bytes).strict_nans(true);
+    /// match dec.items().next().unwrap() {
+    ///     Err(CborError::AtOffset {
+    ///         kind: ReadError::NonCanonicalNan { offset: 4 }, offset: 4,
+    ///     }) => {}
+    ///     r => panic!("unexpected {:?}", r),
+    /// }
+    /// ```
+    pub fn strict_nans(mut self, yes: bool) -> Decoder<R> {
+        self.strict_nans = yes;
+        self
+    }
+
+    /// When enabled, integers, lengths and tag numbers must be encoded
+    /// with as few bytes as possible.
+    ///
+    /// Anything else (e.g., `10` encoded as an eight byte integer) returns
+    /// a `ReadError::NonMinimalEncoding` error. This is required by
+    /// deterministic encodings, like the canonical form of COSE or
+    /// DAG-CBOR, which can be checked this way.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborError, Decoder, ReadError};
+    ///
+    /// // ["a"], where the length of the string is encoded with one byte
+    /// let bytes = vec![0x81, 0x78, 0x01, b'a'];
+    /// let mut dec = Decoder::from_bytes(bytes).strict_minimal(true);
+    /// match dec.items().next().unwrap() {
+    ///     Err(CborError::AtOffset {
+    ///         kind: ReadError::NonMinimalEncoding { offset: 1 }, offset: 1,
+    ///     }) => {}
+    ///     r => panic!("unexpected {:?}", r),
+    /// }
+    /// ```
+    pub fn strict_minimal(mut self, yes: bool) -> Decoder<R> {
+        self.strict_minimal = yes;
+        self
+    }
+
+    /// When enabled, only data in the DAG-CBOR subset used by IPLD is
+    /// accepted.
+    ///
+    /// This enables `strict_minimal` and additionally requires map keys
+    /// to be sorted as in `cmp_canonical_keys` (without duplicates) and
+    /// floats to be finite and double precision. Tag 42 (see `Cid`) is
+    /// the only tag allowed, and `false`, `true` and `null` are the only
+    /// simple values. Anything else returns a `ReadError::DagCbor` error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborError, Decoder, ReadError};
+    ///
+    /// // {"b": 1, "a": 2}
+    /// let bytes = vec![0xa2, 0x61, b'b', 0x01, 0x61, b'a', 0x02];
+    /// let mut dec = Decoder::from_bytes(bytes).dag_cbor(true);
+    /// match dec.items().next().unwrap() {
+    ///     Err(CborError::AtOffset {
+    ///         kind: ReadError::DagCbor(_), offset: 4,
+    ///     }) => {}
+    ///     r => panic!("unexpected {:?}", r),
+    /// }
+    /// ```
+    pub fn dag_cbor(mut self, yes: bool) -> Decoder<R> {
+        self.dag_cbor = yes;
+        self.strict_minimal = yes;
+        self
+    }
+
+    /// When enabled, strings that are written as references with the
+    /// stringref extension (tags 256 and 25) are resolved while reading.
+    ///
+    /// The tags are removed, so stringrefs are invisible to `decode` and
+    /// `items`. Data written with `Encoder::stringrefs` must be read this
+    /// way. Tag 25 outside of tag 256 is left alone.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Decoder, Encoder};
+    ///
+    /// let names = vec!["hello".to_string(), "hello".to_string()];
+    /// let mut enc = Encoder::from_memory().stringrefs(true);
+    /// enc.encode(&[&names]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes()).stringrefs(true);
+    /// let back: Vec<String> = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(back, names);
+    /// ```
+    pub fn stringrefs(mut self, yes: bool) -> Decoder<R> {
+        self.stringrefs = yes;
+        self
+    }
+
+    /// When enabled, values that are shared with tags 28 and 29 (see
+    /// `CborShared`) are resolved while reading.
+    ///
+    /// The tags are removed, and every reference is replaced by a copy of
+    /// the value it refers to. A reference to a value that contains it
+    /// returns an error, since a `Cbor` value can't have cycles.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Cbor, Decoder};
+    ///
+    /// // [28("abc"), 29(0)]
+    /// let bytes = vec![0x82, 0xd8, 0x1c, 0x63, b'a', b'b', b'c',
+    ///                  0xd8, 0x1d, 0x00];
+    /// let mut dec = Decoder::from_bytes(bytes).shared_values(true);
+    /// let v = dec.items().next().unwrap().unwrap();
+    /// assert_eq!(v, Cbor::from_diagnostic(r#"["abc", "abc"]"#).unwrap());
+    /// ```
+    pub fn shared_values(mut self, yes: bool) -> Decoder<R> {
+        self.shared_values = yes;
+        self
+    }
+
+    /// Set the names of struct fields that are written with integer keys
+    /// (see `Encoder::field_keys`).
+    ///
+    /// A map key that is an integer in `keys` is read as the name it is
+    /// paired with, so derived `Decodable` impls find their fields. This
+    /// applies to every map, including the ones yielded by `items`. Other
+    /// keys must still be Unicode strings.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate cbor;
+    /// # extern crate rustc_serialize;
+    /// # fn main() {
+    /// use cbor::Decoder;
+    ///
+    /// #[derive(RustcDecodable)]
+    /// struct Header { alg: i32, kid: String }
+    ///
+    /// // {1: -7, 4: "k"}
+    /// let bytes = vec![0xa2, 0x01, 0x26, 0x04, 0x61, b'k'];
+    /// let mut dec = Decoder::from_bytes(bytes)
+    ///                       .field_keys(&[("alg", 1), ("kid", 4)]);
+    /// let header: Header = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!((header.alg, header.kid), (-7, "k".to_string()));
+    /// # }
+    /// ```
+    pub fn field_keys(mut self, keys: &[(&str, i64)]) -> Decoder<R> {
+        self.field_keys = keys.iter().map(|&(name, key)| {
+            (key, name.to_string())
+        }).collect();
+        self
+    }
+
+    /// Set the maximum number of arrays, maps and tags that a data item
+    /// may be nested in. A top-level array of integers has depth 1.
+    ///
+    /// Deeper data returns a `ReadError::TooDeep` error. Data items are read
+    /// recursively, so this protects against running out of stack space.
+    /// The default is `128`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborError, Decoder, ReadError};
+    ///
+    /// // [[[]]]
+    /// let mut dec = Decoder::from_bytes(vec![0x81, 0x81, 0x80])
+    ///                       .max_depth(2);
+    /// match dec.items().next().unwrap() {
+    ///     Err(CborError::AtOffset {
+    ///         kind: ReadError::TooDeep { max: 2 }, offset: 2,
+    ///     }) => {}
+    ///     r => panic!("unexpected {:?}", r),
+    /// }
+    /// ```
+    pub fn max_depth(mut self, max: usize) -> Decoder<R> {
+        self.limits.max_depth = max;
+        self
+    }
+
+    /// Set the maximum length in bytes of byte and Unicode strings.
+    ///
+    /// Longer strings return a `ReadError::TooLong` error before anything
+    /// is allocated for them. This doesn't apply to `bytes_reader`.
+    pub fn max_size(mut self, max: u64) -> Decoder<R> {
+        self.limits.max_size = max;
+        self
+    }
+
+    /// Set the maximum number of items in an array or entries in a map.
+    ///
+    /// Larger arrays and maps return a `ReadError::TooLong` error before
+    /// anything is allocated for them.
+    pub fn max_items(mut self, max: u64) -> Decoder<R> {
+        self.limits.max_items = max;
+        self
+    }
+
+    /// Set what to do when a map has more than one entry with the same key.
+    /// The default is `DuplicateKeyPolicy::Last`.
+    ///
+    /// Map keys must be Unicode strings, or integers that name a field
+    /// with `field_keys`. Those are compared by the field's name, so `1`
+    /// and `"alg"` are the same key when `1` names the field `alg`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use cbor::{Decoder, DuplicateKeyPolicy};
+    ///
+    /// // {"a": 1, "a": 2}
+    /// let bytes = vec![0xa2, 0x61, b'a', 0x01, 0x61, b'a', 0x02];
+    ///
+    /// let mut dec = Decoder::from_bytes(&bytes[..])
+    ///                       .duplicate_keys(DuplicateKeyPolicy::First);
+    /// let map: HashMap<String, u8> = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(map["a"], 1);
+    ///
+    /// let mut dec = Decoder::from_bytes(&bytes[..])
+    ///                       .duplicate_keys(DuplicateKeyPolicy::Error);
+    /// assert!(dec.items().next().unwrap().is_err());
+    /// ```
+    pub fn duplicate_keys(mut self, policy: DuplicateKeyPolicy)
+                         -> Decoder<R> {
+        self.limits.duplicate_keys = policy;
+        self
+    }
+
+    /// Set the maximum number of bytes this decoder reads in total, over
+    /// all of the data items it reads.
+    ///
+    /// Going over returns a `ReadError::BudgetExceeded` error. The lengths
+    /// of strings are checked against the budget before anything is
+    /// allocated for them, and so is the length of a `bytes_reader`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Budget, CborError, Decoder, ReadError};
+    ///
+    /// let mut dec = Decoder::from_bytes(vec![0x01, 0x02, 0x03])
+    ///                       .max_total_bytes(2);
+    /// let mut items = dec.items();
+    /// assert!(items.next().unwrap().is_ok());
+    /// assert!(items.next().unwrap().is_ok());
+    /// match items.next().unwrap() {
+    ///     Err(CborError::AtOffset {
+    ///         kind: ReadError::BudgetExceeded(Budget::Bytes(2)), ..
+    ///     }) => {}
+    ///     r => panic!("unexpected {:?}", r),
+    /// }
+    /// ```
+    pub fn max_total_bytes(mut self, max: u64) -> Decoder<R> {
+        self.rdr.max_bytes = max;
+        self
+    }
+
+    /// Set the maximum number of data items this decoder reads in total.
+    /// Every element of an array, key and value of a map and tagged data
+    /// item counts as a data item of its own.
+    ///
+    /// Going over returns a `ReadError::BudgetExceeded` error. Unlike
+    /// `max_items`, this bounds the total amount of work done, no matter
+    /// how the data items are nested or split up.
+    pub fn max_total_items(mut self, max: u64) -> Decoder<R> {
+        self.limits.max_total_items = max;
+        self
+    }
+
     /// Decode a sequence of top-level CBOR data items into Rust values.
     ///
     /// # Example
@@ -50,10 +591,46 @@ impl<R: io::Read> Decoder<R> {
     pub fn decode<D: Decodable>(&mut self) -> DecodedItems<R, D> {
         DecodedItems {
             it: self.items(),
-            _phantom: ::std::marker::PhantomData,
+            _phantom: PhantomData,
         }
     }
 
+    /// Decode the next top-level data item with `seed`, or return `None`
+    /// if there are no more data items.
+    ///
+    /// Unlike `decode`, which always makes a new value, this lets the
+    /// value be decoded by something with state of its own, e.g., a
+    /// `Vec` whose capacity is reused for every message, or a table of
+    /// strings that have already been seen. See `DecodeSeed`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Decoder, Encoder};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[vec![1, 2, 3], vec![4]]).unwrap();
+    ///
+    /// let mut dec = Decoder::from_bytes(enc.as_bytes());
+    /// let mut buf: Vec<u32> = Vec::with_capacity(16);
+    /// dec.decode_seed(&mut buf).unwrap().unwrap();
+    /// assert_eq!(buf, vec![1, 2, 3]);
+    /// dec.decode_seed(&mut buf).unwrap().unwrap();
+    /// assert_eq!(buf, vec![4]);
+    /// assert!(buf.capacity() >= 16);
+    /// assert!(dec.decode_seed(&mut buf).is_none());
+    /// ```
+    pub fn decode_seed<S: DecodeSeed>(&mut self, seed: S)
+                                     -> Option<CborResult<S::Value>> {
+        let offset = self.rdr.bytes_read;
+        let opts = self.opts;
+        self.items().next().map(|result| {
+            result.and_then(|v| {
+                CborDecoder::decode_seed_at(v, offset, seed, opts)
+            })
+        })
+    }
+
     /// Read a sequence of top-level CBOR data items.
     ///
     /// This yields data items represented by the `Cbor` type, which is its
@@ -93,45 +670,234 @@ impl<R: io::Read> Decoder<R> {
         Items { dec: self }
     }
 
+    /// Returns the number of bytes that have been read from the underlying
+    /// reader.
+    ///
+    /// After a data item has been decoded, this is the offset of the next
+    /// one. This can be used to find where a message ends in a buffer of
+    /// concatenated messages.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Decoder;
+    ///
+    /// // "ab", 1
+    /// let mut dec = Decoder::from_bytes(vec![0x62, b'a', b'b', 0x01]);
+    /// let s: String = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(s, "ab");
+    /// assert_eq!(dec.bytes_consumed(), 3);
+    /// ```
+    pub fn bytes_consumed(&self) -> usize {
+        self.rdr.bytes_read
+    }
+
+    /// Skips over the next data item without decoding it, and returns
+    /// false if there are no more data items.
+    ///
+    /// Nothing is allocated for the data item: the contents of byte and
+    /// Unicode strings are discarded as they are read, and arrays and maps
+    /// are skipped by counting the heads of the data items in them. The
+    /// data is only checked as far as needed to find where it ends (e.g.,
+    /// Unicode strings aren't checked to be valid UTF-8), but the limits on
+    /// depth and on the size of the input still apply.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Decoder;
+    ///
+    /// // [h'0000...', {"a": [1, 2]}], 3
+    /// let mut bytes = vec![0x82, 0x59, 0x10, 0x00];
+    /// bytes.extend(vec![0; 4096]);
+    /// bytes.extend(vec![0xa1, 0x61, b'a', 0x82, 0x01, 0x02, 0x03]);
+    /// let mut dec = Decoder::from_bytes(bytes);
+    /// assert!(dec.skip().unwrap());
+    /// let n: u8 = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(n, 3);
+    /// assert!(!dec.skip().unwrap());
+    /// ```
+    pub fn skip(&mut self) -> CborResult<bool> {
+        let mut next = match try!(self.rdr.read_first()) {
+            Some(first) => Some(first),
+            None => return Ok(false),
+        };
+        // Each entry is the number of data items left to skip in an open
+        // container, or `None` if the container is terminated by a break.
+        let mut open: Vec<Option<u64>> = vec![];
+        loop {
+            let first = match next.take() {
+                Some(first) => first,
+                None => try!(self.rdr.read_u8()),
+            };
+            let pushed = match try!(self.skip_head(first)) {
+                // A break is encoded as major type 7 with no argument.
+                (7, None) => match open.pop() {
+                    Some(None) => None,
+                    _ => {
+                        let offset = self.rdr.last_offset;
+                        return Err(self.errat(
+                            ReadError::UnexpectedBreak { offset: offset }));
+                    }
+                },
+                (2, Some(len)) | (3, Some(len)) => {
+                    try!(self.rdr.discard(len));
+                    None
+                }
+                (4, Some(len)) => Some(Some(len)),
+                // Saturating is fine, since the reader would run out long
+                // before that many items are read.
+                (5, Some(len)) => Some(Some(len.saturating_mul(2))),
+                (6, _) => Some(Some(1)),
+                (_, None) => Some(None),
+                _ => None,
+            };
+            match pushed {
+                Some(Some(0)) => {}
+                Some(remaining) => {
+                    if open.len() >= self.limits.max_depth {
+                        let err = ReadError::TooDeep {
+                            max: self.limits.max_depth,
+                        };
+                        return Err(self.errat(err));
+                    }
+                    open.push(remaining);
+                    continue;
+                }
+                None => {}
+            }
+            // A data item was completed, which may complete its parents.
+            loop {
+                let finished = match open.last_mut() {
+                    None => return Ok(true),
+                    Some(&mut None) => false,
+                    Some(&mut Some(ref mut n)) => { *n -= 1; *n == 0 }
+                };
+                if !finished {
+                    break;
+                }
+                open.pop();
+            }
+        }
+    }
+
+    /// Reads the rest of the head that starts with `first`, and returns
+    /// its major type and argument. The argument is `None` for an
+    /// indefinite length or a break.
+    fn skip_head(&mut self, first: u8) -> CborResult<(u8, Option<u64>)> {
+        let (major, add) = (first >> 5, first & 0b000_11111);
+        let arg = match add {
+            0...23 => add as u64,
+            24 => try!(self.rdr.read_u8()) as u64,
+            25 => try!(self.rdr.read_u16()) as u64,
+            26 => try!(self.rdr.read_u32()) as u64,
+            27 => try!(self.rdr.read_u64()),
+            31 if major >= 2 && major != 6 => return Ok((major, None)),
+            _ => {
+                return Err(self.errat(
+                    ReadError::Unassigned { major: major, add: add }));
+            }
+        };
+        Ok((major, Some(arg)))
+    }
+
+    /// Read the head of the next data item, which must be a byte string,
+    /// and return a reader over its contents.
+    ///
+    /// This makes it possible to process a very large byte string without
+    /// holding it in memory. The contents must be read to the end before
+    /// the next data item can be read from this decoder.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::io::Read;
+    /// use cbor::{Cbor, Decoder};
+    ///
+    /// // h'616263', 1
+    /// let bytes = vec![0x43, b'a', b'b', b'c', 0x01];
+    /// let mut dec = Decoder::from_bytes(&bytes[..]);
+    /// let mut contents = vec![];
+    /// dec.bytes_reader().unwrap().read_to_end(&mut contents).unwrap();
+    /// assert_eq!(contents, b"abc");
+    ///
+    /// let next = dec.items().next().unwrap().unwrap();
+    /// assert_eq!(next, Cbor::from_diagnostic("1").unwrap());
+    /// ```
+    pub fn bytes_reader(&mut self) -> CborResult<BytesReader<R>> {
+        let first = match try!(self.rdr.read_first()) {
+            Some(first) => first,
+            None => return Err(CborError::UnexpectedEOF),
+        };
+        if (first & 0b111_00000) >> 5 != 2 {
+            return Err(self.errat(ReadError::miss(Type::Bytes, first)));
+        }
+        let len = try!(self.read_uint(first));
+        let len = try!(len.to_u64().map_err(|err| self.errat(err)));
+        try!(self.rdr.reserve(len));
+        Ok(BytesReader { rdr: &mut self.rdr, left: len })
+    }
+
     fn read_data_item(&mut self, first: Option<u8>) -> CborResult<Cbor> {
         let first = match first {
             Some(first) => first,
             None => try!(self.rdr.read_u8()),
         };
+        if self.items_read >= self.limits.max_total_items {
+            let max = self.limits.max_total_items;
+            return Err(self.errat(ReadError::BudgetExceeded(
+                Budget::Items(max))));
+        }
+        self.items_read += 1;
         match (first & 0b111_00000) >> 5 {
-            0 => self.read_uint(first).map(Cbor::Unsigned),
-            1 => self.read_int(first).map(Cbor::Signed),
+            0 => {
+                let v = try!(self.read_uint(first));
+                Ok(self.widen(first, Cbor::Unsigned(v)))
+            }
+            1 => {
+                let v = try!(self.read_int(first));
+                Ok(self.widen(first, v))
+            }
             2 => self.read_bytes(first),
             3 => self.read_string(first),
             4 => self.read_array(first),
             5 => self.read_map(first),
             6 => self.read_tag(first),
-            7 => match first & 0b000_11111 {
+            // `first >> 5` can only produce 8 distinct values, so this is
+            // major type 7.
+            _ => match first & 0b000_11111 {
                 v @ 0...23 => self.read_simple_value(v),
-                24 => {
-                    let b = try!(self.rdr.read_u8());
-                    self.read_simple_value(b)
-                }
+                24 => match try!(self.rdr.read_u8()) {
+                    // Values below 32 must use the one byte encoding.
+                    v @ 0...31 => Err(self.errat(
+                        ReadError::Reserved { major: 7, add: v })),
+                    v => self.read_simple_value(v),
+                },
                 25...27 => self.read_float(first).map(Cbor::Float),
                 v @ 28...30 =>
                     Err(self.errat(
                         ReadError::Unassigned { major: 7, add: v })),
-                31 => Ok(Cbor::Break),
-                // Because max(byte & 0b000_11111) == 2^5 - 1 == 31
-                _ => unreachable!(),
+                // Breaks that end indefinite lengths are read along with
+                // their arrays and maps, so a break (31, the only value
+                // left) is never expected here.
+                _ => {
+                    let offset = self.rdr.last_offset;
+                    Err(self.errat(
+                        ReadError::UnexpectedBreak { offset: offset }))
+                }
             },
-            // This is truly unreachable because `byte & 0b111_00000 >> 5`
-            // can only produce 8 distinct values---each of which are handled
-            // above. ---AG
-            _ => unreachable!(),
         }
     }
 
     fn read_simple_value(&mut self, val: u8) -> CborResult<Cbor> {
+        if self.dag_cbor && (val < 20 || val > 22) {
+            let what = match val {
+                23 => "undefined".to_string(),
+                v => format!("simple value {}", v),
+            };
+            return Err(self.errat(ReadError::DagCbor(what)));
+        }
         Ok(match val {
-            v @ 0...19 =>
-                return Err(self.errat(
-                    ReadError::Unassigned { major: 7, add: v })),
             20 => Cbor::Bool(false),
             21 => Cbor::Bool(true),
             22 => Cbor::Null,
@@ -139,99 +905,422 @@ impl<R: io::Read> Decoder<R> {
             v @ 24...31 =>
                 return Err(self.errat(
                     ReadError::Reserved { major: 7, add: v })),
-            v /* 32...255 */ =>
-                return Err(self.errat(
-                    ReadError::Unassigned { major: 7, add: v })),
+            v /* 0...19, 32...255 */ => Cbor::Simple(CborSimple(v)),
         })
     }
 
     fn read_float(&mut self, first: u8) -> CborResult<CborFloat> {
-        Ok(match first & 0b000_11111 {
+        // The initial byte has just been read.
+        let at = self.rdr.bytes_read - 1;
+        let (v, canonical) = match first & 0b000_11111 {
             25 => {
-                // Rust doesn't have a `f16` type, so just read a u16 and
-                // cast it to a u32 and then a f32.
-                // I think this is wrong. ---AG
-                let n = try!(self.rdr.read_u16::<BigEndian>());
-                CborFloat::Float16(unsafe { transmute(n as u32) })
+                // Rust doesn't have a `f16` type, but every half precision
+                // float can be represented exactly by a `f32`.
+                let n = try!(self.rdr.read_u16());
+                (CborFloat::Float16(f16_to_f64(n) as f32), n == 0x7e00)
             }
-            26 => CborFloat::Float32(try!(self.rdr.read_f32::<BigEndian>())),
-            27 => CborFloat::Float64(try!(self.rdr.read_f64::<BigEndian>())),
+            26 => (CborFloat::Float32(try!(self.rdr.read_f32())), false),
+            27 => (CborFloat::Float64(try!(self.rdr.read_f64())), false),
             // Reaching this case is probably a bug. ---AG
             v => return Err(self.errat(
                 ReadError::InvalidAddValue { ty: Type::Float, val: v })),
-        })
+        };
+        let (nan, finite) = match v {
+            CborFloat::Float16(v) | CborFloat::Float32(v) => {
+                (v.is_nan(), v.is_finite())
+            }
+            CborFloat::Float64(v) => (v.is_nan(), v.is_finite()),
+        };
+        if self.strict_nans && nan && !canonical {
+            return Err(CborError::AtOffset {
+                kind: ReadError::NonCanonicalNan { offset: at },
+                offset: at,
+            });
+        }
+        if self.dag_cbor {
+            let err = match v {
+                CborFloat::Float64(_) if finite => return Ok(v),
+                CborFloat::Float64(_) => "NaN or infinity",
+                _ => "floats that aren't double precision",
+            };
+            return Err(CborError::AtOffset {
+                kind: ReadError::DagCbor(err.to_string()),
+                offset: at,
+            });
+        }
+        Ok(v)
     }
 
     fn read_tag(&mut self, first: u8) -> CborResult<Cbor> {
+        let at = self.rdr.bytes_read - 1;
         let tag = try!(self.read_uint(first));
         let tag = try!(tag.to_u64().map_err(|err| self.errat(err)));
+        if self.dag_cbor && tag != 42 {
+            return Err(CborError::AtOffset {
+                kind: ReadError::DagCbor(format!("tag {}", tag)),
+                offset: at,
+            });
+        }
+        if self.shared_values {
+            match tag {
+                28 => {
+                    let i = self.shared.len();
+                    self.shared.push(None);
+                    try!(self.enter());
+                    let data = try!(self.read_data_item(None));
+                    self.depth -= 1;
+                    self.shared[i] = Some(data.clone());
+                    return Ok(data);
+                }
+                29 => return self.read_shared_ref(at),
+                _ => {}
+            }
+        }
+        if self.stringrefs {
+            match tag {
+                256 => {
+                    try!(self.enter());
+                    self.strings.push(vec![]);
+                    let data = self.read_data_item(None);
+                    self.strings.pop();
+                    self.depth -= 1;
+                    return data;
+                }
+                25 if !self.strings.is_empty() => {
+                    return self.read_string_ref(at);
+                }
+                _ => {}
+            }
+        }
+        try!(self.enter());
         let data = try!(self.read_data_item(None));
+        self.depth -= 1;
         Ok(Cbor::Tag(CborTag { tag: tag, data: Box::new(data) }))
     }
 
+    /// Reads the index inside of tag 25, which starts at byte offset `at`,
+    /// and returns the string it refers to.
+    fn read_string_ref(&mut self, at: usize) -> CborResult<Cbor> {
+        let index = match try!(self.read_data_item(None)) {
+            Cbor::Unsigned(n) => n.to_u64().unwrap(),
+            v => return Err(CborError::AtOffset {
+                kind: ReadError::mismatch(Type::UInt, &v),
+                offset: at,
+            }),
+        };
+        let strings = self.strings.last().unwrap();
+        if index >= strings.len() as u64 {
+            return Err(CborError::AtOffset {
+                kind: ReadError::Other(format!(
+                    "String reference {} doesn't exist.", index)),
+                offset: at,
+            });
+        }
+        Ok(strings[index as usize].clone())
+    }
+
+    /// Reads the index inside of tag 29, which starts at byte offset `at`,
+    /// and returns the value it refers to.
+    fn read_shared_ref(&mut self, at: usize) -> CborResult<Cbor> {
+        let index = match try!(self.read_data_item(None)) {
+            Cbor::Unsigned(n) => n.to_u64().unwrap(),
+            v => return Err(CborError::AtOffset {
+                kind: ReadError::mismatch(Type::UInt, &v),
+                offset: at,
+            }),
+        };
+        let msg = match self.shared.get(index as usize) {
+            Some(&Some(ref v)) if index < self.shared.len() as u64 => {
+                return Ok(v.clone());
+            }
+            Some(&None) => format!(
+                "Shared value {} refers to a value that contains it.", index),
+            _ => format!("Shared value {} doesn't exist.", index),
+        };
+        Err(CborError::AtOffset { kind: ReadError::Other(msg), offset: at })
+    }
+
+    /// Gives a string the next index in the current stringref namespace,
+    /// if it is long enough to get one.
+    fn assign_string(&mut self, len: usize, v: &Cbor) {
+        if let Some(strings) = self.strings.last_mut() {
+            if len >= stringref_min_len(strings.len() as u64) {
+                strings.push(v.clone());
+            }
+        }
+    }
+
     fn read_map(&mut self, first: u8) -> CborResult<Cbor> {
-        let len = try!(self.read_len(first));
-        let mut map = HashMap::with_capacity(len);
-        let at = self.rdr.bytes_read; // for coherent error reporting
-        for _ in 0..len {
-            let key = match try!(self.read_data_item(None)) {
+        let len = try!(self.read_container_len(first, Type::Map));
+        try!(self.enter());
+        let mut map = CborMap::with_capacity(
+            cmp::min(len.unwrap_or(0), MAX_PREALLOC));
+        // The previous key, for checking the order of keys in DAG-CBOR.
+        let mut last_key: Option<String> = None;
+        let mut n = 0;
+        loop {
+            let key_at = self.rdr.bytes_read;
+            let first = match try!(self.next_item(len, n, Type::Map)) {
+                Some(first) => first,
+                None => break,
+            };
+            n += 1;
+            let key = match try!(self.read_data_item(Some(first))) {
                 Cbor::Unicode(s) => s,
-                v => return Err(CborError::AtOffset {
-                    kind: ReadError::mismatch(Type::Unicode, &v),
-                    offset: at,
-                }),
+                v => match self.field_name(&v) {
+                    Some(name) => name,
+                    None => return Err(CborError::AtOffset {
+                        kind: ReadError::mismatch(Type::Unicode, &v),
+                        offset: key_at,
+                    }),
+                },
             };
+            if self.dag_cbor {
+                let sorted = match last_key {
+                    Some(ref last) => {
+                        cmp_canonical_keys(last, &key) == Ordering::Less
+                    }
+                    None => true,
+                };
+                if !sorted {
+                    return Err(CborError::AtOffset {
+                        kind: ReadError::DagCbor(
+                            "map keys that aren't sorted".to_string()),
+                        offset: key_at,
+                    });
+                }
+                last_key = Some(key.clone());
+            }
             let val = try!(self.read_data_item(None));
+            if map.contains_key(&key) {
+                match self.limits.duplicate_keys {
+                    DuplicateKeyPolicy::Last => {}
+                    DuplicateKeyPolicy::First => continue,
+                    DuplicateKeyPolicy::Error => {
+                        return Err(CborError::AtOffset {
+                            kind: ReadError::DuplicateKey(key),
+                            offset: key_at,
+                        });
+                    }
+                }
+            }
             map.insert(key, val);
         }
+        self.depth -= 1;
         Ok(Cbor::Map(map))
     }
 
+    /// Returns the name of the struct field that is written with the
+    /// integer map key `key`, if any.
+    fn field_name(&self, key: &Cbor) -> Option<String> {
+        let key = match *key {
+            Cbor::Unsigned(n) => {
+                let n = n.to_u64().unwrap();
+                if n > ::std::i64::MAX as u64 {
+                    return None;
+                }
+                n as i64
+            }
+            Cbor::Signed(n) => n.to_i64().unwrap(),
+            _ => return None,
+        };
+        self.field_keys.get(&key).cloned()
+    }
+
     fn read_array(&mut self, first: u8) -> CborResult<Cbor> {
-        let len = try!(self.read_len(first));
-        let mut array = Vec::with_capacity(len);
-        for _ in 0..len {
-            let v = try!(self.read_data_item(None));
+        let len = try!(self.read_container_len(first, Type::Array));
+        try!(self.enter());
+        let mut array =
+            Vec::with_capacity(cmp::min(len.unwrap_or(0), MAX_PREALLOC));
+        while let Some(first) =
+                try!(self.next_item(len, array.len(), Type::Array)) {
+            let v = try!(self.read_data_item(Some(first)));
             array.push(v);
         }
+        self.depth -= 1;
         Ok(Cbor::Array(array))
     }
 
+    /// Reads the length of an array or map, which is `None` for an
+    /// indefinite length.
+    fn read_container_len(&mut self, first: u8, ty: Type)
+                         -> CborResult<Option<usize>> {
+        if first & 0b000_11111 != 31 {
+            let max = self.limits.max_items;
+            return self.read_limited_len(first, ty, max).map(Some);
+        }
+        if self.dag_cbor {
+            return Err(self.errat(
+                ReadError::DagCbor("indefinite lengths".to_string())));
+        }
+        Ok(None)
+    }
+
+    /// Reads the initial byte of the next item of an array or map with
+    /// `len` items (or entries), of which `n` have been read. `None` is
+    /// returned after the last item, including the break that ends an
+    /// indefinite length.
+    fn next_item(&mut self, len: Option<usize>, n: usize, ty: Type)
+                -> CborResult<Option<u8>> {
+        match len {
+            Some(len) if n >= len => return Ok(None),
+            Some(len) => {
+                let eof = match try!(self.rdr.read_first()) {
+                    Some(0xff) => false,
+                    Some(first) => return Ok(Some(first)),
+                    None => true,
+                };
+                let offset = if eof {
+                    self.rdr.bytes_read
+                } else {
+                    self.rdr.last_offset
+                };
+                return Err(CborError::AtOffset {
+                    kind: ReadError::MissingItems {
+                        ty: ty,
+                        expected: len as u64,
+                        got: n as u64,
+                        eof: eof,
+                    },
+                    offset: offset,
+                });
+            }
+            None => {}
+        }
+        let first = try!(self.rdr.read_u8());
+        if first == 0xff {
+            return Ok(None);
+        }
+        // The length isn't known up front, so the limit is checked as the
+        // items are read.
+        let max = self.limits.max_items;
+        if n as u64 >= max {
+            return Err(self.errat(
+                ReadError::TooLong { ty: ty, len: n as u64 + 1, max: max }));
+        }
+        Ok(Some(first))
+    }
+
+    /// Goes one level deeper into nested data items, if that's allowed.
+    fn enter(&mut self) -> CborResult<()> {
+        if self.depth >= self.limits.max_depth {
+            let max = self.limits.max_depth;
+            return Err(self.errat(ReadError::TooDeep { max: max }));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
     fn read_string(&mut self, first: u8) -> CborResult<Cbor> {
-        let len = try!(self.read_len(first));
-        let mut buf = vec_from_elem(len, 0u8);
-        try!(self.rdr.read_full(&mut buf));
-        String::from_utf8(buf)
-               .map(Cbor::Unicode)
-               .map_err(|err| self.errstr(err.utf8_error().to_string()))
+        let max = self.limits.max_size;
+        let len = try!(self.read_limited_len(first, Type::Unicode, max));
+        try!(self.rdr.reserve(len as u64));
+        let buf = try!(self.rdr.read_vec(len));
+        let v = try!(String::from_utf8(buf)
+                           .map(Cbor::Unicode)
+                           .map_err(|err| self.errat(
+                               ReadError::InvalidUtf8(err.utf8_error()))));
+        self.assign_string(len, &v);
+        Ok(v)
     }
 
     fn read_bytes(&mut self, first: u8) -> CborResult<Cbor> {
-        let len = try!(self.read_len(first));
-        let mut buf = vec_from_elem(len, 0u8);
-        try!(self.rdr.read_full(&mut buf));
-        Ok(Cbor::Bytes(CborBytes(buf)))
+        let max = self.limits.max_size;
+        let len = try!(self.read_limited_len(first, Type::Bytes, max));
+        try!(self.rdr.reserve(len as u64));
+        let buf = try!(self.rdr.read_vec(len));
+        let v = Cbor::Bytes(CborBytes(buf));
+        self.assign_string(len, &v);
+        Ok(v)
+    }
+
+    fn read_limited_len(&mut self, first: u8, ty: Type, max: u64)
+                       -> CborResult<usize> {
+        let n = try!(self.read_uint(first));
+        let len = try!(n.to_u64().map_err(|err| self.errat(err)));
+        if len > max {
+            return Err(self.errat(
+                ReadError::TooLong { ty: ty, len: len, max: max }));
+        }
+        n.to_usize().map_err(|err| self.errat(err))
+    }
+
+    /// Turns an integer into `Cbor::Wide` if it was encoded with more bytes
+    /// than needed and `faithful_integers` is enabled.
+    fn widen(&self, first: u8, v: Cbor) -> Cbor {
+        let width = match first & 0b000_11111 {
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return v,
+        };
+        let (negative, arg) = match v {
+            Cbor::Unsigned(n) => (false, n.to_u64().unwrap()),
+            Cbor::Signed(n) => (true, (-1 - n.to_i64().unwrap()) as u64),
+            v => return v,
+        };
+        if !self.faithful_integers || CborWide::width_of(arg) == width {
+            return v;
+        }
+        Cbor::Wide(CborWide { negative: negative, arg: arg, width: width })
     }
 
-    fn read_len(&mut self, first: u8) -> CborResult<usize> {
-        self.read_uint(first)
-            .and_then(|v| v.to_usize().map_err(|err| self.errat(err)))
+    /// Returns an error if the argument `arg` of the head that starts at
+    /// byte offset `at` wasn't encoded minimally and `strict_minimal` is
+    /// enabled.
+    fn check_minimal(&self, first: u8, arg: u64, at: usize)
+                    -> CborResult<()> {
+        let width = match first & 0b000_11111 {
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Ok(()),
+        };
+        if self.strict_minimal && CborWide::width_of(arg) != width {
+            return Err(CborError::AtOffset {
+                kind: ReadError::NonMinimalEncoding { offset: at },
+                offset: at,
+            });
+        }
+        Ok(())
     }
 
     fn read_uint(&mut self, first: u8) -> CborResult<CborUnsigned> {
+        // The initial byte has just been read.
+        let at = self.rdr.bytes_read - 1;
+        let v = try!(self.read_uint_arg(first));
+        try!(self.check_minimal(first, v.to_u64().unwrap(), at));
+        Ok(v)
+    }
+
+    fn read_uint_arg(&mut self, first: u8) -> CborResult<CborUnsigned> {
         Ok(match first & 0b000_11111 {
             n @ 0...23 => CborUnsigned::UInt8(n),
             24 => CborUnsigned::UInt8(try!(self.rdr.read_u8())),
-            25 => CborUnsigned::UInt16(try!(self.rdr.read_u16::<BigEndian>())),
-            26 => CborUnsigned::UInt32(try!(self.rdr.read_u32::<BigEndian>())),
-            27 => CborUnsigned::UInt64(try!(self.rdr.read_u64::<BigEndian>())),
+            25 => CborUnsigned::UInt16(try!(self.rdr.read_u16())),
+            26 => CborUnsigned::UInt32(try!(self.rdr.read_u32())),
+            27 => CborUnsigned::UInt64(try!(self.rdr.read_u64())),
             v => return Err(self.errat(
                 ReadError::InvalidAddValue { ty: Type::UInt, val: v })),
         })
     }
 
-    fn read_int(&mut self, first: u8) -> CborResult<CborSigned> {
-        Ok(match first & 0b000_11111 {
+    fn read_int(&mut self, first: u8) -> CborResult<Cbor> {
+        let at = self.rdr.bytes_read - 1;
+        let v = try!(self.read_int_arg(first));
+        let arg = match v {
+            Cbor::Signed(n) => (-1 - n.to_i64().unwrap()) as u64,
+            // Always 8 bytes wide, so it can't be non-minimal.
+            _ => return Ok(v),
+        };
+        try!(self.check_minimal(first, arg, at));
+        Ok(v)
+    }
+
+    fn read_int_arg(&mut self, first: u8) -> CborResult<Cbor> {
+        Ok(Cbor::Signed(match first & 0b000_11111 {
             n @ 0...23 => CborSigned::Int8(-1 - (n as i8)),
             24 => {
                 let n = try!(self.rdr.read_u8());
@@ -242,7 +1331,7 @@ impl<R: io::Read> Decoder<R> {
                 }
             }
             25 => {
-                let n = try!(self.rdr.read_u16::<BigEndian>());
+                let n = try!(self.rdr.read_u16());
                 if n > ::std::i16::MAX as u16 {
                     CborSigned::Int32(-1 - (n as i32))
                 } else {
@@ -250,7 +1339,7 @@ impl<R: io::Read> Decoder<R> {
                 }
             }
             26 => {
-                let n = try!(self.rdr.read_u32::<BigEndian>());
+                let n = try!(self.rdr.read_u32());
                 if n > ::std::i32::MAX as u32 {
                     CborSigned::Int64(-1 - (n as i64))
                 } else {
@@ -258,16 +1347,20 @@ impl<R: io::Read> Decoder<R> {
                 }
             }
             27 => {
-                let n = try!(self.rdr.read_u64::<BigEndian>());
+                let n = try!(self.rdr.read_u64());
                 if n > ::std::i64::MAX as u64 {
-                    return Err(self.errstr(format!(
-                        "Negative integer out of range: {:?}", n)));
+                    // Only `Cbor::Wide` can hold integers below `i64::MIN`.
+                    return Ok(Cbor::Wide(CborWide {
+                        negative: true,
+                        arg: n,
+                        width: 8,
+                    }));
                 }
                 CborSigned::Int64(-1 - (n as i64))
             }
             v => return Err(self.errat(
                 ReadError::InvalidAddValue { ty: Type::Int, val: v })),
-        })
+        }))
     }
 }
 
@@ -277,7 +1370,276 @@ impl Decoder<io::Cursor<Vec<u8>>> {
     /// The buffer is usually given as either a `Vec<u8>` or a `&[u8]`.
     pub fn from_bytes<T>(bytes: T) -> Decoder<io::Cursor<Vec<u8>>>
             where T: Into<Vec<u8>> {
-        Decoder { rdr: CborReader::new(io::Cursor::new(bytes.into())) }
+        let mut dec = Decoder::from_reader(io::Cursor::new(bytes.into()));
+        dec.rdr.read_ahead = false;
+        dec
+    }
+
+    /// Replaces the input of this decoder with a copy of `bytes`, so that
+    /// one decoder can be used for many messages.
+    ///
+    /// The settings of the decoder are kept, and everything about the old
+    /// input is forgotten: byte offsets start over at `0` and the budgets
+    /// of `max_total_bytes` and `max_total_items` are full again. The
+    /// buffer that holds the input is reused, so it only grows when a
+    /// message is bigger than all of the ones before it, instead of being
+    /// allocated for every message.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Decoder;
+    ///
+    /// let mut dec = Decoder::from_bytes(vec![]).max_depth(4);
+    /// for msg in &[&[0x82, 0x01, 0x02][..], &[0x81, 0x03][..]] {
+    ///     dec.reset(msg);
+    ///     let v: Vec<u8> = dec.decode().next().unwrap().unwrap();
+    ///     assert_eq!(v, &msg[1..]);
+    ///     assert_eq!(dec.bytes_consumed(), msg.len());
+    /// }
+    /// ```
+    pub fn reset(&mut self, bytes: &[u8]) {
+        {
+            let buf = self.rdr.rdr.get_mut();
+            buf.clear();
+            buf.extend(bytes.iter().cloned());
+        }
+        self.rdr.rdr.set_position(0);
+        self.rdr.clear();
+        self.rdr.last_offset = 0;
+        self.rdr.bytes_read = 0;
+        self.strings.clear();
+        self.shared.clear();
+        self.depth = 0;
+        self.items_read = 0;
+    }
+}
+
+impl<'a> Decoder<SliceRead<'a>> {
+    /// Create a new CBOR decoder that reads directly from a slice.
+    ///
+    /// Unlike `from_bytes`, the input isn't copied into a buffer owned by
+    /// the decoder first: bytes are read out of the slice by index, and
+    /// strings are copied once, straight into the values decoded. Prefer
+    /// this when the data is already in memory and outlives the decoder,
+    /// e.g., the bytes of a memory mapped file.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Decoder;
+    ///
+    /// let bytes = [0x82, 0x61, b'a', 0x01];
+    /// let mut dec = Decoder::from_slice(&bytes);
+    /// let v: (String, u8) = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(v, ("a".to_string(), 1));
+    /// ```
+    pub fn from_slice(bytes: &'a [u8]) -> Decoder<SliceRead<'a>> {
+        let mut dec = Decoder::from_reader(SliceRead { slice: bytes, pos: 0 });
+        dec.rdr.read_ahead = false;
+        dec
+    }
+}
+
+impl<'a> Decoder<Chunks<'a>> {
+    /// Create a new CBOR decoder that reads from a sequence of buffers, as
+    /// if they had been concatenated.
+    ///
+    /// This is useful when data arrives in several pieces (e.g., network
+    /// buffers), since they don't have to be copied into one buffer first.
+    /// Data items may span any number of chunks.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Decoder;
+    ///
+    /// // ["ab", 1], split in the middle of the string
+    /// let (first, second) = ([0x82, 0x62, b'a'], [b'b', 0x01]);
+    /// let chunks = [&first[..], &second[..]];
+    /// let mut dec = Decoder::from_chunks(&chunks);
+    /// let v: (String, u8) = dec.decode().next().unwrap().unwrap();
+    /// assert_eq!(v, ("ab".to_string(), 1));
+    /// ```
+    pub fn from_chunks(chunks: &'a [&'a [u8]]) -> Decoder<Chunks<'a>> {
+        let mut dec = Decoder::from_reader(Chunks { chunks: chunks, pos: 0 });
+        dec.rdr.read_ahead = false;
+        dec
+    }
+}
+
+/// A reader over a sequence of buffers.
+///
+/// This is created by `Decoder::from_chunks`.
+pub struct Chunks<'a> {
+    chunks: &'a [&'a [u8]],
+    // the position in the first chunk
+    pos: usize,
+}
+
+impl<'a> io::Read for Chunks<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Skip past chunks that have been read, including empty ones.
+        while !self.chunks.is_empty() && self.pos == self.chunks[0].len() {
+            self.chunks = &self.chunks[1..];
+            self.pos = 0;
+        }
+        if self.chunks.is_empty() {
+            return Ok(0);
+        }
+        let n = try!((&self.chunks[0][self.pos..]).read(buf));
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Reads from a slice by index.
+///
+/// This is created by `Decoder::from_slice`.
+pub struct SliceRead<'a> {
+    slice: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceRead<'a> {
+    /// Returns the part of the slice that hasn't been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.slice[self.pos..]
+    }
+}
+
+impl<'a> io::Read for SliceRead<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if buf.len() == 1 && self.pos < self.slice.len() {
+            // Most reads are of one byte, e.g., the head of a data item.
+            buf[0] = self.slice[self.pos];
+            1
+        } else {
+            try!((&self.slice[self.pos..]).read(buf))
+        };
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Decodes `bytes`, which must hold exactly one data item, into a Rust
+/// value.
+///
+/// Unlike `Decoder::decode`, which reads a sequence of data items, this
+/// returns `ReadError::TrailingBytes` if there is anything after the first
+/// data item. An empty input is an `UnexpectedEOF` error.
+///
+/// # Example
+///
+/// ```rust
+/// let v: Vec<u8> = cbor::from_slice_exact(&[0x82, 0x01, 0x02]).unwrap();
+/// assert_eq!(v, vec![1, 2]);
+/// assert!(cbor::from_slice_exact::<Vec<u8>>(&[0x80, 0x00]).is_err());
+/// ```
+pub fn from_slice_exact<D: Decodable>(bytes: &[u8]) -> CborResult<D> {
+    let mut dec = Decoder::from_slice(bytes);
+    let v = match dec.items().next() {
+        Some(v) => try!(v),
+        None => {
+            return Err(CborError::AtOffset {
+                kind: ReadError::UnexpectedEOF { needed: 1, offset: 0 },
+                offset: 0,
+            });
+        }
+    };
+    let end = dec.bytes_consumed();
+    if end < bytes.len() {
+        return Err(CborError::AtOffset {
+            kind: ReadError::TrailingBytes { remaining: bytes.len() - end },
+            offset: end,
+        });
+    }
+    CborDecoder::decode_in(v, bytes, 0, Options::default())
+}
+
+/// Decodes the value of one entry of the map in `bytes`, without decoding
+/// the rest of it.
+///
+/// `bytes` must hold exactly one data item, which must be a map. The other
+/// entries are skipped over without being decoded, which is much faster
+/// than decoding all of the map when only one field is needed (e.g., to
+/// dispatch a message on its `method`). Only Unicode string keys can match
+/// `name`. If `name` occurs more than once, the last value is used, like
+/// `Decoder` does by default. `None` is returned if `name` isn't a key.
+///
+/// Byte offsets in errors are relative to the start of `bytes`.
+///
+/// # Example
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use cbor::Encoder;
+///
+/// let mut msg = HashMap::new();
+/// msg.insert("method", "ping");
+/// msg.insert("payload", "...");
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[msg]).unwrap();
+/// let method: Option<String> =
+///     cbor::get_field(enc.as_bytes(), "method").unwrap();
+/// assert_eq!(method, Some("ping".to_string()));
+/// let id: Option<u64> = cbor::get_field(enc.as_bytes(), "id").unwrap();
+/// assert_eq!(id, None);
+/// ```
+pub fn get_field<D: Decodable>(bytes: &[u8], name: &str)
+                              -> CborResult<Option<D>> {
+    let mut dec = SliceDecoder::new(bytes);
+    let len = try!(dec.read_map());
+    let mut found = None;
+    let mut n = 0;
+    loop {
+        let done = match len {
+            Some(len) => n == len,
+            None => try!(dec.read_break()),
+        };
+        if done {
+            break;
+        }
+        n += 1;
+        let matched = dec.clone().read_str().map(|k| k == name)
+                                            .unwrap_or(false);
+        try!(dec.skip());
+        let start = dec.offset();
+        try!(dec.skip());
+        if matched {
+            found = Some((start, dec.offset()));
+        }
+    }
+    if !dec.is_empty() {
+        return Err(CborError::AtOffset {
+            kind: ReadError::TrailingBytes {
+                remaining: bytes.len() - dec.offset(),
+            },
+            offset: dec.offset(),
+        });
+    }
+    let (start, end) = match found {
+        Some(span) => span,
+        None => return Ok(None),
+    };
+    match from_slice_exact(&bytes[start..end]) {
+        Ok(v) => Ok(Some(v)),
+        Err(CborError::AtOffset { kind, offset }) => {
+            Err(CborError::AtOffset { kind: kind, offset: start + offset })
+        }
+        Err(CborError::AtPath { kind, offset, path }) => {
+            let path = if path.is_empty() || path.starts_with('[') {
+                format!("{}{}", name, path)
+            } else {
+                format!("{}.{}", name, path)
+            };
+            Err(CborError::AtPath {
+                kind: kind,
+                offset: start + offset,
+                path: path,
+            })
+        }
+        Err(err) => Err(err),
     }
 }
 
@@ -285,9 +1647,79 @@ impl<R: io::Read> Decoder<R> {
     fn errat(&self, err: ReadError) -> CborError {
         CborError::AtOffset { kind: err, offset: self.rdr.last_offset }
     }
+}
+
+/// A reader over the contents of a byte string.
+///
+/// This is created by `Decoder::bytes_reader`.
+pub struct BytesReader<'a, R: 'a> {
+    rdr: &'a mut CborReader<R>,
+    left: u64,
+}
+
+impl<'a, R: io::Read> BytesReader<'a, R> {
+    /// Returns the number of bytes of the byte string that haven't been
+    /// read yet.
+    pub fn remaining(&self) -> u64 {
+        self.left
+    }
+}
 
-    fn errstr(&self, s: String) -> CborError {
-        self.errat(ReadError::Other(s))
+impl<'a, R: io::Read> io::Read for BytesReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 {
+            return Ok(0);
+        }
+        let n = ::std::cmp::min(buf.len() as u64, self.left) as usize;
+        let n = try!(self.rdr.read(&mut buf[..n]));
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      "unexpected EOF in byte string"));
+        }
+        self.left -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Decodes a value with state that the value itself doesn't have.
+///
+/// This is to `Decodable` what a closure is to a function: `decode` is
+/// given `self`, so a seed can decode into memory that is already
+/// allocated, or look up what it decodes in a table. Seeds are used with
+/// `Decoder::decode_seed`, or with any other decoder (e.g., a
+/// `DirectDecoder`) by calling `decode` directly.
+///
+/// `PhantomData<T>` is the seed that decodes a new `T`, and `&mut Vec<T>`
+/// is the seed that decodes a sequence into an existing vector, replacing
+/// its contents but keeping its capacity.
+pub trait DecodeSeed {
+    /// The type of the value produced by decoding.
+    type Value;
+
+    /// Decodes a value from `d`.
+    fn decode<D: RustcDecoder>(self, d: &mut D)
+                              -> Result<Self::Value, D::Error>;
+}
+
+impl<T: Decodable> DecodeSeed for PhantomData<T> {
+    type Value = T;
+
+    fn decode<D: RustcDecoder>(self, d: &mut D) -> Result<T, D::Error> {
+        Decodable::decode(d)
+    }
+}
+
+impl<'a, T: Decodable> DecodeSeed for &'a mut Vec<T> {
+    type Value = ();
+
+    fn decode<D: RustcDecoder>(self, d: &mut D) -> Result<(), D::Error> {
+        self.clear();
+        d.read_seq(|d, len| {
+            for i in 0..len {
+                self.push(try!(d.read_seq_elt(i, Decodable::decode)));
+            }
+            Ok(())
+        })
     }
 }
 
@@ -304,8 +1736,10 @@ impl<'a, R: io::Read, D: Decodable> Iterator for DecodedItems<'a, R, D> {
     type Item = CborResult<D>;
 
     fn next(&mut self) -> Option<CborResult<D>> {
+        let offset = self.it.dec.rdr.bytes_read;
+        let opts = self.it.dec.opts;
         self.it.next().map(|result| {
-            result.and_then(|v| Decodable::decode(&mut CborDecoder::new(v)))
+            result.and_then(|v| CborDecoder::decode_at(v, offset, opts))
         })
     }
 }
@@ -322,26 +1756,67 @@ impl<'a, R: io::Read> Iterator for Items<'a, R> {
     type Item = CborResult<Cbor>;
 
     fn next(&mut self) -> Option<CborResult<Cbor>> {
-        match self.dec.read_data_item(None) {
-            Err(ref err) if err.is_eof() => None,
+        // EOF is only OK at the start of a top-level data item. Anywhere
+        // else means the data was truncated.
+        self.dec.depth = 0;
+        self.dec.shared.clear();
+        match self.dec.rdr.read_first() {
+            Ok(None) => None,
+            Ok(Some(first)) => Some(self.dec.read_data_item(Some(first))),
             Err(err) => Some(Err(err)),
-            Ok(v) => Some(Ok(v)),
         }
     }
 }
 
 /// A very light layer over a basic reader that keeps track of offset
 /// information at the byte level.
+///
+/// Bytes are read from `rdr` in blocks of up to `BUF_SIZE` into `buf`, so
+/// that the head of a data item can be taken from there with a bounds
+/// check instead of a call to the underlying reader.
 struct CborReader<R> {
     rdr: R,
+    // the bytes read from `rdr` that haven't been used yet are `buf[pos..]`
+    buf: Vec<u8>,
+    pos: usize,
     // used for error reporting
     last_offset: usize,
     bytes_read: usize,
+    // the total number of bytes that may be read
+    max_bytes: u64,
+    // False if `rdr` is already in memory, in which case copying its bytes
+    // into `buf` would only slow reads down.
+    read_ahead: bool,
 }
 
+/// The most bytes that `CborReader` reads ahead of what has been used.
+const BUF_SIZE: usize = 8 * 1024;
+
 impl<R: io::Read> io::Read for CborReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let n = try!(self.rdr.read(buf));
+        let n = if self.pos < self.buf.len() {
+            let n = try!((&self.buf[self.pos..]).read(buf));
+            self.pos += n;
+            n
+        } else if buf.len() >= BUF_SIZE || !self.read_ahead {
+            // Nothing is buffered, so big reads (and all reads of input
+            // that is already in memory) go straight to `rdr` instead of
+            // being copied twice.
+            try!(self.rdr.read(buf))
+        } else {
+            // Start small, since many inputs are only a few bytes long.
+            let size = cmp::min(BUF_SIZE, cmp::max(64, 2 * self.buf.len()));
+            self.buf.resize(size, 0);
+            let n = match self.rdr.read(&mut self.buf) {
+                Ok(n) => n,
+                Err(err) => { self.buf.clear(); return Err(err); }
+            };
+            self.buf.truncate(n);
+            self.pos = 0;
+            let n = try!((&self.buf[..]).read(buf));
+            self.pos = n;
+            n
+        };
         self.last_offset = self.bytes_read;
         self.bytes_read += n;
         Ok(n)
@@ -352,26 +1827,185 @@ impl<R: io::Read> CborReader<R> {
     fn new(rdr: R) -> CborReader<R> {
         CborReader {
             rdr: rdr,
+            buf: vec![],
+            pos: 0,
             last_offset: 0,
             bytes_read: 0,
+            max_bytes: ::std::u64::MAX,
+            read_ahead: true,
+        }
+    }
+
+    /// Forgets about any bytes that were read ahead.
+    fn clear(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
+    }
+
+    /// Takes the next `n` bytes from the buffer if they are all there and
+    /// within budget, or returns `None` if they have to be read.
+    #[inline]
+    fn buffered(&mut self, n: usize) -> Option<&[u8]> {
+        let end = self.pos + n;
+        let budget = self.max_bytes.saturating_sub(self.bytes_read as u64);
+        if end > self.buf.len() || n as u64 > budget {
+            return None;
+        }
+        let start = self.pos;
+        self.pos = end;
+        self.last_offset = self.bytes_read;
+        self.bytes_read += n;
+        Some(&self.buf[start..end])
+    }
+
+    /// Returns an error if reading `n` more bytes would go over budget.
+    fn reserve(&self, n: u64) -> CborResult<()> {
+        let left = self.max_bytes.saturating_sub(self.bytes_read as u64);
+        if n > left {
+            return Err(self.over_budget(self.bytes_read));
+        }
+        Ok(())
+    }
+
+    fn over_budget(&self, offset: usize) -> CborError {
+        CborError::AtOffset {
+            kind: ReadError::BudgetExceeded(Budget::Bytes(self.max_bytes)),
+            offset: offset,
         }
     }
 
     fn read_full(&mut self, buf: &mut [u8]) -> CborResult<()> {
+        try!(self.reserve(buf.len() as u64));
         let mut nread = 0usize;
         while nread < buf.len() {
             match try!(self.read(&mut buf[nread..])) {
-                0 => return Err(CborError::UnexpectedEOF),
+                0 => return Err(CborError::AtOffset {
+                    kind: ReadError::UnexpectedEOF {
+                        needed: buf.len() - nread,
+                        offset: self.bytes_read,
+                    },
+                    offset: self.bytes_read,
+                }),
                 n => nread += n,
             }
         }
         Ok(())
     }
-}
 
-fn vec_from_elem<T: Copy>(len: usize, v: T) -> Vec<T> {
-    let mut xs = Vec::with_capacity(len);
-    unsafe { xs.set_len(len); }
-    for x in &mut xs { *x = v; }
-    xs
+    /// Reads exactly `len` bytes into a new vector.
+    ///
+    /// `len` comes from the data and may be far more than what is actually
+    /// there, so at most `MAX_PREALLOC` bytes are allocated up front, and
+    /// the vector grows as the rest is read. This way, most strings are
+    /// read with one allocation and one copy.
+    fn read_vec(&mut self, len: usize) -> CborResult<Vec<u8>> {
+        try!(self.reserve(len as u64));
+        let mut buf = vec![0; cmp::min(len, MAX_PREALLOC)];
+        try!(self.read_full(&mut buf));
+        if buf.len() < len {
+            let rest = (len - buf.len()) as u64;
+            try!(io::Read::by_ref(self).take(rest).read_to_end(&mut buf));
+        }
+        if buf.len() < len {
+            return Err(CborError::AtOffset {
+                kind: ReadError::UnexpectedEOF {
+                    needed: len - buf.len(),
+                    offset: self.bytes_read,
+                },
+                offset: self.bytes_read,
+            });
+        }
+        Ok(buf)
+    }
+
+    /// Reads and throws away exactly `len` bytes.
+    fn discard(&mut self, len: u64) -> CborResult<()> {
+        try!(self.reserve(len));
+        let n = try!(io::copy(&mut io::Read::by_ref(self).take(len),
+                              &mut io::sink()));
+        if n < len {
+            return Err(CborError::AtOffset {
+                kind: ReadError::UnexpectedEOF {
+                    needed: (len - n) as usize,
+                    offset: self.bytes_read,
+                },
+                offset: self.bytes_read,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the first byte of a top-level data item, or `None` if the
+    /// underlying reader is exhausted.
+    #[inline]
+    fn read_first(&mut self) -> CborResult<Option<u8>> {
+        if let Some(b) = self.buffered(1) {
+            return Ok(Some(b[0]));
+        }
+        let mut buf = [0; 1];
+        match try!(self.read(&mut buf)) {
+            0 => Ok(None),
+            // Using up the budget exactly at the end of the data is fine,
+            // so this is only checked once there's more.
+            _ if self.bytes_read as u64 > self.max_bytes => {
+                Err(self.over_budget(self.last_offset))
+            }
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    #[inline]
+    fn read_u8(&mut self) -> CborResult<u8> {
+        if let Some(b) = self.buffered(1) {
+            return Ok(b[0]);
+        }
+        let mut buf = [0; 1];
+        try!(self.read_full(&mut buf));
+        Ok(buf[0])
+    }
+
+    fn read_u16(&mut self) -> CborResult<u16> {
+        if let Some(b) = self.buffered(2) {
+            return Ok(<BigEndian as ByteOrder>::read_u16(b));
+        }
+        let mut buf = [0; 2];
+        try!(self.read_full(&mut buf));
+        Ok(<BigEndian as ByteOrder>::read_u16(&buf))
+    }
+
+    fn read_u32(&mut self) -> CborResult<u32> {
+        if let Some(b) = self.buffered(4) {
+            return Ok(<BigEndian as ByteOrder>::read_u32(b));
+        }
+        let mut buf = [0; 4];
+        try!(self.read_full(&mut buf));
+        Ok(<BigEndian as ByteOrder>::read_u32(&buf))
+    }
+
+    fn read_u64(&mut self) -> CborResult<u64> {
+        if let Some(b) = self.buffered(8) {
+            return Ok(<BigEndian as ByteOrder>::read_u64(b));
+        }
+        let mut buf = [0; 8];
+        try!(self.read_full(&mut buf));
+        Ok(<BigEndian as ByteOrder>::read_u64(&buf))
+    }
+
+    fn read_f32(&mut self) -> CborResult<f32> {
+        if let Some(b) = self.buffered(4) {
+            return Ok(<BigEndian as ByteOrder>::read_f32(b));
+        }
+        let mut buf = [0; 4];
+        try!(self.read_full(&mut buf));
+        Ok(<BigEndian as ByteOrder>::read_f32(&buf))
+    }
+
+    fn read_f64(&mut self) -> CborResult<f64> {
+        if let Some(b) = self.buffered(8) {
+            return Ok(<BigEndian as ByteOrder>::read_f64(b));
+        }
+        let mut buf = [0; 8];
+        try!(self.read_full(&mut buf));
+        Ok(<BigEndian as ByteOrder>::read_f64(&buf))
+    }
 }
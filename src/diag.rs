@@ -0,0 +1,750 @@
+use std::fmt;
+use std::str::{self, FromStr};
+
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::{FromHex, ToHex};
+use rustc_serialize::json::Json;
+
+use {
+    Cbor, CborBytes, CborFloat, CborSimple, CborTag,
+    CborUnsigned, CborWide, CborError, CborMap, CborResult, ReadError,
+    DEFAULT_MAX_DEPTH, negative_string,
+};
+
+impl Cbor {
+    /// Returns this data item in diagnostic notation (RFC 8949, section 8).
+    ///
+    /// When `indent` is `0`, everything is written on one line. This is the
+    /// same as the `Display` impl. Otherwise, the contents of arrays and
+    /// maps are written one per line and indented by `indent` spaces per
+    /// level of nesting. (The alternate `Display` format, `{:#}`, uses an
+    /// indent of `2`.)
+    ///
+    /// Map entries are written in the order they were decoded in.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Decoder, Encoder};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[(1, "a", vec![-1.5])]).unwrap();
+    /// let item = Decoder::from_bytes(enc.as_bytes()).items().next().unwrap();
+    /// let item = item.unwrap();
+    ///
+    /// assert_eq!(item.to_string(), r#"[1, "a", [-1.5]]"#);
+    /// assert_eq!(item.to_diagnostic(2), "[
+    ///   1,
+    ///   \"a\",
+    ///   [
+    ///     -1.5
+    ///   ]
+    /// ]");
+    /// ```
+    pub fn to_diagnostic(&self, indent: usize) -> String {
+        let mut out = String::new();
+        write_diag(&mut out, self, indent, 0);
+        out
+    }
+
+    /// Parses a data item written in diagnostic notation.
+    ///
+    /// This accepts what `to_diagnostic` writes along with most of the
+    /// extended diagnostic notation described in RFC 8610, appendix G:
+    ///
+    /// * Integers can be written in hexadecimal, octal or binary, e.g.,
+    ///   `0x1f`.
+    /// * Byte strings can be written as `h'0102'`, `b64'AQI'` or `'text'`.
+    /// * Indefinite length items (e.g., `[_ 1, 2]` or `(_ h'01', h'02')`)
+    ///   are accepted, but they are read as definite length items.
+    /// * Encoding indicators (e.g., `1_2`) are accepted. Integers with an
+    ///   indicator that is wider than needed become `Cbor::Wide`, and floats
+    ///   use the indicated precision. Indicators on anything else are
+    ///   ignored.
+    /// * Comments are delimited by `/` or run from `#` to the end of the
+    ///   line.
+    ///
+    /// Map keys must be text strings and can't be repeated. If there is a
+    /// syntax error, a `ReadError::Syntax` is returned with the byte offset
+    /// of the error, and arrays, maps and tags nested more than 128 deep
+    /// are a `ReadError::TooDeep` error, as with `Decoder`. `FromStr` is
+    /// also implemented with this method.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Cbor, Encoder};
+    ///
+    /// let item: Cbor = r#"{"a": [1, -2.5, h'ff']}"#.parse().unwrap();
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[item]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[
+    ///     0xa1, 0x61, b'a', 0x83, 0x01, 0xfb, 0xc0, 0x04, 0, 0, 0, 0, 0, 0,
+    ///     0x41, 0xff,
+    /// ][..]);
+    /// ```
+    pub fn from_diagnostic(s: &str) -> CborResult<Cbor> {
+        let mut p = Parser { s: s.as_bytes(), pos: 0, depth: 0 };
+        let v = try!(p.item());
+        p.ws();
+        if p.pos < p.s.len() {
+            return Err(p.err("expected end of input"));
+        }
+        Ok(v)
+    }
+}
+
+impl FromStr for Cbor {
+    type Err = CborError;
+
+    fn from_str(s: &str) -> CborResult<Cbor> {
+        Cbor::from_diagnostic(s)
+    }
+}
+
+impl fmt::Display for Cbor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indent = if f.alternate() { 2 } else { 0 };
+        write!(f, "{}", self.to_diagnostic(indent))
+    }
+}
+
+fn write_diag(out: &mut String, v: &Cbor, indent: usize, depth: usize) {
+    match *v {
+        // Not a data item, but print something recognizable.
+        Cbor::Break => out.push_str("break"),
+        Cbor::Undefined => out.push_str("undefined"),
+        Cbor::Null => out.push_str("null"),
+        Cbor::Bool(v) => out.push_str(if v { "true" } else { "false" }),
+        Cbor::Unsigned(v) => out.push_str(&v.to_u64().unwrap().to_string()),
+        Cbor::Signed(v) => out.push_str(&v.to_i64().unwrap().to_string()),
+        Cbor::Wide(v) => {
+            if v.negative {
+                out.push_str(&negative_string(v.arg));
+            } else {
+                out.push_str(&v.arg.to_string());
+            }
+            // Integers below `i64::MIN` can have their minimal width.
+            if v.width != CborWide::width_of(v.arg) {
+                out.push_str(match v.width {
+                    1 => "_0",
+                    2 => "_1",
+                    4 => "_2",
+                    _ => "_3",
+                });
+            }
+        }
+        Cbor::Float(v) => out.push_str(&float(v)),
+        Cbor::Bytes(ref v) => {
+            out.push_str("h'");
+            out.push_str(&v.to_hex());
+            out.push('\'');
+        }
+        Cbor::Unicode(ref v) => {
+            out.push_str(&Json::String(v.clone()).to_string());
+        }
+        Cbor::Array(ref vs) => {
+            out.push('[');
+            for (i, v) in vs.iter().enumerate() {
+                separate(out, i, indent, depth + 1);
+                write_diag(out, v, indent, depth + 1);
+            }
+            close(out, vs.is_empty(), indent, depth);
+            out.push(']');
+        }
+        Cbor::Map(ref map) => {
+            out.push('{');
+            for (i, (k, v)) in map.iter().enumerate() {
+                separate(out, i, indent, depth + 1);
+                out.push_str(&Json::String(k.clone()).to_string());
+                out.push_str(": ");
+                write_diag(out, v, indent, depth + 1);
+            }
+            close(out, map.is_empty(), indent, depth);
+            out.push('}');
+        }
+        Cbor::Tag(ref tag) => {
+            out.push_str(&tag.tag.to_string());
+            out.push('(');
+            write_diag(out, &tag.data, indent, depth);
+            out.push(')');
+        }
+        Cbor::Simple(v) => out.push_str(&format!("simple({})", v.0)),
+    }
+}
+
+/// Writes what comes before the `i`th element of an array or map.
+fn separate(out: &mut String, i: usize, indent: usize, depth: usize) {
+    if i > 0 {
+        out.push(',');
+        if indent == 0 {
+            out.push(' ');
+        }
+    }
+    newline(out, indent, depth);
+}
+
+/// Writes what comes before the closing bracket of an array or map.
+fn close(out: &mut String, empty: bool, indent: usize, depth: usize) {
+    if !empty {
+        newline(out, indent, depth);
+    }
+}
+
+fn newline(out: &mut String, indent: usize, depth: usize) {
+    if indent > 0 {
+        out.push('\n');
+        for _ in 0..indent * depth {
+            out.push(' ');
+        }
+    }
+}
+
+/// Floats always have a decimal point or exponent so that they can't be
+/// confused with integers.
+fn float(v: CborFloat) -> String {
+    let s = match v {
+        CborFloat::Float16(v) | CborFloat::Float32(v) => {
+            if v.is_nan() {
+                return "NaN".to_string();
+            }
+            format!("{:?}", v)
+        }
+        CborFloat::Float64(v) => {
+            if v.is_nan() {
+                return "NaN".to_string();
+            }
+            format!("{:?}", v)
+        }
+    };
+    if s == "inf" {
+        "Infinity".to_string()
+    } else if s == "-inf" {
+        "-Infinity".to_string()
+    } else if s.contains('.') || s.contains('e') {
+        s
+    } else {
+        s + ".0"
+    }
+}
+
+/// Returns the argument of the negative integer whose magnitude has the
+/// (non-zero) `digits` given, i.e., the magnitude minus one.
+fn neg_arg(digits: &str, radix: u32) -> Option<u64> {
+    let (init, last) = digits.split_at(digits.len() - 1);
+    let last = match last.chars().next().and_then(|c| c.to_digit(radix)) {
+        Some(d) => d as u64,
+        None => return None,
+    };
+    let init = if init.is_empty() {
+        0
+    } else {
+        match u64::from_str_radix(init, radix) {
+            Ok(n) => n,
+            Err(_) => return None,
+        }
+    };
+    let n = match init.checked_mul(radix as u64) {
+        Some(n) => n,
+        None => return None,
+    };
+    if last > 0 {
+        n.checked_add(last - 1)
+    } else {
+        n.checked_sub(1)
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    // the number of arrays, maps and tags being parsed
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn err(&self, msg: &str) -> CborError {
+        self.err_at(msg, self.pos)
+    }
+
+    fn err_at(&self, msg: &str, offset: usize) -> CborError {
+        CborError::AtOffset {
+            kind: ReadError::Syntax(msg.to_string()),
+            offset: offset,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> CborResult<()> {
+        self.ws();
+        if self.eat(b) {
+            Ok(())
+        } else {
+            Err(self.err(&format!("expected '{}'", b as char)))
+        }
+    }
+
+    fn starts_with(&self, lit: &str) -> bool {
+        self.s[self.pos..].starts_with(lit.as_bytes())
+    }
+
+    /// Skips whitespace and comments.
+    fn ws(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') => {
+                    self.pos += 1;
+                }
+                Some(b'/') => {
+                    let rest = &self.s[self.pos + 1..];
+                    match rest.iter().position(|&b| b == b'/') {
+                        Some(i) => self.pos += i + 2,
+                        // Leave it for the caller to complain about.
+                        None => return,
+                    }
+                }
+                Some(b'#') => {
+                    let rest = &self.s[self.pos..];
+                    match rest.iter().position(|&b| b == b'\n') {
+                        Some(i) => self.pos += i + 1,
+                        None => self.pos = self.s.len(),
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skips an encoding indicator (e.g., `_1`), returning its digit. An
+    /// underscore by itself (for indefinite length items) returns `b'_'`.
+    fn indicator(&mut self) -> Option<u8> {
+        if !self.eat(b'_') {
+            return None;
+        }
+        match self.peek() {
+            Some(b @ b'0'...b'3') | Some(b @ b'i') => {
+                self.pos += 1;
+                Some(b)
+            }
+            _ => Some(b'_'),
+        }
+    }
+
+    fn item(&mut self) -> CborResult<Cbor> {
+        self.ws();
+        self.value()
+    }
+
+    /// Parses the rest of an array, map or tag that starts at `at` with
+    /// `f`, one level deeper.
+    fn nested<F>(&mut self, at: usize, f: F) -> CborResult<Cbor>
+        where F: FnOnce(&mut Parser<'a>) -> CborResult<Cbor> {
+        if self.depth >= DEFAULT_MAX_DEPTH {
+            return Err(CborError::AtOffset {
+                kind: ReadError::TooDeep { max: DEFAULT_MAX_DEPTH },
+                offset: at,
+            });
+        }
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+
+    fn value(&mut self) -> CborResult<Cbor> {
+        let at = self.pos;
+        match self.peek() {
+            None => Err(self.err("unexpected end of input")),
+            Some(b'[') => { self.pos += 1; self.nested(at, Parser::array) }
+            Some(b'{') => { self.pos += 1; self.nested(at, Parser::map) }
+            Some(b'(') => { self.pos += 1; self.chunks() }
+            Some(b'"') => self.quoted(b'"').map(Cbor::Unicode),
+            Some(b'\'') => {
+                let s = try!(self.quoted(b'\''));
+                Ok(Cbor::Bytes(CborBytes(s.into_bytes())))
+            }
+            Some(b'h') if self.starts_with("h'") => self.hex(),
+            Some(b'b') if self.starts_with("b64'") => self.base64(),
+            Some(b'-') | Some(b'0'...b'9') => self.number(),
+            Some(b'I') | Some(b'N') => self.number(),
+            _ => self.word(),
+        }
+    }
+
+    fn array(&mut self) -> CborResult<Cbor> {
+        self.indicator();
+        let mut items = vec![];
+        self.ws();
+        if self.eat(b']') {
+            return Ok(Cbor::Array(items));
+        }
+        loop {
+            items.push(try!(self.item()));
+            self.ws();
+            if !self.eat(b',') {
+                try!(self.expect(b']'));
+                return Ok(Cbor::Array(items));
+            }
+        }
+    }
+
+    fn map(&mut self) -> CborResult<Cbor> {
+        self.indicator();
+        let mut map = CborMap::new();
+        self.ws();
+        if self.eat(b'}') {
+            return Ok(Cbor::Map(map));
+        }
+        loop {
+            self.ws();
+            let at = self.pos;
+            let key = match try!(self.item()) {
+                Cbor::Unicode(key) => key,
+                _ => return Err(self.err_at("map keys must be text strings",
+                                            at)),
+            };
+            if map.contains_key(&key) {
+                return Err(self.err_at("duplicate map key", at));
+            }
+            try!(self.expect(b':'));
+            let val = try!(self.item());
+            map.insert(key, val);
+            self.ws();
+            if !self.eat(b',') {
+                try!(self.expect(b'}'));
+                return Ok(Cbor::Map(map));
+            }
+        }
+    }
+
+    /// Parses the chunks of an indefinite length string, e.g.,
+    /// `(_ "a", "b")`, as a single string.
+    fn chunks(&mut self) -> CborResult<Cbor> {
+        let start = self.pos - 1;
+        self.ws();
+        if !self.eat(b'_') {
+            return Err(self.err("expected '_'"));
+        }
+        let mut bytes = vec![];
+        let mut text = None;
+        loop {
+            self.ws();
+            let at = self.pos;
+            if self.peek() == Some(b'(') {
+                // Chunks can't be nested, which also keeps this from
+                // recursing without limit.
+                return Err(self.err_at(
+                    "chunks must all be byte strings or all be text strings",
+                    at));
+            }
+            match (try!(self.item()), text) {
+                (Cbor::Bytes(b), None) | (Cbor::Bytes(b), Some(false)) => {
+                    text = Some(false);
+                    bytes.extend(b.0.into_iter());
+                }
+                (Cbor::Unicode(s), None) | (Cbor::Unicode(s), Some(true)) => {
+                    text = Some(true);
+                    bytes.extend(s.into_bytes().into_iter());
+                }
+                _ => return Err(self.err_at(
+                    "chunks must all be byte strings or all be text strings",
+                    at)),
+            }
+            self.ws();
+            if !self.eat(b',') {
+                break;
+            }
+        }
+        try!(self.expect(b')'));
+        if text == Some(true) {
+            // Each chunk is valid UTF-8, so the whole thing is too.
+            match String::from_utf8(bytes) {
+                Ok(s) => Ok(Cbor::Unicode(s)),
+                Err(_) => Err(self.err_at("invalid UTF-8", start)),
+            }
+        } else {
+            Ok(Cbor::Bytes(CborBytes(bytes)))
+        }
+    }
+
+    /// Parses a string delimited by `quote` with JSON style escapes.
+    fn quoted(&mut self, quote: u8) -> CborResult<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut buf = vec![];
+        loop {
+            match self.peek() {
+                None => return Err(self.err_at("unterminated string", start)),
+                Some(b) if b == quote => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = try!(self.escape());
+                    let mut s = String::new();
+                    s.push(c);
+                    buf.extend(s.into_bytes().into_iter());
+                }
+                Some(b) => {
+                    self.pos += 1;
+                    buf.push(b);
+                }
+            }
+        }
+        // The input is a `&str` and it was only split at ASCII characters.
+        Ok(String::from_utf8(buf).unwrap())
+    }
+
+    fn escape(&mut self) -> CborResult<char> {
+        let at = self.pos - 1;
+        let b = match self.peek() {
+            None => return Err(self.err("unterminated string")),
+            Some(b) => b,
+        };
+        self.pos += 1;
+        Ok(match b {
+            b'"' | b'\'' | b'\\' | b'/' => b as char,
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let mut n = try!(self.hex4());
+                if n >= 0xd800 && n < 0xdc00 {
+                    // A surrogate pair.
+                    if !self.starts_with("\\u") {
+                        return Err(self.err_at("unpaired surrogate", at));
+                    }
+                    self.pos += 2;
+                    let low = try!(self.hex4());
+                    if low < 0xdc00 || low >= 0xe000 {
+                        return Err(self.err_at("unpaired surrogate", at));
+                    }
+                    n = 0x10000 + ((n - 0xd800) << 10) + (low - 0xdc00);
+                }
+                match ::std::char::from_u32(n) {
+                    Some(c) => c,
+                    None => return Err(self.err_at("invalid escape", at)),
+                }
+            }
+            _ => return Err(self.err_at("invalid escape", at)),
+        })
+    }
+
+    fn hex4(&mut self) -> CborResult<u32> {
+        let end = self.pos + 4;
+        if end > self.s.len() {
+            return Err(self.err("invalid escape"));
+        }
+        let digits = str::from_utf8(&self.s[self.pos..end]).unwrap_or("");
+        match u32::from_str_radix(digits, 16) {
+            Ok(n) => {
+                self.pos = end;
+                Ok(n)
+            }
+            Err(_) => Err(self.err("invalid escape")),
+        }
+    }
+
+    /// Returns the text between the current position and the next `'`,
+    /// without whitespace.
+    fn literal(&mut self) -> CborResult<String> {
+        let start = self.pos;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.err_at("unterminated string", start)),
+                Some(b'\'') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') => {}
+                Some(b) => s.push(b as char),
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn hex(&mut self) -> CborResult<Cbor> {
+        let start = self.pos;
+        self.pos += 2;
+        let s = try!(self.literal());
+        match s.from_hex() {
+            Ok(bytes) => Ok(Cbor::Bytes(CborBytes(bytes))),
+            Err(_) => Err(self.err_at("invalid hex byte string", start)),
+        }
+    }
+
+    fn base64(&mut self) -> CborResult<Cbor> {
+        let start = self.pos;
+        self.pos += 4;
+        let s = try!(self.literal());
+        match s.from_base64() {
+            Ok(bytes) => Ok(Cbor::Bytes(CborBytes(bytes))),
+            Err(_) => Err(self.err_at("invalid base64 byte string", start)),
+        }
+    }
+
+    fn number(&mut self) -> CborResult<Cbor> {
+        let start = self.pos;
+        let neg = self.eat(b'-');
+        if self.starts_with("Infinity") || self.starts_with("NaN") {
+            self.pos += if self.peek() == Some(b'I') { 8 } else { 3 };
+            let s = str::from_utf8(&self.s[start..self.pos]).unwrap();
+            let v = match s {
+                "Infinity" => ::std::f64::INFINITY,
+                "-Infinity" => ::std::f64::NEG_INFINITY,
+                "NaN" => ::std::f64::NAN,
+                _ => return Err(self.err_at("invalid number", start)),
+            };
+            return Ok(self.float(v));
+        }
+        let radix = if self.starts_with("0x") {
+            16
+        } else if self.starts_with("0o") {
+            8
+        } else if self.starts_with("0b") {
+            2
+        } else {
+            10
+        };
+        if radix != 10 {
+            self.pos += 2;
+        }
+        let digits_start = self.pos;
+        let mut is_float = false;
+        while let Some(b) = self.peek() {
+            match b {
+                b'0'...b'9' => {}
+                b'a'...b'f' | b'A'...b'F' if radix == 16 => {}
+                b'.' if radix == 10 => is_float = true,
+                b'e' | b'E' if radix == 10 => {
+                    is_float = true;
+                    if self.s.get(self.pos + 1) == Some(&b'-')
+                       || self.s.get(self.pos + 1) == Some(&b'+') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        if self.pos == digits_start {
+            return Err(self.err_at("invalid number", start));
+        }
+        if is_float {
+            let s = str::from_utf8(&self.s[start..self.pos]).unwrap();
+            return match s.parse() {
+                Ok(v) => Ok(self.float(v)),
+                Err(_) => Err(self.err_at("invalid number", start)),
+            };
+        }
+        let digits = str::from_utf8(&self.s[digits_start..self.pos]).unwrap();
+        let zero = digits.bytes().all(|b| b == b'0');
+        // For a negative integer, this is its argument rather than its
+        // magnitude, so that -18446744073709551616 fits.
+        let n = if neg && !zero {
+            neg_arg(digits, radix)
+        } else {
+            u64::from_str_radix(digits, radix).ok()
+        };
+        let n = match n {
+            Some(n) => n,
+            None => return Err(self.err_at("integer out of range", start)),
+        };
+        let width = match self.indicator() {
+            Some(b'0') => 1,
+            Some(b'1') => 2,
+            Some(b'2') => 4,
+            Some(b'3') => 8,
+            _ => 0,
+        };
+        if !neg && self.eat(b'(') {
+            return self.nested(start, |p| {
+                let data = try!(p.item());
+                try!(p.expect(b')'));
+                Ok(Cbor::Tag(CborTag { tag: n, data: Box::new(data) }))
+            });
+        }
+        if neg && zero {
+            return Ok(Cbor::Unsigned(CborUnsigned::UInt8(0)));
+        }
+        let arg = n;
+        if width > 0 && width != CborWide::width_of(arg) {
+            if width < CborWide::width_of(arg) {
+                return Err(self.err_at(
+                    "integer too large for its encoding indicator", start));
+            }
+            return Ok(Cbor::Wide(CborWide {
+                negative: neg, arg: arg, width: width,
+            }));
+        }
+        Ok(if neg {
+            CborWide { negative: true, arg: arg, width: 8 }.to_cbor()
+        } else {
+            Cbor::Unsigned(CborUnsigned::smallest(arg))
+        })
+    }
+
+    /// Builds a float, taking an encoding indicator into account.
+    fn float(&mut self, v: f64) -> Cbor {
+        Cbor::Float(match self.indicator() {
+            Some(b'1') => CborFloat::Float16(v as f32),
+            Some(b'2') => CborFloat::Float32(v as f32),
+            _ => CborFloat::Float64(v),
+        })
+    }
+
+    fn word(&mut self) -> CborResult<Cbor> {
+        let start = self.pos;
+        while let Some(b'a'...b'z') = self.peek() {
+            self.pos += 1;
+        }
+        match str::from_utf8(&self.s[start..self.pos]).unwrap() {
+            "false" => Ok(Cbor::Bool(false)),
+            "true" => Ok(Cbor::Bool(true)),
+            "null" => Ok(Cbor::Null),
+            "undefined" => Ok(Cbor::Undefined),
+            "simple" => {
+                try!(self.expect(b'('));
+                self.ws();
+                let at = self.pos;
+                // Only a number is read, so this doesn't recurse without
+                // limit.
+                let n = match self.peek() {
+                    Some(b'0'...b'9') => try!(self.number()),
+                    _ => return Err(self.err_at("invalid simple value", at)),
+                };
+                let n = match n {
+                    Cbor::Unsigned(n) => n.to_u64().unwrap(),
+                    _ => return Err(self.err_at("invalid simple value", at)),
+                };
+                try!(self.expect(b')'));
+                match n {
+                    20 => Ok(Cbor::Bool(false)),
+                    21 => Ok(Cbor::Bool(true)),
+                    22 => Ok(Cbor::Null),
+                    23 => Ok(Cbor::Undefined),
+                    24...31 => Err(self.err_at("reserved simple value", at)),
+                    n if n <= 255 => Ok(Cbor::Simple(CborSimple(n as u8))),
+                    _ => Err(self.err_at("invalid simple value", at)),
+                }
+            }
+            _ => Err(self.err_at("unexpected character", start)),
+        }
+    }
+}
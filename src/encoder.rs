@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::iter::IntoIterator;
-use std::io;
+use std::io::{self, Read};
+use std::mem::{self, transmute};
 use std::u8;
 use std::u16;
 use std::u32;
@@ -8,38 +10,229 @@ use byteorder::{ByteOrder, BigEndian};
 use rustc_serialize::Encodable;
 use rustc_serialize::Encoder as RustcEncoder;
 
-use {CborError, CborResult, Type, WriteError};
+use {
+    BytesMode, CborError, CborResult, OptionFormat, Type,
+    UnknownLengthPolicy, WriteError, stringref_min_len,
+};
+
+/// The encoding of NaN in deterministically encoded CBOR.
+const CANONICAL_NAN: [u8; 3] = [(7 << 5) | 25, 0x7e, 0x00];
 
 /// Encodes Rust values to CBOR bytes in the underlying writer `W`.
 ///
 /// Note that currently, using the serialization infrastructure is the only
 /// way to write CBOR in this crate.
+///
+/// How values are written can be changed with the builder methods
+/// `canonical`, `preferred`, `dag_cbor`, `pack_floats`, `canonical_nans`,
+/// `stringrefs` and `self_describe`.
 pub struct Encoder<W> {
     buf: W,
+    opts: Options,
+    state: State,
     emitting_key: bool,
-    byte_string: bool,
-    tag: bool,
+    // Buffers for data that can't be written yet. When not empty, all
+    // output goes to the last buffer instead of `buf`.
+    sinks: Vec<Vec<u8>>,
+    // The encoded entries of each open map that needs to be sorted.
+    entries: Vec<Vec<(Vec<u8>, Vec<u8>)>>,
+    // The index of every string (by major type and contents) in the
+    // stringref namespace of the current top-level data item.
+    strings: HashMap<(u8, Vec<u8>), u64>,
+    // The number of indexes assigned in that namespace, which includes
+    // byte strings whose contents weren't kept.
+    strings_assigned: u64,
+    // The index of every shared value (by address) written in the current
+    // top-level data item, and the address of the one being written.
+    shared: HashMap<usize, u64>,
+    shared_id: usize,
+    // The integer keys that struct fields are written with, by name.
+    field_keys: HashMap<String, i64>,
+    // The number of entries in each open `CborFlatten` map so far.
+    flat_lens: Vec<u64>,
+    // The key of each map entry whose value is being written, for the
+    // paths in errors. Only the first `nkeys` are used, and the rest are
+    // kept so that writing keys doesn't allocate.
+    keys: Vec<String>,
+    nkeys: usize,
+    // The number of items (or entries) written so far in each open array
+    // and map, which must match the length that was given for it.
+    lens: Vec<u64>,
+    // For each open array, its length if its items are being buffered in
+    // the last sink because it may be written as a byte string instead.
+    byte_seqs: Vec<Option<usize>>,
+    // What has been written so far in the item of such an array that is
+    // being written, to tell which items are `u8`s.
+    item: Item,
+    // True between the tag number of a `CborTagEncode` and its data, whose
+    // field is written without a map key.
+    tag_data: bool,
+}
+
+/// Options that control how Rust values are encoded.
+#[derive(Clone, Copy, Debug, Default)]
+struct Options {
+    bytes: BytesMode,
+    canonical: bool,
+    canonical_nans: bool,
+    chars_as_integers: bool,
+    dag_cbor: bool,
+    options: OptionFormat,
+    pack_floats: bool,
+    self_describe: bool,
+    stringrefs: bool,
+    unknown_lengths: UnknownLengthPolicy,
+    wrap_newtypes: bool,
+}
+
+/// What the encoder does with the values it is given.
+///
+/// The special structs in this crate (e.g., `CborTagEncode` and
+/// `CborBytes`) switch to the other states through their names, and the
+/// encoder goes back to `Normal` once their data has been written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Values are encoded as usual.
+    Normal,
+    /// The next `u64` is a tag number.
+    Tag,
+    /// The next sequence is a byte string whose contents are its `u8`s.
+    Bytes,
+    /// The next `u8` is a simple value.
+    Simple,
+    /// `u8`s are written as is and sequences have no head.
+    Raw,
+    /// The next `usize` is the address of a shared value, which follows.
+    Shared,
+    /// The next struct or map is merged into the open `CborFlatten` map.
+    Flatten,
+    /// The struct being written has one field, and is written as the value
+    /// of the field if it's a newtype struct.
+    Newtype,
+}
+
+/// What has been written so far in an item of a sequence that may be
+/// written as a byte string.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Item {
+    /// Nothing.
+    Empty,
+    /// A `u8`, and nothing else.
+    Uint8,
+    /// Anything else, including a value that holds a `u8` (e.g., an
+    /// `Option<u8>` or a newtype struct), which can have the same encoding.
+    Other,
 }
 
 impl<W: io::Write> Encoder<W> {
+    fn write(&mut self, bytes: &[u8]) -> CborResult<()> {
+        match self.sinks.last_mut() {
+            Some(sink) => { sink.extend(bytes.iter().cloned()); Ok(()) }
+            None => self.buf.write_all(bytes).map_err(write_error),
+        }
+    }
+
+    /// Runs `f` and returns everything it wrote instead of writing it.
+    fn capture<F>(&mut self, f: F) -> CborResult<Vec<u8>>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.sinks.push(vec![]);
+        let r = f(self);
+        let bytes = self.sinks.pop().unwrap();
+        r.map(|_| bytes)
+    }
+
+    /// Runs `f`, which writes the entries of a map, and writes the
+    /// entries in canonical order afterwards.
+    fn write_sorted<F>(&mut self, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.entries.push(vec![]);
+        let r = f(self);
+        let entries = self.entries.pop().unwrap();
+        try!(r);
+        self.write_entries(entries)
+    }
+
+    /// Returns the entries of the innermost map being sorted, or an error
+    /// if `what` was written outside of one.
+    fn open_entries(&mut self, what: &'static str)
+                   -> CborResult<&mut Vec<(Vec<u8>, Vec<u8>)>> {
+        self.entries.last_mut().ok_or_else(|| out_of_order(what))
+    }
+
+    /// Writes the encoded entries of a map in canonical order.
+    fn write_entries(&mut self, mut entries: Vec<(Vec<u8>, Vec<u8>)>)
+                    -> CborResult<()> {
+        // Comparing the encoded keys bytewise is the canonical order.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (k, v) in entries {
+            try!(self.write(&k));
+            try!(self.write(&v));
+        }
+        Ok(())
+    }
+
+    fn write_float(&mut self, v: f64) -> CborResult<()> {
+        if self.opts.dag_cbor {
+            // DAG-CBOR always uses double precision, even where packing
+            // floats was asked for.
+            if !v.is_finite() {
+                return Err(dag_error("NaN or infinity"));
+            }
+            return self.write_f64(v);
+        }
+        if v.is_nan() && self.opts.canonical_nans {
+            return self.write(&CANONICAL_NAN);
+        }
+        let single = v as f32;
+        if !self.opts.pack_floats || (single as f64 != v && !v.is_nan()) {
+            return self.write_f64(v);
+        }
+        self.write_f32(single)
+    }
+
+    fn write_f64(&mut self, v: f64) -> CborResult<()> {
+        let mut buf = [(7 << 5) | 27, 0, 0, 0, 0, 0, 0, 0, 0];
+        <BigEndian as ByteOrder>::write_f64(&mut buf[1..], v);
+        self.write(&buf)
+    }
+
+    fn write_f32(&mut self, v: f32) -> CborResult<()> {
+        if self.opts.dag_cbor {
+            return self.write_float(v as f64);
+        }
+        if v.is_nan() && self.opts.canonical_nans {
+            return self.write(&CANONICAL_NAN);
+        }
+        if self.opts.pack_floats {
+            if let Some(half) = f16_bits(v) {
+                let mut buf = [(7 << 5) | 25, 0, 0];
+                <BigEndian as ByteOrder>::write_u16(&mut buf[1..], half);
+                return self.write(&buf);
+            }
+        }
+        let mut buf = [(7 << 5) | 26, 0, 0, 0, 0];
+        <BigEndian as ByteOrder>::write_f32(&mut buf[1..], v);
+        self.write(&buf)
+    }
+
     fn write_num(&mut self, major: u8, n: u64) -> CborResult<()> {
         let major = major << 5;
         if n <= 23 {
-            fromerr!(self.buf.write_all(&[major | n as u8]))
+            self.write(&[major | n as u8])
         } else if n <= u8::MAX as u64 {
-            fromerr!(self.buf.write_all(&[major | 24, n as u8]))
+            self.write(&[major | 24, n as u8])
         } else if n <= u16::MAX as u64 {
             let mut buf = [major | 25, 0, 0];
             <BigEndian as ByteOrder>::write_u16(&mut buf[1..], n as u16);
-            fromerr!(self.buf.write_all(&buf))
+            self.write(&buf)
         } else if n <= u32::MAX as u64 {
             let mut buf = [major | 26, 0, 0, 0, 0];
             <BigEndian as ByteOrder>::write_u32(&mut buf[1..], n as u32);
-            fromerr!(self.buf.write_all(&buf))
+            self.write(&buf)
         } else {
             let mut buf = [major | 27, 0, 0, 0, 0, 0, 0, 0, 0];
             <BigEndian as ByteOrder>::write_u64(&mut buf[1..], n);
-            fromerr!(self.buf.write_all(&buf))
+            self.write(&buf)
         }
     }
 
@@ -55,26 +248,684 @@ impl<W: io::Write> Encoder<W> {
         }
     }
 
-    fn is_tag_field(&self, name: &str) -> bool {
-        self.tag && name.starts_with("__cbor_tag_encode")
+    /// Stringrefs are only used when the order in which strings are written
+    /// is the order in which they are read.
+    fn using_stringrefs(&self) -> bool {
+        self.opts.stringrefs && !self.opts.canonical
+    }
+
+    /// Writes a definite length string of major type 2 or 3, or a
+    /// reference to an equal string that was written before it.
+    fn write_string(&mut self, major: u8, bytes: &[u8]) -> CborResult<()> {
+        if self.using_stringrefs() {
+            let key = (major, bytes.to_vec());
+            if let Some(&i) = self.strings.get(&key) {
+                try!(self.write_num(6, 25));
+                return self.write_uint(i);
+            }
+            self.assign_string(bytes.len() as u64, Some(key));
+        }
+        try!(self.write_num(major, bytes.len() as u64));
+        self.write(bytes)
+    }
+
+    /// Gives a string of `len` bytes the next index in the stringref
+    /// namespace, if it is long enough to get one. Decoders do the same,
+    /// so this must be called for every definite length string written.
+    fn assign_string(&mut self, len: u64, key: Option<(u8, Vec<u8>)>) {
+        let i = self.strings_assigned;
+        if len < stringref_min_len(i) as u64 {
+            return;
+        }
+        if let Some(key) = key {
+            self.strings.insert(key, i);
+        }
+        self.strings_assigned += 1;
+    }
+
+    /// Writes the shared value that `f` writes, or a reference to it if
+    /// it has been written before.
+    fn write_shared<F>(&mut self, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.state = State::Normal;
+        if self.opts.canonical {
+            // References have to come after the value they refer to, which
+            // sorting map entries doesn't preserve.
+            return f(self);
+        }
+        if let Some(&i) = self.shared.get(&self.shared_id) {
+            try!(self.write_num(6, 29));
+            return self.write_uint(i);
+        }
+        // The value is known before it is written, so that a cycle back to
+        // it becomes a reference.
+        let i = self.shared.len() as u64;
+        self.shared.insert(self.shared_id, i);
+        try!(self.write_num(6, 28));
+        f(self)
+    }
+
+    /// Writes the map that the parts of a `CborFlatten` are merged into.
+    /// `f` writes the parts.
+    fn write_flattened<F>(&mut self, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        if self.state == State::Flatten {
+            // This is a part of another `CborFlatten`, so its parts go into
+            // the same map.
+            let r = f(self);
+            self.state = State::Normal;
+            return r;
+        }
+        self.state = State::Flatten;
+        self.flat_lens.push(0);
+        let r = self.write_container(5, None, |e| {
+            try!(f(e));
+            Ok(*e.flat_lens.last().unwrap())
+        });
+        self.flat_lens.pop();
+        self.state = State::Normal;
+        r
+    }
+
+    /// Writes an array (major type 4) or a map (5) whose items (or
+    /// entries) are written and counted by `f`.
+    ///
+    /// `len` is the number of items, if it is known up front, and must
+    /// match. Otherwise, the container is written as `unknown_lengths`
+    /// says.
+    fn write_container<F>(&mut self, major: u8, len: Option<usize>, f: F)
+                         -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<u64> {
+        let check = |n: u64| match len {
+            Some(len) if len as u64 != n => {
+                Err(CborError::Encode(WriteError::LengthMismatch {
+                    expected: len as u64,
+                    got: n,
+                }))
+            }
+            _ => Ok(()),
+        };
+        if self.opts.canonical && major == 5 {
+            // The entries are collected for sorting anyway, so they don't
+            // need to be buffered as well.
+            self.entries.push(vec![]);
+            let r = f(self);
+            let entries = self.entries.pop().unwrap();
+            try!(check(try!(r)));
+            try!(self.write_num(5, entries.len() as u64));
+            return self.write_entries(entries);
+        }
+        if let Some(len) = len {
+            try!(self.write_num(major, len as u64));
+            return check(try!(f(self)));
+        }
+        let indefinite =
+            self.opts.unknown_lengths == UnknownLengthPolicy::Indefinite;
+        if indefinite && !self.opts.canonical {
+            try!(self.write(&[(major << 5) | 31]));
+            try!(f(self));
+            return self.write(&[0xff]);
+        }
+        let mut n = 0;
+        let bytes = try!(self.capture(|e| { n = try!(f(e)); Ok(()) }));
+        try!(self.write_num(major, n));
+        self.write(&bytes)
+    }
+
+    /// Writes what comes before every top-level data item, and starts over
+    /// the references to strings and shared values.
+    fn start_item(&mut self) -> CborResult<()> {
+        if self.opts.self_describe {
+            try!(self.write_num(6, 55799));
+        }
+        if self.using_stringrefs() {
+            self.strings.clear();
+            self.strings_assigned = 0;
+            try!(self.write_num(6, 256));
+        }
+        self.shared.clear();
+        Ok(())
+    }
+
+    /// Writes a part of a `CborFlatten`, which must be a struct or a map.
+    fn write_flat_part<F>(&mut self, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        try!(f(self));
+        if self.state == State::Flatten {
+            // Nothing took the entries.
+            return Err(CborError::Encode(WriteError::InvalidFlatten));
+        }
+        self.state = State::Flatten;
+        Ok(())
+    }
+
+    fn write_simple(&mut self, v: u8) -> CborResult<()> {
+        if self.opts.dag_cbor && (v < 20 || v > 22) {
+            return Err(dag_error(&match v {
+                23 => "undefined".to_string(),
+                v => format!("simple value {}", v),
+            }));
+        }
+        match v {
+            0...23 => self.write(&[(7 << 5) | v]),
+            24...31 => {
+                Err(CborError::Encode(WriteError::InvalidSimpleValue(v)))
+            }
+            _ => self.write(&[(7 << 5) | 24, v]),
+        }
+    }
+
+    /// Writes the map key of a struct field, which is its integer key (see
+    /// `field_keys`) or else its name.
+    fn write_field_key(&mut self, name: &str) -> CborResult<()> {
+        match self.field_keys.get(name).cloned() {
+            Some(key) if self.opts.dag_cbor => Err(dag_error(&format!(
+                "integer map key {} (for field {:?})", key, name))),
+            Some(key) => self.write_int(key),
+            None => self.emit_str(name),
+        }
+    }
+
+    /// Runs `f`, which writes the one field of a struct. A newtype struct,
+    /// whose field is `_field0` as derived for tuple structs, is written as
+    /// the value of its field, so the head of the map waits for the name
+    /// of the field (see `emit_struct_field`).
+    fn write_newtype<F>(&mut self, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.state = State::Newtype;
+        let r = f(self);
+        let empty = self.state == State::Newtype;
+        self.state = State::Normal;
+        try!(r);
+        if empty {
+            return Err(CborError::Encode(WriteError::LengthMismatch {
+                expected: 1,
+                got: 0,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Runs `f`, which writes the items of an array or the entries of a
+    /// map, and checks that it wrote `len` of them. The head has already
+    /// been written, so anything else would be invalid CBOR.
+    fn write_counted<F>(&mut self, len: usize, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.lens.push(0);
+        let r = f(self);
+        let n = self.lens.pop().unwrap_or(0);
+        try!(r);
+        if n != len as u64 {
+            return Err(CborError::Encode(WriteError::LengthMismatch {
+                expected: len as u64,
+                got: n,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Counts an item (or entry) of the innermost open array (or map).
+    fn count_item(&mut self) {
+        if let Some(n) = self.lens.last_mut() {
+            *n += 1;
+        }
+    }
+
+    /// Writes an array of `len` items, which `f` writes. If `bytes` is
+    /// true, the array is written as a byte string instead when
+    /// `bytes_mode` says so and every item turns out to be a `u8`.
+    fn write_seq<F>(&mut self, len: usize, bytes: bool, f: F)
+                   -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.item = Item::Other;
+        let bytes = bytes && self.state == State::Normal
+                    && self.opts.bytes == BytesMode::Auto;
+        if !bytes {
+            self.byte_seqs.push(None);
+            let r = self.write_array(len, f);
+            self.byte_seqs.pop();
+            return r;
+        }
+        // The head waits until the items are known to be `u8`s or not.
+        // `emit_seq_elt` checks each item, and writes the head and the
+        // items so far as an array at the first one that isn't.
+        self.byte_seqs.push(Some(len));
+        self.sinks.push(vec![]);
+        let r = self.write_counted(len, f);
+        match self.byte_seqs.pop().unwrap() {
+            Some(_) => {
+                let items = self.sinks.pop().unwrap();
+                try!(r);
+                self.write_string(2, &uint8_values(&items))
+            }
+            None => r,
+        }
+    }
+
+    /// Writes an array of `len` items, unless the special structs in this
+    /// crate say otherwise.
+    fn write_array<F>(&mut self, len: usize, f: F) -> CborResult<()>
+            where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        match self.state {
+            State::Raw => return self.write_counted(len, f),
+            State::Bytes if self.using_stringrefs() => {
+                let bytes = self.capture(|e| e.write_counted(len, f));
+                self.state = State::Normal;
+                return self.write_string(2, &try!(bytes));
+            }
+            State::Bytes => {
+                try!(self.write_num(2, len as u64));
+                let v = self.write_counted(len, f);
+                self.state = State::Normal;
+                return v;
+            }
+            _ => {}
+        }
+        try!(self.write_num(4, len as u64));
+        self.write_counted(len, f)
+    }
+
+    /// Writes the innermost open sequence as an array after all, since an
+    /// item of it wasn't a `u8`.
+    fn flush_byte_seq(&mut self) -> CborResult<()> {
+        let len = self.byte_seqs.last_mut().unwrap().take().unwrap();
+        let items = self.sinks.pop().unwrap();
+        try!(self.write_num(4, len as u64));
+        self.write(&items)
     }
 }
 
 impl<W: io::Write> Encoder<W> {
     /// Encode CBOR to an arbitrary writer.
+    ///
+    /// The writer is wrapped in a `BufWriter`, since data items are written
+    /// a few bytes at a time. Use `from_writer_unbuffered` if `wtr` is
+    /// already buffered.
     pub fn from_writer(wtr: W) -> Encoder<io::BufWriter<W>> {
         Encoder::from_writer_raw(io::BufWriter::new(wtr))
     }
 
+    /// Encode CBOR to a writer without buffering it.
+    ///
+    /// Every head and string is written with its own call to `write_all`,
+    /// so this is best for writers that are already buffered (e.g., a
+    /// `BufWriter` or a `Vec<u8>`).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::io::{BufWriter, Write};
+    /// use cbor::Encoder;
+    ///
+    /// let mut out = BufWriter::new(vec![]);
+    /// out.write_all(b"header").unwrap();
+    /// let mut enc = Encoder::from_writer_unbuffered(out);
+    /// enc.encode(&[1, 2]).unwrap();
+    /// let out = enc.into_inner().unwrap().into_inner().unwrap();
+    /// assert_eq!(out, b"header\x01\x02");
+    /// ```
+    pub fn from_writer_unbuffered(wtr: W) -> Encoder<W> {
+        Encoder::from_writer_raw(wtr)
+    }
+
     fn from_writer_raw(wtr: W) -> Encoder<W> {
         Encoder {
             buf: wtr,
+            opts: Options::default(),
+            state: State::Normal,
             emitting_key: false,
-            byte_string: false,
-            tag: false,
+            sinks: vec![],
+            entries: vec![],
+            strings: HashMap::new(),
+            strings_assigned: 0,
+            shared: HashMap::new(),
+            shared_id: 0,
+            field_keys: HashMap::new(),
+            flat_lens: vec![],
+            keys: vec![],
+            nkeys: 0,
+            lens: vec![],
+            byte_seqs: vec![],
+            item: Item::Other,
+            tag_data: false,
         }
     }
 
+    /// When enabled, data is written in the deterministic encoding of
+    /// RFC 8949 (section 4.2.1).
+    ///
+    /// Integers and lengths are always written in their shortest form and
+    /// indefinite lengths are never used. In addition, this sorts the
+    /// entries of maps (including structs) by their encoded keys and packs
+    /// floats (see `pack_floats` and `canonical_nans`), so that the same
+    /// value always produces the same bytes. This is useful for data that
+    /// is signed or hashed.
+    ///
+    /// Entries are sorted in memory, so every map is buffered until it has
+    /// been fully encoded.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    /// use cbor::Encoder;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.insert("bb".to_string(), 1);
+    /// map.insert("c".to_string(), 2);
+    ///
+    /// let mut enc = Encoder::from_memory().canonical(true);
+    /// enc.encode(&[map]).unwrap();
+    /// assert_eq!(enc.as_bytes(),
+    ///            &[0xa2, 0x61, b'c', 0x02, 0x62, b'b', b'b', 0x01][..]);
+    /// ```
+    pub fn canonical(mut self, yes: bool) -> Encoder<W> {
+        self.opts.canonical = yes;
+        self.opts.pack_floats = self.opts.pack_floats || yes;
+        self.opts.canonical_nans = self.opts.canonical_nans || yes;
+        self
+    }
+
+    /// When enabled, data is written in the preferred serialization of
+    /// RFC 8949 (section 4.1), so that it is byte for byte the same as
+    /// the output of other implementations that use it.
+    ///
+    /// Integers, lengths and tags are always written in their shortest
+    /// form and strings, arrays and maps always have definite lengths, so
+    /// this only needs to pack floats (see `pack_floats`). Unlike
+    /// `canonical`, the entries of maps are written in the order given.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::from_memory().preferred(true);
+    /// enc.encode(&[0.0f64, 100000.0, 1.1]).unwrap();
+    /// assert_eq!(&enc.as_bytes()[..3], &[0xf9, 0x00, 0x00][..]);
+    /// assert_eq!(&enc.as_bytes()[3..8],
+    ///            &[0xfa, 0x47, 0xc3, 0x50, 0x00][..]);
+    /// assert_eq!(enc.as_bytes()[8], 0xfb);
+    /// ```
+    pub fn preferred(self, yes: bool) -> Encoder<W> {
+        self.pack_floats(yes)
+    }
+
+    /// When enabled, data is written in DAG-CBOR, the subset of CBOR used
+    /// by IPLD (e.g., for IPFS blocks).
+    ///
+    /// This enables `canonical`, except that floats are always written in
+    /// double precision. Writing NaN, infinity, `undefined`, any other
+    /// simple value besides `false`, `true` and `null`, any tag other than
+    /// 42 (see `Cid`), or a struct field with an integer key (see
+    /// `field_keys`) returns a `WriteError::DagCbor` error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborError, Encoder, WriteError};
+    ///
+    /// let mut enc = Encoder::from_memory().dag_cbor(true);
+    /// enc.encode(&[1.5f32]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0][..]);
+    ///
+    /// match enc.encode(&[::std::f64::INFINITY]) {
+    ///     Err(CborError::Encode(WriteError::DagCbor(_))) => {}
+    ///     r => panic!("unexpected result: {:?}", r),
+    /// }
+    /// ```
+    pub fn dag_cbor(mut self, yes: bool) -> Encoder<W> {
+        self.opts.dag_cbor = yes;
+        self.opts.canonical = self.opts.canonical || yes;
+        self
+    }
+
+    /// When enabled, floats are written in the smallest precision (half,
+    /// single or double) that represents them exactly.
+    ///
+    /// By default, `f32` values are written as single precision floats and
+    /// `f64` values as double precision floats. All NaNs are packed into
+    /// the same half precision NaN.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::from_memory().pack_floats(true);
+    /// enc.encode(&[1.5f64, 0.1]).unwrap();
+    /// assert_eq!(&enc.as_bytes()[..3], &[0xf9, 0x3e, 0x00][..]);
+    /// assert_eq!(enc.as_bytes()[3], 0xfb);
+    /// ```
+    pub fn pack_floats(mut self, yes: bool) -> Encoder<W> {
+        self.opts.pack_floats = yes;
+        self
+    }
+
+    /// Set whether sequences of `u8`s are written as byte strings. The
+    /// default is `BytesMode::Auto`, which writes a `Vec<u8>` or `[u8; N]`
+    /// as a byte string without wrapping it in `CborBytes`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{BytesMode, Encoder};
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[[1u8, 2]]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0x42, 0x01, 0x02][..]);
+    ///
+    /// let mut enc = Encoder::from_memory().bytes_mode(BytesMode::Array);
+    /// enc.encode(&[[1u8, 2]]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0x82, 0x01, 0x02][..]);
+    /// ```
+    pub fn bytes_mode(mut self, mode: BytesMode) -> Encoder<W> {
+        self.opts.bytes = mode;
+        self
+    }
+
+    /// When enabled (the default), a newtype struct (a tuple struct with
+    /// one field) is written as the value of its field, e.g., `Meters(2)`
+    /// as `2`.
+    ///
+    /// When disabled, it is written like any other struct, i.e., as a map
+    /// with the key `_field0` that `rustc_serialize` names its field with.
+    /// The type based decoder accepts both.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate cbor;
+    /// # extern crate rustc_serialize;
+    /// use cbor::Encoder;
+    ///
+    /// #[derive(RustcEncodable)]
+    /// struct Meters(u8);
+    ///
+    /// # fn main() {
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&[Meters(2)]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0x02][..]);
+    ///
+    /// let mut enc = Encoder::from_memory().transparent_newtypes(false);
+    /// enc.encode(&[Meters(2)]).unwrap();
+    /// assert_eq!(enc.as_bytes()[0], 0xa1);
+    /// # }
+    /// ```
+    pub fn transparent_newtypes(mut self, yes: bool) -> Encoder<W> {
+        self.opts.wrap_newtypes = !yes;
+        self
+    }
+
+    /// When enabled, a `char` is written as its Unicode scalar value, as
+    /// older versions of this crate did.
+    ///
+    /// By default, a `char` is written as a text string of one character.
+    /// The decoders in this crate accept both.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.encode(&['a']).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0x61, b'a'][..]);
+    ///
+    /// let mut enc = Encoder::from_memory().chars_as_integers(true);
+    /// enc.encode(&['a']).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0x18, 0x61][..]);
+    /// ```
+    pub fn chars_as_integers(mut self, yes: bool) -> Encoder<W> {
+        self.opts.chars_as_integers = yes;
+        self
+    }
+
+    /// Set how `Option`s are written. The default is `OptionFormat::Null`,
+    /// which can't tell `None` and `Some(None)` apart. Use
+    /// `Decoder::option_format` with the same format to read the data back.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Encoder, OptionFormat};
+    ///
+    /// let format = OptionFormat::Array;
+    /// let mut enc = Encoder::from_memory().option_format(format);
+    /// enc.encode(&[Some(None::<u8>), Some(Some(1)), None]).unwrap();
+    /// // [null], [[1]], null
+    /// assert_eq!(enc.as_bytes(),
+    ///            &[0x81, 0xf6, 0x81, 0x81, 0x01, 0xf6][..]);
+    /// ```
+    pub fn option_format(mut self, format: OptionFormat) -> Encoder<W> {
+        self.opts.options = format;
+        self
+    }
+
+    /// When enabled, every NaN is written as the same half precision
+    /// float, `f9 7e 00`, no matter its payload, sign or precision.
+    ///
+    /// By default, NaNs are written with their bits intact (unless
+    /// `pack_floats` is enabled, which also does this). Combined with
+    /// `Decoder::strict_nans`, this makes sure a NaN only ever has one
+    /// encoding.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::from_memory().canonical_nans(true);
+    /// enc.encode(&[-::std::f64::NAN]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0xf9, 0x7e, 0x00][..]);
+    /// ```
+    pub fn canonical_nans(mut self, yes: bool) -> Encoder<W> {
+        self.opts.canonical_nans = yes;
+        self
+    }
+
+    /// When enabled, strings that repeat are written as references to
+    /// their first occurrence, using the stringref extension (tags 256 and
+    /// 25, see http://cbor.schmorp.de/stringref).
+    ///
+    /// Every top-level data item written by `encode` is tagged with 256,
+    /// which starts a new set of strings. Only strings that are at least
+    /// as long as a reference to them are shared. This is useful for data
+    /// with many repeated map keys, e.g., a list of structs. Decoders must
+    /// understand stringrefs to read the data (see `Decoder::stringrefs`).
+    ///
+    /// Sorting map entries would change the order in which strings are
+    /// written, so this does nothing while `canonical` is enabled.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::from_memory().stringrefs(true);
+    /// enc.encode(&[vec!["hello", "hello"]]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[
+    ///     0xd9, 0x01, 0x00, 0x82,
+    ///     0x65, b'h', b'e', b'l', b'l', b'o', 0xd8, 0x19, 0x00,
+    /// ][..]);
+    /// ```
+    pub fn stringrefs(mut self, yes: bool) -> Encoder<W> {
+        self.opts.stringrefs = yes;
+        self
+    }
+
+    /// When enabled, every top-level data item written by `encode` is
+    /// prefixed with the self-described CBOR tag (55799).
+    ///
+    /// The tag doesn't change the meaning of the data, but its bytes
+    /// (`d9 d9 f7`) make it easy to recognize a file as CBOR.
+    pub fn self_describe(mut self, yes: bool) -> Encoder<W> {
+        self.opts.self_describe = yes;
+        self
+    }
+
+    /// Set how arrays and maps whose length isn't known up front are
+    /// written. The default is `UnknownLengthPolicy::Buffer`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use cbor::{CborFlatten, Encoder, UnknownLengthPolicy};
+    ///
+    /// let (mut a, mut b) = (HashMap::new(), HashMap::new());
+    /// a.insert("a", 1);
+    /// b.insert("b", 2);
+    /// let policy = UnknownLengthPolicy::Indefinite;
+    /// let mut enc = Encoder::from_memory().unknown_lengths(policy);
+    /// enc.encode(&[CborFlatten(a, b)]).unwrap();
+    /// // {_ "a": 1, "b": 2}
+    /// assert_eq!(enc.as_bytes(),
+    ///            &[0xbf, 0x61, b'a', 0x01, 0x61, b'b', 0x02, 0xff][..]);
+    /// ```
+    pub fn unknown_lengths(mut self, policy: UnknownLengthPolicy)
+                          -> Encoder<W> {
+        self.opts.unknown_lengths = policy;
+        self
+    }
+
+    /// Set integer keys for struct fields. A field whose name is in `keys`
+    /// is written with the integer as its map key instead of its name.
+    ///
+    /// Protocols like COSE, CWT and CTAP use small integers as map keys to
+    /// save space. With this, derived `Encodable` impls can write them. The
+    /// table applies to the fields of every struct, but not to the keys of
+    /// other maps (e.g., a `HashMap`). Use `Decoder::field_keys` with the
+    /// same table to read the data back.
+    ///
+    /// DAG-CBOR only allows string keys, so with `dag_cbor`, writing a
+    /// field that has an integer key returns a `WriteError::DagCbor` error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate cbor;
+    /// # extern crate rustc_serialize;
+    /// # fn main() {
+    /// use cbor::Encoder;
+    ///
+    /// #[derive(RustcEncodable)]
+    /// struct Header { alg: i32, kid: String }
+    ///
+    /// let mut enc = Encoder::from_memory()
+    ///                       .field_keys(&[("alg", 1), ("kid", 4)]);
+    /// enc.encode(&[Header { alg: -7, kid: "k".to_string() }]).unwrap();
+    /// // {1: -7, 4: "k"}
+    /// assert_eq!(enc.as_bytes(), &[0xa2, 0x01, 0x26, 0x04, 0x61, b'k'][..]);
+    /// # }
+    /// ```
+    pub fn field_keys(mut self, keys: &[(&str, i64)]) -> Encoder<W> {
+        self.field_keys = keys.iter().map(|&(name, key)| {
+            (name.to_string(), key)
+        }).collect();
+        self
+    }
+
     /// Encode an iterator of Rust values to CBOR in the underlying writer.
     ///
     /// Every value in the iterator must satisfy `Encodable` (from the
@@ -98,37 +949,288 @@ impl<W: io::Write> Encoder<W> {
         where I: IntoIterator,
               <<I as IntoIterator>::IntoIter as Iterator>::Item: Encodable {
         for v in it.into_iter() {
+            try!(self.start_item());
             try!(v.encode(self))
         }
         Ok(())
     }
 
+    /// Encode the values of an iterator as a single top-level array. Each
+    /// value is written as soon as it is yielded, so they never need to be
+    /// collected first.
+    ///
+    /// `len` is the number of values, if it is known up front. If the
+    /// iterator yields a different number, `WriteError::LengthMismatch` is
+    /// returned (after all of them have been written). If `len` is `None`,
+    /// the array is written as `unknown_lengths` says: by default, the
+    /// encoded values are buffered until they have been counted. Use
+    /// `UnknownLengthPolicy::Indefinite` to write them straight through.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{Encoder, UnknownLengthPolicy};
+    ///
+    /// let rows = (1..4).map(|n| n * 10);
+    /// let policy = UnknownLengthPolicy::Indefinite;
+    /// let mut enc = Encoder::from_memory().unknown_lengths(policy);
+    /// enc.encode_seq_from_iter(None, rows).unwrap();
+    /// // [_ 10, 20, 30]
+    /// assert_eq!(enc.as_bytes(), &[0x9f, 0x0a, 0x14, 0x18, 0x1e, 0xff][..]);
+    /// ```
+    pub fn encode_seq_from_iter<I>(&mut self, len: Option<usize>, it: I)
+                                  -> CborResult<()>
+            where I: IntoIterator, I::Item: Encodable {
+        try!(self.start_item());
+        self.write_container(4, len, |e| {
+            let mut n = 0;
+            for v in it {
+                try!(e.emit_seq_elt(n, |e| v.encode(e)));
+                n += 1;
+            }
+            Ok(n as u64)
+        })
+    }
+
+    /// Encode the key/value pairs of an iterator as a single top-level map.
+    /// Each entry is written as soon as it is yielded, except in canonical
+    /// mode, where the entries are sorted.
+    ///
+    /// Keys must encode as Unicode strings. `len` works the same as for
+    /// `encode_seq_from_iter`.
+    pub fn encode_map_from_iter<I, K, V>(&mut self, len: Option<usize>,
+                                         it: I) -> CborResult<()>
+            where I: IntoIterator<Item=(K, V)>,
+                  K: Encodable, V: Encodable {
+        try!(self.start_item());
+        self.write_container(5, len, |e| {
+            let mut n = 0;
+            for (k, v) in it {
+                try!(e.emit_map_elt_key(n, |e| k.encode(e)));
+                try!(e.emit_map_elt_val(n, |e| v.encode(e)));
+                n += 1;
+            }
+            Ok(n as u64)
+        })
+    }
+
+    /// Write a byte string of `len` bytes whose contents are read from
+    /// `rdr`.
+    ///
+    /// The contents are copied directly to the underlying writer, so they
+    /// never need to be in memory all at once. Exactly `len` bytes are read
+    /// from `rdr`. If it has fewer, then `WriteError::ShortRead` is
+    /// returned.
+    ///
+    /// The byte string is a top-level data item, so like the values given
+    /// to `encode`, it gets the tags of `self_describe` and `stringrefs`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::from_memory();
+    /// enc.write_bytes_from_reader(3, &mut &b"abcd"[..]).unwrap();
+    /// assert_eq!(enc.as_bytes(), &[0x43, b'a', b'b', b'c'][..]);
+    /// ```
+    pub fn write_bytes_from_reader<R: io::Read>(&mut self, len: u64,
+                                                rdr: &mut R)
+                                               -> CborResult<()> {
+        try!(self.start_item());
+        if self.using_stringrefs() {
+            // The contents can't be kept, so the string is never shared,
+            // but it still takes up an index.
+            self.assign_string(len, None);
+        }
+        try!(self.write_num(2, len));
+        let got = match self.sinks.last_mut() {
+            Some(sink) => try!(io::copy(&mut rdr.take(len), sink)),
+            None => {
+                try!(io::copy(&mut rdr.take(len), &mut self.buf)
+                         .map_err(write_error))
+            }
+        };
+        if got < len {
+            return Err(CborError::Encode(
+                WriteError::ShortRead { expected: len, got: got }));
+        }
+        Ok(())
+    }
+
     /// Flush the underlying writer.
     pub fn flush(&mut self) -> CborResult<()> {
         fromerr!(self.buf.flush())
     }
+
+    /// Flush the underlying writer and return it.
+    ///
+    /// For an encoder made with `from_writer`, this is the `BufWriter`
+    /// that wraps the original writer, which has nothing buffered.
+    pub fn into_inner(mut self) -> CborResult<W> {
+        try!(self.flush());
+        Ok(self.buf)
+    }
 }
 
 impl Encoder<Vec<u8>> {
     /// Encode CBOR to an in memory buffer.
+    ///
+    /// Room for 64 KiB is allocated up front. Use `with_capacity` to
+    /// allocate more or less.
     pub fn from_memory() -> Encoder<Vec<u8>> {
-        Encoder::from_writer_raw(Vec::with_capacity(1024 * 64))
+        Encoder::with_capacity(1024 * 64)
     }
 
-    /// Flush and retrieve the CBOR bytes that have been written.
-    pub fn as_bytes(&mut self) -> &[u8] {
-        self.flush().unwrap();
+    /// Encode CBOR to an in memory buffer that starts with room for `n`
+    /// bytes.
+    pub fn with_capacity(n: usize) -> Encoder<Vec<u8>> {
+        Encoder::from_writer_raw(Vec::with_capacity(n))
+    }
+
+    /// Throws away the CBOR bytes that have been written, so that the
+    /// encoder (and its buffer) can be used for another message.
+    ///
+    /// The settings of the encoder are kept. This also recovers from an
+    /// error in the middle of a data item, since everything the encoder
+    /// keeps about the data item being written is thrown away too.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut enc = Encoder::with_capacity(16).canonical(true);
+    /// for n in 0..3 {
+    ///     enc.clear();
+    ///     enc.encode(&[n]).unwrap();
+    ///     assert_eq!(enc.as_bytes(), &[n as u8]);
+    /// }
+    /// ```
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.state = State::Normal;
+        self.emitting_key = false;
+        self.sinks.clear();
+        self.entries.clear();
+        self.strings.clear();
+        self.strings_assigned = 0;
+        self.shared.clear();
+        self.shared_id = 0;
+        self.flat_lens.clear();
+        self.nkeys = 0;
+        self.lens.clear();
+        self.byte_seqs.clear();
+        self.item = Item::Other;
+        self.tag_data = false;
+    }
+
+    /// Retrieve the CBOR bytes that have been written.
+    ///
+    /// Data is written straight to the vector, so there is nothing to
+    /// flush and this can't fail.
+    pub fn as_bytes(&self) -> &[u8] {
         &self.buf
     }
 
-    /// Flush and retrieve the CBOR bytes that have been written without
-    /// copying.
-    pub fn into_bytes(mut self) -> Vec<u8> {
-        self.flush().unwrap();
+    /// Retrieve the CBOR bytes that have been written without copying.
+    pub fn into_bytes(self) -> Vec<u8> {
         self.buf
     }
 }
 
+impl<'a> Encoder<&'a mut Vec<u8>> {
+    /// Encode CBOR to the end of a vector owned by the caller.
+    ///
+    /// This lets one buffer be reused for many messages (or hold other
+    /// data around the CBOR), without moving it in and out of an encoder.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::Encoder;
+    ///
+    /// let mut buf = vec![0xff];
+    /// Encoder::from_vec(&mut buf).encode(&[1, 2]).unwrap();
+    /// assert_eq!(buf, vec![0xff, 1, 2]);
+    /// ```
+    pub fn from_vec(buf: &'a mut Vec<u8>) -> Encoder<&'a mut Vec<u8>> {
+        Encoder::from_writer_raw(buf)
+    }
+}
+
+impl<'a> Encoder<io::Cursor<&'a mut [u8]>> {
+    /// Encode CBOR into a fixed size buffer.
+    ///
+    /// Nothing is allocated while encoding, unless `canonical` is enabled
+    /// (which buffers maps to sort them). If the encoded data doesn't fit
+    /// in `buf`, then `WriteError::BufferTooSmall` is returned and `buf`
+    /// holds as much of the data as fit.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use cbor::{CborError, Encoder, WriteError};
+    ///
+    /// let mut buf = [0; 4];
+    /// {
+    ///     let mut enc = Encoder::from_slice(&mut buf);
+    ///     enc.encode(&[1, 2]).unwrap();
+    ///     assert_eq!(enc.bytes_written(), 2);
+    /// }
+    /// assert_eq!(&buf[..2], &[1, 2][..]);
+    ///
+    /// let mut enc = Encoder::from_slice(&mut buf);
+    /// match enc.encode(&["hello"]) {
+    ///     Err(CborError::Encode(WriteError::BufferTooSmall)) => {}
+    ///     r => panic!("unexpected result: {:?}", r),
+    /// }
+    /// ```
+    pub fn from_slice(buf: &'a mut [u8])
+                     -> Encoder<io::Cursor<&'a mut [u8]>> {
+        Encoder::from_writer_raw(io::Cursor::new(buf))
+    }
+
+    /// Returns the number of bytes written to the buffer so far.
+    pub fn bytes_written(&self) -> usize {
+        self.buf.position() as usize
+    }
+}
+
+/// Returns the number of bytes that `v` is encoded to with the default
+/// settings (e.g., by `Encoder::from_memory`), without writing them.
+///
+/// This is useful to allocate a buffer of the right size, or to check a
+/// message against a size limit before encoding it.
+///
+/// # Example
+///
+/// ```rust
+/// let v = (vec![1u32, 1000], "hello");
+/// let mut enc = cbor::Encoder::from_memory();
+/// enc.encode(&[&v]).unwrap();
+/// assert_eq!(cbor::encoded_size(&v).unwrap(), enc.as_bytes().len());
+/// ```
+pub fn encoded_size<T: Encodable>(v: &T) -> CborResult<usize> {
+    let mut enc = Encoder::from_writer_raw(Counter(0));
+    try!(enc.encode(&[v]));
+    Ok(enc.buf.0)
+}
+
+/// A writer that only counts the bytes written to it.
+struct Counter(usize);
+
+impl io::Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 // /// Encodes a data item directly to CBOR bytes.
 // ///
 // /// This is useful when writing `Encodable` implementations with
@@ -139,11 +1241,31 @@ impl Encoder<Vec<u8>> {
     // Ok(enc.into_bytes())
 // }
 
+/// Converts an error from the underlying writer, which returns
+/// `WriteZero` when it has no room left.
+fn write_error(err: io::Error) -> CborError {
+    if err.kind() == io::ErrorKind::WriteZero {
+        CborError::Encode(WriteError::BufferTooSmall)
+    } else {
+        CborError::Io(err)
+    }
+}
+
+fn dag_error(what: &str) -> CborError {
+    CborError::Encode(WriteError::DagCbor(what.to_string()))
+}
+
+fn out_of_order(what: &'static str) -> CborError {
+    CborError::Encode(WriteError::OutOfOrder(what))
+}
+
 macro_rules! no_string_key {
     ($enc:expr) => (
         if $enc.emitting_key {
             return Err(CborError::Encode(WriteError::InvalidMapKey {
                 got: None,
+                key: None,
+                path: String::new(),
             }));
         }
     );
@@ -151,27 +1273,92 @@ macro_rules! no_string_key {
         if $enc.emitting_key {
             return Err(CborError::Encode(WriteError::InvalidMapKey {
                 got: Some($ty),
+                key: None,
+                path: String::new(),
+            }));
+        }
+    );
+    ($enc:expr, $ty:expr, $key:expr) => (
+        if $enc.emitting_key {
+            return Err(CborError::Encode(WriteError::InvalidMapKey {
+                got: Some($ty),
+                key: Some($key),
+                path: String::new(),
             }));
         }
     );
 }
 
+/// A step in the path to a value, for errors.
+enum PathElem<'a> {
+    Field(&'a str),
+    Index(usize),
+    Key(&'a str),
+}
+
+/// Adds `elem` to the front of the path of an error about a value.
+fn in_path(err: CborError, elem: PathElem) -> CborError {
+    match err {
+        CborError::Encode(WriteError::InvalidMapKey { got, key, path }) => {
+            CborError::Encode(WriteError::InvalidMapKey {
+                got: got,
+                key: key,
+                path: join_path(elem, &path),
+            })
+        }
+        CborError::Encode(kind @ WriteError::DagCbor(_))
+        | CborError::Encode(kind @ WriteError::InvalidSimpleValue(_))
+        | CborError::Encode(kind @ WriteError::InvalidFlatten)
+        | CborError::Encode(kind @ WriteError::OutOfOrder(_))
+        | CborError::Encode(kind @ WriteError::LengthMismatch { .. })
+        | CborError::Encode(kind @ WriteError::UnsupportedType(_)) => {
+            CborError::EncodeAtPath { kind: kind, path: join_path(elem, "") }
+        }
+        CborError::EncodeAtPath { kind, path } => {
+            let path = join_path(elem, &path);
+            CborError::EncodeAtPath { kind: kind, path: path }
+        }
+        err => err,
+    }
+}
+
+fn join_path(elem: PathElem, path: &str) -> String {
+    // Fields are separated from what follows them by a dot.
+    let sep = if path.is_empty() || path.starts_with('[') { "" } else { "." };
+    match elem {
+        PathElem::Field(name) => format!("{}{}{}", name, sep, path),
+        PathElem::Index(i) => format!("[{}]{}{}", i, sep, path),
+        PathElem::Key(k) => format!("[{:?}]{}{}", k, sep, path),
+    }
+}
+
 impl<W: io::Write> RustcEncoder for Encoder<W> {
     type Error = CborError;
 
     fn emit_nil(&mut self) -> CborResult<()> {
-        no_string_key!(self, Type::Null);
-        fromerr!(self.buf.write_all(&[(7 << 5) | 22]))
+        no_string_key!(self, Type::Null, "null".to_string());
+        self.write(&[(7 << 5) | 22])
     }
 
     fn emit_usize(&mut self, v: usize) -> CborResult<()> {
-        no_string_key!(self, Type::UInt);
+        no_string_key!(self, Type::UInt, v.to_string());
+        if self.state == State::Shared {
+            self.shared_id = v;
+            return Ok(());
+        }
         self.write_uint(v as u64)
     }
 
     fn emit_u64(&mut self, v: u64) -> CborResult<()> {
-        no_string_key!(self, Type::UInt64);
-        if self.tag {
+        no_string_key!(self, Type::UInt64, v.to_string());
+        if self.state == State::Tag {
+            // Only the tag number is special. The data item that follows
+            // is encoded as usual.
+            self.state = State::Normal;
+            self.tag_data = true;
+            if self.opts.dag_cbor && v != 42 {
+                return Err(dag_error(&format!("tag {}", v)));
+            }
             self.write_num(6, v)
         } else {
             self.write_uint(v)
@@ -179,81 +1366,92 @@ impl<W: io::Write> RustcEncoder for Encoder<W> {
     }
 
     fn emit_u32(&mut self, v: u32) -> CborResult<()> {
-        no_string_key!(self, Type::UInt32);
+        no_string_key!(self, Type::UInt32, v.to_string());
         self.write_uint(v as u64)
     }
 
     fn emit_u16(&mut self, v: u16) -> CborResult<()> {
-        no_string_key!(self, Type::UInt16);
+        no_string_key!(self, Type::UInt16, v.to_string());
         self.write_uint(v as u64)
     }
 
     fn emit_u8(&mut self, v: u8) -> CborResult<()> {
-        no_string_key!(self, Type::UInt8);
-        if self.byte_string {
-            fromerr!(self.buf.write_all(&[v]))
-        } else {
-            self.write_uint(v as u64)
+        no_string_key!(self, Type::UInt8, v.to_string());
+        match self.state {
+            State::Bytes | State::Raw => self.write(&[v]),
+            State::Simple => self.write_simple(v),
+            State::Normal => {
+                self.item = match self.item {
+                    Item::Empty => Item::Uint8,
+                    _ => Item::Other,
+                };
+                self.write_uint(v as u64)
+            }
+            State::Tag | State::Shared | State::Flatten | State::Newtype => {
+                self.write_uint(v as u64)
+            }
         }
     }
 
     fn emit_isize(&mut self, v: isize) -> CborResult<()> {
-        no_string_key!(self, Type::Int);
+        no_string_key!(self, Type::Int, v.to_string());
         self.write_int(v as i64)
     }
 
     fn emit_i64(&mut self, v: i64) -> CborResult<()> {
-        no_string_key!(self, Type::Int64);
+        no_string_key!(self, Type::Int64, v.to_string());
         self.write_int(v)
     }
 
     fn emit_i32(&mut self, v: i32) -> CborResult<()> {
-        no_string_key!(self, Type::Int32);
+        no_string_key!(self, Type::Int32, v.to_string());
         self.write_int(v as i64)
     }
 
     fn emit_i16(&mut self, v: i16) -> CborResult<()> {
-        no_string_key!(self, Type::Int16);
+        no_string_key!(self, Type::Int16, v.to_string());
         self.write_int(v as i64)
     }
 
     fn emit_i8(&mut self, v: i8) -> CborResult<()> {
-        no_string_key!(self, Type::Int8);
+        no_string_key!(self, Type::Int8, v.to_string());
         self.write_int(v as i64)
     }
 
     fn emit_f64(&mut self, v: f64) -> CborResult<()> {
-        no_string_key!(self, Type::Float64);
-        let mut buf = [(7 << 5) | 27, 0, 0, 0, 0, 0, 0, 0, 0];
-        <BigEndian as ByteOrder>::write_f64(&mut buf[1..], v);
-        fromerr!(self.buf.write_all(&buf))
+        no_string_key!(self, Type::Float64, v.to_string());
+        self.write_float(v)
     }
 
     fn emit_f32(&mut self, v: f32) -> CborResult<()> {
-        no_string_key!(self, Type::Float32);
-        let mut buf = [(7 << 5) | 26, 0, 0, 0, 0];
-        <BigEndian as ByteOrder>::write_f32(&mut buf[1..], v);
-        fromerr!(self.buf.write_all(&buf))
+        no_string_key!(self, Type::Float32, v.to_string());
+        self.write_f32(v)
     }
 
     fn emit_bool(&mut self, v: bool) -> CborResult<()> {
-        no_string_key!(self, Type::Bool);
+        no_string_key!(self, Type::Bool, v.to_string());
         let n = if v { 21 } else { 20 };
-        fromerr!(self.buf.write_all(&[(7 << 5) | n]))
+        self.write(&[(7 << 5) | n])
     }
 
     fn emit_char(&mut self, v: char) -> CborResult<()> {
-        no_string_key!(self, Type::UInt32);
-        self.emit_u32(v as u32)
+        if self.opts.chars_as_integers {
+            no_string_key!(self, Type::UInt32, v.to_string());
+            return self.emit_u32(v as u32);
+        }
+        self.emit_str(&v.to_string())
     }
 
     fn emit_str(&mut self, v: &str) -> CborResult<()> {
-        try!(self.write_num(3, v.len() as u64));
-        fromerr!(self.buf.write_all(v.as_bytes()))
+        if self.emitting_key {
+            self.keys[self.nkeys].push_str(v);
+        }
+        self.write_string(3, v.as_bytes())
     }
 
     fn emit_enum<F>(&mut self, _name: &str, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.item = Item::Other;
         f(self)
     }
 
@@ -270,6 +1468,14 @@ impl<W: io::Write> RustcEncoder for Encoder<W> {
         }
         no_string_key!(self);
         try!(self.write_num(5, 2));
+        if self.opts.canonical {
+            // "fields" is shorter, so it sorts before "variant".
+            try!(self.emit_str("fields"));
+            try!(self.write_num(4, len as u64));
+            try!(f(self));
+            try!(self.emit_str("variant"));
+            return self.emit_str(v_name);
+        }
         try!(self.emit_str("variant"));
         try!(self.emit_str(v_name));
         try!(self.emit_str("fields"));
@@ -313,44 +1519,127 @@ impl<W: io::Write> RustcEncoder for Encoder<W> {
         f: F,
     ) -> CborResult<()>
     where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        self.item = Item::Other;
+        let special = match name {
+            "CborFlatten" | "CborBreak" | "CborTagEncode" | "CborBytes"
+            | "CborSimple" | "CborWide" | "CborShared" | "CborEncoded" => true,
+            _ => false,
+        };
+        if len == 1 && !special && self.state == State::Normal
+                && !self.opts.wrap_newtypes {
+            return self.write_newtype(f);
+        }
+        if len == 0 && !special && self.state == State::Normal {
+            // A unit struct has nothing to write, so it's `null` like `()`.
+            try!(self.emit_nil());
+            return f(self);
+        }
         no_string_key!(self, Type::Map);
-        match name {
-            "CborTag" => {
-                self.tag = true;
-                let v = f(self);
-                self.tag = false;
-                return v;
+        self.state = match name {
+            "CborFlatten" => return self.write_flattened(f),
+            "CborBreak" => {
+                // A break only exists as the end of an indefinite length
+                // item, which the encoder writes itself.
+                return Err(CborError::Encode(
+                    WriteError::UnsupportedType(Type::Break)));
             }
-            "CborTagEncode" => {
-                self.tag = true;
-                let v = f(self);
-                self.tag = false;
-                return v;
+            "CborTagEncode" | "CborBytes" | "CborSimple" | "CborWide"
+            | "CborShared" | "CborEncoded"
+                    if self.state == State::Flatten => {
+                return Err(CborError::Encode(WriteError::InvalidFlatten));
             }
-            "CborBytes" => { self.byte_string = true; }
-            _ => { try!(self.write_num(5, len as u64)); }
-        }
-        f(self)
+            _ if self.state == State::Flatten => {
+                self.state = State::Normal;
+                *self.flat_lens.last_mut().unwrap() += len as u64;
+                return self.write_counted(len, f);
+            }
+            "CborTagEncode" => State::Tag,
+            "CborBytes" => State::Bytes,
+            "CborSimple" => State::Simple,
+            "CborWide" => State::Raw,
+            "CborShared" => State::Shared,
+            "CborEncoded" => {
+                // The data item inside is read on its own, so it can't
+                // refer to strings or values outside of it.
+                let stringrefs = self.opts.stringrefs;
+                self.opts.stringrefs = false;
+                let shared = mem::replace(&mut self.shared, HashMap::new());
+                let bytes = self.capture(f);
+                self.opts.stringrefs = stringrefs;
+                self.shared = shared;
+                return self.write_string(2, &try!(bytes));
+            }
+            _ => {
+                // Fields are counted like map entries, since a hand written
+                // `Encodable` may skip some (e.g., `None`s) and has to pass
+                // the number it actually writes.
+                try!(self.write_num(5, len as u64));
+                if self.opts.canonical {
+                    return self.write_counted(len, |e| e.write_sorted(f));
+                }
+                return self.write_counted(len, f);
+            }
+        };
+        let v = f(self);
+        self.state = State::Normal;
+        v
     }
 
     fn emit_struct_field<F>(
         &mut self,
         f_name: &str,
-        _f_idx: usize,
+        f_idx: usize,
         f: F,
     ) -> CborResult<()>
     where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
+        if self.state == State::Newtype {
+            self.state = State::Normal;
+            if f_name == "_field0" {
+                return f(self);
+            }
+            // Any other struct with one field is a map, as usual.
+            no_string_key!(self, Type::Map);
+            try!(self.write_num(5, 1));
+            let canonical = self.opts.canonical;
+            return self.write_counted(1, |e| {
+                if canonical {
+                    e.write_sorted(|e| e.emit_struct_field(f_name, f_idx, f))
+                } else {
+                    e.emit_struct_field(f_name, f_idx, f)
+                }
+            });
+        }
         no_string_key!(self);
-        if !self.byte_string && !self.is_tag_field(f_name) {
-            try!(self.emit_str(f_name));
+        if self.state == State::Shared && f_name == "value" {
+            return self.write_shared(f);
         }
-        f(self)
+        if self.state == State::Flatten {
+            return self.write_flat_part(f);
+        }
+        if self.state != State::Normal {
+            return f(self);
+        }
+        if self.tag_data && f_name == "__cbor_tag_encode_data" {
+            self.tag_data = false;
+            return f(self);
+        }
+        self.count_item();
+        if self.opts.canonical {
+            let key = try!(self.capture(|e| e.write_field_key(f_name)));
+            let val = try!(self.capture(f).map_err(|err| {
+                in_path(err, PathElem::Field(f_name))
+            }));
+            try!(self.open_entries("struct field")).push((key, val));
+            return Ok(());
+        }
+        try!(self.write_field_key(f_name));
+        f(self).map_err(|err| in_path(err, PathElem::Field(f_name)))
     }
 
     fn emit_tuple<F>(&mut self, len: usize, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self, Type::Array);
-        self.emit_seq(len, f)
+        self.write_seq(len, false, f)
     }
 
     fn emit_tuple_arg<F>(&mut self, idx: usize, f: F) -> CborResult<()>
@@ -367,7 +1656,7 @@ impl<W: io::Write> RustcEncoder for Encoder<W> {
     ) -> CborResult<()>
     where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self, Type::Array);
-        self.emit_seq(len, f)
+        self.write_seq(len, false, f)
     }
 
     fn emit_tuple_struct_arg<F>(&mut self, idx: usize, f: F) -> CborResult<()>
@@ -379,6 +1668,7 @@ impl<W: io::Write> RustcEncoder for Encoder<W> {
     fn emit_option<F>(&mut self, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self);
+        self.item = Item::Other;
         f(self)
     }
 
@@ -390,47 +1680,157 @@ impl<W: io::Write> RustcEncoder for Encoder<W> {
     fn emit_option_some<F>(&mut self, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self);
-        f(self)
+        match self.opts.options {
+            OptionFormat::Null => f(self),
+            OptionFormat::Array => {
+                self.write_seq(1, false, |e| e.emit_seq_elt(0, f))
+            }
+        }
     }
 
     fn emit_seq<F>(&mut self, len: usize, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self, Type::Array);
-        if self.byte_string {
-            try!(self.write_num(2, len as u64));
-            let v = f(self);
-            self.byte_string = false;
-            return v;
-        }
-        try!(self.write_num(4, len as u64));
-        f(self)
+        self.write_seq(len, true, f)
     }
 
-    fn emit_seq_elt<F>(&mut self, _idx: usize, f: F) -> CborResult<()>
+    fn emit_seq_elt<F>(&mut self, idx: usize, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self);
-        f(self)
+        self.count_item();
+        let buffered = match self.byte_seqs.last() {
+            Some(&Some(_)) => true,
+            _ => false,
+        };
+        if !buffered {
+            return f(self).map_err(|err| in_path(err, PathElem::Index(idx)));
+        }
+        // The items so far were `u8`s. Check that this one is too, and
+        // not just a value around one.
+        self.item = Item::Empty;
+        try!(f(self).map_err(|err| in_path(err, PathElem::Index(idx))));
+        let is_u8 = self.item == Item::Uint8;
+        // For the item of any enclosing sequence, this one is a sequence.
+        self.item = Item::Other;
+        if !is_u8 {
+            return self.flush_byte_seq();
+        }
+        Ok(())
     }
 
     fn emit_map<F>(&mut self, len: usize, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self, Type::Map);
+        self.item = Item::Other;
+        if self.state == State::Flatten {
+            self.state = State::Normal;
+            *self.flat_lens.last_mut().unwrap() += len as u64;
+            return self.write_counted(len, f);
+        }
         try!(self.write_num(5, len as u64));
-        f(self)
+        if self.opts.canonical {
+            return self.write_counted(len, |e| e.write_sorted(f));
+        }
+        self.write_counted(len, f)
     }
 
     fn emit_map_elt_key<F>(&mut self, _idx: usize, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self);
+        self.count_item();
+        if self.keys.len() == self.nkeys {
+            self.keys.push(String::new());
+        }
+        self.keys[self.nkeys].clear();
         self.emitting_key = true;
-        let r = f(self);
+        let r = if self.opts.canonical {
+            self.capture(f).and_then(|key| {
+                try!(self.open_entries("map key")).push((key, vec![]));
+                Ok(())
+            })
+        } else {
+            f(self)
+        };
         self.emitting_key = false;
+        if r.is_ok() {
+            // `emit_str` kept the key, for errors in the value.
+            self.nkeys += 1;
+        }
         r
     }
 
     fn emit_map_elt_val<F>(&mut self, _idx: usize, f: F) -> CborResult<()>
             where F: FnOnce(&mut Encoder<W>) -> CborResult<()> {
         no_string_key!(self);
-        f(self)
+        let r = if self.opts.canonical {
+            self.capture(f).and_then(|val| {
+                let entries = try!(self.open_entries("map value"));
+                match entries.last_mut() {
+                    Some(entry) => entry.1 = val,
+                    None => return Err(out_of_order("map value")),
+                }
+                Ok(())
+            })
+        } else {
+            f(self)
+        };
+        if self.nkeys == 0 {
+            return r;
+        }
+        self.nkeys -= 1;
+        r.map_err(|err| {
+            in_path(err, PathElem::Key(&self.keys[self.nkeys]))
+        })
+    }
+}
+
+/// Returns the values of `items`, which are all unsigned integers that fit
+/// in a `u8`.
+fn uint8_values(items: &[u8]) -> Vec<u8> {
+    let mut values = Vec::with_capacity(items.len());
+    let mut i = 0;
+    while i < items.len() {
+        if items[i] == 24 {
+            i += 1;
+        }
+        values.push(items[i]);
+        i += 1;
+    }
+    values
+}
+
+/// Returns the bits of the half precision float that is equal to `v`, if
+/// there is one. Every NaN is mapped to the same quiet NaN.
+fn f16_bits(v: f32) -> Option<u16> {
+    let bits: u32 = unsafe { transmute(v) };
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7fffff;
+    if exp == 0xff {
+        return Some(if mant == 0 { sign | 0x7c00 } else { 0x7e00 });
+    }
+    if exp == 0 {
+        // Zero, or a subnormal far too small for a half.
+        return if mant == 0 { Some(sign) } else { None };
+    }
+    let e = exp - 127;
+    if e > 15 {
+        None
+    } else if e >= -14 {
+        if mant & 0x1fff != 0 {
+            return None;
+        }
+        Some(sign | (((e + 15) as u16) << 10) | (mant >> 13) as u16)
+    } else if e >= -24 {
+        // This can only be a subnormal half, whose value is its mantissa
+        // times 2^-24.
+        let shift = -(e + 1);
+        let full = mant | 0x800000;
+        if full & ((1 << shift) - 1) != 0 {
+            return None;
+        }
+        Some(sign | (full >> shift) as u16)
+    } else {
+        None
     }
 }
@@ -0,0 +1,290 @@
+//! Length prefixed messages, for sending CBOR over streams like TCP
+//! connections.
+//!
+//! A data item can't be read without parsing all of it, so a service
+//! reading from a socket can't tell where a message ends until it has
+//! arrived in full. These functions prefix every message with its length,
+//! in one of two ways (see `Framing`). A stream of messages framed with
+//! `Framing::ByteString` is itself a sequence of CBOR data items, so
+//! generic CBOR tools can still read it.
+//!
+//! When reading, every message must be at most `max_len` bytes long, so
+//! a peer can't make the reader buffer more than it's willing to.
+//!
+//! `write_frame` and `read_frame` work on encoded messages, e.g., from an
+//! `Encoder` with non-default options. `write_message` and `read_message`
+//! encode and decode the message as well. `write_message_with` and
+//! `read_message_with` also pass every message through a `PayloadTransform`
+//! (e.g., encryption) between encoding it and framing it.
+//!
+//! # Example
+//!
+//! ```rust
+//! use cbor::framed::{self, Framing};
+//!
+//! let mut stream = vec![];
+//! framed::write_message(&mut stream, Framing::ByteString, &(1, "a"))
+//!     .unwrap();
+//! framed::write_message(&mut stream, Framing::ByteString, &(2, "b"))
+//!     .unwrap();
+//! assert_eq!(&stream[..5], &[0x44, 0x82, 0x01, 0x61, b'a'][..]);
+//!
+//! let mut rdr = &stream[..];
+//! let first: Option<(u8, String)> =
+//!     framed::read_message(&mut rdr, Framing::ByteString, 1024).unwrap();
+//! assert_eq!(first, Some((1, "a".to_string())));
+//! let second: Option<(u8, String)> =
+//!     framed::read_message(&mut rdr, Framing::ByteString, 1024).unwrap();
+//! assert_eq!(second, Some((2, "b".to_string())));
+//! let end: Option<(u8, String)> =
+//!     framed::read_message(&mut rdr, Framing::ByteString, 1024).unwrap();
+//! assert_eq!(end, None);
+//! ```
+
+use std::io::{self, Read, Write};
+
+use byteorder::{ByteOrder, BigEndian};
+use rustc_serialize::{Decodable, Encodable};
+
+use {
+    CborError, CborResult, Encoder, ReadError, Type, eof, errat,
+    from_slice_exact, write_head,
+};
+
+/// How the length of a message is written before it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// The message is the contents of a definite length byte string (i.e.,
+    /// CBOR data in a byte string).
+    ByteString,
+    /// The message follows its length in bytes, which is written as a 4
+    /// byte big-endian integer. Messages can't be longer than 4 GiB.
+    Length32,
+}
+
+/// A reversible transformation of encoded messages, such as encryption.
+///
+/// `seal` is applied to each message after it is encoded, and `open` to
+/// each one before it is decoded, so `open` must undo `seal`. Both take
+/// `&mut self`, so a transform can keep state across messages, e.g., a
+/// counter for the nonce of an AEAD cipher.
+///
+/// # Example
+///
+/// This "cipher" is only an illustration: it provides no security at all.
+///
+/// ```rust
+/// use cbor::{CborError, CborResult, ReadError};
+/// use cbor::framed::{self, Framing, PayloadTransform};
+///
+/// struct Xor(u8);
+///
+/// impl PayloadTransform for Xor {
+///     fn seal(&mut self, mut msg: Vec<u8>) -> CborResult<Vec<u8>> {
+///         for b in &mut msg {
+///             *b ^= self.0;
+///         }
+///         msg.push(self.0);
+///         Ok(msg)
+///     }
+///
+///     fn open(&mut self, mut msg: Vec<u8>) -> CborResult<Vec<u8>> {
+///         if msg.pop() != Some(self.0) {
+///             let err = ReadError::Other("wrong key".to_string());
+///             return Err(CborError::Decode(err));
+///         }
+///         for b in &mut msg {
+///             *b ^= self.0;
+///         }
+///         Ok(msg)
+///     }
+/// }
+///
+/// let mut stream = vec![];
+/// framed::write_message_with(&mut stream, Framing::ByteString,
+///                            &mut Xor(0x55), &"secret").unwrap();
+/// assert!(!stream.windows(6).any(|w| w == b"secret"));
+///
+/// let msg: Option<String> = framed::read_message_with(
+///     &mut &stream[..], Framing::ByteString, 1024, &mut Xor(0x55)).unwrap();
+/// assert_eq!(msg, Some("secret".to_string()));
+/// assert!(framed::read_message_with::<_, String, _>(
+///     &mut &stream[..], Framing::ByteString, 1024, &mut Xor(0x66)).is_err());
+/// ```
+pub trait PayloadTransform {
+    /// Transforms an encoded message before it is written.
+    fn seal(&mut self, msg: Vec<u8>) -> CborResult<Vec<u8>>;
+
+    /// Undoes `seal` on a message that was read, before it is decoded.
+    fn open(&mut self, msg: Vec<u8>) -> CborResult<Vec<u8>>;
+}
+
+/// Writes an encoded message to `wtr`, prefixed with its length.
+pub fn write_frame<W: Write>(wtr: &mut W, framing: Framing, msg: &[u8])
+                            -> CborResult<()> {
+    let len = msg.len() as u64;
+    let mut head = vec![];
+    match framing {
+        Framing::ByteString => write_head(&mut head, 2, len),
+        Framing::Length32 => {
+            if len > ::std::u32::MAX as u64 {
+                return Err(CborError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "message is too long for a 4 byte length")));
+            }
+            let mut buf = [0; 4];
+            <BigEndian as ByteOrder>::write_u32(&mut buf, len as u32);
+            head.extend(buf.iter().cloned());
+        }
+    }
+    try!(wtr.write_all(&head));
+    try!(wtr.write_all(msg));
+    Ok(())
+}
+
+/// Reads the next message that was written with `write_frame` from `rdr`,
+/// without its length.
+///
+/// `None` is returned if `rdr` is at EOF before the message starts. Byte
+/// offsets in errors are relative to the start of the message's length.
+///
+/// Nothing past the message is read from `rdr`, so the next message can be
+/// read from it afterwards. A message that is longer than `max_len` bytes
+/// is a `ReadError::TooLong` error, which is returned before any of the
+/// message is read. Otherwise, the length still isn't trusted: the message
+/// is read in pieces instead of being allocated up front, so a bogus length
+/// can only make this read until EOF.
+pub fn read_frame<R: Read>(rdr: &mut R, framing: Framing, max_len: u64)
+                          -> CborResult<Option<Vec<u8>>> {
+    let mut head = [0; 9];
+    if try!(read_full(rdr, &mut head[..1])) == 0 {
+        return Ok(None);
+    }
+    let width = match framing {
+        Framing::ByteString => {
+            let first = head[0];
+            if first >> 5 != 2 {
+                return Err(errat(ReadError::miss(Type::Bytes, first), 0));
+            }
+            match first & 0b000_11111 {
+                n @ 0...23 => {
+                    return read_msg(rdr, n as u64, 1, max_len).map(Some);
+                }
+                24 => 1,
+                25 => 2,
+                26 => 4,
+                27 => 8,
+                v => {
+                    let err = ReadError::InvalidAddValue {
+                        ty: Type::Bytes,
+                        val: v,
+                    };
+                    return Err(errat(err, 0));
+                }
+            }
+        }
+        Framing::Length32 => 3,
+    };
+    let n = try!(read_full(rdr, &mut head[1..1 + width]));
+    if n < width {
+        return Err(eof(width - n, 1 + n));
+    }
+    let len = match framing {
+        Framing::ByteString => &head[1..1 + width],
+        Framing::Length32 => &head[..4],
+    }.iter().fold(0, |len, &b| (len << 8) | b as u64);
+    let at = match framing {
+        Framing::ByteString => 1 + width,
+        Framing::Length32 => 4,
+    };
+    read_msg(rdr, len, at, max_len).map(Some)
+}
+
+/// Encodes `v` and writes it to `wtr` as a message, prefixed with its
+/// length.
+pub fn write_message<W, T>(wtr: &mut W, framing: Framing, v: &T)
+                          -> CborResult<()>
+        where W: Write, T: Encodable {
+    let mut enc = Encoder::from_memory();
+    try!(enc.encode(&[v]));
+    write_frame(wtr, framing, &enc.into_bytes())
+}
+
+/// Reads the next message from `rdr` (see `read_frame`) and decodes it,
+/// which must be exactly one data item.
+///
+/// `None` is returned if `rdr` is at EOF before the message starts. Byte
+/// offsets in decoding errors are relative to the start of the data item.
+pub fn read_message<R, T>(rdr: &mut R, framing: Framing, max_len: u64)
+                         -> CborResult<Option<T>>
+        where R: Read, T: Decodable {
+    match try!(read_frame(rdr, framing, max_len)) {
+        None => Ok(None),
+        Some(msg) => from_slice_exact(&msg).map(Some),
+    }
+}
+
+/// Like `write_message`, but the encoded message is passed through
+/// `transform.seal` before it is written.
+pub fn write_message_with<W, T, P>(wtr: &mut W, framing: Framing,
+                                   transform: &mut P, v: &T)
+                                  -> CborResult<()>
+        where W: Write, T: Encodable, P: PayloadTransform {
+    let mut enc = Encoder::from_memory();
+    try!(enc.encode(&[v]));
+    let msg = try!(transform.seal(enc.into_bytes()));
+    write_frame(wtr, framing, &msg)
+}
+
+/// Like `read_message`, but the message is passed through `transform.open`
+/// before it is decoded.
+///
+/// Byte offsets in decoding errors are relative to the start of the data
+/// item that `open` returns.
+pub fn read_message_with<R, T, P>(rdr: &mut R, framing: Framing,
+                                  max_len: u64, transform: &mut P)
+                                 -> CborResult<Option<T>>
+        where R: Read, T: Decodable, P: PayloadTransform {
+    match try!(read_frame(rdr, framing, max_len)) {
+        None => Ok(None),
+        Some(msg) => {
+            let msg = try!(transform.open(msg));
+            from_slice_exact(&msg).map(Some)
+        }
+    }
+}
+
+/// Reads a message of `len` bytes that starts at byte offset `at`, unless
+/// it is longer than `max_len`.
+fn read_msg<R: Read>(rdr: &mut R, len: u64, at: usize, max_len: u64)
+                    -> CborResult<Vec<u8>> {
+    if len > max_len {
+        let err = ReadError::TooLong {
+            ty: Type::Bytes,
+            len: len,
+            max: max_len,
+        };
+        return Err(errat(err, 0));
+    }
+    let mut msg = vec![];
+    try!(rdr.take(len).read_to_end(&mut msg));
+    if (msg.len() as u64) < len {
+        return Err(eof((len - msg.len() as u64) as usize, at + msg.len()));
+    }
+    Ok(msg)
+}
+
+/// Reads into `buf` until it is full or EOF is found, and returns the
+/// number of bytes read.
+fn read_full<R: Read>(rdr: &mut R, buf: &mut [u8]) -> CborResult<usize> {
+    let mut nread = 0;
+    while nread < buf.len() {
+        match rdr.read(&mut buf[nread..]) {
+            Ok(0) => break,
+            Ok(n) => nread += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(CborError::Io(err)),
+        }
+    }
+    Ok(nread)
+}
@@ -0,0 +1,121 @@
+//! Entry points for fuzzing this crate.
+//!
+//! Each function takes arbitrary bytes and runs them through some part of
+//! the crate. Errors are expected and ignored, but a panic (or a crash, or
+//! running out of memory) is always a bug. The functions are deterministic,
+//! so they can be called directly from a `cargo fuzz` target:
+//!
+//! ```rust,ignore
+//! fuzz_target!(|data: &[u8]| cbor::fuzz::roundtrip(data));
+//! ```
+//!
+//! Decoders are given limits that are proportional to the size of the
+//! input, since fuzzing without them mostly finds ways to allocate a lot of
+//! memory or nest deeply enough to overflow the stack.
+
+use std::io::Cursor;
+
+use rustc_serialize::json::ToJson;
+
+use {
+    annotate, transcode_json, Cbor, Decoded, Decoder, Encoder, LazyReader,
+    SliceRead, StreamingDecoder,
+};
+
+/// The maximum nesting depth allowed while fuzzing.
+const MAX_DEPTH: usize = 128;
+
+/// The number of bytes of input to annotate.
+const MAX_ANNOTATE: usize = 1024;
+
+/// Reads `data` with every reader in this crate.
+///
+/// This covers `Decoder` (both as abstract syntax and into Rust values),
+/// `StreamingDecoder` (with the data split in two), `LazyReader`,
+/// `annotate` (on the first kilobyte only) and `transcode_json`.
+pub fn decode_any(data: &[u8]) {
+    for item in decoder(data).items() {
+        match item {
+            Ok(v) => { let _ = v.to_json(); }
+            Err(_) => break,
+        }
+    }
+    for item in decoder(data).decode::<Cbor>() {
+        if item.is_err() {
+            break;
+        }
+    }
+    for item in decoder(data).decode::<(String, Vec<u64>)>() {
+        if item.is_err() {
+            break;
+        }
+    }
+
+    let mut stream = StreamingDecoder::new().max_depth(MAX_DEPTH);
+    let mid = data.len() / 2;
+    let _ = stream.feed(&data[..mid]);
+    while let Ok(Decoded::Item(_)) = stream.next_item() {}
+    let _ = stream.feed(&data[mid..]);
+    while let Ok(Decoded::Item(_)) = stream.next_item() {}
+
+    if let Ok(mut lazy) = LazyReader::new(Cursor::new(data)) {
+        let keys: Vec<String> = lazy.keys().map(|k| k.to_string()).collect();
+        for key in keys {
+            let _ = lazy.get_item(&key);
+        }
+    }
+
+    // The indentation of an annotated dump grows with the nesting depth,
+    // so its size is quadratic in the size of the input.
+    let _ = annotate(&data[..::std::cmp::min(data.len(), MAX_ANNOTATE)]);
+    let _ = transcode_json(data, &mut vec![]);
+}
+
+/// Decodes `data` and checks that every data item survives being
+/// re-encoded and decoded again, both as CBOR and as diagnostic notation.
+///
+/// # Panics
+///
+/// This panics if a data item that was decoded successfully doesn't
+/// round trip.
+pub fn roundtrip(data: &[u8]) {
+    for item in decoder(data).items() {
+        let v = match item {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+        // Half precision floats are encoded with more precision, so only
+        // the encoding after one round trip has to be stable.
+        let bytes = encode(&v);
+        let again = match decoder(&bytes).items().next() {
+            Some(Ok(again)) => again,
+            r => panic!("{:?} encoded as {:?}, which decodes as {:?}",
+                        v, bytes, r),
+        };
+        assert_eq!(encode(&again), bytes);
+
+        // Diagnostic notation doesn't show the precision of floats, so
+        // only the notation itself has to be stable.
+        let diag = v.to_string();
+        match Cbor::from_diagnostic(&diag) {
+            Ok(parsed) => assert_eq!(parsed.to_string(), diag),
+            Err(err) => panic!("can't parse {:?}: {}", diag, err),
+        }
+    }
+}
+
+fn decoder(data: &[u8]) -> Decoder<SliceRead> {
+    // Every string byte, array item and map entry takes at least one byte
+    // of input, so these limits never reject well formed data.
+    let max = data.len() as u64;
+    Decoder::from_slice(data).max_depth(MAX_DEPTH)
+                             .max_size(max)
+                             .max_items(max)
+                             .faithful_integers(true)
+}
+
+fn encode(v: &Cbor) -> Vec<u8> {
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[v]).unwrap();
+    enc.into_bytes()
+}
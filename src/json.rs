@@ -1,7 +1,15 @@
-use rustc_serialize::base64::{STANDARD, ToBase64};
+use std::io::Write;
+use std::str;
+
+use rustc_serialize::base64::{STANDARD, URL_SAFE, ToBase64};
+use rustc_serialize::hex::ToHex;
 use rustc_serialize::json::{Json, ToJson};
 
-use {Cbor, CborFloat, CborSigned, CborUnsigned};
+use lexer::{Lexer, Token};
+use {
+    Cbor, CborError, CborFloat, CborResult, CborSigned, CborUnsigned,
+    ReadError, Type, DEFAULT_MAX_DEPTH,
+};
 
 /// A trait for converting values to CBOR.
 pub trait ToCbor {
@@ -9,38 +17,62 @@ pub trait ToCbor {
     fn to_cbor(&self) -> Cbor;
 }
 
+/// Converts CBOR to JSON.
+///
+/// Byte strings become base64 strings with padding, tags are dropped, and
+/// `undefined` and simple values become `null`. Use `to_json_rfc8949` for
+/// the conversion recommended by RFC 8949.
 impl ToJson for Cbor {
     fn to_json(&self) -> Json {
-        match *self {
-            Cbor::Break => unimplemented!(),
-            Cbor::Undefined => Json::Null,
-            Cbor::Null => Json::Null,
-            Cbor::Bool(v) => Json::Boolean(v),
-            Cbor::Unsigned(v) => Json::U64(v.to_u64().unwrap()),
-            Cbor::Signed(v) => Json::I64(v.to_i64().unwrap()),
-            Cbor::Float(v) => Json::F64(v.to_f64().unwrap()),
-            Cbor::Bytes(ref v) => Json::String(v.to_base64(STANDARD)),
-            Cbor::Unicode(ref v) => Json::String(v.clone()),
-            Cbor::Array(ref v) => Json::Array(
-                v.iter().map(|v| v.to_json()).collect()
-            ),
-            Cbor::Map(ref v) => Json::Object(
-                v.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()
-            ),
-            Cbor::Tag(ref v) => v.data.to_json(),
-        }
+        to_json(self, Encoding::Base64, false)
     }
 }
 
+/// Converts CBOR to JSON following the rules in RFC 8949, section 6.1.
+///
+/// Namely:
+///
+/// * Byte strings become base64url strings without padding, unless they are
+///   tagged with an expected encoding (tags 21, 22 and 23 for base64url,
+///   base64 and base16).
+/// * Bignums (tags 2 and 3) become base64url strings. Negative bignums are
+///   prefixed with a `~`.
+/// * Any other tag is dropped and its data item is converted.
+/// * Floats that are NaN or infinite, `undefined` and other simple values
+///   become `null`.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate cbor;
+/// # extern crate rustc_serialize;
+/// # fn main() {
+/// use cbor::{Cbor, CborBytes};
+/// use rustc_serialize::json::{Json, ToJson};
+///
+/// let bytes = Cbor::Bytes(CborBytes(vec![0xfb, 0xff]));
+/// assert_eq!(cbor::to_json_rfc8949(&bytes),
+///            Json::String("-_8".to_string()));
+/// assert_eq!(bytes.to_json(), Json::String("+/8=".to_string()));
+/// # }
+/// ```
+pub fn to_json_rfc8949(v: &Cbor) -> Json {
+    to_json(v, Encoding::Base64Url, true)
+}
+
+/// Converts JSON to CBOR following the rules in RFC 8949, section 6.2.
+///
+/// Integers are represented with the smallest CBOR type that can hold them.
 impl ToCbor for Json {
     fn to_cbor(&self) -> Cbor {
-        // TODO: Use the smallest sized number types possible.
-        // Maybe factor out some of the code in the encoder? ---AG
         match *self {
             Json::Null => Cbor::Null,
             Json::Boolean(v) => Cbor::Bool(v),
-            Json::U64(v) => Cbor::Unsigned(CborUnsigned::UInt64(v)),
-            Json::I64(v) => Cbor::Signed(CborSigned::Int64(v)),
+            Json::U64(v) => Cbor::Unsigned(CborUnsigned::smallest(v)),
+            Json::I64(v) if v >= 0 => {
+                Cbor::Unsigned(CborUnsigned::smallest(v as u64))
+            }
+            Json::I64(v) => Cbor::Signed(CborSigned::smallest(v)),
             Json::F64(v) => Cbor::Float(CborFloat::Float64(v)),
             Json::String(ref v) => Cbor::Unicode(v.clone()),
             Json::Array(ref v) => Cbor::Array(
@@ -52,3 +84,295 @@ impl ToCbor for Json {
         }
     }
 }
+
+/// The encoding used for byte strings in JSON.
+#[derive(Clone, Copy)]
+enum Encoding {
+    Base64Url,
+    Base64,
+    Base16,
+}
+
+/// Converts `v` to JSON, writing byte strings with `enc`. Tags are only
+/// interpreted if `rfc` is true.
+fn to_json(v: &Cbor, enc: Encoding, rfc: bool) -> Json {
+    match *v {
+        Cbor::Break | Cbor::Undefined | Cbor::Null | Cbor::Simple(_) => {
+            Json::Null
+        }
+        Cbor::Bool(v) => Json::Boolean(v),
+        // Below `i64::MIN`, so only a float can hold it (inexactly).
+        Cbor::Wide(v) if v.negative && v.arg > ::std::i64::MAX as u64 => {
+            Json::F64(-1.0 - v.arg as f64)
+        }
+        Cbor::Wide(v) => to_json(&v.to_cbor(), enc, rfc),
+        Cbor::Unsigned(v) => Json::U64(v.to_u64().unwrap()),
+        Cbor::Signed(v) => Json::I64(v.to_i64().unwrap()),
+        Cbor::Float(v) => {
+            let v = v.to_f64().unwrap();
+            if v.is_nan() || v.is_infinite() {
+                Json::Null
+            } else {
+                Json::F64(v)
+            }
+        }
+        Cbor::Bytes(ref v) => Json::String(encode(v, enc)),
+        Cbor::Unicode(ref v) => Json::String(v.clone()),
+        Cbor::Array(ref v) => Json::Array(
+            v.iter().map(|v| to_json(v, enc, rfc)).collect()
+        ),
+        Cbor::Map(ref v) => Json::Object(
+            v.iter().map(|(k, v)| (k.clone(), to_json(v, enc, rfc)))
+                    .collect()
+        ),
+        Cbor::Tag(ref v) if !rfc => to_json(&v.data, enc, rfc),
+        Cbor::Tag(ref v) => match (v.tag, &*v.data) {
+            (2, &Cbor::Bytes(ref b)) => Json::String(b.to_base64(URL_SAFE)),
+            (3, &Cbor::Bytes(ref b)) => {
+                Json::String(format!("~{}", b.to_base64(URL_SAFE)))
+            }
+            (21, data) => to_json(data, Encoding::Base64Url, rfc),
+            (22, data) => to_json(data, Encoding::Base64, rfc),
+            (23, data) => to_json(data, Encoding::Base16, rfc),
+            (_, data) => to_json(data, enc, rfc),
+        },
+    }
+}
+
+/// Converts CBOR data to JSON without building any intermediate values.
+///
+/// Each top-level data item in `bytes` is written to `wtr` as compact JSON
+/// followed by a new line. The conversion follows the same rules as
+/// `to_json_rfc8949`, except that map keys must be Unicode strings
+/// and integers are never truncated. Only the data item currently being
+/// converted needs to fit in memory as tokens are borrowed from `bytes`,
+/// which makes this suitable for converting very large (e.g., memory
+/// mapped) files.
+///
+/// The conversion is recursive, so arrays, maps and tags nested more than
+/// 128 deep return a `ReadError::TooDeep` error, as with `Decoder`.
+///
+/// # Example
+///
+/// ```rust
+/// // [1, {"a": h'ff'}]
+/// let bytes = [0x82, 0x01, 0xa1, 0x61, b'a', 0x41, 0xff];
+/// let mut json = vec![];
+/// cbor::transcode_json(&bytes, &mut json).unwrap();
+/// assert_eq!(json, b"[1,{\"a\":\"_w\"}]\n");
+/// ```
+pub fn transcode_json<W: Write>(bytes: &[u8], wtr: W) -> CborResult<()> {
+    let mut trans = Transcoder {
+        bytes: bytes,
+        lex: Lexer::new(bytes),
+        wtr: wtr,
+        depth: 0,
+    };
+    while let Some(result) = trans.lex.next() {
+        let (at, tok) = try!(result);
+        try!(trans.value(at, tok, Encoding::Base64Url));
+        try!(trans.wtr.write_all(b"\n"));
+    }
+    Ok(())
+}
+
+struct Transcoder<'a, W> {
+    bytes: &'a [u8],
+    lex: Lexer<'a>,
+    wtr: W,
+    // the number of arrays, maps and tags being converted
+    depth: usize,
+}
+
+impl<'a, W: Write> Transcoder<'a, W> {
+    fn next(&mut self) -> CborResult<(usize, Token<'a>)> {
+        match self.lex.next() {
+            Some(result) => result,
+            None => {
+                let end = self.lex.offset();
+                Err(CborError::AtOffset {
+                    kind: ReadError::UnexpectedEOF { needed: 1, offset: end },
+                    offset: end,
+                })
+            }
+        }
+    }
+
+    fn value(&mut self, at: usize, tok: Token<'a>, enc: Encoding)
+            -> CborResult<()> {
+        match tok {
+            Token::BeginArray(_) | Token::BeginMap(_) | Token::Tag(_) => {}
+            _ => return self.convert(at, tok, enc),
+        }
+        if self.depth >= DEFAULT_MAX_DEPTH {
+            return Err(CborError::AtOffset {
+                kind: ReadError::TooDeep { max: DEFAULT_MAX_DEPTH },
+                offset: at,
+            });
+        }
+        self.depth += 1;
+        let r = self.convert(at, tok, enc);
+        self.depth -= 1;
+        r
+    }
+
+    fn convert(&mut self, at: usize, tok: Token<'a>, enc: Encoding)
+              -> CborResult<()> {
+        match tok {
+            Token::UInt(n) => try!(write!(self.wtr, "{}", n)),
+            Token::NegInt(::std::u64::MAX) => {
+                try!(write!(self.wtr, "-18446744073709551616"))
+            }
+            Token::NegInt(n) => try!(write!(self.wtr, "-{}", n + 1)),
+            Token::Bytes(_) | Token::BeginBytes => {
+                let bytes = try!(self.bytes(at, tok));
+                try!(self.string(&encode(&bytes, enc)));
+            }
+            Token::Text(_) | Token::BeginText => {
+                let s = try!(self.text(at, tok));
+                try!(self.string(&s));
+            }
+            Token::BeginArray(len) => {
+                try!(self.wtr.write_all(b"["));
+                let mut i = 0;
+                while let Some((at, tok)) = try!(self.elem(len, i)) {
+                    if i > 0 {
+                        try!(self.wtr.write_all(b","));
+                    }
+                    try!(self.value(at, tok, enc));
+                    i += 1;
+                }
+                try!(self.wtr.write_all(b"]"));
+            }
+            Token::BeginMap(len) => {
+                try!(self.wtr.write_all(b"{"));
+                let mut i = 0;
+                while let Some((at, tok)) = try!(self.elem(len, i)) {
+                    if i > 0 {
+                        try!(self.wtr.write_all(b","));
+                    }
+                    let key = try!(self.text(at, tok));
+                    try!(self.string(&key));
+                    try!(self.wtr.write_all(b":"));
+                    let (at, tok) = try!(self.next());
+                    try!(self.value(at, tok, enc));
+                    i += 1;
+                }
+                try!(self.wtr.write_all(b"}"));
+            }
+            Token::Tag(tag) => {
+                let (at, tok) = try!(self.next());
+                match (tag, tok) {
+                    (2, Token::Bytes(_)) | (2, Token::BeginBytes) => {
+                        let bytes = try!(self.bytes(at, tok));
+                        try!(self.string(&bytes.to_base64(URL_SAFE)));
+                    }
+                    (3, Token::Bytes(_)) | (3, Token::BeginBytes) => {
+                        let bytes = try!(self.bytes(at, tok));
+                        try!(self.string(
+                            &format!("~{}", bytes.to_base64(URL_SAFE))));
+                    }
+                    (21, _) => {
+                        try!(self.value(at, tok, Encoding::Base64Url))
+                    }
+                    (22, _) => try!(self.value(at, tok, Encoding::Base64)),
+                    (23, _) => try!(self.value(at, tok, Encoding::Base16)),
+                    (_, _) => try!(self.value(at, tok, enc)),
+                }
+            }
+            Token::Float(v) => {
+                try!(write!(self.wtr, "{}", to_json(
+                    &Cbor::Float(CborFloat::Float64(v)), enc, true)))
+            }
+            Token::Bool(v) => try!(write!(self.wtr, "{}", v)),
+            Token::Null | Token::Undefined | Token::Simple(_) => {
+                try!(self.wtr.write_all(b"null"))
+            }
+            Token::Break => return Err(CborError::AtOffset {
+                kind: ReadError::UnexpectedBreak { offset: at },
+                offset: at,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Returns the next element of an array or map with `len` elements, or
+    /// `None` if there are no more. Indefinite length containers end with a
+    /// break. Each map entry counts as one element.
+    fn elem(&mut self, len: Option<usize>, i: usize)
+           -> CborResult<Option<(usize, Token<'a>)>> {
+        match len {
+            Some(len) if i >= len => Ok(None),
+            Some(_) => self.next().map(Some),
+            None => match try!(self.next()) {
+                (_, Token::Break) => Ok(None),
+                v => Ok(Some(v)),
+            },
+        }
+    }
+
+    /// Reads a byte string, which may be made up of chunks.
+    fn bytes(&mut self, at: usize, tok: Token<'a>) -> CborResult<Vec<u8>> {
+        self.chunks(at, tok, Type::Bytes)
+    }
+
+    /// Reads a Unicode string, which may be made up of chunks.
+    fn text(&mut self, at: usize, tok: Token<'a>) -> CborResult<String> {
+        let buf = try!(self.chunks(at, tok, Type::Unicode));
+        String::from_utf8(buf).map_err(|err| CborError::AtOffset {
+            kind: ReadError::InvalidUtf8(err.utf8_error()),
+            offset: at,
+        })
+    }
+
+    fn chunks(&mut self, at: usize, tok: Token<'a>, ty: Type)
+             -> CborResult<Vec<u8>> {
+        let mut buf = vec![];
+        let (indefinite, mut at, mut tok) = match tok {
+            Token::BeginBytes | Token::BeginText => {
+                let (at, tok) = try!(self.next());
+                (true, at, tok)
+            }
+            tok => (false, at, tok),
+        };
+        loop {
+            match (ty, tok) {
+                (Type::Bytes, Token::Bytes(b))
+                | (Type::Unicode, Token::Text(b)) => {
+                    buf.extend(b.iter().cloned())
+                }
+                (_, Token::Break) if indefinite => return Ok(buf),
+                _ => {
+                    let kind = match Type::from_desc(self.bytes[at]) {
+                        Ok(got) => ReadError::TypeMismatch {
+                            expected: ty, got: got,
+                        },
+                        Err(err) => err,
+                    };
+                    return Err(CborError::AtOffset {
+                        kind: kind, offset: at,
+                    });
+                }
+            }
+            if !indefinite {
+                return Ok(buf);
+            }
+            let (next_at, next_tok) = try!(self.next());
+            at = next_at;
+            tok = next_tok;
+        }
+    }
+
+    fn string(&mut self, s: &str) -> CborResult<()> {
+        try!(write!(self.wtr, "{}", Json::String(s.to_string())));
+        Ok(())
+    }
+}
+
+fn encode(bytes: &[u8], enc: Encoding) -> String {
+    match enc {
+        Encoding::Base64Url => bytes.to_base64(URL_SAFE),
+        Encoding::Base64 => bytes.to_base64(STANDARD),
+        Encoding::Base16 => bytes.to_hex(),
+    }
+}
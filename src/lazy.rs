@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use rustc_serialize::Decodable;
+
+use {Cbor, CborError, CborResult, Decoder, ReadError, Type};
+
+/// Reads entries of a top-level CBOR map on demand.
+///
+/// When a `LazyReader` is created, it scans the map once to record the key
+/// and byte range of every entry. The values are skipped over without
+/// decoding them (byte and Unicode strings are skipped with `seek`), so
+/// only the keys need to fit in memory. Afterwards, any entry can be
+/// decoded by seeking straight to it.
+///
+/// This is useful for very large documents of which only a few parts are
+/// needed.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::Cursor;
+/// use cbor::LazyReader;
+///
+/// // {"a": [1, 2], "b": "x"}
+/// let bytes = vec![0xa2, 0x61, b'a', 0x82, 0x01, 0x02, 0x61, b'b',
+///                  0x61, b'x'];
+/// let mut lazy = LazyReader::new(Cursor::new(bytes)).unwrap();
+/// assert_eq!(lazy.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+///
+/// let b: Option<String> = lazy.get("b").unwrap();
+/// assert_eq!(b, Some("x".to_string()));
+/// let a: Option<Vec<u32>> = lazy.get("a").unwrap();
+/// assert_eq!(a, Some(vec![1, 2]));
+/// ```
+pub struct LazyReader<R> {
+    rdr: R,
+    // Every entry's key with the byte offset and length of its value, in
+    // the order they appear in the map.
+    entries: Vec<(String, u64, u64)>,
+    // Maps a key to its position in `entries`. If a key is repeated, the
+    // last entry wins.
+    index: HashMap<String, usize>,
+}
+
+impl<R: Read + Seek> LazyReader<R> {
+    /// Create a new lazy reader for the map that starts at the current
+    /// position of `rdr`.
+    ///
+    /// The whole map is scanned, so this returns an error if it isn't well
+    /// formed or if any of its keys aren't Unicode strings.
+    pub fn new(rdr: R) -> CborResult<LazyReader<R>> {
+        let mut scan = Scanner { rdr: rdr, pos: 0, end: 0, first: 0 };
+        scan.pos = try!(scan.rdr.seek(SeekFrom::Current(0)));
+        // Seeking past the end of a stream isn't an error, so strings are
+        // checked against its length instead.
+        scan.end = try!(scan.rdr.seek(SeekFrom::End(0)));
+        try!(scan.rdr.seek(SeekFrom::Start(scan.pos)));
+        let at = scan.pos;
+        let len = match try!(scan.head()) {
+            (5, len) => len,
+            _ => {
+                let err = scan.mismatch(Type::Map);
+                return Err(scan.errat(err, at));
+            }
+        };
+        let mut entries = vec![];
+        let mut index = HashMap::new();
+        loop {
+            match len {
+                Some(len) if entries.len() as u64 >= len => break,
+                None if try!(scan.at_break()) => break,
+                _ => {}
+            }
+            let key = try!(scan.key());
+            let start = scan.pos;
+            try!(scan.skip());
+            index.insert(key.clone(), entries.len());
+            entries.push((key, start, scan.pos - start));
+        }
+        Ok(LazyReader { rdr: scan.rdr, entries: entries, index: index })
+    }
+
+    /// Returns the keys of the map in the order they appear in.
+    pub fn keys(&self) -> Keys {
+        Keys { it: self.entries.iter() }
+    }
+
+    /// Returns true if the map has an entry for `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Decodes the value of the entry for `key` into a Rust value.
+    ///
+    /// `None` is returned if there is no such entry. Byte offsets in errors
+    /// are relative to the start of the value.
+    pub fn get<D: Decodable>(&mut self, key: &str) -> CborResult<Option<D>> {
+        match try!(self.value(key)) {
+            None => Ok(None),
+            Some(mut dec) => match dec.decode().next() {
+                Some(result) => result.map(Some),
+                // The reader changed since the map was scanned.
+                None => Err(CborError::UnexpectedEOF),
+            },
+        }
+    }
+
+    /// Reads the value of the entry for `key` as CBOR abstract syntax.
+    ///
+    /// `None` is returned if there is no such entry. Byte offsets in errors
+    /// are relative to the start of the value.
+    pub fn get_item(&mut self, key: &str) -> CborResult<Option<Cbor>> {
+        match try!(self.value(key)) {
+            None => Ok(None),
+            Some(mut dec) => match dec.items().next() {
+                Some(result) => result.map(Some),
+                None => Err(CborError::UnexpectedEOF),
+            },
+        }
+    }
+
+    /// Unwraps this reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.rdr
+    }
+
+    fn value(&mut self, key: &str)
+            -> CborResult<Option<Decoder<io::Take<&mut R>>>> {
+        let (start, len) = match self.index.get(key) {
+            None => return Ok(None),
+            Some(&i) => (self.entries[i].1, self.entries[i].2),
+        };
+        try!(self.rdr.seek(SeekFrom::Start(start)));
+        Ok(Some(Decoder::from_reader((&mut self.rdr).take(len))))
+    }
+}
+
+/// An iterator over the keys of a `LazyReader` in order.
+pub struct Keys<'a> {
+    it: ::std::slice::Iter<'a, (String, u64, u64)>,
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.it.next().map(|e| &*e.0)
+    }
+}
+
+/// Walks over data items in a reader without decoding them.
+struct Scanner<R> {
+    rdr: R,
+    // the absolute position of `rdr`
+    pos: u64,
+    // the absolute position of the end of `rdr`
+    end: u64,
+    // the initial byte of the last head read
+    first: u8,
+}
+
+impl<R: Read + Seek> Scanner<R> {
+    fn errat(&self, err: ReadError, at: u64) -> CborError {
+        CborError::AtOffset { kind: err, offset: at as usize }
+    }
+
+    /// Returns an error for finding the last head read where a data item of
+    /// type `expected` should be.
+    fn mismatch(&self, expected: Type) -> ReadError {
+        match Type::from_desc(self.first) {
+            Ok(got) => {
+                ReadError::TypeMismatch { expected: expected, got: got }
+            }
+            Err(err) => err,
+        }
+    }
+
+    fn read_full(&mut self, buf: &mut [u8]) -> CborResult<()> {
+        let mut nread = 0;
+        while nread < buf.len() {
+            match try!(self.rdr.read(&mut buf[nread..])) {
+                0 => {
+                    let at = self.pos;
+                    return Err(self.errat(ReadError::UnexpectedEOF {
+                        needed: buf.len() - nread,
+                        offset: at as usize,
+                    }, at));
+                }
+                n => { nread += n; self.pos += n as u64; }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the head of a data item, returning its major type and its
+    /// argument. The argument is `None` for indefinite length items.
+    fn head(&mut self) -> CborResult<(u8, Option<u64>)> {
+        let at = self.pos;
+        let mut buf = [0; 8];
+        try!(self.read_full(&mut buf[..1]));
+        self.first = buf[0];
+        let (major, add) = (buf[0] >> 5, buf[0] & 0b000_11111);
+        let n = match add {
+            0...23 => return Ok((major, Some(add as u64))),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 if major >= 2 && major != 6 => return Ok((major, None)),
+            v => {
+                let err = match Type::from_desc(buf[0]) {
+                    Ok(ty) => ReadError::InvalidAddValue { ty: ty, val: v },
+                    Err(err) => err,
+                };
+                return Err(self.errat(err, at));
+            }
+        };
+        try!(self.read_full(&mut buf[..n]));
+        let arg = buf[..n].iter().fold(0, |arg, &b| (arg << 8) | b as u64);
+        Ok((major, Some(arg)))
+    }
+
+    /// Returns true and consumes the next byte if it is a break.
+    fn at_break(&mut self) -> CborResult<bool> {
+        let mut buf = [0];
+        try!(self.read_full(&mut buf));
+        if buf[0] == 0xff {
+            return Ok(true);
+        }
+        try!(self.rdr.seek(SeekFrom::Current(-1)));
+        self.pos -= 1;
+        Ok(false)
+    }
+
+    /// Reads a map key, which must be a definite length Unicode string.
+    fn key(&mut self) -> CborResult<String> {
+        let at = self.pos;
+        let len = match try!(self.head()) {
+            (3, Some(len)) => len,
+            _ => {
+                let err = self.mismatch(Type::Unicode);
+                return Err(self.errat(err, at));
+            }
+        };
+        // The length hasn't been checked against the data yet, so let the
+        // buffer grow as the key is read instead of allocating it up front.
+        let mut buf = vec![];
+        try!((&mut self.rdr).take(len).read_to_end(&mut buf));
+        self.pos += buf.len() as u64;
+        if (buf.len() as u64) < len {
+            let at = self.pos;
+            return Err(self.errat(ReadError::UnexpectedEOF {
+                needed: (len - buf.len() as u64) as usize,
+                offset: at as usize,
+            }, at));
+        }
+        String::from_utf8(buf).map_err(|err| {
+            self.errat(ReadError::InvalidUtf8(err.utf8_error()), at)
+        })
+    }
+
+    /// Skips the contents of a byte or Unicode string of `len` bytes, whose
+    /// head starts at `at`.
+    fn skip_string(&mut self, major: u8, len: u64, at: u64) -> CborResult<()> {
+        // Neither can be larger than any stream `Seek` can handle.
+        let max = ::std::i64::MAX as u64;
+        let end = match self.pos.checked_add(len) {
+            Some(end) if len <= max && end <= max => end,
+            _ => {
+                let ty = if major == 2 { Type::Bytes } else { Type::Unicode };
+                let err = ReadError::TooLong { ty: ty, len: len, max: max };
+                return Err(self.errat(err, at));
+            }
+        };
+        if end > self.end {
+            let at = self.end;
+            return Err(self.errat(ReadError::UnexpectedEOF {
+                needed: (end - at) as usize,
+                offset: at as usize,
+            }, at));
+        }
+        try!(self.rdr.seek(SeekFrom::Current(len as i64)));
+        self.pos = end;
+        Ok(())
+    }
+
+    /// Skips over one complete data item.
+    fn skip(&mut self) -> CborResult<()> {
+        // Each entry is the number of data items left to skip in an open
+        // container, or `None` if the container is terminated by a break.
+        let mut open: Vec<Option<u64>> = vec![];
+        loop {
+            let at = self.pos;
+            let (major, arg) = try!(self.head());
+            let pushed = match (major, arg) {
+                // A break is encoded as major type 7 with no argument.
+                (7, None) => match open.pop() {
+                    Some(None) => None,
+                    _ => return Err(self.errat(
+                        ReadError::UnexpectedBreak { offset: at as usize },
+                        at)),
+                },
+                (2, Some(len)) | (3, Some(len)) => {
+                    try!(self.skip_string(major, len, at));
+                    None
+                }
+                (4, Some(len)) => Some(Some(len)),
+                // Saturating is fine, since the reader would run out long
+                // before that many items are read.
+                (5, Some(len)) => Some(Some(len.saturating_mul(2))),
+                (6, _) => Some(Some(1)),
+                (_, None) => Some(None),
+                _ => None,
+            };
+            match pushed {
+                Some(Some(0)) => {}
+                Some(remaining) => { open.push(remaining); continue; }
+                None => {}
+            }
+            // A data item was completed, which may complete its parents.
+            loop {
+                let finished = match open.last_mut() {
+                    None => return Ok(()),
+                    Some(&mut None) => false,
+                    Some(&mut Some(ref mut n)) => { *n -= 1; *n == 0 }
+                };
+                if !finished {
+                    break;
+                }
+                open.pop();
+            }
+        }
+    }
+}
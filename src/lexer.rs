@@ -0,0 +1,488 @@
+use std::str;
+
+use byteorder::{ByteOrder, BigEndian};
+
+use {CborError, CborResult, ReadError, Type};
+
+/// A single lexical unit of CBOR data.
+///
+/// Tokens correspond to the *heads* of CBOR data items. No attempt is made
+/// to check that tokens nest properly (e.g., that an array actually contains
+/// as many items as its length says). That is left to the consumer of the
+/// tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token<'a> {
+    /// An unsigned integer (major type 0).
+    UInt(u64),
+    /// A negative integer (major type 1).
+    ///
+    /// This is the raw argument `n` as encoded. The integer it represents is
+    /// `-1 - n`, which does not necessarily fit in an `i64`.
+    NegInt(u64),
+    /// A byte string (major type 2), or one chunk of an indefinite length
+    /// byte string.
+    Bytes(&'a [u8]),
+    /// A Unicode string (major type 3), or one chunk of an indefinite length
+    /// Unicode string.
+    ///
+    /// The bytes are *not* checked for UTF-8 validity.
+    Text(&'a [u8]),
+    /// The start of an indefinite length byte string. Chunks follow as
+    /// `Bytes` tokens until a `Break`.
+    BeginBytes,
+    /// The start of an indefinite length Unicode string. Chunks follow as
+    /// `Text` tokens until a `Break`.
+    BeginText,
+    /// The start of an array (major type 4). The length is `None` when the
+    /// array has indefinite length.
+    BeginArray(Option<usize>),
+    /// The start of a map (major type 5). The length (in pairs) is `None`
+    /// when the map has indefinite length.
+    BeginMap(Option<usize>),
+    /// A tag number (major type 6). The tagged data item follows.
+    Tag(u64),
+    /// A boolean (major type 7, values 20 or 21).
+    Bool(bool),
+    /// A null (major type 7, value 22).
+    Null,
+    /// An undefined value (major type 7, value 23).
+    Undefined,
+    /// Any other simple value (major type 7).
+    Simple(u8),
+    /// A half, single or double precision float (major type 7).
+    Float(f64),
+    /// The "break" stop code, which ends an indefinite length item.
+    Break,
+}
+
+/// A tokenizer over CBOR bytes.
+///
+/// This is the lowest level interface for reading CBOR in this crate. It
+/// yields a `Token` along with its byte offset for every data item head in
+/// the input, without building any values. Byte and Unicode strings are
+/// borrowed from the input.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{Lexer, Token};
+///
+/// // [1, "a"]
+/// let bytes = [0x82, 0x01, 0x61, b'a'];
+/// let toks = Lexer::new(&bytes).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(toks, vec![
+///     (0, Token::BeginArray(Some(2))),
+///     (1, Token::UInt(1)),
+///     (2, Token::Text(b"a")),
+/// ]);
+/// ```
+#[derive(Clone)]
+pub struct Lexer<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    /// Create a new tokenizer over the bytes given.
+    pub fn new(bytes: &'a [u8]) -> Lexer<'a> {
+        Lexer { bytes: bytes, pos: 0 }
+    }
+
+    /// Returns the byte offset of the next token.
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    fn errat(&self, err: ReadError, offset: usize) -> CborError {
+        CborError::AtOffset { kind: err, offset: offset }
+    }
+
+    fn take(&mut self, n: usize) -> CborResult<&'a [u8]> {
+        let left = self.bytes.len() - self.pos;
+        if left < n {
+            let end = self.bytes.len();
+            return Err(self.errat(
+                ReadError::UnexpectedEOF { needed: n - left, offset: end },
+                end));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Reads the argument of a data item head. `None` is returned for the
+    /// indefinite length marker.
+    fn read_arg(&mut self, first: u8, at: usize) -> CborResult<Option<u64>> {
+        Ok(Some(match first & 0b000_11111 {
+            n @ 0...23 => n as u64,
+            24 => try!(self.take(1))[0] as u64,
+            25 => {
+                <BigEndian as ByteOrder>::read_u16(try!(self.take(2))) as u64
+            }
+            26 => {
+                <BigEndian as ByteOrder>::read_u32(try!(self.take(4))) as u64
+            }
+            27 => <BigEndian as ByteOrder>::read_u64(try!(self.take(8))),
+            31 => return Ok(None),
+            v => {
+                let ty = Type::from_major(first >> 5);
+                return Err(self.errat(
+                    ReadError::InvalidAddValue { ty: ty, val: v }, at));
+            }
+        }))
+    }
+
+    fn read_len(&mut self, first: u8, at: usize)
+               -> CborResult<Option<usize>> {
+        match try!(self.read_arg(first, at)) {
+            None => Ok(None),
+            Some(n) if n > ::std::usize::MAX as u64 => {
+                Err(self.errat(ReadError::Other(format!(
+                    "Length {} does not fit in a usize.", n)), at))
+            }
+            Some(n) => Ok(Some(n as usize)),
+        }
+    }
+
+    fn definite(&self, first: u8, arg: Option<u64>, at: usize)
+               -> CborResult<u64> {
+        match arg {
+            Some(n) => Ok(n),
+            None => {
+                let ty = Type::from_major(first >> 5);
+                Err(self.errat(
+                    ReadError::InvalidAddValue { ty: ty, val: 31 }, at))
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> CborResult<Token<'a>> {
+        let at = self.pos;
+        let first = try!(self.take(1))[0];
+        Ok(match (first & 0b111_00000) >> 5 {
+            0 => {
+                let arg = try!(self.read_arg(first, at));
+                Token::UInt(try!(self.definite(first, arg, at)))
+            }
+            1 => {
+                let arg = try!(self.read_arg(first, at));
+                Token::NegInt(try!(self.definite(first, arg, at)))
+            }
+            2 => match try!(self.read_len(first, at)) {
+                None => Token::BeginBytes,
+                Some(len) => Token::Bytes(try!(self.take(len))),
+            },
+            3 => match try!(self.read_len(first, at)) {
+                None => Token::BeginText,
+                Some(len) => Token::Text(try!(self.take(len))),
+            },
+            4 => Token::BeginArray(try!(self.read_len(first, at))),
+            5 => Token::BeginMap(try!(self.read_len(first, at))),
+            6 => {
+                let arg = try!(self.read_arg(first, at));
+                Token::Tag(try!(self.definite(first, arg, at)))
+            }
+            // `first >> 5` can only produce 8 distinct values, so this is
+            // major type 7.
+            _ => match first & 0b000_11111 {
+                20 => Token::Bool(false),
+                21 => Token::Bool(true),
+                22 => Token::Null,
+                23 => Token::Undefined,
+                v @ 0...19 => Token::Simple(v),
+                24 => match try!(self.take(1))[0] {
+                    v @ 0...31 => return Err(self.errat(
+                        ReadError::Reserved { major: 7, add: v }, at)),
+                    v => Token::Simple(v),
+                },
+                25 => {
+                    let buf = try!(self.take(2));
+                    Token::Float(f16_to_f64(
+                        <BigEndian as ByteOrder>::read_u16(buf)))
+                }
+                26 => {
+                    let buf = try!(self.take(4));
+                    Token::Float(
+                        <BigEndian as ByteOrder>::read_f32(buf) as f64)
+                }
+                27 => {
+                    let buf = try!(self.take(8));
+                    Token::Float(<BigEndian as ByteOrder>::read_f64(buf))
+                }
+                31 => Token::Break,
+                v /* 28...30 */ => return Err(self.errat(
+                    ReadError::Unassigned { major: 7, add: v }, at)),
+            },
+        })
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = CborResult<(usize, Token<'a>)>;
+
+    fn next(&mut self) -> Option<CborResult<(usize, Token<'a>)>> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let at = self.pos;
+        match self.read_token() {
+            Ok(tok) => Some(Ok((at, tok))),
+            Err(err) => {
+                // Don't try to make sense of anything after an error.
+                self.pos = self.bytes.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Converts the bits of an IEEE 754 half-precision float to an `f64`.
+pub fn f16_to_f64(half: u16) -> f64 {
+    let exp = (half >> 10) & 0x1f;
+    let mant = (half & 0x3ff) as f64;
+    let val = if exp == 0 {
+        mant * 2f64.powi(-24)
+    } else if exp != 31 {
+        (mant + 1024.0) * 2f64.powi(exp as i32 - 25)
+    } else if mant == 0.0 {
+        ::std::f64::INFINITY
+    } else {
+        ::std::f64::NAN
+    };
+    if half & 0x8000 != 0 { -val } else { val }
+}
+
+/// Returns the length in bytes of the first complete data item in `bytes`.
+///
+/// If `bytes` ends before a complete data item is found, then `None` is
+/// returned. Only the structure of the data is checked (e.g., Unicode strings
+/// are not checked for UTF-8 validity).
+pub fn item_len(bytes: &[u8]) -> CborResult<Option<usize>> {
+    ItemScanner::new().scan(bytes)
+}
+
+/// Finds the end of the first complete data item in bytes that arrive a
+/// piece at a time, like `item_len`.
+///
+/// Each scan continues after the last complete token of the one before,
+/// so only a token that was cut off is scanned again.
+#[derive(Clone, Debug, Default)]
+pub struct ItemScanner {
+    // Each entry is the number of data items left to read in an open
+    // container, or `None` if the container is terminated by a break.
+    open: Vec<Option<u64>>,
+    // The offset of the first token that hasn't been scanned.
+    pos: usize,
+}
+
+impl ItemScanner {
+    /// Creates a scanner that hasn't scanned anything.
+    pub fn new() -> ItemScanner {
+        ItemScanner::default()
+    }
+
+    /// Returns the length in bytes of the first complete data item in
+    /// `bytes`, which must start with the bytes given to the last scan.
+    ///
+    /// Once a data item is found, the scanner starts over for the next one,
+    /// whose bytes are then given from its start.
+    pub fn scan(&mut self, bytes: &[u8]) -> CborResult<Option<usize>> {
+        let mut lex = Lexer { bytes: bytes, pos: self.pos };
+        loop {
+            self.pos = lex.offset();
+            let (at, tok) = match lex.next() {
+                None => return Ok(None),
+                Some(Err(ref err)) if err.is_eof() => return Ok(None),
+                Some(Err(err)) => return Err(err),
+                Some(Ok(v)) => v,
+            };
+            match tok {
+                Token::BeginArray(Some(n)) if n > 0 => {
+                    self.open.push(Some(n as u64));
+                    continue;
+                }
+                Token::BeginMap(Some(n)) if n > 0 => {
+                    // Saturating is fine, since no map that large fits in
+                    // `bytes`.
+                    self.open.push(Some((n as u64).saturating_mul(2)));
+                    continue;
+                }
+                Token::BeginArray(None) | Token::BeginMap(None)
+                | Token::BeginBytes | Token::BeginText => {
+                    self.open.push(None);
+                    continue;
+                }
+                Token::Tag(_) => {
+                    self.open.push(Some(1));
+                    continue;
+                }
+                Token::Break => match self.open.pop() {
+                    Some(None) => {}
+                    _ => return Err(CborError::AtOffset {
+                        kind: ReadError::UnexpectedBreak { offset: at },
+                        offset: at,
+                    }),
+                },
+                _ => {}
+            }
+            // A data item was completed, which may complete its parents
+            // too.
+            loop {
+                let finished = match self.open.last_mut() {
+                    None => {
+                        self.pos = 0;
+                        return Ok(Some(lex.offset()));
+                    }
+                    Some(&mut None) => false,
+                    Some(&mut Some(ref mut n)) => { *n -= 1; *n == 0 }
+                };
+                if !finished {
+                    break;
+                }
+                self.open.pop();
+            }
+        }
+    }
+}
+
+/// Checks that `bytes` holds exactly one well formed data item.
+///
+/// This follows RFC 8949 (appendix C) without building any values: heads
+/// must use assigned additional information, containers must be complete
+/// and nest properly, the chunks of indefinite length strings must be
+/// definite length strings of the same type, and Unicode strings must be
+/// valid UTF-8. The data item must also end exactly where `bytes` does,
+/// or else `ReadError::TrailingBytes` is returned.
+///
+/// Errors carry the byte offset of the problem. This is cheaper than
+/// decoding, so it can be used to reject bad input before doing anything
+/// expensive with it.
+///
+/// # Example
+///
+/// ```rust
+/// // [1, (_ "a", "b")]
+/// assert!(cbor::validate(&[0x82, 0x01, 0x7f, 0x61, b'a', 0x61, b'b',
+///                          0xff]).is_ok());
+/// // [1, (_ "a", 2)]
+/// assert!(cbor::validate(&[0x82, 0x01, 0x7f, 0x61, b'a', 0x02,
+///                          0xff]).is_err());
+/// // 1 2
+/// assert!(cbor::validate(&[0x01, 0x02]).is_err());
+/// ```
+pub fn validate(bytes: &[u8]) -> CborResult<()> {
+    enum Open {
+        // The number of data items left in a definite length container.
+        Items(u64),
+        // An indefinite length array or map.
+        Indefinite,
+        // An indefinite length string, whose chunks have this type.
+        Chunks(Type),
+    }
+    let errat = |err: ReadError, at: usize| {
+        CborError::AtOffset { kind: err, offset: at }
+    };
+    let mut lex = Lexer::new(bytes);
+    let mut open: Vec<Open> = vec![];
+    loop {
+        let (at, tok) = match lex.next() {
+            None => {
+                let end = bytes.len();
+                return Err(errat(
+                    ReadError::UnexpectedEOF { needed: 1, offset: end }, end));
+            }
+            Some(r) => try!(r),
+        };
+        if let Some(&Open::Chunks(ty)) = open.last() {
+            match tok {
+                Token::Break => {}
+                Token::Bytes(_) if ty == Type::Bytes => {}
+                Token::Text(_) if ty == Type::Unicode => {}
+                _ => {
+                    let err = ReadError::ty_mismatch(ty, token_type(&tok));
+                    return Err(errat(err, at));
+                }
+            }
+        }
+        match tok {
+            Token::Text(text) => {
+                if let Err(err) = str::from_utf8(text) {
+                    return Err(errat(ReadError::InvalidUtf8(err), at));
+                }
+            }
+            Token::BeginArray(Some(n)) if n > 0 => {
+                open.push(Open::Items(n as u64));
+                continue;
+            }
+            Token::BeginMap(Some(n)) if n > 0 => {
+                // Saturating is fine, since no map that large fits in
+                // `bytes`.
+                open.push(Open::Items((n as u64).saturating_mul(2)));
+                continue;
+            }
+            Token::BeginArray(None) | Token::BeginMap(None) => {
+                open.push(Open::Indefinite);
+                continue;
+            }
+            Token::BeginBytes => {
+                open.push(Open::Chunks(Type::Bytes));
+                continue;
+            }
+            Token::BeginText => {
+                open.push(Open::Chunks(Type::Unicode));
+                continue;
+            }
+            Token::Tag(_) => {
+                open.push(Open::Items(1));
+                continue;
+            }
+            Token::Break => match open.pop() {
+                Some(Open::Indefinite) | Some(Open::Chunks(_)) => {}
+                _ => {
+                    return Err(errat(
+                        ReadError::UnexpectedBreak { offset: at }, at));
+                }
+            },
+            _ => {}
+        }
+        // A data item was completed, which may complete its parents too.
+        loop {
+            let finished = match open.last_mut() {
+                None => {
+                    let end = lex.offset();
+                    if end < bytes.len() {
+                        return Err(errat(ReadError::TrailingBytes {
+                            remaining: bytes.len() - end,
+                        }, end));
+                    }
+                    return Ok(());
+                }
+                Some(&mut Open::Items(ref mut n)) => { *n -= 1; *n == 0 }
+                Some(_) => false,
+            };
+            if !finished {
+                break;
+            }
+            open.pop();
+        }
+    }
+}
+
+/// Returns the type of the data item that a token starts.
+pub fn token_type(tok: &Token) -> Type {
+    match *tok {
+        Token::UInt(_) => Type::UInt,
+        Token::NegInt(_) => Type::Int,
+        Token::Bytes(_) | Token::BeginBytes => Type::Bytes,
+        Token::Text(_) | Token::BeginText => Type::Unicode,
+        Token::BeginArray(_) => Type::Array,
+        Token::BeginMap(_) => Type::Map,
+        Token::Tag(_) => Type::Tag,
+        Token::Bool(_) => Type::Bool,
+        Token::Null => Type::Null,
+        Token::Undefined => Type::Undefined,
+        Token::Simple(_) => Type::Simple,
+        Token::Float(_) => Type::Float,
+        Token::Break => Type::Break,
+    }
+}
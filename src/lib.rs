@@ -120,21 +120,42 @@ so you can convert JSON to CBOR in a similar manner as above.
 #![feature(convert)]
 
 extern crate byteorder;
+#[cfg(feature = "testing")]
+extern crate quickcheck;
 extern crate rustc_serialize;
 
-use std::collections::HashMap;
-use std::error::FromError;
+use std::ascii::AsciiExt;
+use std::cmp::Ordering;
+use std::error::{self, FromError};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::ops::Deref;
+use std::str;
 
 use rustc_serialize::Decoder as RustcDecoder;
 use rustc_serialize::Encoder as RustcEncoder;
 use rustc_serialize::{Decodable, Encodable};
 
-pub use decoder::Decoder;
-pub use encoder::Encoder;
-pub use json::ToCbor;
+use rustc_decoder::CborDecoder;
+
+pub use annotate::annotate;
+pub use canonical::{CanonicalIter, CanonicalMap, cmp_canonical_keys};
+pub use decoder::{
+    BytesReader, Chunks, DecodeSeed, Decoder, SliceRead, from_slice_exact,
+    get_field,
+};
+pub use encoder::{Encoder, encoded_size};
+pub use json::{ToCbor, to_json_rfc8949, transcode_json};
+pub use lazy::LazyReader;
+pub use lexer::{Lexer, Token, validate};
+pub use map::{CborMap, decode_any_map, encode_any_map};
+pub use packed::{pack, unpack};
+pub use projection::Projection;
 pub use rustc_decoder_direct::CborDecoder as DirectDecoder;
+pub use slice::SliceDecoder;
+pub use streaming::{Decoded, StreamingDecoder, decode_all};
+pub use versioned::{Versioned, Versions};
 
 // A trivial logging macro. No reason to pull in `log`, which has become
 // difficult to use in tests.
@@ -149,6 +170,121 @@ macro_rules! fromerr {
     ($e:expr) => ($e.map_err(::std::error::FromError::from_error));
 }
 
+/// Builds a `Cbor` value from a JSON-like literal.
+///
+/// Arrays are written as `[a, b]` and maps as `{key => value}`, where keys
+/// are anything that converts to a `String`. `null` and `undefined` are
+/// those simple values, and `tag(n, value)` tags a value with `n`. Anything
+/// else is an expression whose value is converted with `ToCbor`, e.g., a
+/// number, a string or a variable. Integers are given their smallest size.
+///
+/// # Example
+///
+/// ```rust
+/// #[macro_use] extern crate cbor;
+///
+/// # fn main() {
+/// let n = 2;
+/// let item = cbor!({
+///     "a" => 1,
+///     "b" => [-n, "x", null, tag(0, "2024-01-01T00:00:00Z")],
+/// });
+/// assert_eq!(item, cbor::Cbor::from_diagnostic(
+///     r#"{"a": 1, "b": [-2, "x", null, 0("2024-01-01T00:00:00Z")]}"#,
+/// ).unwrap());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! cbor {
+    (null) => ($crate::Cbor::Null);
+    (undefined) => ($crate::Cbor::Undefined);
+    (tag($tag:expr, $($val:tt)+)) => ($crate::Cbor::Tag($crate::CborTag {
+        tag: $tag,
+        data: Box::new(cbor!($($val)+)),
+    }));
+    ([$($tt:tt)*]) => ($crate::Cbor::Array(cbor!(@array [] () $($tt)*)));
+    ({$($tt:tt)*}) => ($crate::Cbor::Map(cbor!(@map [] () $($tt)*)));
+
+    // Arrays collect the tokens of each item until a comma.
+    (@array [$($done:expr,)*] ()) => (vec![$($done),*]);
+    (@array [$($done:expr,)*] ($($item:tt)+)) => (
+        vec![$($done,)* cbor!($($item)+)]
+    );
+    (@array [$($done:expr,)*] ($($item:tt)+) , $($rest:tt)*) => (
+        cbor!(@array [$($done,)* cbor!($($item)+),] () $($rest)*)
+    );
+    (@array [$($done:expr,)*] ($($item:tt)*) $next:tt $($rest:tt)*) => (
+        cbor!(@array [$($done,)*] ($($item)* $next) $($rest)*)
+    );
+
+    // Maps collect the tokens of each key until `=>`, and then the tokens
+    // of its value until a comma.
+    (@map [$(($($key:tt)+) => $val:expr,)*] ()) => ({
+        let mut map = $crate::CborMap::new();
+        $(map.insert($($key)+, $val);)*
+        map
+    });
+    (@map [$($done:tt)*] ($($key:tt)+) => $($rest:tt)*) => (
+        cbor!(@value [$($done)*] ($($key)+) () $($rest)*)
+    );
+    (@map [$($done:tt)*] ($($key:tt)*) $next:tt $($rest:tt)*) => (
+        cbor!(@map [$($done)*] ($($key)* $next) $($rest)*)
+    );
+    (@value [$($done:tt)*] ($($key:tt)+) ($($val:tt)+)) => (
+        cbor!(@map [$($done)* ($($key)+) => cbor!($($val)+),] ())
+    );
+    (@value [$($done:tt)*] ($($key:tt)+) ($($val:tt)+) , $($rest:tt)*) => (
+        cbor!(@map [$($done)* ($($key)+) => cbor!($($val)+),] () $($rest)*)
+    );
+    (@value [$($done:tt)*] ($($key:tt)+) ($($val:tt)*) $next:tt
+     $($rest:tt)*) => (
+        cbor!(@value [$($done)*] ($($key)+) ($($val)* $next) $($rest)*)
+    );
+
+    ($val:expr) => ($crate::ToCbor::to_cbor(&$val));
+}
+
+/// The media type of a single CBOR data item (RFC 8949), for the
+/// `Content-Type` and `Accept` headers of HTTP requests and responses.
+pub const MIME_TYPE: &'static str = "application/cbor";
+
+/// The media type of a CBOR sequence (RFC 8742), i.e., zero or more data
+/// items one after another, as written by `Encoder::encode`.
+pub const MIME_TYPE_SEQ: &'static str = "application/cbor-seq";
+
+/// Returns true if the value of a `Content-Type` header says that the body
+/// is CBOR.
+///
+/// Parameters and case are ignored. Besides `MIME_TYPE`, this accepts
+/// media types with the `+cbor` structured syntax suffix (e.g.,
+/// `application/senml+cbor`), since those are CBOR too. A CBOR sequence
+/// isn't a single data item, so `MIME_TYPE_SEQ` isn't accepted.
+///
+/// This works with the header of any HTTP library, so a handler can check
+/// it before decoding the body with `Decoder::from_bytes`, and reply with
+/// `415 Unsupported Media Type` otherwise.
+///
+/// # Example
+///
+/// ```rust
+/// assert!(cbor::is_cbor_media_type("application/cbor"));
+/// assert!(cbor::is_cbor_media_type("Application/CBOR; charset=binary"));
+/// assert!(cbor::is_cbor_media_type("application/senml+cbor"));
+/// assert!(!cbor::is_cbor_media_type("application/json"));
+/// assert!(!cbor::is_cbor_media_type(cbor::MIME_TYPE_SEQ));
+/// ```
+pub fn is_cbor_media_type(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("")
+                              .trim().to_ascii_lowercase();
+    let mut parts = essence.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(ty), Some(sub)) if !ty.is_empty() => {
+            essence == MIME_TYPE || (sub.ends_with("+cbor") && sub.len() > 5)
+        }
+        _ => false,
+    }
+}
+
 /// All core types defined in the CBOR specification.
 ///
 /// For the most part, this is used for convenient error reporting.
@@ -159,7 +295,7 @@ pub enum Type {
     Int, Int8, Int16, Int32, Int64,
     Float, Float16, Float32, Float64,
     Bytes, Unicode, Array, Map, Tag,
-    Any, Null, Undefined, Bool, Break,
+    Any, Null, Undefined, Bool, Simple, Break,
 }
 
 impl fmt::Display for Type {
@@ -186,12 +322,11 @@ impl Type {
             (4, _) => Type::Array,
             (5, _) => Type::Map,
             (6, _) => Type::Tag,
-            (7, v @ 0...19) => return Err(ReadError::Unassigned {
-                major: 7, add: v,
-            }),
+            (7, 0...19) => Type::Simple,
             (7, 20...21) => Type::Bool,
             (7, 22) => Type::Null,
             (7, 23) => Type::Undefined,
+            (7, 24) => Type::Simple,
             (7, 25) => Type::Float16,
             (7, 26) => Type::Float32,
             (7, 27) => Type::Float64,
@@ -205,6 +340,20 @@ impl Type {
         })
     }
 
+    /// Returns the most general type for a major type value.
+    fn from_major(major: u8) -> Type {
+        match major {
+            0 => Type::UInt,
+            1 => Type::Int,
+            2 => Type::Bytes,
+            3 => Type::Unicode,
+            4 => Type::Array,
+            5 => Type::Map,
+            6 => Type::Tag,
+            _ => Type::Any,
+        }
+    }
+
     fn major(self) -> u8 {
         match self {
             Type::UInt | Type::UInt8 | Type::UInt16
@@ -217,7 +366,8 @@ impl Type {
             Type::Map => 5,
             Type::Tag => 6,
             Type::Float | Type::Float16 | Type::Float32 | Type::Float64 => 7,
-            Type::Null | Type::Undefined | Type::Bool | Type::Break => 7,
+            Type::Null | Type::Undefined | Type::Bool | Type::Simple
+            | Type::Break => 7,
             Type::Any => unreachable!(),
         }
     }
@@ -230,9 +380,23 @@ impl Type {
 /// keys.
 ///
 /// Note that this representation distinguishes the size of an encoded number.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Values are totally ordered, so they can be used as the keys of a
+/// `BTreeMap` or a `HashMap` (see `decode_any_map` for maps whose keys
+/// aren't strings). Data items are ordered by major type first: integers,
+/// byte strings, Unicode strings, arrays, maps, tags and then simple
+/// values. Integers are ordered by their value, with the size of their
+/// encoding breaking ties. Floats are compared by their bit patterns in the
+/// IEEE 754 total order, so a NaN is equal to itself and `-0.0` is less
+/// than `0.0`. Maps are compared by their entries sorted by key.
+#[derive(Clone, Debug)]
 pub enum Cbor {
     /// A code used to signify the end of an indefinite length data item.
+    ///
+    /// This is never produced by the decoder. A break outside of an
+    /// indefinite length data item is reported as
+    /// `ReadError::UnexpectedBreak`, or as `ReadError::MissingItems` in an
+    /// array or map of definite length.
     Break, // does this really belong here?
     /// An undefined data item (major type 7, value 23).
     Undefined,
@@ -253,9 +417,16 @@ pub enum Cbor {
     /// An array (major type 4).
     Array(Vec<Cbor>),
     /// A map (major type 5).
-    Map(HashMap<String, Cbor>),
+    Map(CborMap),
     /// A tag (major type 6).
     Tag(CborTag),
+    /// A simple value other than a boolean, null or undefined (major type
+    /// 7, values 0-19 and 32-255).
+    Simple(CborSimple),
+    /// An integer (major type 0 or 1) that was encoded with more bytes than
+    /// needed. This is only produced when `Decoder::faithful_integers` is
+    /// enabled.
+    Wide(CborWide),
 }
 
 /// An unsigned integer (major type 0).
@@ -303,15 +474,549 @@ pub enum CborFloat {
 }
 
 /// A byte string (major type 2).
-#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, RustcEncodable)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, RustcEncodable)]
 pub struct CborBytes(pub Vec<u8>);
 
+/// A simple value (major type 7).
+///
+/// Most simple values are unassigned, but application protocols are free to
+/// use them. This type can encode and decode any simple value. (The values
+/// 20-23 are `false`, `true`, `null` and `undefined`. The values 24-31 are
+/// reserved and can't be encoded.)
+///
+/// Like `CborBytes`, this is a "special" type whose use is hard-coded into
+/// the implementation of the encoder and decoder.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd,
+         RustcEncodable)]
+pub struct CborSimple(pub u8);
+
+/// An integer that was encoded with more bytes than needed.
+///
+/// Encoders should use the shortest encoding of an integer, but they aren't
+/// required to. When a signature covers the encoded bytes, re-encoding such
+/// an integer must reproduce its original width, so `Decoder` can be asked
+/// (with `faithful_integers`) to produce this type for them. It is encoded
+/// with exactly the width it was read with.
+///
+/// Negative integers below `i64::MIN` don't fit in `Cbor::Signed`, so they
+/// are always decoded as this type (with a width of 8).
+///
+/// Like `CborBytes`, this is a "special" type whose use is hard-coded into
+/// the implementation of the encoder.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CborWide {
+    /// True for a negative integer (major type 1).
+    pub negative: bool,
+    /// The encoded argument. For negative integers, the value is
+    /// `-1 - arg`.
+    pub arg: u64,
+    /// The number of bytes used to encode `arg`: 1, 2, 4 or 8.
+    pub width: u8,
+}
+
+impl CborWide {
+    /// Returns the integer as `Cbor::Unsigned` or `Cbor::Signed`, which is
+    /// what the decoder produces by default.
+    ///
+    /// Negative integers below `i64::MIN` can only be represented by
+    /// `Cbor::Wide`, so they are returned as is (with a width of 8).
+    pub fn to_cbor(self) -> Cbor {
+        if self.negative && self.arg > ::std::i64::MAX as u64 {
+            Cbor::Wide(CborWide { width: 8, ..self })
+        } else if self.negative {
+            Cbor::Signed(CborSigned::smallest(-1 - self.arg as i64))
+        } else {
+            Cbor::Unsigned(CborUnsigned::smallest(self.arg))
+        }
+    }
+
+    /// Returns the number of bytes needed to encode the argument `arg`
+    /// after the initial byte.
+    fn width_of(arg: u64) -> u8 {
+        if arg < 24 {
+            0
+        } else if arg <= ::std::u8::MAX as u64 {
+            1
+        } else if arg <= ::std::u16::MAX as u64 {
+            2
+        } else if arg <= ::std::u32::MAX as u64 {
+            4
+        } else {
+            8
+        }
+    }
+
+    fn typ(self) -> Type {
+        match (self.negative, self.width) {
+            (false, 1) => Type::UInt8,
+            (false, 2) => Type::UInt16,
+            (false, 4) => Type::UInt32,
+            (false, _) => Type::UInt64,
+            (true, 1) => Type::Int8,
+            (true, 2) => Type::Int16,
+            (true, 4) => Type::Int32,
+            (true, _) => Type::Int64,
+        }
+    }
+}
+
+impl Encodable for CborWide {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        // The struct name tells the encoder to write the bytes as is.
+        let major = if self.negative { 1 << 5 } else { 0 };
+        let (add, width) = match self.width {
+            1 => (24, 1),
+            2 => (25, 2),
+            4 => (26, 4),
+            _ => (27, 8),
+        };
+        let mut bytes = vec![major | add];
+        for i in (0..width).rev() {
+            bytes.push((self.arg >> (8 * i)) as u8);
+        }
+        e.emit_struct("CborWide", 1, |e| {
+            e.emit_struct_field("bytes", 0, |e| bytes.encode(e))
+        })
+    }
+}
+
+/// A data item that is encoded inside a byte string.
+///
+/// Some protocols (e.g., COSE and SUIT) wrap CBOR data in a byte string, so
+/// that it can be signed or hashed as is. `CborEncoded<T>` encodes `T` to
+/// CBOR and writes the result as a byte string. When decoded, it reads a
+/// byte string and decodes `T` from its contents, which must be exactly one
+/// data item.
+///
+/// Like `CborBytes`, this is a "special" type whose use is hard-coded into
+/// the implementation of the encoder.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{CborEncoded, Decoder, Encoder};
+///
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[(1, CborEncoded(vec![2, 3]))]).unwrap();
+/// let bytes = enc.into_bytes();
+/// assert_eq!(bytes, vec![0x82, 0x01, 0x43, 0x82, 0x02, 0x03]);
+///
+/// let mut dec = Decoder::from_bytes(bytes);
+/// let (_, inner): (u8, CborEncoded<Vec<u8>>) =
+///     dec.decode().next().unwrap().unwrap();
+/// assert_eq!(inner.0, vec![2, 3]);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct CborEncoded<T>(pub T);
+
+impl<T: Encodable> Encodable for CborEncoded<T> {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        // The struct name tells the encoder to capture the data item and
+        // write it as a byte string.
+        e.emit_struct("CborEncoded", 1, |e| self.0.encode(e))
+    }
+}
+
+impl<T: Decodable> Decodable for CborEncoded<T> {
+    fn decode<D: RustcDecoder>(d: &mut D)
+                              -> Result<CborEncoded<T>, D::Error> {
+        let bytes: Vec<u8> = try!(Decodable::decode(d));
+        let mut dec = Decoder::from_bytes(bytes);
+        let v = match dec.decode().next() {
+            Some(Ok(v)) => v,
+            Some(Err(err)) => return Err(d.error(&err.to_string())),
+            None => return Err(d.error("expected a data item")),
+        };
+        if dec.items().next().is_some() {
+            return Err(d.error("found more than one data item"));
+        }
+        Ok(CborEncoded(v))
+    }
+}
+
+/// A value that is written once and referred to wherever else it appears,
+/// using the tags for shared values (28 and 29, see
+/// http://cbor.schmorp.de/value-sharing).
+///
+/// `P` is a pointer to the value, like `Rc<T>` or `Arc<T>`. The first time
+/// the encoder sees a value, it writes it tagged with 28. Every other
+/// `CborShared` that points to the same value is written as tag 29 around
+/// the index of that first occurrence, so data shared through the pointers
+/// isn't duplicated. Since the value is known before it is written, a
+/// cycle (e.g., through a `RefCell`) becomes a reference as well, instead
+/// of recursing forever. The indexes start over for each top-level data
+/// item. In canonical mode, where map entries are reordered, values are
+/// always written in full.
+///
+/// The decoder resolves the references when `Decoder::shared_values` is
+/// enabled, so decoding gives back the same data. Each `CborShared` gets
+/// its own copy of the value, though, since a `Decodable` impl can't keep
+/// pointers around between values. A reference to a value that contains
+/// it (i.e., a cycle) is an error when decoding.
+///
+/// Like `CborBytes`, this is a "special" type whose use is hard-coded into
+/// the implementation of the encoder.
+///
+/// # Example
+///
+/// ```rust
+/// use std::rc::Rc;
+/// use cbor::{CborShared, Decoder, Encoder};
+///
+/// let name = Rc::new("abc".to_string());
+/// let names = vec![CborShared(name.clone()), CborShared(name)];
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[&names]).unwrap();
+/// let bytes = enc.into_bytes();
+/// assert_eq!(bytes, vec![0x82, 0xd8, 0x1c, 0x63, b'a', b'b', b'c',
+///                              0xd8, 0x1d, 0x00]);
+///
+/// let mut dec = Decoder::from_bytes(bytes).shared_values(true);
+/// let back: Vec<CborShared<Rc<String>>> =
+///     dec.decode().next().unwrap().unwrap();
+/// assert_eq!(back, names);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct CborShared<P>(pub P);
+
+impl<P> Encodable for CborShared<P>
+        where P: Deref, P::Target: Encodable + Sized {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        // The address of the value tells the encoder whether it has been
+        // written before.
+        let id = &*self.0 as *const P::Target as usize;
+        e.emit_struct("CborShared", 2, |e| {
+            try!(e.emit_struct_field("id", 0, |e| id.encode(e)));
+            e.emit_struct_field("value", 1, |e| (*self.0).encode(e))
+        })
+    }
+}
+
+impl<T: Decodable, P: Deref<Target=T> + From<T>> Decodable
+        for CborShared<P> {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<CborShared<P>, D::Error> {
+        let v: T = try!(Decodable::decode(d));
+        Ok(CborShared(P::from(v)))
+    }
+}
+
+/// A 128 bit unsigned integer, as its high and low 64 bits.
+///
+/// Values that fit in a `u64` are encoded as plain integers. Larger values
+/// are encoded as bignums, i.e., tag 2 around a byte string that holds the
+/// value in big-endian order without leading zeros.
+///
+/// When decoded, both forms are accepted, and so is a bignum with leading
+/// zeros. Values that don't fit return an error.
+///
+/// When decoding, the tag must not be ignored (see `TagPolicy`).
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{CborU128, Decoder, Encoder};
+///
+/// // 1 and 2^64
+/// let nums = vec![CborU128::from_u64(1), CborU128 { hi: 1, lo: 0 }];
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&nums).unwrap();
+/// let bytes = enc.into_bytes();
+/// assert_eq!(bytes, vec![0x01, 0xc2, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+///
+/// let mut dec = Decoder::from_bytes(bytes);
+/// let back = dec.decode::<CborU128>().collect::<Result<Vec<_>, _>>();
+/// assert_eq!(back.unwrap(), nums);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CborU128 {
+    /// The high 64 bits.
+    pub hi: u64,
+    /// The low 64 bits.
+    pub lo: u64,
+}
+
+impl CborU128 {
+    /// Returns `n` as a 128 bit integer.
+    pub fn from_u64(n: u64) -> CborU128 {
+        CborU128 { hi: 0, lo: n }
+    }
+}
+
+/// A 128 bit signed integer in two's complement, as its high 64 bits
+/// (which hold the sign) and its low 64 bits.
+///
+/// Values that fit in a `u64`, or that are at least `-2^64`, are encoded
+/// as plain integers. Other values are encoded as bignums: tag 2 for
+/// non-negative values and tag 3 for negative ones (whose byte string holds
+/// `-1 - n`).
+///
+/// When decoded, both forms are accepted, and so is a bignum with leading
+/// zeros. Values that don't fit return an error.
+///
+/// When decoding, the tag must not be ignored (see `TagPolicy`).
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{CborI128, Decoder, Encoder};
+///
+/// // -1, -2^64 and -2^64 - 1
+/// let nums = vec![CborI128::from_i64(-1), CborI128 { hi: -1, lo: 0 },
+///                 CborI128 { hi: -2, lo: ::std::u64::MAX }];
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&nums).unwrap();
+/// let bytes = enc.into_bytes();
+/// assert_eq!(bytes, vec![0x20,
+///                        0x3b, 0xff, 0xff, 0xff, 0xff,
+///                              0xff, 0xff, 0xff, 0xff,
+///                        0xc3, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+///
+/// let mut dec = Decoder::from_bytes(bytes);
+/// let back = dec.decode::<CborI128>().collect::<Result<Vec<_>, _>>();
+/// assert_eq!(back.unwrap(), nums);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CborI128 {
+    /// The high 64 bits, which are negative if the integer is.
+    pub hi: i64,
+    /// The low 64 bits.
+    pub lo: u64,
+}
+
+impl CborI128 {
+    /// Returns `n` as a 128 bit integer.
+    pub fn from_i64(n: i64) -> CborI128 {
+        CborI128 { hi: if n < 0 { -1 } else { 0 }, lo: n as u64 }
+    }
+}
+
+impl Encodable for CborU128 {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        if self.hi == 0 {
+            e.emit_u64(self.lo)
+        } else {
+            encode_bignum(e, 2, self.hi, self.lo)
+        }
+    }
+}
+
+impl Decodable for CborU128 {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<CborU128, D::Error> {
+        match try!(decode_big_int(d)) {
+            (false, hi, lo) => Ok(CborU128 { hi: hi, lo: lo }),
+            (true, _, _) => {
+                Err(d.error("negative integer doesn't fit in 128 bits"))
+            }
+        }
+    }
+}
+
+impl Encodable for CborI128 {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        // For a negative integer `n`, `-1 - n` is `!n` in two's complement.
+        let (hi, lo) = (self.hi, self.lo);
+        if hi >= 0 {
+            CborU128 { hi: hi as u64, lo: lo }.encode(e)
+        } else if hi == -1 && lo > ::std::i64::MAX as u64 {
+            e.emit_i64(lo as i64)
+        } else if hi == -1 {
+            // Below `i64::MIN`, but still a plain negative integer.
+            CborWide { negative: true, arg: !lo, width: 8 }.encode(e)
+        } else {
+            encode_bignum(e, 3, !hi as u64, !lo)
+        }
+    }
+}
+
+impl Decodable for CborI128 {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<CborI128, D::Error> {
+        let (negative, hi, lo) = try!(decode_big_int(d));
+        if hi > ::std::i64::MAX as u64 {
+            return Err(d.error("integer doesn't fit in 128 bits"));
+        }
+        Ok(if negative {
+            CborI128 { hi: !hi as i64, lo: !lo }
+        } else {
+            CborI128 { hi: hi as i64, lo: lo }
+        })
+    }
+}
+
+/// Encodes the integer with the high and low 64 bits given as a bignum
+/// with the tag given (2 or 3).
+fn encode_bignum<E: RustcEncoder>(e: &mut E, tag: u64, hi: u64, lo: u64)
+                                 -> Result<(), E::Error> {
+    let bytes: Vec<u8> = [hi, lo].iter()
+                                 .flat_map(|&n| {
+                                     (0..8).rev().map(move |i| {
+                                         (n >> (8 * i)) as u8
+                                     })
+                                 })
+                                 .skip_while(|&b| b == 0)
+                                 .collect();
+    CborTagEncode::new(tag, &CborBytes(bytes)).encode(e)
+}
+
+/// Decodes an integer or a bignum, returning the high and low 64 bits of
+/// its argument. For a negative integer `n`, this returns true and the
+/// bits of `-1 - n`.
+fn decode_big_int<D: RustcDecoder>(d: &mut D)
+                                  -> Result<(bool, u64, u64), D::Error> {
+    let v: Cbor = try!(Decodable::decode(d));
+    match v {
+        Cbor::Unsigned(n) => Ok((false, 0, n.to_u64().unwrap())),
+        Cbor::Signed(n) => Ok((true, 0, (-1 - n.to_i64().unwrap()) as u64)),
+        Cbor::Wide(n) => Ok((n.negative, 0, n.arg)),
+        Cbor::Tag(CborTag { tag: tag @ 2...3, data }) => {
+            let bytes = match *data {
+                Cbor::Bytes(bytes) => bytes.0,
+                _ => return Err(d.error("bignum isn't a byte string")),
+            };
+            let start = bytes.iter().position(|&b| b != 0)
+                             .unwrap_or(bytes.len());
+            if bytes.len() - start > 16 {
+                return Err(d.error("bignum doesn't fit in 128 bits"));
+            }
+            let (mut hi, mut lo) = (0u64, 0u64);
+            for &b in &bytes[start..] {
+                hi = (hi << 8) | (lo >> 56);
+                lo = (lo << 8) | b as u64;
+            }
+            Ok((tag == 3, hi, lo))
+        }
+        v => Err(d.error(&format!("expected an integer, found {}",
+                                  v.typ()))),
+    }
+}
+
+/// Two structs (or maps) that are encoded as a single map.
+///
+/// This is the same as flattening one struct into another: the entries of
+/// `A` are followed by the entries of `B`, and the map's length is their
+/// total. The encoder buffers the merged map, since its length isn't known
+/// until both have been written. A `CborFlatten` in `A` or `B` is merged
+/// into the same map, so more than two parts can be combined.
+///
+/// When decoded, `A` and `B` are both decoded from the whole map. Fields
+/// of one that the other doesn't know about are ignored, like any extra
+/// map entries. A map gets all of the entries, though, including the
+/// fields of the other part. The parts are decoded with the default
+/// decoding options.
+///
+/// Like `CborBytes`, this is a "special" type whose use is hard-coded into
+/// the implementation of the encoder.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate cbor;
+/// # extern crate rustc_serialize;
+/// # fn main() {
+/// use cbor::{CborFlatten, Decoder, Encoder};
+///
+/// #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+/// struct Id { id: u32 }
+/// #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+/// struct Meta { name: String }
+///
+/// let v = CborFlatten(Id { id: 1 }, Meta { name: "a".to_string() });
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[&v]).unwrap();
+/// let bytes = enc.into_bytes();
+/// // {"id": 1, "name": "a"}
+/// assert_eq!(bytes, vec![0xa2, 0x62, b'i', b'd', 0x01,
+///                        0x64, b'n', b'a', b'm', b'e', 0x61, b'a']);
+///
+/// let mut dec = Decoder::from_bytes(bytes);
+/// let back: CborFlatten<Id, Meta> = dec.decode().next().unwrap().unwrap();
+/// assert_eq!(back, v);
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct CborFlatten<A, B>(pub A, pub B);
+
+impl<A: Encodable, B: Encodable> Encodable for CborFlatten<A, B> {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        // The struct name tells the encoder to merge the maps that its
+        // fields are encoded as.
+        e.emit_struct("CborFlatten", 2, |e| {
+            try!(e.emit_struct_field("0", 0, |e| self.0.encode(e)));
+            e.emit_struct_field("1", 1, |e| self.1.encode(e))
+        })
+    }
+}
+
+impl<A: Decodable, B: Decodable> Decodable for CborFlatten<A, B> {
+    fn decode<D: RustcDecoder>(d: &mut D)
+                              -> Result<CborFlatten<A, B>, D::Error> {
+        let v: Cbor = try!(Decodable::decode(d));
+        let opts = rustc_decoder::Options::default();
+        let a = CborDecoder::decode_at(v.clone(), 0, opts);
+        let b = CborDecoder::decode_at(v, 0, opts);
+        match (a, b) {
+            (Ok(a), Ok(b)) => Ok(CborFlatten(a, b)),
+            (Err(err), _) | (_, Err(err)) => Err(d.error(&err.to_string())),
+        }
+    }
+}
+
+/// A content identifier (CID) as used by IPLD, which is tag 42 in
+/// DAG-CBOR.
+///
+/// This holds the binary form of the CID. It is encoded as a byte string
+/// that starts with the identity multibase prefix (`0x00`), tagged with
+/// 42.
+///
+/// When decoding, the tag must not be ignored (see `TagPolicy`).
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{Cid, Decoder, Encoder};
+///
+/// let cid = Cid(vec![0x01, 0x71, 0x12, 0x00]);
+/// let mut enc = Encoder::from_memory().dag_cbor(true);
+/// enc.encode(&[&cid]).unwrap();
+/// let bytes = enc.into_bytes();
+/// assert_eq!(bytes, vec![0xd8, 0x2a, 0x45, 0x00, 0x01, 0x71, 0x12, 0x00]);
+///
+/// let mut dec = Decoder::from_bytes(bytes).dag_cbor(true);
+/// let back: Cid = dec.decode().next().unwrap().unwrap();
+/// assert_eq!(back, cid);
+/// ```
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Cid(pub Vec<u8>);
+
+impl Encodable for Cid {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        let mut bytes = Vec::with_capacity(self.0.len() + 1);
+        bytes.push(0);
+        bytes.extend(self.0.iter().cloned());
+        CborTagEncode::new(42, &CborBytes(bytes)).encode(e)
+    }
+}
+
+impl Decodable for Cid {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<Cid, D::Error> {
+        let tag = try!(d.read_u64());
+        if tag != 42 {
+            return Err(d.error(&format!("expected tag 42, found {}", tag)));
+        }
+        let bytes: CborBytes = try!(Decodable::decode(d));
+        match bytes.0.split_first() {
+            Some((&0, cid)) => Ok(Cid(cid.to_vec())),
+            _ => Err(d.error("CID doesn't start with the multibase prefix")),
+        }
+    }
+}
+
 /// A tag (major type 6).
 ///
-/// Note that if you want to *encode* a tag, you should use the `CborTagEncode`
-/// type and *not* this type. This type is only useful when your manually
+/// Note that if you want to *encode* a tag with your own data, you should use
+/// the `CborTagEncode` type, which doesn't require converting your data to
+/// CBOR abstract syntax first. This type is only useful when your manually
 /// expecting the structure of a CBOR data item.
-#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct CborTag {
     /// The tag number.
     ///
@@ -423,11 +1128,124 @@ impl Cbor {
             Cbor::Array(_) => Type::Array,
             Cbor::Map(_) => Type::Map,
             Cbor::Tag(_) => Type::Tag,
+            Cbor::Simple(_) => Type::Simple,
+            Cbor::Wide(v) => v.typ(),
+        }
+    }
+
+    /// Returns the position of this kind of data item in the order of
+    /// values. Integers share a position, since they are ordered by value.
+    fn rank(&self) -> u8 {
+        match *self {
+            Cbor::Break => 0,
+            Cbor::Unsigned(_) | Cbor::Signed(_) | Cbor::Wide(_) => 1,
+            Cbor::Bytes(_) => 2,
+            Cbor::Unicode(_) => 3,
+            Cbor::Array(_) => 4,
+            Cbor::Map(_) => 5,
+            Cbor::Tag(_) => 6,
+            Cbor::Simple(_) => 7,
+            Cbor::Bool(_) => 8,
+            Cbor::Null => 9,
+            Cbor::Undefined => 10,
+            Cbor::Float(_) => 11,
+        }
+    }
+
+    /// Returns a key that orders integers by value, and then by the way
+    /// they are represented.
+    fn int_key(&self) -> Option<(bool, u64, u8)> {
+        let (negative, arg, kind) = match *self {
+            Cbor::Unsigned(CborUnsigned::UInt8(v)) => (false, v as u64, 0),
+            Cbor::Unsigned(CborUnsigned::UInt16(v)) => (false, v as u64, 1),
+            Cbor::Unsigned(CborUnsigned::UInt32(v)) => (false, v as u64, 2),
+            Cbor::Unsigned(CborUnsigned::UInt64(v)) => (false, v, 3),
+            Cbor::Signed(CborSigned::Int8(v)) => (true, !(v as i64) as u64, 4),
+            Cbor::Signed(CborSigned::Int16(v)) => {
+                (true, !(v as i64) as u64, 5)
+            }
+            Cbor::Signed(CborSigned::Int32(v)) => {
+                (true, !(v as i64) as u64, 6)
+            }
+            Cbor::Signed(CborSigned::Int64(v)) => (true, !v as u64, 7),
+            Cbor::Wide(v) => (v.negative, v.arg, 8 + v.width),
+            _ => return None,
+        };
+        // A negative integer is `-1 - arg`, so the bigger its argument, the
+        // smaller it is.
+        Some(if negative { (false, !arg, kind) } else { (true, arg, kind) })
+    }
+}
+
+impl PartialEq for Cbor {
+    fn eq(&self, other: &Cbor) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Cbor {}
+
+impl PartialOrd for Cbor {
+    fn partial_cmp(&self, other: &Cbor) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Cbor {
+    fn cmp(&self, other: &Cbor) -> Ordering {
+        match (self, other) {
+            (&Cbor::Bool(a), &Cbor::Bool(b)) => a.cmp(&b),
+            (&Cbor::Float(a), &Cbor::Float(b)) => {
+                a.order_key().cmp(&b.order_key())
+            }
+            (&Cbor::Bytes(ref a), &Cbor::Bytes(ref b)) => a.cmp(b),
+            (&Cbor::Unicode(ref a), &Cbor::Unicode(ref b)) => a.cmp(b),
+            (&Cbor::Array(ref a), &Cbor::Array(ref b)) => a.cmp(b),
+            (&Cbor::Map(ref a), &Cbor::Map(ref b)) => a.cmp(b),
+            (&Cbor::Tag(ref a), &Cbor::Tag(ref b)) => a.cmp(b),
+            (&Cbor::Simple(a), &Cbor::Simple(b)) => a.cmp(&b),
+            _ => match (self.int_key(), other.int_key()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => self.rank().cmp(&other.rank()),
+            },
+        }
+    }
+}
+
+impl Hash for Cbor {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match *self {
+            Cbor::Break | Cbor::Undefined | Cbor::Null => {}
+            Cbor::Bool(v) => v.hash(state),
+            Cbor::Unsigned(_) | Cbor::Signed(_) | Cbor::Wide(_) => {
+                self.int_key().hash(state)
+            }
+            Cbor::Float(v) => v.order_key().hash(state),
+            Cbor::Bytes(ref v) => v.hash(state),
+            Cbor::Unicode(ref v) => v.hash(state),
+            Cbor::Array(ref v) => v.hash(state),
+            Cbor::Map(ref v) => v.hash(state),
+            Cbor::Tag(ref v) => v.hash(state),
+            Cbor::Simple(v) => v.hash(state),
         }
     }
 }
 
 impl CborUnsigned {
+    /// Returns the smallest representation of `n`.
+    fn smallest(n: u64) -> CborUnsigned {
+        if n <= ::std::u8::MAX as u64 {
+            CborUnsigned::UInt8(n as u8)
+        } else if n <= ::std::u16::MAX as u64 {
+            CborUnsigned::UInt16(n as u16)
+        } else if n <= ::std::u32::MAX as u64 {
+            CborUnsigned::UInt32(n as u32)
+        } else {
+            CborUnsigned::UInt64(n)
+        }
+    }
+
     fn typ(self) -> Type {
         match self {
             CborUnsigned::UInt8(_) => Type::UInt8,
@@ -438,14 +1256,13 @@ impl CborUnsigned {
     }
 
     fn to_usize(self) -> ReadResult<usize> {
-        // It should be possible for this to fail. e.g., Converting a
-        // UInt64 to a usize when usize is 32 bits.
-        Ok(match self {
-            CborUnsigned::UInt8(v) => v as usize,
-            CborUnsigned::UInt16(v) => v as usize,
-            CborUnsigned::UInt32(v) => v as usize,
-            CborUnsigned::UInt64(v) => v as usize,
-        })
+        // This can fail when converting a UInt64 to a usize when usize is
+        // 32 bits.
+        let n = try!(self.to_u64());
+        if n > ::std::usize::MAX as u64 {
+            return Err(ReadError::overflow(Type::UInt, n));
+        }
+        Ok(n as usize)
     }
 
     fn to_u64(self) -> ReadResult<u64> {
@@ -458,33 +1275,26 @@ impl CborUnsigned {
             CborUnsigned::UInt64(v) => v,
         })
     }
-
-    fn to_u32(self) -> ReadResult<u32> {
-        Ok(match self {
-            CborUnsigned::UInt8(v) => v as u32,
-            CborUnsigned::UInt16(v) => v as u32,
-            CborUnsigned::UInt32(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::UInt32, self.typ())),
-        })
-    }
-
-    fn to_u16(self) -> ReadResult<u16> {
-        Ok(match self {
-            CborUnsigned::UInt8(v) => v as u16,
-            CborUnsigned::UInt16(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::UInt16, self.typ())),
-        })
-    }
-
-    fn to_u8(self) -> ReadResult<u8> {
-        Ok(match self {
-            CborUnsigned::UInt8(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::UInt8, self.typ())),
-        })
-    }
 }
 
 impl CborSigned {
+    /// Returns the smallest representation of the negative integer `n`.
+    ///
+    /// This follows the decoder, which picks the type based on the
+    /// magnitude of the encoded argument `-1 - n`.
+    fn smallest(n: i64) -> CborSigned {
+        let arg = -1 - n;
+        if arg <= ::std::i8::MAX as i64 {
+            CborSigned::Int8(n as i8)
+        } else if arg <= ::std::i16::MAX as i64 {
+            CborSigned::Int16(n as i16)
+        } else if arg <= ::std::i32::MAX as i64 {
+            CborSigned::Int32(n as i32)
+        } else {
+            CborSigned::Int64(n)
+        }
+    }
+
     fn typ(self) -> Type {
         match self {
             CborSigned::Int8(_) => Type::Int8,
@@ -494,17 +1304,6 @@ impl CborSigned {
         }
     }
 
-    fn to_isize(self) -> ReadResult<isize> {
-        // It should be possible for this to fail. e.g., Converting a
-        // UInt64 to a usize when usize is 32 bits.
-        Ok(match self {
-            CborSigned::Int8(v) => v as isize,
-            CborSigned::Int16(v) => v as isize,
-            CborSigned::Int32(v) => v as isize,
-            CborSigned::Int64(v) => v as isize,
-        })
-    }
-
     fn to_i64(self) -> ReadResult<i64> {
         // I don't think this can fail, but it's convenient for it to have
         // the same return type as all of the other integer conversions. ---AG
@@ -515,33 +1314,29 @@ impl CborSigned {
             CborSigned::Int64(v) => v,
         })
     }
-
-    fn to_i32(self) -> ReadResult<i32> {
-        Ok(match self {
-            CborSigned::Int8(v) => v as i32,
-            CborSigned::Int16(v) => v as i32,
-            CborSigned::Int32(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::Int32, self.typ())),
-        })
-    }
-
-    fn to_i16(self) -> ReadResult<i16> {
-        Ok(match self {
-            CborSigned::Int8(v) => v as i16,
-            CborSigned::Int16(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::Int16, self.typ())),
-        })
-    }
-
-    fn to_i8(self) -> ReadResult<i8> {
-        Ok(match self {
-            CborSigned::Int8(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::Int8, self.typ())),
-        })
-    }
 }
 
 impl CborFloat {
+    /// Returns a key that orders floats by the IEEE 754 total order of
+    /// their values, and then by precision.
+    ///
+    /// The original bits are included, in case converting to an `f64`
+    /// changed a NaN.
+    fn order_key(self) -> (u64, u8, u32) {
+        fn total(bits: u64) -> u64 {
+            if bits >> 63 == 1 { !bits } else { bits | 1 << 63 }
+        }
+        match self {
+            CborFloat::Float16(v) => {
+                (total(f64_bits(v as f64)), 0, f32_bits(v))
+            }
+            CborFloat::Float32(v) => {
+                (total(f64_bits(v as f64)), 1, f32_bits(v))
+            }
+            CborFloat::Float64(v) => (total(f64_bits(v)), 2, 0),
+        }
+    }
+
     fn typ(self) -> Type {
         match self {
             CborFloat::Float16(_) => Type::Float16,
@@ -559,24 +1354,15 @@ impl CborFloat {
             CborFloat::Float64(v) => v,
         })
     }
-
-    fn to_f32(self) -> ReadResult<f32> {
-        Ok(match self {
-            CborFloat::Float16(v) => v,
-            CborFloat::Float32(v) => v,
-            _ => return Err(ReadError::ty_mismatch(Type::Float32, self.typ())),
-        })
-    }
 }
 
 impl Encodable for Cbor {
     fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
         match *self {
-            // Not sure what to do with `Break` here. I guess if we need to
-            // be able to encode, we'll have to add special support for it
-            // in the encoder.
-            Cbor::Break => unimplemented!(),
-            Cbor::Undefined => e.emit_nil(),
+            // The encoder returns an error for this, since a break can
+            // only end an indefinite length item.
+            Cbor::Break => e.emit_struct("CborBreak", 0, |_| Ok(())),
+            Cbor::Undefined => CborSimple(23).encode(e),
             Cbor::Null => e.emit_nil(),
             Cbor::Bool(v) => v.encode(e),
             Cbor::Unsigned(v) => v.encode(e),
@@ -584,13 +1370,51 @@ impl Encodable for Cbor {
             Cbor::Float(v) => v.encode(e),
             Cbor::Bytes(ref v) => v.encode(e),
             Cbor::Unicode(ref v) => v.encode(e),
-            Cbor::Array(ref v) => v.encode(e),
+            // A tuple is always an array, even if its items are all
+            // integers that fit in a `u8` (see `BytesMode`).
+            Cbor::Array(ref v) => e.emit_tuple(v.len(), |e| {
+                for (i, v) in v.iter().enumerate() {
+                    try!(e.emit_tuple_arg(i, |e| v.encode(e)));
+                }
+                Ok(())
+            }),
             Cbor::Map(ref v) => v.encode(e),
             Cbor::Tag(ref v) => v.encode(e),
+            Cbor::Simple(v) => v.encode(e),
+            Cbor::Wide(ref v) => v.encode(e),
         }
     }
 }
 
+/// Decodes any data item as is.
+///
+/// This makes it possible to leave parts of a structure undecoded, e.g., a
+/// payload whose type depends on another field. Byte strings, tags and
+/// simple values are preserved exactly.
+///
+/// This only works with the type based decoder in this crate (i.e.,
+/// `Decoder::decode`).
+impl Decodable for Cbor {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<Cbor, D::Error> {
+        // The struct name tells the decoder to hand over the data item
+        // re-encoded as CBOR, since there's no other way to pass it through
+        // the `Decoder` interface.
+        let bytes: Vec<u8> =
+            try!(d.read_struct("Cbor", 0, |d| Decodable::decode(d)));
+        match Decoder::from_bytes(bytes).items().next() {
+            Some(Ok(v)) => Ok(v),
+            Some(Err(err)) => Err(d.error(&err.to_string())),
+            None => Err(d.error("expected a data item")),
+        }
+    }
+}
+
+impl Encodable for CborTag {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        CborTagEncode::new(self.tag, &*self.data).encode(e)
+    }
+}
+
 impl Encodable for CborUnsigned {
     fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
         match *self {
@@ -639,6 +1463,165 @@ impl Decodable for CborBytes {
     }
 }
 
+impl Decodable for CborSimple {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<CborSimple, D::Error> {
+        // The struct name tells the decoder to expect a simple value, which
+        // it then hands over as a `u8`.
+        d.read_struct("CborSimple", 1, |d| d.read_u8()).map(CborSimple)
+    }
+}
+
+/// How to decode a number into a Rust type that can't represent it.
+///
+/// This only depends on the value of the number and never on how many bytes
+/// were used to encode it. For example, `5` can always be decoded into a
+/// `u8` and `300` never can, regardless of whether either was encoded as a
+/// CBOR `uint16` or `uint64`.
+///
+/// The default is `Narrowing::Error`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Narrowing {
+    /// Return an error. Integers that are out of range produce
+    /// `ReadError::IntegerOverflow` and floats that can't be represented
+    /// exactly produce `ReadError::InexactFloat`, as do integers decoded
+    /// into a float type that can't represent them exactly (e.g., 2^53 + 1
+    /// as an `f64`).
+    Error,
+    /// Convert as if with an `as` cast. Integers keep their low bits and
+    /// floats (and integers decoded into floats) are rounded to the nearest
+    /// representable value (which may be infinite).
+    Truncate,
+    /// Clamp to the nearest representable value. Integers become the
+    /// minimum or maximum of the type and finite floats that are out of
+    /// range become the largest finite value with the same sign. Integers
+    /// decoded into floats are rounded, as with `Truncate`.
+    Saturate,
+}
+
+impl Default for Narrowing {
+    fn default() -> Narrowing { Narrowing::Error }
+}
+
+/// How to decode a tagged data item into a Rust type.
+///
+/// The default is `TagPolicy::Preserve`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TagPolicy {
+    /// Decode a tag as its tag number followed by its data item. This is
+    /// what `Decodable` impls that read tags expect (see `CborTagEncode`),
+    /// but it means a tagged value can't be decoded into a type that
+    /// doesn't know about the tag.
+    Preserve,
+    /// Skip tags and decode their data items as if they weren't tagged.
+    Ignore,
+    /// Like `Preserve`, but return a `TypeMismatch` error if anything other
+    /// than a `u64` is read where a tag is found. This reports tags that
+    /// the type being decoded into doesn't handle where they are, instead
+    /// of wherever decoding happens to fail afterwards.
+    Error,
+}
+
+impl Default for TagPolicy {
+    fn default() -> TagPolicy { TagPolicy::Preserve }
+}
+
+/// What to do when a map has more than one entry with the same key.
+///
+/// The default is `DuplicateKeyPolicy::Last`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DuplicateKeyPolicy {
+    /// Keep the value of the last entry with the key. (The entry stays in
+    /// the position of the first one.)
+    Last,
+    /// Keep the value of the first entry with the key and ignore the rest.
+    First,
+    /// Return a `ReadError::DuplicateKey` error. RFC 8949 calls maps with
+    /// duplicate keys invalid, and different decoders disagree about which
+    /// value wins, so this is the safest choice for untrusted input.
+    Error,
+}
+
+impl Default for DuplicateKeyPolicy {
+    fn default() -> DuplicateKeyPolicy { DuplicateKeyPolicy::Last }
+}
+
+/// How an encoder writes an array or map whose length isn't known before
+/// its items are written (e.g., the map of a `CborFlatten`).
+///
+/// The default is `UnknownLengthPolicy::Buffer`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnknownLengthPolicy {
+    /// Buffer the items in memory until they have all been written, and
+    /// then write them with a definite length.
+    Buffer,
+    /// Write the items as they come with an indefinite length, which ends
+    /// with a break. Nothing is buffered, but not every decoder supports
+    /// indefinite lengths. Canonical mode never uses them, so it always
+    /// buffers.
+    Indefinite,
+}
+
+impl Default for UnknownLengthPolicy {
+    fn default() -> UnknownLengthPolicy { UnknownLengthPolicy::Buffer }
+}
+
+/// Whether an encoder writes sequences of `u8`s (e.g., a `Vec<u8>` or a
+/// `[u8; 32]`) as byte strings or as arrays of integers.
+///
+/// The decoders in this crate read both as sequences of `u8`s, so only the
+/// encoder needs to be told. The default is `BytesMode::Auto`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BytesMode {
+    /// A sequence whose items are all `u8`s is a byte string, and so is an
+    /// empty sequence, which has no items to tell by. So a `Vec<u8>` is
+    /// always a byte string, but an empty sequence of any other type is
+    /// too, which other decoders may not accept. Items that only hold a
+    /// `u8` (e.g., `Option<u8>`) aren't `u8`s.
+    Auto,
+    /// Every sequence is an array, as older versions of this crate wrote
+    /// them. Use `CborBytes` to write a byte string.
+    Array,
+}
+
+impl Default for BytesMode {
+    fn default() -> BytesMode { BytesMode::Auto }
+}
+
+/// How an `Option` is written and read.
+///
+/// The encoder and the decoder must use the same format. The default is
+/// `OptionFormat::Null`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OptionFormat {
+    /// `None` is `null` and `Some(v)` is `v`. This is what other CBOR
+    /// implementations expect, but it's lossy: `None`, `Some(())` and
+    /// `Some(None)` are all `null`, so e.g. an `Option<Option<u8>>` of
+    /// `Some(None)` is read back as `None`.
+    Null,
+    /// `None` is `null` and `Some(v)` is an array with `v` as its only
+    /// item. This costs a byte per `Some`, but every `Option` is read back
+    /// as it was written, however deeply they're nested.
+    Array,
+}
+
+impl Default for OptionFormat {
+    fn default() -> OptionFormat { OptionFormat::Null }
+}
+
+/// A budget of a decoder that ran out, with its size.
+///
+/// See `Decoder::max_total_bytes`, `Decoder::max_total_items` and
+/// `StreamingDecoder::max_buffer`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Budget {
+    /// The total number of bytes read.
+    Bytes(u64),
+    /// The total number of data items read.
+    Items(u64),
+    /// The number of bytes buffered by a `StreamingDecoder`.
+    Buffer(u64),
+}
+
 /// Type synonym for `Result<T, CborError>`.
 pub type CborResult<T> = Result<T, CborError>;
 
@@ -651,7 +1634,7 @@ pub enum CborError {
     /// An error as a result of an  underlying IO operation.
     Io(io::Error),
     /// An error from the type based decoder.
-    Decode(ReadError), // decoder loses byte offset information :-(
+    Decode(ReadError),
     /// An error from the type based encoder.
     Encode(WriteError),
     /// An error reading CBOR at a particular offset.
@@ -664,9 +1647,43 @@ pub enum CborError {
         /// The byte offset at which the error occurred.
         offset: usize,
     },
+    /// An error from the type based decoder at a particular location.
+    ///
+    /// The type based decoder works on CBOR abstract syntax, which doesn't
+    /// retain byte offsets of nested data items. So the location is given
+    /// as the path of the offending value inside of the top-level data
+    /// item, along with a byte offset.
+    AtPath {
+        /// The exact read error.
+        kind: ReadError,
+        /// The byte offset of the value that failed to decode, when the
+        /// data was decoded from a slice (e.g., by `from_slice_exact`).
+        /// Otherwise, the byte offset of the top-level data item that it
+        /// is in.
+        offset: usize,
+        /// The path to the value that failed to decode, e.g.,
+        /// `items[3].header.id`. Map entries are written as `["key"]`. This
+        /// is empty if the error is with the top-level value itself.
+        path: String,
+    },
+    /// An error from the type based encoder at a particular location.
+    ///
+    /// This is used for errors about a value nested in the one being
+    /// encoded, e.g., a NaN that DAG-CBOR doesn't allow.
+    EncodeAtPath {
+        /// The exact write error.
+        kind: WriteError,
+        /// The path to the value that couldn't be encoded, in the same
+        /// format as for `AtPath`, e.g., `items[3].header.id`.
+        path: String,
+    },
     /// EOF is found but more bytes were expected to decode the next data item.
     ///
     /// EOF is triggered when the underlying reader returns `0` bytes.
+    ///
+    /// Note that this is only produced by the direct decoder. Other readers
+    /// report truncated data with `ReadError::UnexpectedEOF`, which says how
+    /// many more bytes are needed.
     UnexpectedEOF,
 }
 
@@ -674,6 +1691,12 @@ impl CborError {
     fn is_eof(&self) -> bool {
         match *self {
             CborError::UnexpectedEOF => true,
+            CborError::AtOffset {
+                kind: ReadError::UnexpectedEOF { .. }, ..
+            } => true,
+            CborError::AtOffset {
+                kind: ReadError::MissingItems { eof, .. }, ..
+            } => eof,
             _ => false,
         }
     }
@@ -710,6 +1733,132 @@ pub enum ReadError {
         /// Additional information value.
         add: u8,
     },
+    /// The data ended in the middle of a data item.
+    ///
+    /// This is never produced when the data ends cleanly between top-level
+    /// data items.
+    UnexpectedEOF {
+        /// The number of additional bytes needed to finish the read that
+        /// failed. Completing the data item may require more than this.
+        needed: usize,
+        /// The byte offset at which the data ended.
+        offset: usize,
+    },
+    /// A "break" stop code was found where a data item was expected, i.e.,
+    /// outside of an indefinite length data item.
+    UnexpectedBreak {
+        /// The byte offset of the break.
+        offset: usize,
+    },
+    /// A Unicode string contains invalid UTF-8.
+    InvalidUtf8(str::Utf8Error),
+    /// Data items are nested deeper than the decoder allows (see
+    /// `Decoder::max_depth`).
+    TooDeep {
+        /// The maximum depth.
+        max: usize,
+    },
+    /// A string, array or map is longer than the decoder allows (see
+    /// `Decoder::max_size` and `Decoder::max_items`).
+    TooLong {
+        /// The type of the data item.
+        ty: Type,
+        /// Its length in bytes (for strings) or items (for arrays and
+        /// maps).
+        len: u64,
+        /// The maximum length.
+        max: u64,
+    },
+    /// A map has more than one entry with the same key and the decoder
+    /// was told not to allow that (see `Decoder::duplicate_keys`).
+    DuplicateKey(String),
+    /// Reading more data would go over one of the decoder's budgets.
+    BudgetExceeded(Budget),
+    /// A struct field is missing from a map.
+    MissingField(String),
+    /// An enum variant name isn't one of the variants of the type being
+    /// decoded.
+    UnknownVariant(String),
+    /// An array or map ended before all of the items (or entries) that its
+    /// length promised had been read.
+    ///
+    /// The byte offset of the error is where the items ran out.
+    MissingItems {
+        /// The type of the data item, i.e., `Array` or `Map`.
+        ty: Type,
+        /// The number of items (or entries) given by the length.
+        expected: u64,
+        /// The number that were read.
+        got: u64,
+        /// True if the data ended, which usually means that it was cut
+        /// short. If false, a "break" stop code was found instead, which
+        /// means that the data was written with the wrong length.
+        eof: bool,
+    },
+    /// An array has a different length than the tuple being decoded.
+    LengthMismatch {
+        /// The expected length.
+        expected: usize,
+        /// The length of the array found.
+        got: usize,
+    },
+    /// An integer is out of range for the type being decoded into.
+    ///
+    /// This depends only on the value of the integer and not on how many
+    /// bytes were used to encode it.
+    IntegerOverflow {
+        /// The type being decoded into.
+        expected: Type,
+        /// The integer found, in decimal.
+        value: String,
+    },
+    /// A float can't be represented exactly by the type being decoded into.
+    InexactFloat {
+        /// The type being decoded into.
+        expected: Type,
+        /// The float found.
+        value: f64,
+    },
+    /// Diagnostic notation (or a CDDL schema) couldn't be parsed.
+    Syntax(String),
+    /// A data item doesn't match a CDDL schema.
+    SchemaMismatch(String),
+    /// An integer, length or tag number is encoded with more bytes than
+    /// needed and the decoder was told not to allow that (see
+    /// `Decoder::strict_minimal`).
+    NonMinimalEncoding {
+        /// The byte offset of the head of the data item.
+        offset: usize,
+    },
+    /// A NaN isn't encoded as the half precision float `f9 7e 00` and the
+    /// decoder was told not to allow that (see `Decoder::strict_nans`).
+    NonCanonicalNan {
+        /// The byte offset of the float.
+        offset: usize,
+    },
+    /// A data item isn't allowed in DAG-CBOR (see `Decoder::dag_cbor`).
+    /// The string describes what was found.
+    DagCbor(String),
+    /// There is more data after a data item that should have ended the
+    /// input.
+    TrailingBytes {
+        /// The number of bytes left over.
+        remaining: usize,
+    },
+    /// The checksum of a data item read from a `checksum` envelope doesn't
+    /// match the one stored with it, i.e., the data has been corrupted.
+    ChecksumMismatch {
+        /// The checksum stored in the envelope.
+        expected: u32,
+        /// The checksum of the data item that was read.
+        got: u32,
+    },
+    /// A `Versioned` value has a version that wasn't registered with the
+    /// `Versions` decoding it.
+    UnknownVersion(u64),
+    /// An error reported by a `Decodable` implementation through
+    /// `Decoder::error`.
+    Custom(String),
     /// Some other error occurred.
     Other(String),
 }
@@ -721,7 +1870,58 @@ pub enum WriteError {
     InvalidMapKey {
         /// The received type (if that information is available).
         got: Option<Type>,
+        /// The key, if it is a number, a boolean or null.
+        key: Option<String>,
+        /// The path to the map in the value being encoded, e.g.,
+        /// `config.limits` or `items[3]`. Map entries are written as
+        /// `["key"]`. This is empty if the map is the top-level value.
+        path: String,
     },
+    /// Occurs when writing a reserved simple value (24-31).
+    InvalidSimpleValue(u8),
+    /// Occurs when a reader runs out of bytes before the length given for
+    /// a byte string.
+    ///
+    /// The part of the byte string that could be read has already been
+    /// written, so the output is not valid CBOR.
+    ShortRead {
+        /// The length of the byte string.
+        expected: u64,
+        /// The number of bytes that could be read.
+        got: u64,
+    },
+    /// Occurs when the underlying writer has no room for more data, e.g.,
+    /// when encoding into a fixed size buffer with `Encoder::from_slice`.
+    BufferTooSmall,
+    /// Occurs when writing a value that isn't allowed in DAG-CBOR (see
+    /// `Encoder::dag_cbor`). The string describes the value.
+    DagCbor(String),
+    /// Occurs when a part of a `CborFlatten` isn't a struct or a map.
+    InvalidFlatten,
+    /// Occurs when an array, a map or a struct has a different number of
+    /// items (or fields) than the length given for it, either by an
+    /// `Encodable` implementation or to `Encoder::encode_seq_from_iter`.
+    ///
+    /// An implementation that skips some fields of a struct (e.g., ones
+    /// that are `None`) has to give the number of fields it writes to
+    /// `emit_struct`, since that is the length of the map.
+    ///
+    /// The items have already been written, so the output is not valid
+    /// CBOR.
+    LengthMismatch {
+        /// The length given.
+        expected: u64,
+        /// The number of items that were written.
+        got: u64,
+    },
+    /// Occurs when an `Encodable` implementation calls the encoder out of
+    /// order, e.g., writes a map value without a key or a struct field
+    /// outside of a struct, in a way that the encoder can't follow. The
+    /// string describes what was written.
+    OutOfOrder(&'static str),
+    /// Occurs when writing a value that can't be encoded on its own, i.e.,
+    /// a `Cbor::Break`.
+    UnsupportedType(Type),
 }
 
 impl FromError<io::Error> for CborError {
@@ -753,6 +1953,19 @@ impl ReadError {
         };
         ReadError::TypeMismatch { expected: expected, got: ty }
     }
+
+    fn overflow<T: fmt::Display>(expected: Type, value: T) -> ReadError {
+        ReadError::IntegerOverflow {
+            expected: expected,
+            value: value.to_string(),
+        }
+    }
+
+    /// An overflow for the negative integer `-1 - n`, which might not fit
+    /// in an `i64`.
+    fn neg_overflow(expected: Type, n: u64) -> ReadError {
+        ReadError::overflow(expected, negative_string(n))
+    }
 }
 
 impl fmt::Display for CborError {
@@ -768,6 +1981,17 @@ impl fmt::Display for CborError {
             CborError::AtOffset { ref kind, offset } => {
                 write!(f, "Error at byte offset {:?}: {}", offset, kind)
             }
+            CborError::AtPath { ref kind, offset, ref path } => {
+                if path.is_empty() {
+                    write!(f, "Error at byte offset {:?}: {}", offset, kind)
+                } else {
+                    write!(f, "Error at byte offset {:?} ({}): {}",
+                           offset, path, kind)
+                }
+            }
+            CborError::EncodeAtPath { ref kind, ref path } => {
+                write!(f, "Error while encoding {}: {}", path, kind)
+            }
             CborError::UnexpectedEOF => write!(f, "Unexpected EOF."),
         }
     }
@@ -791,6 +2015,88 @@ impl fmt::Display for ReadError {
                 write!(f, "Found reserved value (major type: {:?}, \
                            additional information: {:?})", major, add)
             }
+            ReadError::UnexpectedEOF { needed, offset } => {
+                write!(f, "Unexpected EOF at byte offset {:?} \
+                           ({:?} more bytes needed).", offset, needed)
+            }
+            ReadError::UnexpectedBreak { offset } => {
+                write!(f, "Unexpected break at byte offset {:?}.", offset)
+            }
+            ReadError::InvalidUtf8(ref err) => {
+                write!(f, "Invalid UTF-8 in Unicode string: {}", err)
+            }
+            ReadError::TooDeep { max } => {
+                write!(f, "Data items are nested more than {} deep.", max)
+            }
+            ReadError::TooLong { ty, len, max } => {
+                write!(f, "Length {} of {:?} is over the maximum of {}.",
+                       len, ty, max)
+            }
+            ReadError::DuplicateKey(ref key) => {
+                write!(f, "Duplicate map key '{}'.", key)
+            }
+            ReadError::BudgetExceeded(Budget::Bytes(max)) => {
+                write!(f, "Reading more than {} bytes in total isn't \
+                           allowed.", max)
+            }
+            ReadError::BudgetExceeded(Budget::Items(max)) => {
+                write!(f, "Reading more than {} data items in total isn't \
+                           allowed.", max)
+            }
+            ReadError::BudgetExceeded(Budget::Buffer(max)) => {
+                write!(f, "Buffering more than {} bytes isn't allowed.", max)
+            }
+            ReadError::MissingField(ref name) => {
+                write!(f, "Missing field '{}' in map object.", name)
+            }
+            ReadError::UnknownVariant(ref name) => {
+                write!(f, "Unknown variant name '{}'.", name)
+            }
+            ReadError::MissingItems { ty, expected, got, eof } => {
+                let why = if eof { "the data ended" } else { "found a break" };
+                write!(f, "Expected {} items in {:?}, but {} after {}.",
+                       expected, ty, why, got)
+            }
+            ReadError::LengthMismatch { expected, got } => {
+                write!(f, "Expected tuple of length {:?}, but got array of \
+                           length {:?}", expected, got)
+            }
+            ReadError::IntegerOverflow { expected, ref value } => {
+                write!(f, "Integer {} is out of range for type {:?}.",
+                       value, expected)
+            }
+            ReadError::InexactFloat { expected, value } => {
+                write!(f, "Float {} can't be represented exactly as \
+                           type {:?}.", value, expected)
+            }
+            ReadError::Syntax(ref s) => {
+                write!(f, "Syntax error: {}", s)
+            }
+            ReadError::SchemaMismatch(ref s) => {
+                write!(f, "Data item doesn't match the schema: {}", s)
+            }
+            ReadError::NonMinimalEncoding { offset } => {
+                write!(f, "Non-minimal encoding at byte offset {:?}.",
+                       offset)
+            }
+            ReadError::NonCanonicalNan { offset } => {
+                write!(f, "Non-canonical NaN at byte offset {:?}.", offset)
+            }
+            ReadError::DagCbor(ref what) => {
+                write!(f, "DAG-CBOR doesn't allow {}.", what)
+            }
+            ReadError::TrailingBytes { remaining } => {
+                write!(f, "Found {} bytes after the end of the data item.",
+                       remaining)
+            }
+            ReadError::ChecksumMismatch { expected, got } => {
+                write!(f, "Expected checksum {:#010x}, but got {:#010x}.",
+                       expected, got)
+            }
+            ReadError::UnknownVersion(version) => {
+                write!(f, "Found unknown version {}.", version)
+            }
+            ReadError::Custom(ref s) => write!(f, "{}", s),
             ReadError::Other(ref s) => write!(f, "{}", s),
         }
     }
@@ -799,19 +2105,229 @@ impl fmt::Display for ReadError {
 impl fmt::Display for WriteError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            WriteError::InvalidMapKey { got: Some(got) } => {
-                write!(f, "Found invalid map key ({:?}), \
-                           expected Unicode string.", got)
+            WriteError::InvalidMapKey { got, ref key, ref path } => {
+                try!(write!(f, "Found invalid map key"));
+                if let Some(ref key) = *key {
+                    try!(write!(f, " {}", key));
+                }
+                if let Some(got) = got {
+                    try!(write!(f, " ({:?})", got));
+                }
+                if !path.is_empty() {
+                    try!(write!(f, " in map at {}", path));
+                }
+                write!(f, ", expected Unicode string.")
+            }
+            WriteError::InvalidSimpleValue(v) => {
+                write!(f, "Simple value {:?} is reserved.", v)
+            }
+            WriteError::ShortRead { expected, got } => {
+                write!(f, "Expected {} bytes for byte string, but only {} \
+                           could be read.", expected, got)
+            }
+            WriteError::BufferTooSmall => {
+                write!(f, "The buffer is too small for the encoded data.")
+            }
+            WriteError::DagCbor(ref what) => {
+                write!(f, "DAG-CBOR doesn't allow {}.", what)
+            }
+            WriteError::InvalidFlatten => {
+                write!(f, "Only structs and maps can be flattened.")
             }
-            WriteError::InvalidMapKey { got: None } => {
-                write!(f, "Found invalid map key, expected Unicode string.")
+            WriteError::LengthMismatch { expected, got } => {
+                write!(f, "Expected {} items, but {} were written.",
+                       expected, got)
+            }
+            WriteError::OutOfOrder(what) => {
+                write!(f, "Found {} out of order.", what)
+            }
+            WriteError::UnsupportedType(ty) => {
+                write!(f, "A value of type {:?} can't be encoded.", ty)
             }
         }
     }
 }
 
+
+impl error::Error for CborError {
+    fn description(&self) -> &str {
+        match *self {
+            CborError::Io(ref err) => err.description(),
+            CborError::Decode(ref err) => err.description(),
+            CborError::Encode(ref err) => err.description(),
+            CborError::AtOffset { ref kind, .. } => kind.description(),
+            CborError::AtPath { ref kind, .. } => kind.description(),
+            CborError::EncodeAtPath { ref kind, .. } => kind.description(),
+            CborError::UnexpectedEOF => "unexpected EOF",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            CborError::Io(ref err) => Some(err),
+            CborError::Decode(ref err) => Some(err),
+            CborError::Encode(ref err) => Some(err),
+            CborError::AtOffset { ref kind, .. } => Some(kind),
+            CborError::AtPath { ref kind, .. } => Some(kind),
+            CborError::EncodeAtPath { ref kind, .. } => Some(kind),
+            CborError::UnexpectedEOF => None,
+        }
+    }
+}
+
+impl error::Error for ReadError {
+    fn description(&self) -> &str {
+        match *self {
+            ReadError::TypeMismatch { .. } => "type mismatch",
+            ReadError::InvalidAddValue { .. } => {
+                "invalid additional information"
+            }
+            ReadError::Unassigned { .. } => "unassigned value",
+            ReadError::Reserved { .. } => "reserved value",
+            ReadError::UnexpectedEOF { .. } => "unexpected EOF",
+            ReadError::UnexpectedBreak { .. } => "unexpected break",
+            ReadError::InvalidUtf8(ref err) => err.description(),
+            ReadError::TooDeep { .. } => "nested too deep",
+            ReadError::TooLong { .. } => "too long",
+            ReadError::DuplicateKey(_) => "duplicate map key",
+            ReadError::BudgetExceeded(_) => "budget exceeded",
+            ReadError::MissingField(_) => "missing field",
+            ReadError::UnknownVariant(_) => "unknown variant",
+            ReadError::MissingItems { .. } => "missing items",
+            ReadError::LengthMismatch { .. } => "length mismatch",
+            ReadError::IntegerOverflow { .. } => "integer overflow",
+            ReadError::InexactFloat { .. } => "inexact float",
+            ReadError::Syntax(_) => "syntax error",
+            ReadError::SchemaMismatch(_) => "schema mismatch",
+            ReadError::NonMinimalEncoding { .. } => "non-minimal encoding",
+            ReadError::NonCanonicalNan { .. } => "non-canonical NaN",
+            ReadError::DagCbor(_) => "not allowed in DAG-CBOR",
+            ReadError::TrailingBytes { .. } => "trailing bytes",
+            ReadError::ChecksumMismatch { .. } => "checksum mismatch",
+            ReadError::UnknownVersion(_) => "unknown version",
+            ReadError::Custom(ref s) => s,
+            ReadError::Other(ref s) => s,
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ReadError::InvalidUtf8(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn description(&self) -> &str {
+        match *self {
+            WriteError::InvalidMapKey { .. } => "invalid map key",
+            WriteError::InvalidSimpleValue(_) => "reserved simple value",
+            WriteError::ShortRead { .. } => "short read for byte string",
+            WriteError::BufferTooSmall => "buffer too small",
+            WriteError::DagCbor(_) => "not allowed in DAG-CBOR",
+            WriteError::InvalidFlatten => "flattened value isn't a map",
+            WriteError::LengthMismatch { .. } => "wrong number of items",
+            WriteError::OutOfOrder(_) => "encoder called out of order",
+            WriteError::UnsupportedType(_) => "unsupported type",
+        }
+    }
+}
+
+fn f32_bits(v: f32) -> u32 {
+    unsafe { ::std::mem::transmute(v) }
+}
+
+fn f64_bits(v: f64) -> u64 {
+    unsafe { ::std::mem::transmute(v) }
+}
+
+/// Returns the decimal digits of the negative integer `-1 - arg`.
+fn negative_string(arg: u64) -> String {
+    if arg == ::std::u64::MAX {
+        "-18446744073709551616".to_string()
+    } else {
+        format!("-{}", arg + 1)
+    }
+}
+
+/// Appends the shortest head of a data item with the major type `major`
+/// and the argument `arg` to `out`.
+fn write_head(out: &mut Vec<u8>, major: u8, arg: u64) {
+    let width = CborWide::width_of(arg);
+    out.push((major << 5) | match width {
+        0 => arg as u8,
+        1 => 24,
+        2 => 25,
+        4 => 26,
+        _ => 27,
+    });
+    for i in (0..width).rev() {
+        out.push((arg >> (8 * i)) as u8);
+    }
+}
+
+/// Returns true if an integer whose magnitude is `mag` can be represented
+/// exactly by a float with a mantissa of `digits` bits.
+fn int_fits_float(mag: u64, digits: u32) -> bool {
+    mag == 0 || 64 - mag.leading_zeros() - mag.trailing_zeros() <= digits
+}
+
+fn errat(err: ReadError, offset: usize) -> CborError {
+    CborError::AtOffset { kind: err, offset: offset }
+}
+
+fn eof(needed: usize, offset: usize) -> CborError {
+    errat(ReadError::UnexpectedEOF { needed: needed, offset: offset }, offset)
+}
+
+/// The default maximum depth of data items for `Decoder::max_depth` and
+/// `StreamingDecoder::max_depth`.
+const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Returns the minimum length of a string that is assigned the next index
+/// in a stringref namespace, given the number of strings assigned so far.
+///
+/// A reference to a shorter string wouldn't be any shorter than the string
+/// itself.
+fn stringref_min_len(assigned: u64) -> usize {
+    if assigned < 24 {
+        3
+    } else if assigned < 256 {
+        4
+    } else if assigned < 65536 {
+        5
+    } else if assigned < 1 << 32 {
+        7
+    } else {
+        11
+    }
+}
+
+mod access;
+mod annotate;
+mod canonical;
+pub mod checksum;
+pub mod cose;
+pub mod cwt;
+#[cfg(feature = "cddl")]
+pub mod cddl;
 mod decoder;
+mod diag;
 mod encoder;
+pub mod framed;
+pub mod fuzz;
 mod json;
+mod lazy;
+mod lexer;
+mod map;
+mod packed;
+mod projection;
 mod rustc_decoder;
 mod rustc_decoder_direct;
+mod slice;
+mod streaming;
+#[cfg(feature = "testing")]
+mod testing;
+mod to_cbor;
+mod versioned;
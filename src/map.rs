@@ -0,0 +1,368 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::slice;
+use std::vec;
+
+use rustc_serialize::{Encodable, Encoder as RustcEncoder};
+
+use {Cbor, CborError, CborResult, Decoder, Encoder, ReadError, SliceDecoder,
+     write_head};
+
+/// A CBOR map that remembers the order of its entries.
+///
+/// Entries are kept in the order they were decoded (or inserted) in, and
+/// they are encoded in that same order. This means that decoding and then
+/// re-encoding a map reproduces the original bytes, which matters when the
+/// bytes have been signed or hashed.
+///
+/// Keys are also kept in a hash table, so lookups and inserts take constant
+/// time no matter how many entries a map has.
+///
+/// Two maps are equal if they have the same entries, regardless of order.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{Cbor, CborMap, Decoder, Encoder};
+///
+/// // {"b": 1, "a": 2}
+/// let bytes = vec![0xa2, 0x61, b'b', 0x01, 0x61, b'a', 0x02];
+/// let item = Decoder::from_bytes(&bytes[..]).items().next().unwrap();
+/// let map = match item.unwrap() {
+///     Cbor::Map(map) => map,
+///     _ => unreachable!(),
+/// };
+/// assert_eq!(map.keys().collect::<Vec<_>>(), vec!["b", "a"]);
+///
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[map]).unwrap();
+/// assert_eq!(enc.as_bytes(), &bytes[..]);
+/// ```
+#[derive(Clone, Default)]
+pub struct CborMap {
+    entries: Vec<(String, Cbor)>,
+    // Maps each key to the position of its entry in `entries`.
+    index: HashMap<String, usize>,
+}
+
+impl CborMap {
+    /// Create a new empty map.
+    pub fn new() -> CborMap {
+        CborMap { entries: vec![], index: HashMap::new() }
+    }
+
+    /// Create a new empty map with room for `cap` entries.
+    pub fn with_capacity(cap: usize) -> CborMap {
+        CborMap {
+            entries: Vec::with_capacity(cap),
+            index: HashMap::with_capacity(cap),
+        }
+    }
+
+    /// Add an entry to the end of this map. If an entry with the same key
+    /// already exists, then its value is replaced (keeping its position)
+    /// and the old value is returned.
+    pub fn insert<K: Into<String>>(&mut self, key: K, val: Cbor)
+                                  -> Option<Cbor> {
+        let key = key.into();
+        match self.position(&key) {
+            Some(i) => Some(::std::mem::replace(&mut self.entries[i].1, val)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, val));
+                None
+            }
+        }
+    }
+
+    /// Returns the value associated with `key`, if it exists.
+    pub fn get(&self, key: &str) -> Option<&Cbor> {
+        self.position(key).map(|i| &self.entries[i].1)
+    }
+
+    /// Returns a mutable reference to the value associated with `key`, if
+    /// it exists.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Cbor> {
+        match self.position(key) {
+            Some(i) => Some(&mut self.entries[i].1),
+            None => None,
+        }
+    }
+
+    /// Returns true if this map has an entry for `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    /// Removes the entry for `key` and returns its value, if it exists.
+    /// The order of the remaining entries is unchanged.
+    pub fn remove(&mut self, key: &str) -> Option<Cbor> {
+        let i = match self.index.remove(key) {
+            None => return None,
+            Some(i) => i,
+        };
+        let (_, val) = self.entries.remove(i);
+        for e in &self.entries[i..] {
+            if let Some(pos) = self.index.get_mut(&e.0) {
+                *pos -= 1;
+            }
+        }
+        Some(val)
+    }
+
+    /// Returns the number of entries in this map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if this map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the entries of this map in order.
+    pub fn iter(&self) -> Iter {
+        Iter { it: self.entries.iter() }
+    }
+
+    /// Returns an iterator over the keys of this map in order.
+    pub fn keys(&self) -> Keys {
+        Keys { it: self.entries.iter() }
+    }
+
+    /// Returns an iterator over the values of this map in order.
+    pub fn values(&self) -> Values {
+        Values { it: self.entries.iter() }
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.index.get(key).cloned()
+    }
+
+    /// Returns the entries sorted by key, which is how maps are compared
+    /// and hashed.
+    fn sorted(&self) -> Vec<(&String, &Cbor)> {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+impl PartialEq for CborMap {
+    fn eq(&self, other: &CborMap) -> bool {
+        self.len() == other.len()
+        && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl Eq for CborMap {}
+
+impl PartialOrd for CborMap {
+    fn partial_cmp(&self, other: &CborMap) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CborMap {
+    fn cmp(&self, other: &CborMap) -> Ordering {
+        self.sorted().cmp(&other.sorted())
+    }
+}
+
+impl Hash for CborMap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sorted().hash(state)
+    }
+}
+
+impl fmt::Debug for CborMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+        for (i, (k, v)) in self.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, ", "));
+            }
+            try!(write!(f, "{:?}: {:?}", k, v));
+        }
+        write!(f, "}}")
+    }
+}
+
+impl<'a> Index<&'a str> for CborMap {
+    type Output = Cbor;
+
+    fn index(&self, key: &str) -> &Cbor {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl FromIterator<(String, Cbor)> for CborMap {
+    fn from_iter<I>(it: I) -> CborMap
+            where I: IntoIterator<Item=(String, Cbor)> {
+        let mut map = CborMap::new();
+        for (k, v) in it {
+            map.insert(k, v);
+        }
+        map
+    }
+}
+
+impl IntoIterator for CborMap {
+    type Item = (String, Cbor);
+    type IntoIter = vec::IntoIter<(String, Cbor)>;
+
+    fn into_iter(self) -> vec::IntoIter<(String, Cbor)> {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a CborMap {
+    type Item = (&'a String, &'a Cbor);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl Encodable for CborMap {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        e.emit_map(self.len(), |e| {
+            for (i, (k, v)) in self.iter().enumerate() {
+                try!(e.emit_map_elt_key(i, |e| k.encode(e)));
+                try!(e.emit_map_elt_val(i, |e| v.encode(e)));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Decodes a map whose keys can be any data items.
+///
+/// `CborMap` (and so `Cbor`) only has Unicode string keys, but some
+/// protocols use other keys, e.g., the integer labels of COSE headers.
+/// This reads the map in `bytes`, which must be exactly one data item, into
+/// a `BTreeMap` keyed by `Cbor` values. The keys and values themselves are
+/// decoded like `Decoder::items`, so maps nested in them still need string
+/// keys. If a key occurs more than once, the last value wins.
+///
+/// Byte offsets in errors are relative to the start of `bytes`.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{Cbor, CborUnsigned};
+///
+/// // {1: "a", -1: "b", "c": 2}
+/// let bytes = [0xa3, 0x01, 0x61, b'a', 0x20, 0x61, b'b',
+///              0x61, b'c', 0x02];
+/// let map = cbor::decode_any_map(&bytes).unwrap();
+/// let key = Cbor::Unsigned(CborUnsigned::UInt8(1));
+/// assert_eq!(map[&key], Cbor::Unicode("a".to_string()));
+///
+/// // Entries are written in the order of their keys.
+/// assert_eq!(cbor::encode_any_map(&map).unwrap(), vec![
+///     0xa3, 0x20, 0x61, b'b', 0x01, 0x61, b'a', 0x61, b'c', 0x02,
+/// ]);
+/// ```
+pub fn decode_any_map(bytes: &[u8]) -> CborResult<BTreeMap<Cbor, Cbor>> {
+    let mut dec = SliceDecoder::new(bytes);
+    let len = try!(dec.read_map());
+    let mut map = BTreeMap::new();
+    let mut n = 0;
+    loop {
+        let done = match len {
+            Some(len) => n == len,
+            None => try!(dec.read_break()),
+        };
+        if done {
+            break;
+        }
+        let key = try!(decode_item(bytes, &mut dec));
+        let val = try!(decode_item(bytes, &mut dec));
+        map.insert(key, val);
+        n += 1;
+    }
+    if !dec.is_empty() {
+        return Err(CborError::AtOffset {
+            kind: ReadError::TrailingBytes {
+                remaining: bytes.len() - dec.offset(),
+            },
+            offset: dec.offset(),
+        });
+    }
+    Ok(map)
+}
+
+/// Encodes a map whose keys can be any data items (see `decode_any_map`).
+///
+/// Entries are written in the order of their keys, so the same map always
+/// encodes to the same bytes.
+pub fn encode_any_map(map: &BTreeMap<Cbor, Cbor>) -> CborResult<Vec<u8>> {
+    let mut enc = Encoder::from_memory();
+    for (k, v) in map {
+        try!(enc.encode(&[k, v]));
+    }
+    let mut bytes = vec![];
+    write_head(&mut bytes, 5, map.len() as u64);
+    bytes.extend(enc.as_bytes().iter().cloned());
+    Ok(bytes)
+}
+
+/// Decodes the next data item of `dec`, which reads `bytes`.
+pub fn decode_item(bytes: &[u8], dec: &mut SliceDecoder) -> CborResult<Cbor> {
+    let start = dec.offset();
+    try!(dec.skip());
+    let item = &bytes[start..dec.offset()];
+    match Decoder::from_slice(item).items().next() {
+        Some(Ok(v)) => Ok(v),
+        Some(Err(CborError::AtOffset { kind, offset })) => {
+            Err(CborError::AtOffset { kind: kind, offset: start + offset })
+        }
+        Some(Err(err)) => Err(err),
+        None => unreachable!(),
+    }
+}
+
+/// An iterator over the entries of a `CborMap` in order.
+pub struct Iter<'a> {
+    it: slice::Iter<'a, (String, Cbor)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a Cbor);
+
+    fn next(&mut self) -> Option<(&'a String, &'a Cbor)> {
+        self.it.next().map(|&(ref k, ref v)| (k, v))
+    }
+}
+
+/// An iterator over the keys of a `CborMap` in order.
+pub struct Keys<'a> {
+    it: slice::Iter<'a, (String, Cbor)>,
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = &'a String;
+
+    fn next(&mut self) -> Option<&'a String> {
+        self.it.next().map(|e| &e.0)
+    }
+}
+
+/// An iterator over the values of a `CborMap` in order.
+pub struct Values<'a> {
+    it: slice::Iter<'a, (String, Cbor)>,
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = &'a Cbor;
+
+    fn next(&mut self) -> Option<&'a Cbor> {
+        self.it.next().map(|e| &e.1)
+    }
+}
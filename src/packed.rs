@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use lexer::{Lexer, Token, validate};
+use slice::SliceDecoder;
+use {CborError, CborResult, ReadError, errat, write_head};
+
+/// The tag that sets up the tables of packed CBOR.
+const SETUP_TAG: u64 = 113;
+
+/// The tag that refers to shared items past the ones that fit in simple
+/// values.
+const REF_TAG: u64 = 6;
+
+/// Compresses a data item into packed CBOR (draft-ietf-cbor-packed) by
+/// sharing its repeated strings.
+///
+/// Every definite length byte or Unicode string (including map keys) that
+/// occurs often enough to make it worthwhile is put in a table of shared
+/// items, and each occurrence is replaced by a reference to it: one of the
+/// simple values 0 to 15 for the first sixteen items, and tag 6 around an
+/// integer for the rest. The result is `113([shared, [], rump])`, where the
+/// rump is `bytes` with its strings replaced. If that isn't any smaller
+/// than `bytes`, then `bytes` is returned as is.
+///
+/// `bytes` must hold exactly one well formed data item (see `validate`).
+/// Since they would be read as references, it can't already use the simple
+/// values 0 to 15 or tags 6 and 113.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::Encoder;
+///
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[vec![("temperature", 21), ("temperature", 22)]]).unwrap();
+/// let packed = cbor::pack(enc.as_bytes()).unwrap();
+/// assert_eq!(packed, vec![
+///     0xd8, 0x71, 0x83,
+///     0x81, 0x6b, b't', b'e', b'm', b'p', b'e', b'r', b'a', b't', b'u',
+///           b'r', b'e',
+///     0x80,
+///     0x82, 0x82, 0xe0, 0x15, 0x82, 0xe0, 0x16,
+/// ]);
+///
+/// let unpacked = cbor::unpack(&packed).unwrap();
+/// assert_eq!(unpacked, enc.as_bytes());
+/// ```
+pub fn pack(bytes: &[u8]) -> CborResult<Vec<u8>> {
+    try!(validate(bytes));
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    try!(each_token(bytes, |at, tok, shareable, raw| {
+        match tok {
+            Token::Simple(0...15)
+            | Token::Tag(REF_TAG) | Token::Tag(SETUP_TAG) => {
+                return Err(errat(ReadError::Other(
+                    "Data that uses simple values 0 to 15 or tags 6 or 113 \
+                     can't be packed.".to_string()), at));
+            }
+            _ if shareable => *counts.entry(raw).or_insert(0) += 1,
+            _ => {}
+        }
+        Ok(())
+    }));
+
+    // Strings that save the most go first, since they get the shortest
+    // references. Ties are broken by the strings, so that the output is
+    // always the same.
+    let mut repeated: Vec<(&[u8], usize)> =
+        counts.into_iter().filter(|&(_, n)| n > 1).collect();
+    repeated.sort_by(|a, b| {
+        (b.1 * b.0.len(), a.0).cmp(&(a.1 * a.0.len(), b.0))
+    });
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    let mut shared = vec![];
+    for (raw, n) in repeated {
+        let mut reference = vec![];
+        write_ref(&mut reference, shared.len());
+        // The string itself is still written once, in the table.
+        if n * reference.len() + raw.len() < n * raw.len() {
+            index.insert(raw, shared.len());
+            shared.push(raw);
+        }
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    write_head(&mut out, 6, SETUP_TAG);
+    write_head(&mut out, 4, 3);
+    write_head(&mut out, 4, shared.len() as u64);
+    for raw in &shared {
+        out.extend(raw.iter().cloned());
+    }
+    write_head(&mut out, 4, 0);
+    try!(each_token(bytes, |_, _, shareable, raw| {
+        match index.get(raw) {
+            Some(&i) if shareable => write_ref(&mut out, i),
+            _ => out.extend(raw.iter().cloned()),
+        }
+        Ok(())
+    }));
+    if out.len() >= bytes.len() {
+        return Ok(bytes.to_vec());
+    }
+    Ok(out)
+}
+
+/// Expands packed CBOR (draft-ietf-cbor-packed) back into the data item it
+/// stands for.
+///
+/// If `bytes` holds a table setup (tag 113), then every reference to a
+/// shared item in its rump is replaced by the shared item, and the rump is
+/// returned. Anything else is returned as is. The result can be read like
+/// any other CBOR data, e.g., with `Decoder::from_bytes`.
+///
+/// Only shared items are supported: a setup with argument items, or with
+/// references inside of its shared items, returns an error. `bytes` must
+/// hold exactly one well formed data item (see `validate`).
+pub fn unpack(bytes: &[u8]) -> CborResult<Vec<u8>> {
+    try!(validate(bytes));
+    match Lexer::new(bytes).next() {
+        Some(Ok((_, Token::Tag(SETUP_TAG)))) => {}
+        _ => return Ok(bytes.to_vec()),
+    }
+    let mut dec = SliceDecoder::new(bytes);
+    try!(dec.read_tag());
+    let at = dec.offset();
+    if try!(dec.read_array()) != Some(3) {
+        return Err(unsupported("a table setup that isn't an array of 3",
+                               at));
+    }
+    let len = try!(dec.read_array());
+    let mut shared = vec![];
+    while len.map_or(!try!(dec.read_break()), |len| shared.len() < len) {
+        let start = dec.offset();
+        try!(dec.skip());
+        shared.push((start, &bytes[start..dec.offset()]));
+    }
+    for &(at, item) in &shared {
+        try!(expand(item, at, None, &mut vec![]));
+    }
+    let at = dec.offset();
+    match try!(dec.read_array()) {
+        Some(0) => {}
+        None if try!(dec.read_break()) => {}
+        _ => return Err(unsupported("argument items", at)),
+    }
+    let start = dec.offset();
+    let mut out = Vec::with_capacity(bytes.len());
+    try!(expand(&bytes[start..], start, Some(&shared), &mut out));
+    Ok(out)
+}
+
+/// Copies `bytes`, which start at byte offset `base` of the input, to
+/// `out` while replacing references with the items in `table`.
+///
+/// `table` is `None` for the shared items themselves, which can't have
+/// references.
+fn expand(bytes: &[u8], base: usize, table: Option<&[(usize, &[u8])]>,
+          out: &mut Vec<u8>) -> CborResult<()> {
+    let mut lex = Lexer::new(bytes);
+    while let Some(r) = lex.next() {
+        let (at, tok) = try!(r);
+        let index = match tok {
+            Token::Simple(v) if v < 16 => v as u64,
+            Token::Tag(REF_TAG) => match lex.next() {
+                // Even indexes are written as unsigned integers and odd
+                // ones as negative integers.
+                Some(Ok((_, Token::UInt(n)))) => {
+                    n.saturating_mul(2).saturating_add(16)
+                }
+                Some(Ok((_, Token::NegInt(n)))) => {
+                    n.saturating_mul(2).saturating_add(17)
+                }
+                _ => {
+                    return Err(unsupported("tag 6 around anything but an \
+                                            integer", base + at));
+                }
+            },
+            _ => {
+                out.extend(bytes[at..lex.offset()].iter().cloned());
+                continue;
+            }
+        };
+        let table = match table {
+            Some(table) => table,
+            None => {
+                return Err(unsupported("references inside of shared items",
+                                       base + at));
+            }
+        };
+        match table.get(index as usize) {
+            Some(&(_, item)) if index < table.len() as u64 => {
+                out.extend(item.iter().cloned())
+            }
+            _ => {
+                return Err(errat(ReadError::Other(format!(
+                    "Shared item {} doesn't exist.", index)), base + at));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` with the byte offset, the token, whether it is a string that
+/// could be shared and the encoded bytes of each token in `bytes`.
+///
+/// The chunks of an indefinite length string can't be shared, since they
+/// can't be replaced by references.
+fn each_token<'a, F>(bytes: &'a [u8], mut f: F) -> CborResult<()>
+        where F: FnMut(usize, Token<'a>, bool, &'a [u8]) -> CborResult<()> {
+    let mut lex = Lexer::new(bytes);
+    let mut chunked = false;
+    while let Some(r) = lex.next() {
+        let (at, tok) = try!(r);
+        let shareable = match tok {
+            Token::Bytes(_) | Token::Text(_) => !chunked,
+            Token::BeginBytes | Token::BeginText => { chunked = true; false }
+            Token::Break => { chunked = false; false }
+            _ => false,
+        };
+        try!(f(at, tok, shareable, &bytes[at..lex.offset()]));
+    }
+    Ok(())
+}
+
+fn write_ref(out: &mut Vec<u8>, i: usize) {
+    if i < 16 {
+        write_head(out, 7, i as u64);
+    } else {
+        let n = (i - 16) as u64;
+        write_head(out, 6, REF_TAG);
+        write_head(out, (n % 2) as u8, n / 2);
+    }
+}
+
+fn unsupported(what: &str, at: usize) -> CborError {
+    errat(ReadError::Other(format!("Packed CBOR with {} isn't supported.",
+                                   what)), at)
+}
@@ -0,0 +1,181 @@
+use rustc_serialize::Decodable;
+
+use map::decode_item;
+use rustc_decoder::{CborDecoder, Options};
+use {Cbor, CborError, CborMap, CborResult, ReadError, SliceDecoder, Type};
+
+/// Decodes only the parts of a data item that are listed, and skips the
+/// rest without decoding it.
+///
+/// Each path is written like a JSON Pointer (see `Cbor::pointer`), e.g.,
+/// `/user/name` or `/items/0`, where the leading `/` is optional. A
+/// segment of `*` selects every item of an array or every value of a map.
+/// Everything under a listed path is kept.
+///
+/// The result has the same shape as the original data item, but maps and
+/// arrays only have the entries that lead to a listed path. (So the indexes
+/// of array items can change.) Anything that isn't
+/// listed is skipped over in the encoded bytes, which is much faster than
+/// decoding it when only a few fields of large records are needed.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate cbor;
+/// # extern crate rustc_serialize;
+/// use cbor::{Cbor, Projection};
+///
+/// #[derive(RustcDecodable)]
+/// struct Sale { id: u64, total: u64 }
+///
+/// # fn main() {
+/// let item = Cbor::from_diagnostic(r#"{
+///     "id": 7, "customer": {"name": "x", "notes": "..."},
+///     "lines": [{"sku": "a", "qty": 2}, {"sku": "b", "qty": 1}],
+///     "total": 30
+/// }"#).unwrap();
+/// let mut enc = cbor::Encoder::from_memory();
+/// enc.encode(&[item]).unwrap();
+///
+/// let proj = Projection::new().path("/customer/name")
+///                              .path("/lines/*/qty");
+/// assert_eq!(proj.project(enc.as_bytes()).unwrap().to_string(),
+///            r#"{"customer": {"name": "x"}, "#.to_string()
+///            + r#""lines": [{"qty": 2}, {"qty": 1}]}"#);
+///
+/// let sale: Sale = Projection::new().path("id").path("total")
+///                                   .decode(enc.as_bytes()).unwrap();
+/// assert_eq!((sale.id, sale.total), (7, 30));
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Projection {
+    root: Node,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    /// True if everything under this node is kept.
+    all: bool,
+    children: Vec<(String, Node)>,
+}
+
+impl Projection {
+    /// Create a new projection that doesn't select anything.
+    pub fn new() -> Projection {
+        Projection::default()
+    }
+
+    /// Adds a path to the parts to decode, and returns the projection.
+    pub fn path(mut self, path: &str) -> Projection {
+        let path = if path.starts_with('/') { &path[1..] } else { path };
+        let mut node = &mut self.root;
+        if !path.is_empty() {
+            for seg in path.split('/') {
+                let seg = seg.replace("~1", "/").replace("~0", "~");
+                let found = node.children.iter().position(|c| c.0 == seg);
+                let i = match found {
+                    Some(i) => i,
+                    None => {
+                        node.children.push((seg, Node::default()));
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut {node}.children[i].1;
+            }
+        }
+        node.all = true;
+        self
+    }
+
+    /// Decodes the listed parts of `bytes`, which must hold exactly one
+    /// data item.
+    ///
+    /// If nothing is selected (e.g., the data item isn't a map or an
+    /// array), then the result is `Cbor::Null`.
+    pub fn project(&self, bytes: &[u8]) -> CborResult<Cbor> {
+        let mut dec = SliceDecoder::new(bytes);
+        let v = try!(project(bytes, &mut dec, &self.root));
+        if !dec.is_empty() {
+            return Err(CborError::AtOffset {
+                kind: ReadError::TrailingBytes {
+                    remaining: bytes.len() - dec.offset(),
+                },
+                offset: dec.offset(),
+            });
+        }
+        Ok(v.unwrap_or(Cbor::Null))
+    }
+
+    /// Decodes the listed parts of `bytes` into a Rust value, e.g., a
+    /// struct with only those fields.
+    pub fn decode<D: Decodable>(&self, bytes: &[u8]) -> CborResult<D> {
+        let v = try!(self.project(bytes));
+        CborDecoder::decode_at(v, 0, Options::default())
+    }
+}
+
+impl Node {
+    fn child(&self, seg: &str) -> Option<&Node> {
+        self.children.iter().find(|c| c.0 == seg)
+            .or_else(|| self.children.iter().find(|c| c.0 == "*"))
+            .map(|c| &c.1)
+    }
+}
+
+/// Decodes the parts of the next data item of `dec` that `node` selects,
+/// and returns `None` if it doesn't select anything.
+fn project(bytes: &[u8], dec: &mut SliceDecoder, node: &Node)
+          -> CborResult<Option<Cbor>> {
+    if node.all {
+        return decode_item(bytes, dec).map(Some);
+    }
+    let is_map = match try!(dec.peek_type()) {
+        Type::Map if !node.children.is_empty() => true,
+        Type::Array if !node.children.is_empty() => false,
+        _ => {
+            try!(dec.skip());
+            return Ok(None);
+        }
+    };
+    let len = try!(if is_map { dec.read_map() } else { dec.read_array() });
+    let (mut map, mut items) = (CborMap::new(), vec![]);
+    let mut n = 0;
+    loop {
+        let done = match len {
+            Some(len) => n == len,
+            None => try!(dec.read_break()),
+        };
+        if done {
+            break;
+        }
+        if is_map {
+            let key = dec.clone().read_str().ok().map(|k| k.to_string());
+            try!(dec.skip());
+            let v = match key {
+                Some(ref key) => try!(project_child(bytes, dec, node, key)),
+                None => { try!(dec.skip()); None }
+            };
+            if let (Some(key), Some(v)) = (key, v) {
+                map.insert(key, v);
+            }
+        } else {
+            let seg = n.to_string();
+            if let Some(v) = try!(project_child(bytes, dec, node, &seg)) {
+                items.push(v);
+            }
+        }
+        n += 1;
+    }
+    Ok(Some(if is_map { Cbor::Map(map) } else { Cbor::Array(items) }))
+}
+
+/// Decodes the parts of the next data item of `dec` that the child of
+/// `node` for the key or index `seg` selects.
+fn project_child(bytes: &[u8], dec: &mut SliceDecoder, node: &Node,
+                 seg: &str) -> CborResult<Option<Cbor>> {
+    match node.child(seg) {
+        Some(child) => project(bytes, dec, child),
+        None => { try!(dec.skip()); Ok(None) }
+    }
+}
@@ -1,30 +1,185 @@
 use std::borrow::ToOwned;
 use std::char;
-
-use rustc_serialize::Decoder as RustcDecoder;
-
-use {Cbor, CborUnsigned, Type, CborResult, CborError, ReadError};
+use std::marker::PhantomData;
+use std::mem;
+
+use rustc_serialize::{Decodable, Decoder as RustcDecoder};
+
+use {
+    Cbor, CborBytes, CborFloat, CborMap, CborSigned, CborUnsigned, Type,
+    CborResult, CborError, ReadError, Narrowing, Encoder, OptionFormat,
+    TagPolicy, SliceDecoder, DecodeSeed, int_fits_float,
+};
+
+/// Options that control how abstract syntax is decoded into Rust values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // When true, text strings can be decoded as numbers.
+    pub coerce_numbers: bool,
+    // When true, text strings can be decoded as bytes and vice versa.
+    pub coerce_strings: bool,
+    pub narrowing: Narrowing,
+    pub options: OptionFormat,
+    // When false, `undefined` is decoded like `null`.
+    pub strict_undefined: bool,
+    pub tags: TagPolicy,
+}
 
 pub struct CborDecoder {
     stack: Vec<Cbor>,
+    opts: Options,
+    // The logical location of the value currently being decoded.
+    path: Vec<PathElem>,
+    // The location of the value that the error being returned is about.
+    // It is captured by the innermost `at` that fails, before `path` is
+    // truncated, and cleared by the next `at` in case the error was
+    // recovered from.
+    err_path: Option<Vec<PathElem>>,
+    // The key of the map entry currently being decoded.
+    map_key: Option<String>,
+    // True if the struct being decoded has one field, and hasn't read it
+    // yet. Its field is the value on the stack if it's a newtype struct.
+    newtype: bool,
+}
+
+#[derive(Clone)]
+enum PathElem {
+    Field(String),
+    Index(usize),
+    Key(String),
 }
 
 impl CborDecoder {
-    pub fn new(val: Cbor) -> CborDecoder {
-        CborDecoder { stack: vec![val] }
+    pub fn new(val: Cbor, opts: Options) -> CborDecoder {
+        CborDecoder {
+            stack: vec![val],
+            opts: opts,
+            path: vec![],
+            err_path: None,
+            map_key: None,
+            newtype: false,
+        }
+    }
+
+    /// Decodes `val`, which is a top-level data item that starts at
+    /// byte `offset`.
+    ///
+    /// Errors are annotated with `offset` and the path to the value that
+    /// couldn't be decoded.
+    pub fn decode_at<D: Decodable>(val: Cbor, offset: usize,
+                                   opts: Options) -> CborResult<D> {
+        CborDecoder::decode_seed_at(val, offset, PhantomData::<D>, opts)
+    }
+
+    /// Like `decode_at`, but the value is decoded by `seed`.
+    pub fn decode_seed_at<S: DecodeSeed>(val: Cbor, offset: usize, seed: S,
+                                         opts: Options)
+                                        -> CborResult<S::Value> {
+        let mut dec = CborDecoder::new(val, opts);
+        seed.decode(&mut dec).map_err(|err| match err {
+            CborError::Decode(kind) => CborError::AtPath {
+                kind: kind,
+                offset: offset,
+                path: dec.path_string(),
+            },
+            err => err,
+        })
+    }
+
+    /// Like `decode_at`, but `bytes` is the encoding of `val`, so errors
+    /// can be annotated with the byte offset of the value that couldn't be
+    /// decoded instead of the offset of `val`.
+    pub fn decode_in<D: Decodable>(val: Cbor, bytes: &[u8], offset: usize,
+                                   opts: Options) -> CborResult<D> {
+        let mut dec = CborDecoder::new(val, opts);
+        Decodable::decode(&mut dec).map_err(|err| match err {
+            CborError::Decode(kind) => CborError::AtPath {
+                kind: kind,
+                offset: offset + dec.locate(bytes),
+                path: dec.path_string(),
+            },
+            err => err,
+        })
+    }
+
+    /// Returns the path of the value that the last error is about.
+    fn err_path(&self) -> &[PathElem] {
+        self.err_path.as_ref().unwrap_or(&self.path)
+    }
+
+    /// Returns the offset in `bytes` of the value that the last error is
+    /// about, or of the deepest value on the way there that could be found
+    /// (e.g., the struct that a missing field is missing from).
+    fn locate(&self, bytes: &[u8]) -> usize {
+        let mut dec = SliceDecoder::new(bytes);
+        for elem in self.err_path() {
+            match locate_child(&mut dec.clone(), elem) {
+                Ok(Some(child)) => dec = child,
+                _ => break,
+            }
+        }
+        dec.offset()
+    }
+
+    /// Returns the path of the value that the last error is about, in the
+    /// format of `CborError::AtPath`.
+    fn path_string(&self) -> String {
+        let mut s = String::new();
+        for elem in self.err_path() {
+            match *elem {
+                PathElem::Field(ref name) => {
+                    if !s.is_empty() {
+                        s.push('.');
+                    }
+                    s.push_str(name);
+                }
+                PathElem::Index(i) => s.push_str(&format!("[{}]", i)),
+                PathElem::Key(ref k) => s.push_str(&format!("[{:?}]", k)),
+            }
+        }
+        s
+    }
+
+    fn at<T, F>(&mut self, elem: PathElem, f: F) -> CborResult<T>
+            where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
+        let depth = self.path.len();
+        self.path.push(elem);
+        self.err_path = None;
+        let r = f(self);
+        if r.is_err() && self.err_path.is_none() {
+            self.err_path = Some(self.path.clone());
+        }
+        self.path.truncate(depth);
+        r
     }
 
     pub fn pop(&mut self, expected: Type) -> CborResult<Cbor> {
         match self.stack.pop() {
+            // A text string is expected, so a byte string is left as is for
+            // the error (or for `coerce_strings`).
+            Some(Cbor::Bytes(v)) if expected == Type::Unicode => {
+                Ok(Cbor::Bytes(v))
+            }
             Some(Cbor::Bytes(v)) => Ok(Cbor::Array(
                 v.0.into_iter()
                  .map(|b| Cbor::Unsigned(CborUnsigned::UInt8(b)))
                  .collect::<Vec<_>>()
             )),
-            Some(Cbor::Tag(tag)) => {
-                self.stack.push(*tag.data);
-                Ok(Cbor::Unsigned(CborUnsigned::UInt64(tag.tag)))
-            }
+            // The width of an integer doesn't matter for Rust values.
+            Some(Cbor::Wide(v)) => Ok(v.to_cbor()),
+            Some(Cbor::Tag(tag)) => match self.opts.tags {
+                TagPolicy::Ignore => {
+                    self.stack.push(*tag.data);
+                    self.pop(expected)
+                }
+                TagPolicy::Error if expected != Type::UInt64 => {
+                    Err(self.err(ReadError::ty_mismatch(expected, Type::Tag)))
+                }
+                TagPolicy::Preserve | TagPolicy::Error => {
+                    self.stack.push(*tag.data);
+                    Ok(Cbor::Unsigned(CborUnsigned::UInt64(tag.tag)))
+                }
+            },
             Some(v) => Ok(v),
             None => Err(self.errstr(format!(
                 "No data items left (expected a data item with type '{:?}').",
@@ -34,12 +189,25 @@ impl CborDecoder {
 
     pub fn pop_expect(&mut self, expected: &str) -> CborResult<Cbor> {
         match self.stack.pop() {
+            Some(Cbor::Tag(tag)) if self.opts.tags == TagPolicy::Ignore => {
+                self.stack.push(*tag.data);
+                self.pop_expect(expected)
+            }
             Some(v) => Ok(v),
             None => Err(self.errstr(format!(
                 "No data items left (expected {}).", expected))),
         }
     }
 
+    /// Returns true if the next value is a newtype struct that was encoded
+    /// like other structs, i.e., as a map with the key `_field0`.
+    fn is_wrapped_newtype(&self) -> bool {
+        match self.stack.last() {
+            Some(&Cbor::Map(ref map)) => map.get("_field0").is_some(),
+            _ => false,
+        }
+    }
+
     pub fn err(&self, err: ReadError) -> CborError {
         CborError::Decode(err)
     }
@@ -47,97 +215,256 @@ impl CborDecoder {
     pub fn errstr(&self, s: String) -> CborError {
         self.err(ReadError::Other(s))
     }
+
+    /// Returns the number that `v` is the text of when numbers are
+    /// coerced, or `v` itself otherwise.
+    fn number(&self, v: Cbor, float: bool) -> Cbor {
+        let n = match v {
+            Cbor::Unicode(ref s) if self.opts.coerce_numbers => {
+                parse_number(s, float)
+            }
+            _ => None,
+        };
+        n.unwrap_or(v)
+    }
+
+    /// Handles a number that can't be represented by the type being decoded
+    /// into, according to the narrowing option.
+    fn narrow<T>(&self, err: ReadError, truncated: T, saturated: T)
+                -> CborResult<T> {
+        match self.opts.narrowing {
+            Narrowing::Error => Err(self.err(err)),
+            Narrowing::Truncate => Ok(truncated),
+            Narrowing::Saturate => Ok(saturated),
+        }
+    }
+
+    /// Returns the float `m` that an integer `n` (whose magnitude is `mag`)
+    /// was converted to, unless `m` has too few mantissa `digits` to
+    /// represent it exactly. The nearest float is also the saturated value.
+    fn int_to_float<T: Copy>(&self, n: f64, mag: u64, m: T, digits: u32,
+                             ty: Type) -> CborResult<T> {
+        if int_fits_float(mag, digits) {
+            Ok(m)
+        } else {
+            let err = ReadError::InexactFloat { expected: ty, value: n };
+            self.narrow(err, m, m)
+        }
+    }
 }
 
 macro_rules! read_unsigned {
-    ($dec:ident, $cbor_ty:expr, $to:ident) => ({
+    ($dec:ident, $ty:ident, $cbor_ty:expr) => ({
         let v = try!($dec.pop($cbor_ty));
-        match v {
-            Cbor::Unsigned(v) => v.$to().map_err(CborError::Decode),
+        match $dec.number(v, false) {
+            Cbor::Unsigned(v) => {
+                let n = try!(v.to_u64().map_err(CborError::Decode));
+                if n <= ::std::$ty::MAX as u64 {
+                    Ok(n as $ty)
+                } else {
+                    $dec.narrow(ReadError::overflow($cbor_ty, n),
+                                n as $ty, ::std::$ty::MAX)
+                }
+            }
             ref v => return Err($dec.err(ReadError::mismatch($cbor_ty, v))),
         }
     });
 }
 
 macro_rules! read_signed {
-    ($dec:ident, $ty:ty, $cbor_ty:expr, $to:ident, $tou:ident) => ({
+    ($dec:ident, $ty:ident, $cbor_ty:expr) => ({
         let v = try!($dec.pop($cbor_ty));
-        match v {
-            Cbor::Signed(v) => v.$to().map_err(CborError::Decode),
-            Cbor::Unsigned(v) =>
-                v.$tou().map(|n| n as $ty).map_err(CborError::Decode),
+        match $dec.number(v, false) {
+            Cbor::Signed(v) => {
+                let n = try!(v.to_i64().map_err(CborError::Decode));
+                if n >= ::std::$ty::MIN as i64 {
+                    Ok(n as $ty)
+                } else {
+                    $dec.narrow(ReadError::overflow($cbor_ty, n),
+                                n as $ty, ::std::$ty::MIN)
+                }
+            }
+            // Only negative integers below `i64::MIN` are left as is by
+            // `pop`, and they never fit.
+            Cbor::Wide(v) => {
+                $dec.narrow(ReadError::neg_overflow($cbor_ty, v.arg),
+                            !v.arg as $ty, ::std::$ty::MIN)
+            }
+            Cbor::Unsigned(v) => {
+                // Compare against the maximum of the *signed* type so that
+                // e.g. a `u64` above `i64::MAX` doesn't wrap around.
+                let n = try!(v.to_u64().map_err(CborError::Decode));
+                if n <= ::std::$ty::MAX as u64 {
+                    Ok(n as $ty)
+                } else {
+                    $dec.narrow(ReadError::overflow($cbor_ty, n),
+                                n as $ty, ::std::$ty::MAX)
+                }
+            }
             ref v => return Err($dec.err(ReadError::mismatch($cbor_ty, v))),
         }
     });
 }
 
 macro_rules! read_float {
-    ($dec:ident, $ty:ty, $cbor_ty:expr,
-     $to:ident, $toi:ident, $tou:ident) => ({
+    ($dec:ident, $ty:ident, $cbor_ty:expr) => ({
         let v = try!($dec.pop($cbor_ty));
-        match v {
-            Cbor::Float(v) => v.$to().map_err(CborError::Decode),
-            Cbor::Signed(v) =>
-                v.$toi().map(|n| n as $ty).map_err(CborError::Decode),
-            Cbor::Unsigned(v) =>
-                v.$tou().map(|n| n as $ty).map_err(CborError::Decode),
+        match $dec.number(v, true) {
+            Cbor::Float(v) => {
+                let n = try!(v.to_f64().map_err(CborError::Decode));
+                let m = n as $ty;
+                if m as f64 == n || n.is_nan() {
+                    Ok(m)
+                } else {
+                    let saturated = if !m.is_infinite() {
+                        m
+                    } else if n > 0.0 {
+                        ::std::$ty::MAX
+                    } else {
+                        ::std::$ty::MIN
+                    };
+                    $dec.narrow(ReadError::InexactFloat {
+                        expected: $cbor_ty,
+                        value: n,
+                    }, m, saturated)
+                }
+            }
+            Cbor::Signed(v) => {
+                let n = try!(v.to_i64().map_err(CborError::Decode));
+                let mag = if n < 0 { !(n as u64) + 1 } else { n as u64 };
+                $dec.int_to_float(n as f64, mag, n as $ty,
+                                  ::std::$ty::MANTISSA_DIGITS, $cbor_ty)
+            }
+            Cbor::Unsigned(v) => {
+                let n = try!(v.to_u64().map_err(CborError::Decode));
+                $dec.int_to_float(n as f64, n, n as $ty,
+                                  ::std::$ty::MANTISSA_DIGITS, $cbor_ty)
+            }
+            Cbor::Wide(v) => {
+                // The magnitude of `-1 - arg` wraps to 0 for -2^64, which
+                // is exact too.
+                let n = -1.0 - v.arg as f64;
+                $dec.int_to_float(n, v.arg.wrapping_add(1), n as $ty,
+                                  ::std::$ty::MANTISSA_DIGITS, $cbor_ty)
+            }
             ref v => return Err($dec.err(ReadError::mismatch($cbor_ty, v))),
         }
     });
 }
 
+/// Parses `s` as an integer, or as a float if `float` is true and it isn't
+/// an integer.
+fn parse_number(s: &str, float: bool) -> Option<Cbor> {
+    if let Ok(n) = s.parse::<u64>() {
+        Some(Cbor::Unsigned(CborUnsigned::UInt64(n)))
+    } else if let Ok(n) = s.parse::<i64>() {
+        Some(Cbor::Signed(CborSigned::Int64(n)))
+    } else if float {
+        s.parse::<f64>().ok().map(|n| Cbor::Float(CborFloat::Float64(n)))
+    } else {
+        None
+    }
+}
+
+/// Finds the child of the data item at `dec` that `elem` leads to, and
+/// returns a decoder positioned at it.
+fn locate_child<'a>(dec: &mut SliceDecoder<'a>, elem: &PathElem)
+                   -> CborResult<Option<SliceDecoder<'a>>> {
+    while try!(dec.peek_type()) == Type::Tag {
+        try!(dec.read_tag());
+    }
+    let (is_map, len) = match try!(dec.peek_type()) {
+        Type::Map => (true, try!(dec.read_map())),
+        Type::Array => (false, try!(dec.read_array())),
+        _ => return Ok(None),
+    };
+    // Like the decoder, the last of duplicate keys wins.
+    let mut found = None;
+    let mut n = 0;
+    loop {
+        let done = match len {
+            Some(len) => n == len,
+            None => try!(dec.read_break()),
+        };
+        if done {
+            return Ok(found);
+        }
+        let matched = if is_map {
+            let key = dec.clone().read_str().ok();
+            try!(dec.skip());
+            match (elem, key) {
+                (&PathElem::Field(ref name), Some(key)) => name == key,
+                (&PathElem::Key(ref name), Some(key)) => name == key,
+                (&PathElem::Index(i), None) => i == n,
+                _ => false,
+            }
+        } else {
+            match *elem {
+                PathElem::Index(i) => i == n,
+                _ => false,
+            }
+        };
+        if matched {
+            found = Some(dec.clone());
+        }
+        try!(dec.skip());
+        n += 1;
+    }
+}
+
 impl RustcDecoder for CborDecoder {
     type Error = CborError;
 
     fn error(&mut self, err: &str) -> CborError {
-        self.err(ReadError::Other(err.to_owned()))
+        self.err(ReadError::Custom(err.to_owned()))
     }
 
     fn read_nil(&mut self) -> CborResult<()> {
         match try!(self.pop(Type::Null)) {
             Cbor::Null => Ok(()),
+            Cbor::Undefined if !self.opts.strict_undefined => Ok(()),
             v => Err(self.err(ReadError::mismatch(Type::Null, &v))),
         }
     }
 
     fn read_usize(&mut self) -> CborResult<usize> {
-        read_unsigned!(self, Type::UInt, to_usize)
+        read_unsigned!(self, usize, Type::UInt)
     }
 
     fn read_u64(&mut self) -> CborResult<u64> {
-        read_unsigned!(self, Type::UInt64, to_u64)
+        read_unsigned!(self, u64, Type::UInt64)
     }
 
     fn read_u32(&mut self) -> CborResult<u32> {
-        read_unsigned!(self, Type::UInt32, to_u32)
+        read_unsigned!(self, u32, Type::UInt32)
     }
 
     fn read_u16(&mut self) -> CborResult<u16> {
-        read_unsigned!(self, Type::UInt16, to_u16)
+        read_unsigned!(self, u16, Type::UInt16)
     }
 
     fn read_u8(&mut self) -> CborResult<u8> {
-        read_unsigned!(self, Type::UInt8, to_u8)
+        read_unsigned!(self, u8, Type::UInt8)
     }
 
     fn read_isize(&mut self) -> CborResult<isize> {
-        read_signed!(self, isize, Type::Int, to_isize, to_usize)
+        read_signed!(self, isize, Type::Int)
     }
 
     fn read_i64(&mut self) -> CborResult<i64> {
-        read_signed!(self, i64, Type::Int64, to_i64, to_u64)
+        read_signed!(self, i64, Type::Int64)
     }
 
     fn read_i32(&mut self) -> CborResult<i32> {
-        read_signed!(self, i32, Type::Int32, to_i32, to_u32)
+        read_signed!(self, i32, Type::Int32)
     }
 
     fn read_i16(&mut self) -> CborResult<i16> {
-        read_signed!(self, i16, Type::Int16, to_i16, to_u16)
+        read_signed!(self, i16, Type::Int16)
     }
 
     fn read_i8(&mut self) -> CborResult<i8> {
-        read_signed!(self, i8, Type::Int8, to_i8, to_u8)
+        read_signed!(self, i8, Type::Int8)
     }
 
     fn read_bool(&mut self) -> CborResult<bool> {
@@ -148,14 +475,28 @@ impl RustcDecoder for CborDecoder {
     }
 
     fn read_f64(&mut self) -> CborResult<f64> {
-        read_float!(self, f64, Type::Float64, to_f64, to_i64, to_u64)
+        read_float!(self, f64, Type::Float64)
     }
 
     fn read_f32(&mut self) -> CborResult<f32> {
-        read_float!(self, f32, Type::Float32, to_f32, to_i32, to_u32)
+        read_float!(self, f32, Type::Float32)
     }
 
     fn read_char(&mut self) -> CborResult<char> {
+        // Chars are written as text strings of one character, but older
+        // versions of this crate wrote their scalar values instead.
+        match try!(self.pop(Type::Unicode)) {
+            Cbor::Unicode(s) => {
+                let mut chars = s.chars();
+                return match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(c),
+                    _ => Err(self.errstr(format!(
+                        "Expected a string of one character, but got {:?}.",
+                        s))),
+                };
+            }
+            v => self.stack.push(v),
+        }
         let n = try!(self.read_u32());
         match char::from_u32(n) {
             Some(c) => Ok(c),
@@ -167,6 +508,11 @@ impl RustcDecoder for CborDecoder {
     fn read_str(&mut self) -> CborResult<String> {
         match try!(self.pop(Type::Unicode)) {
             Cbor::Unicode(s) => Ok(s),
+            Cbor::Bytes(b) if self.opts.coerce_strings => {
+                String::from_utf8(b.0).map_err(|err| {
+                    self.err(ReadError::InvalidUtf8(err.utf8_error()))
+                })
+            }
             v => Err(self.err(ReadError::mismatch(Type::Unicode, &v))),
         }
     }
@@ -211,19 +557,18 @@ impl RustcDecoder for CborDecoder {
         };
         let idx = match names.iter().position(|&n| n == name) {
             Some(idx) => idx,
-            None => return Err(self.errstr(format!(
-                "Unknown variant name '{}'.", name))),
+            None => return Err(self.err(ReadError::UnknownVariant(name))),
         };
         f(self, idx)
     }
 
     fn read_enum_variant_arg<T, F>(
         &mut self,
-        _a_idx: usize,
+        a_idx: usize,
         f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
-        f(self)
+        self.at(PathElem::Index(a_idx), f)
     }
 
     fn read_enum_struct_variant<T, F>(
@@ -247,19 +592,61 @@ impl RustcDecoder for CborDecoder {
 
     fn read_struct<T, F>(
         &mut self,
-        _s_name: &str,
-        _len: usize,
+        s_name: &str,
+        len: usize,
         f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
+        if s_name == "CborSimple" {
+            // See the `Decodable` impl for `CborSimple`.
+            let v = match try!(self.pop(Type::Simple)) {
+                Cbor::Simple(v) => v.0,
+                Cbor::Bool(false) => 20,
+                Cbor::Bool(true) => 21,
+                Cbor::Null => 22,
+                Cbor::Undefined => 23,
+                v => {
+                    return Err(self.err(ReadError::mismatch(Type::Simple, &v)))
+                }
+            };
+            self.stack.push(Cbor::Unsigned(CborUnsigned::UInt8(v)));
+            return f(self);
+        }
+        if s_name == "Cbor" {
+            // See the `Decodable` impl for `Cbor`.
+            let v = try!(self.pop_expect("a data item"));
+            let mut enc = Encoder::from_memory();
+            try!(enc.encode(&[v]));
+            self.stack.push(Cbor::Bytes(CborBytes(enc.into_bytes())));
+            return f(self);
+        }
+        if len == 0 {
+            // A unit struct is written as `null`, but older versions of
+            // this crate wrote an empty map, which is read below.
+            match self.stack.last() {
+                Some(&Cbor::Null) | Some(&Cbor::Undefined) => {
+                    try!(self.read_nil());
+                    return f(self);
+                }
+                _ => {}
+            }
+        }
+        self.newtype = len == 1;
         let val = try!(f(self));
         // When we read a struct field, we pop the CBOR map off the stack,
         // find and remove the field name and its associated value, and then
         // push the map back on the stack. Therefore, when we're done
         // processing all the struct fields, we'll have an extraneous map
-        // left on the stack. So pop it off. If this assert fails, we have a
-        // bug in the decoder. ---AG
-        assert_eq!(self.stack.pop().unwrap().typ(), Type::Map);
+        // left on the stack. So pop it off. Anything else means that the
+        // `Decodable` impl read something other than struct fields.
+        match self.stack.pop() {
+            Some(Cbor::Map(_)) => {}
+            Some(v) => {
+                return Err(self.err(ReadError::mismatch(Type::Map, &v)));
+            }
+            None => return Err(self.errstr(
+                "read past the end of a struct".to_string())),
+        }
         // Do we want to check if the map popped off here is empty? If it's
         // not, that means the data contains more than what the struct
         // specifies. We should probably be relaxed and let it pass. ---AG
@@ -273,18 +660,27 @@ impl RustcDecoder for CborDecoder {
         f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
+        let newtype = mem::replace(&mut self.newtype, false);
+        if newtype && f_name == "_field0" && !self.is_wrapped_newtype() {
+            // A newtype struct is encoded as the value of its field. The
+            // struct expects a map to be left over, so leave an empty one.
+            let val = try!(f(self));
+            self.stack.push(Cbor::Map(CborMap::new()));
+            return Ok(val);
+        }
         let mut map = match try!(self.pop(Type::Map)) {
             Cbor::Map(map) => map,
             v => return Err(self.err(ReadError::mismatch(Type::Map, &v))),
         };
+        let elem = PathElem::Field(f_name.to_string());
         let val = match map.remove(f_name) {
-            Some(val) => { self.stack.push(val); try!(f(self)) }
+            Some(val) => { self.stack.push(val); try!(self.at(elem, f)) }
             None => {
                 self.stack.push(Cbor::Null);
-                match f(self) {
+                match self.at(elem, f) {
                     Ok(val) => val,
-                    Err(_) => return Err(self.errstr(format!(
-                        "Missing field '{}' in map object.", f_name))),
+                    Err(_) => return Err(self.err(
+                        ReadError::MissingField(f_name.to_string()))),
                 }
             }
         };
@@ -304,9 +700,10 @@ impl RustcDecoder for CborDecoder {
         };
         let got_len = array.len();
         if len != got_len {
-            return Err(self.errstr(format!(
-                "Expected tuple of length {:?}, but got array of length {:?}",
-                len, got_len)));
+            return Err(self.err(ReadError::LengthMismatch {
+                expected: len,
+                got: got_len,
+            }));
         }
         self.stack.extend(array.into_iter().rev());
         f(self)
@@ -314,11 +711,11 @@ impl RustcDecoder for CborDecoder {
 
     fn read_tuple_arg<T, F>(
         &mut self,
-        _a_idx: usize,
+        a_idx: usize,
         f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
-        f(self)
+        self.at(PathElem::Index(a_idx), f)
     }
 
     fn read_tuple_struct<T, F>(
@@ -334,9 +731,10 @@ impl RustcDecoder for CborDecoder {
         };
         let got_len = array.len();
         if len != got_len {
-            return Err(self.errstr(format!(
-                "Expected tuple of length {:?}, but got array of length {:?}",
-                len, got_len)));
+            return Err(self.err(ReadError::LengthMismatch {
+                expected: len,
+                got: got_len,
+            }));
         }
         self.stack.extend(array.into_iter().rev());
         f(self)
@@ -344,18 +742,32 @@ impl RustcDecoder for CborDecoder {
 
     fn read_tuple_struct_arg<T, F>(
         &mut self,
-        _a_idx: usize,
+        a_idx: usize,
         f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
-        f(self)
+        self.at(PathElem::Index(a_idx), f)
     }
 
     fn read_option<T, F>(&mut self, mut f: F) -> CborResult<T>
             where F: FnMut(&mut CborDecoder, bool) -> CborResult<T> {
-        match try!(self.pop(Type::Any)) {
+        // The value is pushed back as it was read, so `Option<Cbor>` gets
+        // byte strings and tags as they are.
+        match try!(self.pop_expect("a data item or null")) {
             Cbor::Null => f(self, false),
-            v => { self.stack.push(v); f(self, true) }
+            Cbor::Undefined if !self.opts.strict_undefined => f(self, false),
+            v => match self.opts.options {
+                OptionFormat::Null => { self.stack.push(v); f(self, true) }
+                OptionFormat::Array => match v {
+                    Cbor::Array(mut items) if items.len() == 1 => {
+                        self.stack.push(items.pop().unwrap());
+                        self.at(PathElem::Index(0), |d| f(d, true))
+                    }
+                    v => Err(self.errstr(format!(
+                        "Expected null or an array of one item for an \
+                         Option, but got {}.", v.typ()))),
+                },
+            },
         }
     }
 
@@ -363,6 +775,12 @@ impl RustcDecoder for CborDecoder {
             where F: FnOnce(&mut CborDecoder, usize) -> CborResult<T> {
         let array = match try!(self.pop(Type::Array)) {
             Cbor::Array(v) => v,
+            // Read like a byte string, i.e., as a sequence of `u8`s.
+            Cbor::Unicode(s) if self.opts.coerce_strings => {
+                s.into_bytes().into_iter()
+                 .map(|b| Cbor::Unsigned(CborUnsigned::UInt8(b)))
+                 .collect()
+            }
             v => return Err(self.err(ReadError::mismatch(Type::Array, &v))),
         };
         let len = array.len();
@@ -370,9 +788,9 @@ impl RustcDecoder for CborDecoder {
         f(self, len)
     }
 
-    fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> CborResult<T>
+    fn read_seq_elt<T, F>(&mut self, idx: usize, f: F) -> CborResult<T>
             where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
-        f(self)
+        self.at(PathElem::Index(idx), f)
     }
 
     fn read_map<T, F>(&mut self, f: F) -> CborResult<T>
@@ -382,20 +800,33 @@ impl RustcDecoder for CborDecoder {
             v => return Err(self.err(ReadError::mismatch(Type::Map, &v))),
         };
         let len = map.len();
-        for (k, v) in map { // order doesn't matter for HashMap
+        // Push in reverse so that entries are popped in their original order.
+        for (k, v) in map.into_iter().rev() {
             self.stack.push(v);
             self.stack.push(Cbor::Unicode(k));
         }
         f(self, len)
     }
 
-    fn read_map_elt_key<T, F>(&mut self, _idx: usize, f: F) -> CborResult<T>
+    fn read_map_elt_key<T, F>(&mut self, idx: usize, f: F) -> CborResult<T>
             where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
-        f(self)
+        self.map_key = match self.stack.last() {
+            Some(&Cbor::Unicode(ref k)) => Some(k.clone()),
+            _ => None,
+        };
+        let elem = match self.map_key {
+            Some(ref k) => PathElem::Key(k.clone()),
+            None => PathElem::Index(idx),
+        };
+        self.at(elem, f)
     }
 
-    fn read_map_elt_val<T, F>(&mut self, _idx: usize, f: F) -> CborResult<T>
+    fn read_map_elt_val<T, F>(&mut self, idx: usize, f: F) -> CborResult<T>
             where F: FnOnce(&mut CborDecoder) -> CborResult<T> {
-        f(self)
+        let elem = match self.map_key.take() {
+            Some(k) => PathElem::Key(k),
+            None => PathElem::Index(idx),
+        };
+        self.at(elem, f)
     }
 }
@@ -1,11 +1,16 @@
 use std::convert::Into;
 use std::char;
 use std::io::{self, Read};
+use std::mem;
 
 use byteorder::{ReadBytesExt, BigEndian};
 use rustc_serialize::Decoder as RustcDecoder;
 
-use {Type, CborResult, CborError, ReadError};
+use lexer::f16_to_f64;
+use {
+    Type, CborResult, CborError, ReadError, Narrowing, OptionFormat,
+    int_fits_float,
+};
 
 /// Experimental and incomplete direct decoder.
 ///
@@ -15,10 +20,23 @@ use {Type, CborResult, CborError, ReadError};
 /// and unbox every data item.
 ///
 /// However, implementing a direct decoder is much harder in the existing
-/// serialization infrastructure. Currently, structs and enums are not
-/// implemented. (But `Vec`s, tuples, `Option`s and maps should work.)
+/// serialization infrastructure. Currently, enums are not implemented,
+/// except for unit variants. Structs can only be read if their fields are in
+/// the order they're declared in, which is how `Encoder` writes them unless
+/// `canonical` is enabled. (But `Vec`s, tuples, `Option`s and maps should
+/// work.)
 pub struct CborDecoder<R> {
     rdr: CborReader<R>,
+    narrowing: Narrowing,
+    strict_undefined: bool,
+    coerce_strings: bool,
+    options: OptionFormat,
+    // True if the struct being read has one field, and hasn't read it yet.
+    // Its field is the next value if it's a newtype struct.
+    newtype: bool,
+    // The contents (in reverse) of the byte string that is being read as
+    // a sequence of `u8`s.
+    seq_bytes: Option<Vec<u8>>,
 }
 
 impl CborDecoder<io::Cursor<Vec<u8>>> {
@@ -27,20 +45,96 @@ impl CborDecoder<io::Cursor<Vec<u8>>> {
     /// The buffer is usually given as either a `Vec<u8>` or a `&[u8]`.
     pub fn from_bytes<'a, T>(bytes: T) -> CborDecoder<io::Cursor<Vec<u8>>>
             where T: Into<Vec<u8>> {
-        CborDecoder { rdr: CborReader::new(io::Cursor::new(bytes.into())) }
+        CborDecoder {
+            rdr: CborReader::new(io::Cursor::new(bytes.into())),
+            narrowing: Narrowing::default(),
+            strict_undefined: false,
+            coerce_strings: false,
+            options: OptionFormat::default(),
+            newtype: false,
+            seq_bytes: None,
+        }
     }
 }
 
 impl<R: io::Read> CborDecoder<io::BufReader<R>> {
     /// Create a new CBOR decoder that reads from the reader given.
     pub fn from_reader(rdr: R) -> CborDecoder<io::BufReader<R>> {
-        CborDecoder { rdr: CborReader::new(io::BufReader::new(rdr)) }
+        CborDecoder {
+            rdr: CborReader::new(io::BufReader::new(rdr)),
+            narrowing: Narrowing::default(),
+            strict_undefined: false,
+            coerce_strings: false,
+            options: OptionFormat::default(),
+            newtype: false,
+            seq_bytes: None,
+        }
     }
 }
 
 impl<R: io::Read> CborDecoder<R> {
+    /// Set how numbers are decoded into Rust types that can't represent
+    /// them. See `Decoder::narrowing` for details.
+    pub fn narrowing(mut self, narrowing: Narrowing) -> CborDecoder<R> {
+        self.narrowing = narrowing;
+        self
+    }
+
+    /// When enabled, `undefined` can't be decoded into Rust values. See
+    /// `Decoder::strict_undefined` for details.
+    pub fn strict_undefined(mut self, yes: bool) -> CborDecoder<R> {
+        self.strict_undefined = yes;
+        self
+    }
+
+    /// When enabled, text strings and byte strings can be decoded as each
+    /// other. See `Decoder::coerce_strings` for details.
+    pub fn coerce_strings(mut self, yes: bool) -> CborDecoder<R> {
+        self.coerce_strings = yes;
+        self
+    }
+
+    /// Set how `Option`s are read. See `Decoder::option_format` for
+    /// details.
+    pub fn option_format(mut self, format: OptionFormat) -> CborDecoder<R> {
+        self.options = format;
+        self
+    }
+
+    /// Returns the number of bytes that have been read from the underlying
+    /// reader. See `Decoder::bytes_consumed` for details.
+    pub fn bytes_consumed(&self) -> usize {
+        // A byte that was peeked at and pushed back hasn't been consumed.
+        self.rdr.bytes_read - self.rdr.buf.len()
+    }
+
+    /// Returns true if `b` is `null`, or `undefined` when that is allowed.
+    fn is_nil(&self, b: u8) -> bool {
+        (b & 0b111_00000) >> 5 == 7
+        && (b & 0b000_11111 == 22
+            || (b & 0b000_11111 == 23 && !self.strict_undefined))
+    }
+
+    /// Handles a number that can't be represented by the type being decoded
+    /// into, according to the narrowing option.
+    fn narrow<T>(&self, err: ReadError, truncated: T, saturated: T)
+                -> CborResult<T> {
+        match self.narrowing {
+            Narrowing::Error => Err(self.err(err)),
+            Narrowing::Truncate => Ok(truncated),
+            Narrowing::Saturate => Ok(saturated),
+        }
+    }
+
     fn err(&self, err: ReadError) -> CborError {
-        CborError::Decode(err)
+        CborError::AtOffset { kind: err, offset: self.rdr.last_offset }
+    }
+
+    /// Enum variants with fields (and `Cbor` values) can't be decoded
+    /// without building the map that represents them first.
+    fn unsupported(&self) -> CborError {
+        self.errstr("enum variants with fields and Cbor values aren't \
+                     supported by DirectDecoder".to_string())
     }
 
     fn errstr(&self, s: String) -> CborError {
@@ -48,6 +142,10 @@ impl<R: io::Read> CborDecoder<R> {
     }
 
     fn miss(&self, expected: Type, got: u8) -> CborError {
+        if got == 0xff {
+            let offset = self.rdr.last_offset;
+            return self.err(ReadError::UnexpectedBreak { offset: offset });
+        }
         self.err(ReadError::miss(expected, got))
     }
 
@@ -66,74 +164,111 @@ impl<R: io::Read> CborDecoder<R> {
         }
     }
 
+    /// Reads the head of the map that a struct of `len` fields is written
+    /// as.
+    fn read_struct_len(&mut self, len: usize) -> CborResult<()> {
+        let b = try!(self.read_type(Type::Map));
+        let got_len = try!(self.read_len(Some(b)));
+        if len != got_len {
+            return Err(self.err(ReadError::LengthMismatch {
+                expected: len,
+                got: got_len,
+            }));
+        }
+        Ok(())
+    }
+
     fn read_float(&mut self, first: Option<u8>, expect_size: u8)
                  -> CborResult<f64> {
         let b = match first {
             Some(b) => b,
             None => try!(self.rdr.read_u8()),
         };
-        let (n, size) = match ((b & 0b111_00000) >> 5, b & 0b000_11111) {
-            (0, n) => {
-                return self.read_uint(Some(n), expect_size).map(|n| n as f64);
+        let n = match ((b & 0b111_00000) >> 5, b & 0b000_11111) {
+            (0, _) => {
+                let n = try!(self.read_uint(Some(b), 64));
+                return self.int_to_float(n as f64, n, expect_size);
             }
-            (1, n) => {
-                return self.read_int(Some(n), expect_size).map(|n| n as f64);
+            (1, _) => {
+                let n = try!(self.read_int(Some(b), 64));
+                let mag = if n < 0 { !(n as u64) + 1 } else { n as u64 };
+                return self.int_to_float(n as f64, mag, expect_size);
             }
             (7, 25) => {
-                // Rust doesn't have a `f16` type, so just read a u16 and
-                // cast it to a f64. I think this is wrong. ---AG
-                (try!(self.rdr.read_u16::<BigEndian>()) as f64, 16)
+                f16_to_f64(try!(self.rdr.read_u16::<BigEndian>()))
             }
-            (7, 26) => (try!(self.rdr.read_f32::<BigEndian>()) as f64, 32),
-            (7, 27) => (try!(self.rdr.read_f64::<BigEndian>()), 64),
+            (7, 26) => try!(self.rdr.read_f32::<BigEndian>()) as f64,
+            (7, 27) => try!(self.rdr.read_f64::<BigEndian>()),
             _ => return Err(self.miss(Type::Float, b)),
         };
-        if size > expect_size {
-            Err(self.errstr(format!(
-                "Expected floating point number ({} bits or fewer), but got \
-                 {} bit floating point number: {}.", expect_size, size, n)))
+        let m = n as f32;
+        if expect_size < 64 && m as f64 != n && !n.is_nan() {
+            let saturated = if !m.is_infinite() {
+                m
+            } else if n > 0.0 {
+                ::std::f32::MAX
+            } else {
+                ::std::f32::MIN
+            };
+            let err = ReadError::InexactFloat {
+                expected: Type::Float32,
+                value: n,
+            };
+            self.narrow(err, m as f64, saturated as f64)
         } else {
             Ok(n)
         }
     }
 
+    /// Returns the integer `n` (whose magnitude is `mag`) as a float, unless
+    /// a float of `expect_size` bits can't represent it exactly.
+    fn int_to_float(&self, n: f64, mag: u64, expect_size: u8)
+                   -> CborResult<f64> {
+        let (digits, ty) = if expect_size < 64 {
+            (::std::f32::MANTISSA_DIGITS, Type::Float32)
+        } else {
+            (::std::f64::MANTISSA_DIGITS, Type::Float64)
+        };
+        if int_fits_float(mag, digits) {
+            return Ok(n);
+        }
+        // The nearest float is also the saturated value.
+        let m = if expect_size < 64 { n as f32 as f64 } else { n };
+        let err = ReadError::InexactFloat { expected: ty, value: n };
+        self.narrow(err, m, m)
+    }
+
     fn read_int(&mut self, first: Option<u8>, expect_size: u8)
                 -> CborResult<i64> {
         let b = match first {
             Some(b) => b,
             None => try!(self.rdr.read_u8()),
         };
+        // The largest `n` such that `n` and `-1 - n` both fit.
+        let max = (1u64 << (expect_size - 1)) - 1;
         let n = match ((b & 0b111_00000) >> 5, b & 0b000_11111) {
-            (0, n) => {
-                return self.read_uint(Some(n), expect_size).map(|n| n as i64);
+            (0, _) => {
+                let n = try!(self.read_uint(Some(b), 64));
+                if n > max {
+                    // Callers cast the result, which does the truncation.
+                    return self.narrow(
+                        ReadError::overflow(int_type(expect_size), n),
+                        n as i64, max as i64);
+                }
+                return Ok(n as i64);
             }
-            (1, n @ 0...23) => n as i64,
-            (1, 24) => try!(self.rdr.read_u8()) as i64,
-            (1, 25) => try!(self.rdr.read_i16::<BigEndian>()) as i64,
-            (1, 26) => try!(self.rdr.read_i32::<BigEndian>()) as i64,
-            (1, 27) => try!(self.rdr.read_i64::<BigEndian>()),
+            (1, n @ 0...23) => n as u64,
+            (1, 24) => try!(self.rdr.read_u8()) as u64,
+            (1, 25) => try!(self.rdr.read_u16::<BigEndian>()) as u64,
+            (1, 26) => try!(self.rdr.read_u32::<BigEndian>()) as u64,
+            (1, 27) => try!(self.rdr.read_u64::<BigEndian>()),
             _ => return Err(self.miss(Type::Int, b)),
         };
-
-        fn size(n: i64) -> u8 {
-            if n <= ::std::i8::MAX as i64 {
-                8
-            } else if n <= ::std::i16::MAX as i64 {
-                16
-            } else if n <= ::std::i32::MAX as i64 {
-                32
-            } else {
-                64
-            }
-        }
-
-        let n_size = size(n);
-        if n_size > expect_size {
-            Err(self.errstr(format!(
-                "Expected negative integer ({} bits or fewer), but got \
-                 {} bit number: {}.", expect_size, n_size, -1 - n)))
+        if n > max {
+            self.narrow(ReadError::neg_overflow(int_type(expect_size), n),
+                        -1 - n as i64, -1 - max as i64)
         } else {
-            Ok(-1 - n)
+            Ok(-1 - n as i64)
         }
     }
 
@@ -143,34 +278,52 @@ impl<R: io::Read> CborDecoder<R> {
             Some(b) => b,
             None => try!(self.rdr.read_u8()),
         };
-        let (n, size) = match ((b & 0b111_00000) >> 5, b & 0b000_11111) {
-            (0, n @ 0...23) => (n as u64, 8),
-            (0, 24) => (try!(self.rdr.read_u8()) as u64, 8),
-            (0, 25) => (try!(self.rdr.read_u16::<BigEndian>()) as u64, 16),
-            (0, 26) => (try!(self.rdr.read_u32::<BigEndian>()) as u64, 32),
-            (0, 27) => (try!(self.rdr.read_u64::<BigEndian>()), 64),
+        let n = match ((b & 0b111_00000) >> 5, b & 0b000_11111) {
+            (0, n @ 0...23) => n as u64,
+            (0, 24) => try!(self.rdr.read_u8()) as u64,
+            (0, 25) => try!(self.rdr.read_u16::<BigEndian>()) as u64,
+            (0, 26) => try!(self.rdr.read_u32::<BigEndian>()) as u64,
+            (0, 27) => try!(self.rdr.read_u64::<BigEndian>()),
             _ => return Err(self.miss(Type::UInt, b)),
         };
-        if size > expect_size {
-            Err(self.errstr(format!(
-                "Expected unsigned integer ({} bits or fewer), but got \
-                 {} bit number: {}.", expect_size, size, n)))
+        // Only the value matters, not the number of bytes used to encode it.
+        if expect_size < 64 && n >= 1 << expect_size {
+            self.narrow(ReadError::overflow(uint_type(expect_size), n),
+                        n, (1 << expect_size) - 1)
         } else {
             Ok(n)
         }
     }
 }
 
+fn uint_type(size: u8) -> Type {
+    match size {
+        8 => Type::UInt8,
+        16 => Type::UInt16,
+        32 => Type::UInt32,
+        _ => Type::UInt64,
+    }
+}
+
+fn int_type(size: u8) -> Type {
+    match size {
+        8 => Type::Int8,
+        16 => Type::Int16,
+        32 => Type::Int32,
+        _ => Type::Int64,
+    }
+}
+
 impl<R: io::Read> RustcDecoder for CborDecoder<R> {
     type Error = CborError;
 
     fn error(&mut self, err: &str) -> CborError {
-        self.err(ReadError::Other(err.into()))
+        self.err(ReadError::Custom(err.into()))
     }
 
     fn read_nil(&mut self) -> CborResult<()> {
         let b = try!(self.rdr.read_u8());
-        if (b & 0b111_00000) >> 5 == 7 && b & 0b000_11111 == 22 {
+        if self.is_nil(b) {
             Ok(())
         } else {
             Err(self.miss(Type::Null, b))
@@ -178,8 +331,12 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
     }
 
     fn read_usize(&mut self) -> CborResult<usize> {
-        // Shouldn't this fail if we read a u64 but usize is only 32 bits?
-        Ok(try!(self.read_uint(None, 64)) as usize)
+        let n = try!(self.read_uint(None, 64));
+        if n > ::std::usize::MAX as u64 {
+            return self.narrow(ReadError::overflow(Type::UInt, n),
+                               n as usize, ::std::usize::MAX);
+        }
+        Ok(n as usize)
     }
 
     fn read_u64(&mut self) -> CborResult<u64> {
@@ -195,12 +352,24 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
     }
 
     fn read_u8(&mut self) -> CborResult<u8> {
+        if let Some(ref mut bytes) = self.seq_bytes {
+            if let Some(b) = bytes.pop() {
+                return Ok(b);
+            }
+        }
         Ok(try!(self.read_uint(None, 8)) as u8)
     }
 
     fn read_isize(&mut self) -> CborResult<isize> {
-        // Shouldn't this fail if we read a i64 but isize is only 32 bits?
-        Ok(try!(self.read_int(None, 64)) as isize)
+        let n = try!(self.read_int(None, 64));
+        if n < ::std::isize::MIN as i64 {
+            return self.narrow(ReadError::overflow(Type::Int, n),
+                               n as isize, ::std::isize::MIN);
+        } else if n > ::std::isize::MAX as i64 {
+            return self.narrow(ReadError::overflow(Type::Int, n),
+                               n as isize, ::std::isize::MAX);
+        }
+        Ok(n as isize)
     }
 
     fn read_i64(&mut self) -> CborResult<i64> {
@@ -233,11 +402,24 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
     }
 
     fn read_f32(&mut self) -> CborResult<f32> {
-        Ok(try!(self.read_float(None, 64)) as f32)
+        Ok(try!(self.read_float(None, 32)) as f32)
     }
 
     fn read_char(&mut self) -> CborResult<char> {
-        let n = try!(self.read_uint(None, 32)) as u32;
+        // Chars are written as text strings of one character, but older
+        // versions of this crate wrote their scalar values instead.
+        let b = try!(self.rdr.read_u8());
+        if (b & 0b111_00000) >> 5 == Type::Unicode.major() {
+            self.rdr.push_byte(b);
+            let s = try!(self.read_str());
+            let mut chars = s.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(self.errstr(format!(
+                    "Expected a string of one character, but got {:?}.", s))),
+            };
+        }
+        let n = try!(self.read_uint(Some(b), 32)) as u32;
         match char::from_u32(n) {
             Some(c) => Ok(c),
             None => Err(self.errstr(format!(
@@ -246,26 +428,44 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
     }
 
     fn read_str(&mut self) -> CborResult<String> {
-        let b = try!(self.read_type(Type::Unicode));
+        let b = try!(self.rdr.read_u8());
+        let b = if self.coerce_strings
+                   && (b & 0b111_00000) >> 5 == Type::Bytes.major() {
+            b
+        } else {
+            self.rdr.push_byte(b);
+            try!(self.read_type(Type::Unicode))
+        };
         let len = try!(self.read_len(Some(b)));
-        let mut buf = vec_from_elem(len, 0u8);
-        try!(self.rdr.read_full(&mut buf));
+        let buf = try!(self.rdr.read_vec(len));
         String::from_utf8(buf)
-               .map_err(|err| self.errstr(err.utf8_error().to_string()))
+               .map_err(|err| self.err(
+                   ReadError::InvalidUtf8(err.utf8_error())))
     }
 
-    fn read_enum<T, F>(&mut self, _name: &str, _f: F) -> CborResult<T>
+    fn read_enum<T, F>(&mut self, _name: &str, f: F) -> CborResult<T>
             where F: FnOnce(&mut CborDecoder<R>) -> CborResult<T> {
-        unreachable!()
+        f(self)
     }
 
     fn read_enum_variant<T, F>(
         &mut self,
-        _names: &[&str],
-        mut _f: F,
+        names: &[&str],
+        mut f: F,
     ) -> CborResult<T>
     where F: FnMut(&mut CborDecoder<R>, usize) -> CborResult<T> {
-        unreachable!()
+        // Only unit variants, which are written as their names, can be
+        // read. The others are maps.
+        let b = try!(self.rdr.read_u8());
+        self.rdr.push_byte(b);
+        if (b & 0b111_00000) >> 5 != Type::Unicode.major() {
+            return Err(self.unsupported());
+        }
+        let name = try!(self.read_str());
+        match names.iter().position(|&n| n == name) {
+            Some(idx) => f(self, idx),
+            None => Err(self.err(ReadError::UnknownVariant(name))),
+        }
     }
 
     fn read_enum_variant_arg<T, F>(
@@ -274,7 +474,7 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
         _f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder<R>) -> CborResult<T> {
-        unreachable!()
+        Err(self.unsupported())
     }
 
     fn read_enum_struct_variant<T, F>(
@@ -283,7 +483,7 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
         _f: F,
     ) -> CborResult<T>
     where F: FnMut(&mut CborDecoder<R>, usize) -> CborResult<T> {
-        unreachable!()
+        Err(self.unsupported())
     }
 
     fn read_enum_struct_variant_field<T, F>(
@@ -293,27 +493,54 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
         _f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder<R>) -> CborResult<T> {
-        unreachable!()
+        Err(self.unsupported())
     }
 
     fn read_struct<T, F>(
         &mut self,
-        _s_name: &str,
-        _len: usize,
-        _f: F,
+        s_name: &str,
+        len: usize,
+        f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder<R>) -> CborResult<T> {
-        unreachable!()
+        if s_name == "Cbor" || s_name == "CborSimple" {
+            // These need the abstract syntax of the data item.
+            return Err(self.unsupported());
+        }
+        match len {
+            // A unit struct is written as `null`, so it has nothing to find.
+            0 => try!(self.read_nil()),
+            // A newtype struct is written as the value of its field, but
+            // that depends on the name of the field.
+            1 => self.newtype = true,
+            _ => try!(self.read_struct_len(len)),
+        }
+        f(self)
     }
 
     fn read_struct_field<T, F>(
         &mut self,
-        _f_name: &str,
+        f_name: &str,
         _f_idx: usize,
-        _f: F,
+        f: F,
     ) -> CborResult<T>
     where F: FnOnce(&mut CborDecoder<R>) -> CborResult<T> {
-        unreachable!()
+        if mem::replace(&mut self.newtype, false) {
+            if f_name == "_field0" {
+                return f(self);
+            }
+            try!(self.read_struct_len(1));
+        }
+        // There's no looking ahead for a field, so the fields have to come
+        // in the order they're read in.
+        let name = try!(self.read_str());
+        if name != f_name {
+            return Err(self.errstr(format!(
+                "Expected struct field '{}', but got '{}'. DirectDecoder \
+                 needs the fields of a struct in the order they're declared.",
+                f_name, name)));
+        }
+        f(self)
     }
 
     fn read_tuple<T, F>(
@@ -325,9 +552,10 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
         let b = try!(self.read_type(Type::Array));
         let got_len = try!(self.read_len(Some(b)));
         if len != got_len {
-            return Err(self.errstr(format!(
-                "Expected tuple of length {:?}, but got array of length {:?}",
-                len, got_len)));
+            return Err(self.err(ReadError::LengthMismatch {
+                expected: len,
+                got: got_len,
+            }));
         }
         f(self)
     }
@@ -363,24 +591,60 @@ impl<R: io::Read> RustcDecoder for CborDecoder<R> {
     fn read_option<T, F>(&mut self, mut f: F) -> CborResult<T>
             where F: FnMut(&mut CborDecoder<R>, bool) -> CborResult<T> {
         let b = try!(self.rdr.read_u8());
-        if (b & 0b111_00000) >> 5 == 7 && b & 0b000_11111 == 22 {
-            f(self, false)
-        } else {
-            self.rdr.push_byte(b);
-            f(self, true)
+        if self.is_nil(b) {
+            return f(self, false);
         }
+        self.rdr.push_byte(b);
+        if self.options == OptionFormat::Array {
+            // `Some(v)` is written as `[v]`.
+            let b = try!(self.read_type(Type::Array));
+            let len = try!(self.read_len(Some(b)));
+            if len != 1 {
+                return Err(self.errstr(format!(
+                    "Expected null or an array of one item for an Option, \
+                     but got an array of {} items.", len)));
+            }
+        }
+        f(self, true)
     }
 
     fn read_seq<T, F>(&mut self, f: F) -> CborResult<T>
             where F: FnOnce(&mut CborDecoder<R>, usize) -> CborResult<T> {
-        let b = try!(self.read_type(Type::Array));
-        let len = try!(self.read_len(Some(b)));
-        f(self, len)
+        let b = try!(self.rdr.read_u8());
+        let major = (b & 0b111_00000) >> 5;
+        let string = major == Type::Bytes.major()
+                     || (self.coerce_strings
+                         && major == Type::Unicode.major());
+        let (len, bytes) = if string {
+            // A sequence of `u8`s may be written as a byte string (or a
+            // text string), whose contents are then read by `read_u8`.
+            let len = try!(self.read_len(Some(b)));
+            let mut bytes = try!(self.rdr.read_vec(len));
+            bytes.reverse();
+            (len, Some(bytes))
+        } else {
+            self.rdr.push_byte(b);
+            let b = try!(self.read_type(Type::Array));
+            (try!(self.read_len(Some(b))), None)
+        };
+        let outer = mem::replace(&mut self.seq_bytes, bytes);
+        let r = f(self, len);
+        self.seq_bytes = outer;
+        r
     }
 
     fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> CborResult<T>
             where F: FnOnce(&mut CborDecoder<R>) -> CborResult<T> {
-        f(self)
+        let left = self.seq_bytes.as_ref().map(|bytes| bytes.len());
+        let v = try!(f(self));
+        let now = self.seq_bytes.as_ref().map(|bytes| bytes.len());
+        match (left, now) {
+            (Some(left), Some(now)) if now + 1 != left => {
+                Err(self.errstr("Expected the items of a byte string to be \
+                                 read as u8s.".to_string()))
+            }
+            _ => Ok(v),
+        }
     }
 
     fn read_map<T, F>(&mut self, f: F) -> CborResult<T>
@@ -452,22 +716,24 @@ impl<R: io::Read> CborReader<R> {
         }
     }
 
-    fn read_full(&mut self, buf: &mut [u8]) -> io::Result<()> {
-        let mut n = 0usize;
-        while n < buf.len() {
-            n += try!(self.read(&mut buf[n..]));
+    /// Reads exactly `len` bytes into a new vector, which grows as bytes
+    /// are read since `len` can't be trusted.
+    fn read_vec(&mut self, len: usize) -> CborResult<Vec<u8>> {
+        let mut buf = vec![];
+        try!(io::Read::by_ref(self).take(len as u64).read_to_end(&mut buf));
+        if buf.len() < len {
+            return Err(CborError::AtOffset {
+                kind: ReadError::UnexpectedEOF {
+                    needed: len - buf.len(),
+                    offset: self.bytes_read,
+                },
+                offset: self.bytes_read,
+            });
         }
-        Ok(())
+        Ok(buf)
     }
 
     fn push_byte(&mut self, b: u8) {
         self.buf.push(b);
     }
 }
-
-fn vec_from_elem<T: Copy>(len: usize, v: T) -> Vec<T> {
-    let mut xs = Vec::with_capacity(len);
-    unsafe { xs.set_len(len); }
-    for x in &mut xs { *x = v; }
-    xs
-}
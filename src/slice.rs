@@ -0,0 +1,246 @@
+use std::str;
+
+use lexer::{Lexer, Token, token_type};
+use {CborError, CborResult, ReadError, Type, DEFAULT_MAX_DEPTH, eof, errat};
+
+/// Reads data items from a byte slice one head at a time, without
+/// allocating.
+///
+/// Byte and Unicode strings are borrowed from the input, and arrays and
+/// maps are read by their length, so the caller decides where their items
+/// go (e.g., into a fixed size array). This makes it usable where there is
+/// no allocator, like in a bootloader or an interrupt handler.
+///
+/// Indefinite length strings can't be borrowed, since their chunks aren't
+/// contiguous, so reading one returns an error. Errors for invalid data
+/// carry the byte offset of the data item. Some of them (e.g., integer
+/// overflow) allocate their message, but reading valid data never does.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::SliceDecoder;
+///
+/// // {"name": "fw", "version": [1, 2, 3]}
+/// let bytes = [0xa2, 0x64, b'n', b'a', b'm', b'e', 0x62, b'f', b'w',
+///              0x67, b'v', b'e', b'r', b's', b'i', b'o', b'n',
+///              0x83, 0x01, 0x02, 0x03];
+/// let mut dec = SliceDecoder::new(&bytes);
+/// let (mut name, mut version) = ("", [0u16; 3]);
+/// for _ in 0..dec.read_map().unwrap().unwrap() {
+///     match dec.read_str().unwrap() {
+///         "name" => name = dec.read_str().unwrap(),
+///         "version" => {
+///             assert_eq!(dec.read_array().unwrap(), Some(3));
+///             for v in version.iter_mut() {
+///                 *v = dec.read_u64().unwrap() as u16;
+///             }
+///         }
+///         _ => dec.skip().unwrap(),
+///     }
+/// }
+/// assert_eq!(name, "fw");
+/// assert_eq!(version, [1, 2, 3]);
+/// assert!(dec.is_empty());
+/// ```
+#[derive(Clone)]
+pub struct SliceDecoder<'a> {
+    lex: Lexer<'a>,
+    len: usize,
+}
+
+impl<'a> SliceDecoder<'a> {
+    /// Create a new decoder over the bytes given.
+    pub fn new(bytes: &'a [u8]) -> SliceDecoder<'a> {
+        SliceDecoder { lex: Lexer::new(bytes), len: bytes.len() }
+    }
+
+    /// Returns the byte offset of the next data item.
+    pub fn offset(&self) -> usize {
+        self.lex.offset()
+    }
+
+    /// Returns true if all of the input has been read.
+    pub fn is_empty(&self) -> bool {
+        self.lex.offset() >= self.len
+    }
+
+    /// Returns the type of the next data item without reading it.
+    pub fn peek_type(&self) -> CborResult<Type> {
+        self.clone().next().map(|(_, tok)| token_type(&tok))
+    }
+
+    /// Reads an unsigned integer.
+    pub fn read_u64(&mut self) -> CborResult<u64> {
+        match try!(self.next()) {
+            (_, Token::UInt(n)) => Ok(n),
+            (at, tok) => Err(mismatch(Type::UInt64, &tok, at)),
+        }
+    }
+
+    /// Reads an integer that fits in an `i64`.
+    pub fn read_i64(&mut self) -> CborResult<i64> {
+        let max = ::std::i64::MAX as u64;
+        match try!(self.next()) {
+            (_, Token::UInt(n)) if n <= max => Ok(n as i64),
+            (_, Token::NegInt(n)) if n <= max => Ok(-1 - n as i64),
+            (at, Token::UInt(n)) => {
+                Err(errat(ReadError::overflow(Type::Int64, n), at))
+            }
+            (at, Token::NegInt(n)) => {
+                Err(errat(ReadError::neg_overflow(Type::Int64, n), at))
+            }
+            (at, tok) => Err(mismatch(Type::Int64, &tok, at)),
+        }
+    }
+
+    /// Reads a float of any precision.
+    pub fn read_f64(&mut self) -> CborResult<f64> {
+        match try!(self.next()) {
+            (_, Token::Float(v)) => Ok(v),
+            (at, tok) => Err(mismatch(Type::Float, &tok, at)),
+        }
+    }
+
+    /// Reads a boolean.
+    pub fn read_bool(&mut self) -> CborResult<bool> {
+        match try!(self.next()) {
+            (_, Token::Bool(b)) => Ok(b),
+            (at, tok) => Err(mismatch(Type::Bool, &tok, at)),
+        }
+    }
+
+    /// Reads a null if it is the next data item, and returns whether it
+    /// was. This is useful for optional values.
+    pub fn read_null(&mut self) -> CborResult<bool> {
+        let mut ahead = self.clone();
+        match try!(ahead.next()) {
+            (_, Token::Null) => { *self = ahead; Ok(true) }
+            _ => Ok(false),
+        }
+    }
+
+    /// Reads a definite length byte string, borrowed from the input.
+    pub fn read_bytes(&mut self) -> CborResult<&'a [u8]> {
+        match try!(self.next()) {
+            (_, Token::Bytes(bytes)) => Ok(bytes),
+            (at, Token::BeginBytes) => Err(indefinite(Type::Bytes, at)),
+            (at, tok) => Err(mismatch(Type::Bytes, &tok, at)),
+        }
+    }
+
+    /// Reads a definite length Unicode string, borrowed from the input.
+    pub fn read_str(&mut self) -> CborResult<&'a str> {
+        match try!(self.next()) {
+            (at, Token::Text(bytes)) => {
+                str::from_utf8(bytes).map_err(|err| {
+                    errat(ReadError::InvalidUtf8(err), at)
+                })
+            }
+            (at, Token::BeginText) => Err(indefinite(Type::Unicode, at)),
+            (at, tok) => Err(mismatch(Type::Unicode, &tok, at)),
+        }
+    }
+
+    /// Reads the head of an array and returns its length, which is `None`
+    /// for an indefinite length array.
+    ///
+    /// The items of the array follow. An indefinite length array ends
+    /// where `read_break` returns true.
+    pub fn read_array(&mut self) -> CborResult<Option<usize>> {
+        match try!(self.next()) {
+            (_, Token::BeginArray(len)) => Ok(len),
+            (at, tok) => Err(mismatch(Type::Array, &tok, at)),
+        }
+    }
+
+    /// Reads the head of a map and returns its length in pairs, which is
+    /// `None` for an indefinite length map.
+    ///
+    /// The keys and values of the map follow, alternating. An indefinite
+    /// length map ends where `read_break` returns true.
+    pub fn read_map(&mut self) -> CborResult<Option<usize>> {
+        match try!(self.next()) {
+            (_, Token::BeginMap(len)) => Ok(len),
+            (at, tok) => Err(mismatch(Type::Map, &tok, at)),
+        }
+    }
+
+    /// Reads a tag number. The tagged data item follows.
+    pub fn read_tag(&mut self) -> CborResult<u64> {
+        match try!(self.next()) {
+            (_, Token::Tag(tag)) => Ok(tag),
+            (at, tok) => Err(mismatch(Type::Tag, &tok, at)),
+        }
+    }
+
+    /// Reads a break if it is the next data item, and returns whether it
+    /// was.
+    pub fn read_break(&mut self) -> CborResult<bool> {
+        let mut ahead = self.clone();
+        match try!(ahead.next()) {
+            (_, Token::Break) => { *self = ahead; Ok(true) }
+            _ => Ok(false),
+        }
+    }
+
+    /// Skips over one complete data item, including anything nested in
+    /// it.
+    pub fn skip(&mut self) -> CborResult<()> {
+        self.skip_nested(0)
+    }
+
+    /// Skips over one data item that is nested `depth` deep, counting
+    /// arrays, maps, tags and indefinite length strings as `Decoder::skip`
+    /// does.
+    fn skip_nested(&mut self, depth: usize) -> CborResult<()> {
+        let (at, tok) = try!(self.next());
+        let nested = match tok {
+            Token::BeginArray(_) | Token::BeginMap(_) | Token::Tag(_)
+            | Token::BeginBytes | Token::BeginText => true,
+            _ => false,
+        };
+        if nested && depth >= DEFAULT_MAX_DEPTH {
+            let max = DEFAULT_MAX_DEPTH;
+            return Err(errat(ReadError::TooDeep { max: max }, at));
+        }
+        let items = match tok {
+            Token::BeginArray(Some(len)) => len as u64,
+            // Saturating is fine, since the input would run out long
+            // before that many items are skipped.
+            Token::BeginMap(Some(len)) => (len as u64).saturating_mul(2),
+            Token::BeginArray(None) | Token::BeginMap(None)
+            | Token::BeginBytes | Token::BeginText => {
+                while !try!(self.read_break()) {
+                    try!(self.skip_nested(depth + 1));
+                }
+                return Ok(());
+            }
+            Token::Tag(_) => 1,
+            Token::Break => {
+                return Err(errat(ReadError::UnexpectedBreak { offset: at },
+                                 at));
+            }
+            _ => 0,
+        };
+        for _ in 0..items {
+            try!(self.skip_nested(depth + 1));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> CborResult<(usize, Token<'a>)> {
+        match self.lex.next() {
+            Some(r) => r,
+            None => Err(eof(1, self.len)),
+        }
+    }
+}
+
+fn mismatch(expected: Type, got: &Token, at: usize) -> CborError {
+    errat(ReadError::ty_mismatch(expected, token_type(got)), at)
+}
+
+fn indefinite(ty: Type, at: usize) -> CborError {
+    errat(ReadError::InvalidAddValue { ty: ty, val: 31 }, at)
+}
@@ -0,0 +1,348 @@
+use rustc_serialize::Decodable;
+
+use decoder::Decoder;
+use lexer::{ItemScanner, item_len};
+use rustc_decoder::{CborDecoder, Options};
+use {
+    Budget, Cbor, CborError, CborResult, Narrowing, OptionFormat, ReadError,
+    TagPolicy, DEFAULT_MAX_DEPTH, errat,
+};
+
+/// The result of asking a `StreamingDecoder` for its next data item.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decoded<T> {
+    /// A complete data item.
+    Item(T),
+    /// The bytes fed so far do not contain a complete data item.
+    NeedMore,
+}
+
+/// A push based CBOR decoder.
+///
+/// A `Decoder` pulls bytes from a reader and blocks until a data item is
+/// complete. A `StreamingDecoder` instead has bytes pushed to it with `feed`
+/// as they become available (e.g., from a non-blocking socket). Complete
+/// top-level data items can then be retrieved with `next_item` or
+/// `decode_next`, which return `Decoded::NeedMore` until enough bytes have
+/// been fed.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{Decoded, Encoder, StreamingDecoder};
+///
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[(1, 2), (3, 4)]).unwrap();
+/// let bytes = enc.into_bytes();
+///
+/// let mut dec = StreamingDecoder::new();
+/// dec.feed(&bytes[0..2]).unwrap();
+/// assert_eq!(dec.decode_next::<(i32, i32)>().unwrap(), Decoded::NeedMore);
+///
+/// dec.feed(&bytes[2..]).unwrap();
+/// assert_eq!(dec.decode_next().unwrap(), Decoded::Item((1, 2)));
+/// assert_eq!(dec.decode_next().unwrap(), Decoded::Item((3, 4)));
+/// assert_eq!(dec.decode_next::<(i32, i32)>().unwrap(), Decoded::NeedMore);
+/// ```
+pub struct StreamingDecoder {
+    buf: Vec<u8>,
+    // start of the bytes in `buf` that haven't been consumed yet
+    pos: usize,
+    // total number of bytes consumed, used for error reporting
+    consumed: usize,
+    // how far the data item at `pos` has been scanned
+    scan: ItemScanner,
+    opts: Options,
+    max_depth: usize,
+    max_buffer: usize,
+}
+
+impl StreamingDecoder {
+    /// Create a new streaming decoder with nothing buffered.
+    pub fn new() -> StreamingDecoder {
+        StreamingDecoder {
+            buf: vec![],
+            pos: 0,
+            consumed: 0,
+            scan: ItemScanner::new(),
+            opts: Options::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_buffer: ::std::usize::MAX,
+        }
+    }
+
+    /// Set how numbers are decoded into Rust types that can't represent
+    /// them. See `Decoder::narrowing` for details.
+    pub fn narrowing(mut self, narrowing: Narrowing) -> StreamingDecoder {
+        self.opts.narrowing = narrowing;
+        self
+    }
+
+    /// When enabled, `undefined` can't be decoded into Rust values. See
+    /// `Decoder::strict_undefined` for details.
+    pub fn strict_undefined(mut self, yes: bool) -> StreamingDecoder {
+        self.opts.strict_undefined = yes;
+        self
+    }
+
+    /// When enabled, text strings that hold numbers can be decoded as
+    /// numbers. See `Decoder::coerce_numbers` for details.
+    pub fn coerce_numbers(mut self, yes: bool) -> StreamingDecoder {
+        self.opts.coerce_numbers = yes;
+        self
+    }
+
+    /// When enabled, text strings and byte strings can be decoded as each
+    /// other. See `Decoder::coerce_strings` for details.
+    pub fn coerce_strings(mut self, yes: bool) -> StreamingDecoder {
+        self.opts.coerce_strings = yes;
+        self
+    }
+
+    /// Set how `Option`s are read. See `Decoder::option_format` for
+    /// details.
+    pub fn option_format(mut self, format: OptionFormat) -> StreamingDecoder {
+        self.opts.options = format;
+        self
+    }
+
+    /// Set how tagged data items are decoded into Rust values. See
+    /// `Decoder::tag_policy` for details.
+    pub fn tag_policy(mut self, tags: TagPolicy) -> StreamingDecoder {
+        self.opts.tags = tags;
+        self
+    }
+
+    /// Set the maximum number of arrays, maps and tags that a data item
+    /// may be nested in. See `Decoder::max_depth` for details. The default
+    /// is `128`.
+    ///
+    /// Data items can't be larger than `max_buffer`, since nothing is read
+    /// until all of a data item's bytes have been fed.
+    pub fn max_depth(mut self, max: usize) -> StreamingDecoder {
+        self.max_depth = max;
+        self
+    }
+
+    /// Set the maximum number of bytes that may be buffered, i.e., fed but
+    /// not yet consumed as part of a complete data item. By default, there
+    /// is no limit.
+    ///
+    /// Without one, a head that announces a huge string (e.g., in corrupt
+    /// or hostile data) makes the decoder buffer everything it is fed.
+    pub fn max_buffer(mut self, max: usize) -> StreamingDecoder {
+        self.max_buffer = max;
+        self
+    }
+
+    /// Add bytes to the end of the input.
+    ///
+    /// If that would buffer more than `max_buffer` bytes, nothing is added
+    /// and a `ReadError::BudgetExceeded` error is returned, with the offset
+    /// of the data item being buffered.
+    pub fn feed(&mut self, bytes: &[u8]) -> CborResult<()> {
+        if self.buffered().saturating_add(bytes.len()) > self.max_buffer {
+            let max = self.max_buffer as u64;
+            return Err(errat(ReadError::BudgetExceeded(Budget::Buffer(max)),
+                             self.consumed));
+        }
+        // Only shift unconsumed bytes to the front once they make up less
+        // than half of the buffer, so that consuming many small items
+        // doesn't become quadratic.
+        if self.pos > 0 && self.pos >= self.buf.len() / 2 {
+            let rest = self.buf[self.pos..].to_vec();
+            self.buf = rest;
+            self.pos = 0;
+        }
+        self.buf.extend(bytes.iter().cloned());
+        Ok(())
+    }
+
+    /// Returns the number of bytes that have been fed but not yet consumed
+    /// as part of a complete data item.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Returns the number of bytes consumed as part of complete data items
+    /// since this decoder was created.
+    ///
+    /// This is the offset, in everything fed so far, of the next data item.
+    pub fn bytes_consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Read the next top-level CBOR data item, if one is complete.
+    ///
+    /// If the data is malformed, an error is returned and the decoder makes
+    /// no further progress. If a data item is well formed but can't be
+    /// represented (e.g., a Unicode string with invalid UTF-8), then it is
+    /// skipped and an error is returned. In that case, the next call
+    /// continues with the following item.
+    pub fn next_item(&mut self) -> CborResult<Decoded<Cbor>> {
+        let consumed = self.consumed;
+        let len = match try!(self.scan.scan(&self.buf[self.pos..])
+                             .map_err(|err| shift_offset(err, consumed))) {
+            None => return Ok(Decoded::NeedMore),
+            Some(len) => len,
+        };
+        let item = {
+            let bytes = &self.buf[self.pos..self.pos + len];
+            Decoder::from_slice(bytes).max_depth(self.max_depth)
+                                      .items()
+                                      .next()
+        };
+        self.pos += len;
+        self.consumed += len;
+        match item {
+            Some(Ok(v)) => Ok(Decoded::Item(v)),
+            Some(Err(err)) => Err(shift_offset(err, consumed)),
+            // The scanner never reports an empty data item.
+            None => Err(CborError::UnexpectedEOF),
+        }
+    }
+
+    /// Decode the next top-level CBOR data item into a Rust value, if one is
+    /// complete.
+    ///
+    /// See `next_item` for how errors are handled.
+    pub fn decode_next<D: Decodable>(&mut self) -> CborResult<Decoded<D>> {
+        let offset = self.consumed;
+        match try!(self.next_item()) {
+            Decoded::NeedMore => Ok(Decoded::NeedMore),
+            Decoded::Item(v) => {
+                CborDecoder::decode_at(v, offset, self.opts)
+                            .map(Decoded::Item)
+            }
+        }
+    }
+}
+
+/// Decodes every top-level data item in `bytes` into a Rust value.
+///
+/// A data item that is well formed but can't be decoded as `D` (e.g., a
+/// string where a number is expected) gives an error in its place, and
+/// decoding continues with the next one. When the data itself is malformed
+/// or truncated, the end of the data item can't be found. Then an error is
+/// added and decoding stops, unless `resync` is true: in that case, the
+/// bytes are scanned for the next offset where a well formed data item
+/// starts that decodes as `D`, and decoding continues from there. This is
+/// best effort, since a few bytes in the middle of a damaged data item can
+/// look like a value too, but it recovers most of a partially corrupted
+/// log.
+///
+/// Byte offsets in errors are relative to the start of `bytes`.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::Encoder;
+///
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[("a", 1), ("b", 2), ("c", 3)]).unwrap();
+/// let mut bytes = enc.into_bytes();
+/// bytes[5] = 0xff; // damage the head of ("b", 2)
+///
+/// let all = cbor::decode_all::<(String, u8)>(&bytes, true);
+/// assert!(all[1].is_err());
+/// let ok: Vec<_> = all.into_iter().filter_map(|r| r.ok()).collect();
+/// assert_eq!(ok, vec![("a".to_string(), 1), ("c".to_string(), 3)]);
+///
+/// assert_eq!(cbor::decode_all::<(String, u8)>(&bytes, false).len(), 2);
+/// ```
+pub fn decode_all<D: Decodable>(bytes: &[u8], resync: bool)
+                               -> Vec<CborResult<D>> {
+    let mut results = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let (len, v) = match decode_at(bytes, pos) {
+            Ok(item) => item,
+            Err(err) => {
+                results.push(Err(err));
+                if !resync {
+                    break;
+                }
+                let found = (pos + 1..bytes.len()).filter_map(|at| {
+                    match decode_at(bytes, at) {
+                        Ok((len, Ok(v))) => Some((at, len, v)),
+                        _ => None,
+                    }
+                }).next();
+                match found {
+                    Some((at, len, v)) => {
+                        pos = at;
+                        (len, Ok(v))
+                    }
+                    None => break,
+                }
+            }
+        };
+        results.push(v);
+        pos += len;
+    }
+    results
+}
+
+/// Decodes the data item at `pos` in `bytes`, and returns its length too.
+///
+/// The outer error is for data that isn't well formed or is truncated,
+/// since then the length of the data item isn't known.
+fn decode_at<D: Decodable>(bytes: &[u8], pos: usize)
+                          -> CborResult<(usize, CborResult<D>)> {
+    let len = try!(item_at(bytes, pos));
+    Ok((len, decode_item(&bytes[pos..pos + len], pos)))
+}
+
+/// Returns the length of the data item at `pos` in `bytes`, which is found
+/// by reading only the heads of the data items in it.
+fn item_at(bytes: &[u8], pos: usize) -> CborResult<usize> {
+    match try!(item_len(&bytes[pos..]).map_err(|err| shift_offset(err, pos))) {
+        Some(len) => Ok(len),
+        None => {
+            let kind = ReadError::UnexpectedEOF {
+                needed: 1,
+                offset: bytes.len(),
+            };
+            Err(CborError::AtOffset { kind: kind, offset: pos })
+        }
+    }
+}
+
+/// Decodes `item`, which is exactly one data item that starts at `pos` in
+/// the input.
+fn decode_item<D: Decodable>(item: &[u8], pos: usize) -> CborResult<D> {
+    match Decoder::from_slice(item).items().next() {
+        Some(Ok(v)) => {
+            CborDecoder::decode_in(v, item, pos, Options::default())
+        }
+        Some(Err(err)) => Err(shift_offset(err, pos)),
+        // `item_len` never reports an empty data item.
+        None => Err(CborError::UnexpectedEOF),
+    }
+}
+
+/// Makes the byte offsets in `err` relative to a position `by` bytes
+/// earlier in the input.
+pub fn shift_offset(err: CborError, by: usize) -> CborError {
+    match err {
+        CborError::AtOffset {
+            kind: ReadError::UnexpectedEOF { needed, offset: eof }, offset,
+        } => CborError::AtOffset {
+            kind: ReadError::UnexpectedEOF {
+                needed: needed,
+                offset: eof + by,
+            },
+            offset: offset + by,
+        },
+        CborError::AtOffset {
+            kind: ReadError::UnexpectedBreak { offset: brk }, offset,
+        } => CborError::AtOffset {
+            kind: ReadError::UnexpectedBreak { offset: brk + by },
+            offset: offset + by,
+        },
+        CborError::AtOffset { kind, offset } => {
+            CborError::AtOffset { kind: kind, offset: offset + by }
+        }
+        err => err,
+    }
+}
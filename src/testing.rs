@@ -0,0 +1,147 @@
+use quickcheck::{Arbitrary, Gen};
+
+use {
+    Cbor, CborBytes, CborFloat, CborMap, CborSigned, CborSimple, CborTag,
+    CborUnsigned, CborWide,
+};
+
+/// Generates any data item that can be encoded.
+///
+/// Generated values are in the form that `Decoder` (with
+/// `faithful_integers` enabled) produces: integers use the smallest type
+/// that fits, floats are single or double precision and never NaN, and
+/// `Cbor::Wide` is only used for integers that are wider than needed or
+/// below `i64::MIN`. This means that any generated value encodes and
+/// decodes back to itself.
+///
+/// Shrinking yields the data items nested inside a value.
+///
+/// This is only available with the `testing` feature.
+impl Arbitrary for Cbor {
+    fn arbitrary<G: Gen>(g: &mut G) -> Cbor {
+        let size = g.size();
+        arbitrary_item(g, size)
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item=Cbor>> {
+        let children: Vec<Cbor> = match *self {
+            Cbor::Array(ref items) => items.clone(),
+            Cbor::Map(ref map) => map.values().cloned().collect(),
+            Cbor::Tag(ref tag) => vec![(*tag.data).clone()],
+            _ => vec![],
+        };
+        Box::new(children.into_iter())
+    }
+}
+
+/// Generates a data item whose nested items have at most `size` items in
+/// total.
+fn arbitrary_item<G: Gen>(g: &mut G, size: usize) -> Cbor {
+    // Only containers can use up the size, so stop picking them once it
+    // runs out.
+    let kinds = if size == 0 { 10 } else { 13 };
+    match g.gen_range(0, kinds) {
+        0 => Cbor::Undefined,
+        1 => Cbor::Null,
+        2 => Cbor::Bool(g.gen()),
+        3 => Cbor::Unsigned(CborUnsigned::smallest(arbitrary_arg(g))),
+        4 => {
+            let arg = arbitrary_arg(g) >> 1;
+            Cbor::Signed(CborSigned::smallest(-1 - arg as i64))
+        }
+        5 => arbitrary_float(g),
+        6 => {
+            let len = g.gen_range(0, 64);
+            Cbor::Bytes(CborBytes(g.gen_iter().take(len).collect()))
+        }
+        7 => Cbor::Unicode(Arbitrary::arbitrary(g)),
+        8 => match g.gen() {
+            // The reserved values 24-31 are skipped, as are the values
+            // that have a type of their own.
+            v @ 0...19 | v @ 32...255 => Cbor::Simple(CborSimple(v)),
+            _ => Cbor::Null,
+        },
+        9 => arbitrary_wide(g),
+        10 => {
+            let len = g.gen_range(0, size + 1);
+            let sizes = split(g, size - len, len);
+            Cbor::Array(sizes.into_iter()
+                             .map(|n| arbitrary_item(g, n))
+                             .collect())
+        }
+        11 => {
+            let len = g.gen_range(0, size + 1);
+            let sizes = split(g, size - len, len);
+            let mut map = CborMap::with_capacity(len);
+            for n in sizes {
+                let key: String = Arbitrary::arbitrary(g);
+                let val = arbitrary_item(g, n);
+                map.insert(key, val);
+            }
+            Cbor::Map(map)
+        }
+        _ => Cbor::Tag(CborTag {
+            tag: arbitrary_arg(g),
+            data: Box::new(arbitrary_item(g, size - 1)),
+        }),
+    }
+}
+
+/// Generates an integer argument, with every encoded width about equally
+/// likely.
+fn arbitrary_arg<G: Gen>(g: &mut G) -> u64 {
+    let shift = g.gen_range(0, 64);
+    g.gen::<u64>() >> shift
+}
+
+fn arbitrary_float<G: Gen>(g: &mut G) -> Cbor {
+    // NaN isn't equal to itself, so it would never decode back to itself.
+    loop {
+        if g.gen() {
+            let v = f32_from_bits(g.gen());
+            if !v.is_nan() {
+                return Cbor::Float(CborFloat::Float32(v));
+            }
+        } else {
+            let v = f64_from_bits(g.gen());
+            if !v.is_nan() {
+                return Cbor::Float(CborFloat::Float64(v));
+            }
+        }
+    }
+}
+
+fn arbitrary_wide<G: Gen>(g: &mut G) -> Cbor {
+    let negative: bool = g.gen();
+    let arg = arbitrary_arg(g);
+    let min = CborWide::width_of(arg);
+    let widths: Vec<u8> = [1, 2, 4, 8].iter()
+                                      .cloned()
+                                      .filter(|&w| w > min)
+                                      .collect();
+    if widths.is_empty() {
+        // The integer already needs the widest encoding.
+        return CborWide { negative: negative, arg: arg, width: 8 }.to_cbor();
+    }
+    let width = widths[g.gen_range(0, widths.len())];
+    Cbor::Wide(CborWide { negative: negative, arg: arg, width: width })
+}
+
+/// Splits `total` into `n` random parts.
+fn split<G: Gen>(g: &mut G, total: usize, n: usize) -> Vec<usize> {
+    let mut parts = vec![0; n];
+    if n > 0 {
+        for _ in 0..total {
+            parts[g.gen_range(0, n)] += 1;
+        }
+    }
+    parts
+}
+
+fn f32_from_bits(bits: u32) -> f32 {
+    unsafe { ::std::mem::transmute(bits) }
+}
+
+fn f64_from_bits(bits: u64) -> f64 {
+    unsafe { ::std::mem::transmute(bits) }
+}
@@ -0,0 +1,84 @@
+use {Cbor, CborBytes, CborFloat, CborMap, CborSigned, CborUnsigned, ToCbor};
+
+impl ToCbor for Cbor {
+    fn to_cbor(&self) -> Cbor { self.clone() }
+}
+
+impl<'a, T: ToCbor + ?Sized> ToCbor for &'a T {
+    fn to_cbor(&self) -> Cbor { (**self).to_cbor() }
+}
+
+impl ToCbor for bool {
+    fn to_cbor(&self) -> Cbor { Cbor::Bool(*self) }
+}
+
+macro_rules! unsigned_to_cbor {
+    ($($ty:ty),*) => {$(
+        impl ToCbor for $ty {
+            fn to_cbor(&self) -> Cbor {
+                Cbor::Unsigned(CborUnsigned::smallest(*self as u64))
+            }
+        }
+    )*}
+}
+
+unsigned_to_cbor!(u8, u16, u32, u64, usize);
+
+macro_rules! signed_to_cbor {
+    ($($ty:ty),*) => {$(
+        impl ToCbor for $ty {
+            fn to_cbor(&self) -> Cbor {
+                if *self >= 0 {
+                    Cbor::Unsigned(CborUnsigned::smallest(*self as u64))
+                } else {
+                    Cbor::Signed(CborSigned::smallest(*self as i64))
+                }
+            }
+        }
+    )*}
+}
+
+signed_to_cbor!(i8, i16, i32, i64, isize);
+
+impl ToCbor for f32 {
+    fn to_cbor(&self) -> Cbor { Cbor::Float(CborFloat::Float32(*self)) }
+}
+
+impl ToCbor for f64 {
+    fn to_cbor(&self) -> Cbor { Cbor::Float(CborFloat::Float64(*self)) }
+}
+
+impl ToCbor for str {
+    fn to_cbor(&self) -> Cbor { Cbor::Unicode(self.to_string()) }
+}
+
+impl ToCbor for String {
+    fn to_cbor(&self) -> Cbor { Cbor::Unicode(self.clone()) }
+}
+
+impl ToCbor for CborBytes {
+    fn to_cbor(&self) -> Cbor { Cbor::Bytes(self.clone()) }
+}
+
+impl ToCbor for CborMap {
+    fn to_cbor(&self) -> Cbor { Cbor::Map(self.clone()) }
+}
+
+impl<T: ToCbor> ToCbor for [T] {
+    fn to_cbor(&self) -> Cbor {
+        Cbor::Array(self.iter().map(|v| v.to_cbor()).collect())
+    }
+}
+
+impl<T: ToCbor> ToCbor for Vec<T> {
+    fn to_cbor(&self) -> Cbor { self[..].to_cbor() }
+}
+
+impl<T: ToCbor> ToCbor for Option<T> {
+    fn to_cbor(&self) -> Cbor {
+        match *self {
+            None => Cbor::Null,
+            Some(ref v) => v.to_cbor(),
+        }
+    }
+}
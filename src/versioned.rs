@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use rustc_serialize::{
+    Decodable, Decoder as RustcDecoder, Encodable, Encoder as RustcEncoder,
+};
+
+use rustc_decoder::{CborDecoder, Options};
+use {Cbor, CborError, CborResult, ReadError, from_slice_exact};
+
+/// A value together with the version of its schema.
+///
+/// This is written as the array `[version, value]`. Decoding a
+/// `Versioned<T>` accepts any version, as long as the value decodes as
+/// `T`; use `Versions` to decode values written with older schemas.
+///
+/// # Example
+///
+/// ```rust
+/// use cbor::{Encoder, Versioned};
+///
+/// let mut enc = Encoder::from_memory();
+/// enc.encode(&[Versioned { version: 2, value: "x" }]).unwrap();
+/// assert_eq!(enc.as_bytes(), &[0x82, 0x02, 0x61, b'x']);
+///
+/// let v: Versioned<String> = cbor::from_slice_exact(enc.as_bytes())
+///                                 .unwrap();
+/// assert_eq!((v.version, v.value), (2, "x".to_string()));
+/// ```
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Versioned<T> {
+    /// The version of the schema of `value`.
+    pub version: u64,
+    /// The value.
+    pub value: T,
+}
+
+impl<T: Encodable> Encodable for Versioned<T> {
+    fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        e.emit_tuple(2, |e| {
+            try!(e.emit_tuple_arg(0, |e| self.version.encode(e)));
+            e.emit_tuple_arg(1, |e| self.value.encode(e))
+        })
+    }
+}
+
+impl<T: Decodable> Decodable for Versioned<T> {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<Versioned<T>, D::Error> {
+        d.read_tuple(2, |d| {
+            Ok(Versioned {
+                version: try!(d.read_tuple_arg(0, Decodable::decode)),
+                value: try!(d.read_tuple_arg(1, Decodable::decode)),
+            })
+        })
+    }
+}
+
+/// Decodes `Versioned` values of any registered version into the current
+/// type `T`.
+///
+/// Each version is registered with the type its values were written as and
+/// a function that upgrades such a value to a `T`, so a service can read
+/// data written by both older and newer releases of itself. Values with a
+/// version that isn't registered are a `ReadError::UnknownVersion` error.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate cbor;
+/// # extern crate rustc_serialize;
+/// use cbor::{Encoder, Versioned, Versions};
+///
+/// #[derive(RustcEncodable, RustcDecodable)]
+/// struct UserV1 { name: String }
+///
+/// #[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+/// struct User { name: String, admin: bool }
+///
+/// # fn main() {
+/// let versions = Versions::new()
+///     .register(1, |u: UserV1| User { name: u.name, admin: false })
+///     .current(2);
+///
+/// let mut enc = Encoder::from_memory();
+/// let old = UserV1 { name: "a".to_string() };
+/// enc.encode(&[Versioned { version: 1, value: old }]).unwrap();
+/// let user = versions.decode(enc.as_bytes()).unwrap();
+/// assert_eq!(user, User { name: "a".to_string(), admin: false });
+/// # }
+/// ```
+pub struct Versions<T> {
+    upgrades: HashMap<u64, Box<Fn(Cbor) -> CborResult<T>>>,
+}
+
+impl<T: 'static> Versions<T> {
+    /// Creates a set of versions with none registered.
+    pub fn new() -> Versions<T> {
+        Versions { upgrades: HashMap::new() }
+    }
+
+    /// Registers `version`, whose values are decoded as `U` and then
+    /// converted to a `T` with `upgrade`. This replaces anything registered
+    /// for `version` before.
+    pub fn register<U, F>(mut self, version: u64, upgrade: F) -> Versions<T>
+            where U: Decodable + 'static, F: Fn(U) -> T + 'static {
+        self.upgrades.insert(version, Box::new(move |v| {
+            let old = try!(CborDecoder::decode_at(v, 0, Options::default()));
+            Ok(upgrade(old))
+        }));
+        self
+    }
+
+    /// Registers `version` as the version of `T` itself, whose values need
+    /// no upgrade.
+    pub fn current(self, version: u64) -> Versions<T> where T: Decodable {
+        self.register(version, |v: T| v)
+    }
+
+    /// Decodes `bytes`, which must hold exactly one `Versioned` data item,
+    /// and upgrades its value to a `T`.
+    pub fn decode(&self, bytes: &[u8]) -> CborResult<T> {
+        self.upgrade(try!(from_slice_exact(bytes)))
+    }
+
+    /// Upgrades a value that has been decoded as the abstract syntax of
+    /// its version to a `T`.
+    pub fn upgrade(&self, v: Versioned<Cbor>) -> CborResult<T> {
+        match self.upgrades.get(&v.version) {
+            Some(upgrade) => upgrade(v.value),
+            None => {
+                let err = ReadError::UnknownVersion(v.version);
+                Err(CborError::Decode(err))
+            }
+        }
+    }
+}
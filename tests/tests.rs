@@ -1,6 +1,6 @@
 #![allow(trivial_casts)]
 
-extern crate cbor;
+#[macro_use] extern crate cbor;
 extern crate quickcheck;
 extern crate rand;
 extern crate rustc_serialize;
@@ -206,3 +206,3294 @@ fn roundtrip_tag_fancier_data() {
         value: vec![1, 2, 3, 4, 5],
     }));
 }
+
+#[test]
+fn tag_field_names_outside_tags() {
+    // Only the fields of `CborTagEncode` itself are written without keys.
+    #[derive(RustcEncodable)]
+    struct Odd {
+        __cbor_tag_encode_data: u8,
+    }
+
+    let odd = Odd { __cbor_tag_encode_data: 1 };
+    assert_eq!(readone(&encode(&odd)).to_string(),
+               r#"{"__cbor_tag_encode_data": 1}"#);
+    assert_eq!(readone(&encode(CborTagEncode::new(5, &odd))).to_string(),
+               r#"5({"__cbor_tag_encode_data": 1})"#);
+}
+
+#[test]
+fn lexer_tokens_with_offsets() {
+    use cbor::{Lexer, Token};
+
+    // [1, [_ -1], "a", 1(h'ff')] with an indefinite length inner array.
+    let bytes = [0x84, 0x01, 0x9f, 0x20, 0xff, 0x61, b'a', 0xc1, 0x41, 0xff];
+    let toks = Lexer::new(&bytes).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(toks, vec![
+        (0, Token::BeginArray(Some(4))),
+        (1, Token::UInt(1)),
+        (2, Token::BeginArray(None)),
+        (3, Token::NegInt(0)),
+        (4, Token::Break),
+        (5, Token::Text(b"a")),
+        (7, Token::Tag(1)),
+        (8, Token::Bytes(&[0xff])),
+    ]);
+}
+
+#[test]
+fn lexer_truncated() {
+    use cbor::Lexer;
+
+    let mut lex = Lexer::new(&[0x19, 0x01]);
+    assert!(lex.next().unwrap().is_err());
+    assert!(lex.next().is_none());
+}
+
+#[test]
+fn streaming_one_byte_at_a_time() {
+    use cbor::{Decoded, StreamingDecoder};
+
+    let data = vec![("a".to_string(), vec![1, 1000]),
+                    ("b".to_string(), vec![100_000])];
+    let mut enc = Encoder::from_memory();
+    enc.encode(&data).unwrap();
+    let bytes = enc.into_bytes();
+    let mut dec = StreamingDecoder::new();
+    let mut got: Vec<(String, Vec<u32>)> = vec![];
+    for &b in &bytes {
+        dec.feed(&[b]).unwrap();
+        match dec.decode_next().unwrap() {
+            Decoded::Item(v) => got.push(v),
+            Decoded::NeedMore => {}
+        }
+    }
+    assert_eq!(got, data);
+    assert_eq!(dec.buffered(), 0);
+}
+
+#[test]
+fn streaming_skips_undecodable_item() {
+    use cbor::{Decoded, StreamingDecoder};
+
+    let mut dec = StreamingDecoder::new();
+    // An invalid UTF-8 string followed by `1`.
+    dec.feed(&[0x61, 0xff, 0x01]).unwrap();
+    assert!(dec.decode_next::<String>().is_err());
+    assert_eq!(dec.decode_next::<u8>().unwrap(), Decoded::Item(1));
+}
+
+#[test]
+fn streaming_max_buffer() {
+    use cbor::{Budget, CborError, Decoded, ReadError, StreamingDecoder};
+
+    let mut dec = StreamingDecoder::new().max_buffer(16);
+    dec.feed(&[0x01]).unwrap();
+    assert_eq!(dec.decode_next::<u8>().unwrap(), Decoded::Item(1));
+    // A byte string that claims to be 2^63 bytes long.
+    dec.feed(&[0x5b, 0x80, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+    dec.feed(&[0; 7]).unwrap();
+    assert_eq!(dec.next_item().unwrap(), Decoded::NeedMore);
+    match dec.feed(&[0]) {
+        Err(CborError::AtOffset {
+            kind: ReadError::BudgetExceeded(Budget::Buffer(16)), offset: 1,
+        }) => {}
+        r => panic!("expected BudgetExceeded, got {:?}", r),
+    }
+    assert_eq!(dec.buffered(), 16);
+
+    // Scanning picks up where it stopped, in nested and indefinite length
+    // data items too.
+    // [[_ "a", {"b": 2}], (_ h'00')], 7
+    let bytes = [0x82, 0x9f, 0x61, b'a', 0xa1, 0x61, b'b', 0x02, 0xff, 0x5f,
+                 0x41, 0x00, 0xff, 0x07];
+    let mut dec = StreamingDecoder::new();
+    for &b in &bytes[..bytes.len() - 2] {
+        dec.feed(&[b]).unwrap();
+        assert_eq!(dec.next_item().unwrap(), Decoded::NeedMore);
+    }
+    dec.feed(&bytes[bytes.len() - 2..]).unwrap();
+    let want = Cbor::from_diagnostic(r#"[["a", {"b": 2}], h'00']"#).unwrap();
+    assert_eq!(dec.next_item().unwrap(), Decoded::Item(want));
+    assert_eq!(dec.decode_next::<u8>().unwrap(), Decoded::Item(7));
+}
+
+#[test]
+fn truncated_item_reports_needed_bytes() {
+    use cbor::{CborError, ReadError};
+
+    // A u16 with one byte missing.
+    match Decoder::from_bytes(vec![0x19, 0x01]).items().next() {
+        Some(Err(CborError::AtOffset {
+            kind: ReadError::UnexpectedEOF { needed: 1, offset: 2 }, ..
+        })) => {}
+        v => panic!("expected UnexpectedEOF, got {:?}", v),
+    }
+    // An array that claims three elements but only has one.
+    match Decoder::from_bytes(vec![0x83, 0x01]).items().next() {
+        Some(Err(CborError::AtOffset {
+            kind: ReadError::MissingItems {
+                expected: 3, got: 1, eof: true, ..
+            },
+            offset: 2,
+        })) => {}
+        v => panic!("expected MissingItems, got {:?}", v),
+    }
+    // EOF between top-level items is not an error.
+    let mut dec = Decoder::from_bytes(vec![0x01, 0x02]);
+    assert_eq!(dec.items().count(), 2);
+}
+
+#[test]
+fn decode_error_has_offset_and_path() {
+    use cbor::CborError;
+
+    #[derive(RustcEncodable)]
+    struct Header { id: u32 }
+    #[derive(RustcEncodable)]
+    struct Item { header: Header }
+    #[derive(Debug, RustcDecodable)]
+    struct BadHeader { id: String }
+    #[derive(Debug, RustcDecodable)]
+    struct Bad { header: BadHeader }
+
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[1]).unwrap();
+    enc.encode(&[vec![Item { header: Header { id: 5 } }]]).unwrap();
+
+    let mut dec = Decoder::from_bytes(enc.as_bytes());
+    let mut it = dec.decode::<Vec<Bad>>();
+    match it.next().unwrap() {
+        Err(CborError::AtPath { offset: 0, ref path, .. }) => {
+            assert_eq!(path, "");
+        }
+        v => panic!("expected AtPath error, got {:?}", v),
+    }
+    match it.next().unwrap() {
+        Err(CborError::AtPath { offset: 1, ref path, .. }) => {
+            assert_eq!(path, "[0].header.id");
+        }
+        v => panic!("expected AtPath error, got {:?}", v),
+    }
+}
+
+#[test]
+fn decode_error_path_after_recovering() {
+    use rustc_serialize::Decoder as RustcDecoder;
+    use cbor::CborError;
+
+    // A sequence of `u8`s, where anything else is skipped unless it's the
+    // last item.
+    #[derive(Debug)]
+    struct Lenient(Vec<u8>);
+
+    impl Decodable for Lenient {
+        fn decode<D: RustcDecoder>(d: &mut D) -> Result<Lenient, D::Error> {
+            d.read_seq(|d, len| {
+                let mut v = vec![];
+                for i in 0..len {
+                    match d.read_seq_elt(i, Decodable::decode) {
+                        Ok(n) => v.push(n),
+                        Err(_) if i + 1 < len => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(Lenient(v))
+            })
+        }
+    }
+
+    let bytes = encode(vec![cbor!([1, "x", 3])]);
+    let v: Vec<Lenient> = cbor::from_slice_exact(&bytes).unwrap();
+    assert_eq!(v[0].0, vec![1, 3]);
+
+    // The error in "x" that was recovered from isn't part of the path.
+    let bytes = encode(vec![cbor!([1, "x", 3, "y"])]);
+    match cbor::from_slice_exact::<Vec<Lenient>>(&bytes) {
+        Err(CborError::AtPath { offset: 6, ref path, .. }) => {
+            assert_eq!(path, "[0][3]");
+        }
+        v => panic!("expected AtPath error, got {:?}", v),
+    }
+}
+
+#[test]
+fn errors_chain_causes() {
+    use std::error::Error;
+    use cbor::{CborError, ReadError};
+
+    let err = Decoder::from_bytes(vec![0x61, 0xff]).items().next()
+                                                   .unwrap().unwrap_err();
+    match err {
+        CborError::AtOffset { kind: ReadError::InvalidUtf8(_), .. } => {}
+        ref err => panic!("expected InvalidUtf8, got {:?}", err),
+    }
+    let kind = err.cause().unwrap();
+    assert!(kind.cause().is_some());
+    assert!(err.to_string().contains("Invalid UTF-8"));
+}
+
+#[test]
+fn decode_errors_are_structured() {
+    use cbor::{CborError, ReadError};
+
+    #[derive(Debug, RustcDecodable)]
+    struct Vowels { s: String, n: u32 }
+    #[derive(Debug, RustcDecodable)]
+    enum Color { Red }
+
+    let mut map = HashMap::new();
+    map.insert("s".to_string(), "cwm".to_string());
+    let bytes = encode(&map);
+    match Decoder::from_bytes(bytes).decode::<Vowels>().next().unwrap() {
+        Err(CborError::AtPath {
+            kind: ReadError::MissingField(ref f), ..
+        }) => assert_eq!(f, "n"),
+        v => panic!("expected MissingField, got {:?}", v),
+    }
+
+    let bytes = encode("Blue");
+    match Decoder::from_bytes(bytes).decode::<Color>().next().unwrap() {
+        Err(CborError::AtPath {
+            kind: ReadError::UnknownVariant(ref v), ..
+        }) => assert_eq!(v, "Blue"),
+        v => panic!("expected UnknownVariant, got {:?}", v),
+    }
+
+    let bytes = encode(&(1, 2, 3));
+    match Decoder::from_bytes(bytes).decode::<(u8, u8)>().next().unwrap() {
+        Err(CborError::AtPath {
+            kind: ReadError::LengthMismatch { expected: 2, got: 3 }, ..
+        }) => {}
+        v => panic!("expected LengthMismatch, got {:?}", v),
+    }
+}
+
+#[test]
+fn integers_decode_by_value() {
+    use cbor::{CborError, ReadError, Type};
+
+    assert_eq!(decode::<u64>(&encode(::std::u64::MAX)), ::std::u64::MAX);
+    // `5` encoded with an 8 byte argument.
+    let bytes = [0x1b, 0, 0, 0, 0, 0, 0, 0, 5];
+    assert_eq!(decode::<u8>(&bytes), 5);
+    assert_eq!(decode::<i8>(&bytes), 5);
+
+    let bytes = encode(::std::i64::MAX as u64 + 1);
+    match Decoder::from_bytes(bytes).decode::<i64>().next().unwrap() {
+        Err(CborError::AtPath {
+            kind: ReadError::IntegerOverflow { expected, ref value }, ..
+        }) => {
+            assert_eq!(expected, Type::Int64);
+            assert_eq!(value, "9223372036854775808");
+        }
+        v => panic!("expected IntegerOverflow, got {:?}", v),
+    }
+    let bytes = encode(300);
+    assert!(Decoder::from_bytes(bytes).decode::<u8>().next().unwrap()
+                                                      .is_err());
+}
+
+#[test]
+fn direct_decoder_integers() {
+    use cbor::DirectDecoder;
+
+    let mut dec = DirectDecoder::from_bytes(encode(&(-40000i32, 200u8)));
+    assert_eq!(<(i32, u8)>::decode(&mut dec).unwrap(), (-40000, 200));
+
+    let mut dec = DirectDecoder::from_bytes(encode(200u8));
+    assert!(i8::decode(&mut dec).is_err());
+}
+
+#[test]
+fn narrowing_options() {
+    use cbor::{CborError, DirectDecoder, Narrowing, ReadError};
+
+    let bytes = encode(&(300, -300, 1e300f64, 0.1f64));
+    let decode_with = |narrowing| {
+        Decoder::from_bytes(&bytes[..])
+                .narrowing(narrowing)
+                .decode::<(u8, i8, f32, f32)>()
+                .next().unwrap()
+    };
+    match decode_with(Narrowing::Error) {
+        Err(CborError::AtPath {
+            kind: ReadError::IntegerOverflow { .. }, ref path, ..
+        }) => assert_eq!(path, "[0]"),
+        v => panic!("expected IntegerOverflow, got {:?}", v),
+    }
+    assert_eq!(decode_with(Narrowing::Truncate).unwrap(),
+               (44, -44, ::std::f32::INFINITY, 0.1));
+    assert_eq!(decode_with(Narrowing::Saturate).unwrap(),
+               (255, -128, ::std::f32::MAX, 0.1));
+
+    let bytes = encode(0.1f64);
+    match Decoder::from_bytes(bytes).decode::<f32>().next().unwrap() {
+        Err(CborError::AtPath {
+            kind: ReadError::InexactFloat { .. }, ..
+        }) => {}
+        v => panic!("expected InexactFloat, got {:?}", v),
+    }
+
+    // Integers decoded into floats must be exact too.
+    let big = (1u64 << 53) + 1;
+    let bytes = encode(&(big, -(1i64 << 24) - 1, 1u64 << 63, -(1i64 << 24)));
+    let decode_with = |narrowing| {
+        Decoder::from_bytes(&bytes[..])
+                .narrowing(narrowing)
+                .decode::<(f64, f32, f32, f32)>()
+                .next().unwrap()
+    };
+    match decode_with(Narrowing::Error) {
+        Err(CborError::AtPath {
+            kind: ReadError::InexactFloat { .. }, ref path, ..
+        }) => assert_eq!(path, "[0]"),
+        v => panic!("expected InexactFloat, got {:?}", v),
+    }
+    let rounded = (big as f64, -16777216.0, 9223372036854775808.0,
+                   -16777216.0);
+    assert_eq!(decode_with(Narrowing::Truncate).unwrap(), rounded);
+    assert_eq!(decode_with(Narrowing::Saturate).unwrap(), rounded);
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert!(<(f64, f32, f32, f32)>::decode(&mut dec).is_err());
+    let mut dec = DirectDecoder::from_bytes(&bytes[..])
+                                .narrowing(Narrowing::Truncate);
+    assert_eq!(<(f64, f32, f32, f32)>::decode(&mut dec).unwrap(), rounded);
+
+    // As must integers below i64::MIN.
+    let bytes = [0x3b, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(decode::<f32>(&bytes), -18446742974197923840.0);
+    let bytes = [0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(decode::<f64>(&bytes), -18446744073709551616.0);
+    let bytes = [0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+    let r = Decoder::from_bytes(&bytes[..]).decode::<f64>().next().unwrap();
+    assert!(r.is_err());
+}
+
+#[test]
+fn undefined_decodes_as_nil() {
+    use cbor::{CborError, DirectDecoder, ReadError, Type};
+
+    // [undefined, undefined]
+    let bytes = [0x82, 0xf7, 0xf7];
+    assert_eq!(decode::<((), Option<u8>)>(&bytes), ((), None));
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert_eq!(<((), Option<u8>)>::decode(&mut dec).unwrap(), ((), None));
+
+    let mut dec = Decoder::from_bytes(&bytes[..]).strict_undefined(true);
+    match dec.decode::<((), Option<u8>)>().next().unwrap() {
+        Err(CborError::AtPath {
+            kind: ReadError::TypeMismatch { got: Type::Undefined, .. }, ..
+        }) => {}
+        v => panic!("expected TypeMismatch, got {:?}", v),
+    }
+}
+
+#[test]
+fn roundtrip_simple_values() {
+    use cbor::CborSimple;
+
+    for &v in &[0u8, 16, 20, 23, 32, 255] {
+        assert!(round_trip(CborSimple(v)));
+    }
+    assert_eq!(encode(CborSimple(16)), vec![0xf0]);
+    assert_eq!(encode(CborSimple(255)), vec![0xf8, 0xff]);
+    assert_eq!(readone(&[0xf8, 0xff]), Cbor::Simple(CborSimple(255)));
+    assert!(Decoder::from_bytes(vec![0xf8, 0x10]).items().next().unwrap()
+                                                   .is_err());
+
+    let mut enc = Encoder::from_memory();
+    assert!(enc.encode(&[CborSimple(24)]).is_err());
+}
+
+#[test]
+fn stray_break_is_an_error() {
+    use cbor::{CborError, Decoded, ReadError, StreamingDecoder};
+
+    // [1, break]
+    let mut dec = Decoder::from_bytes(vec![0x82, 0x01, 0xff]);
+    match dec.items().next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::MissingItems { eof: false, .. }, offset: 2,
+        }) => {}
+        v => panic!("expected MissingItems, got {:?}", v),
+    }
+
+    let mut dec = StreamingDecoder::new();
+    dec.feed(&[0x01, 0xff]).unwrap();
+    assert_eq!(dec.next_item().unwrap(), Decoded::Item(readone(&[0x01])));
+    match dec.next_item() {
+        Err(CborError::AtOffset {
+            kind: ReadError::UnexpectedBreak { offset: 1 }, ..
+        }) => {}
+        v => panic!("expected UnexpectedBreak, got {:?}", v),
+    }
+}
+
+#[test]
+fn json_conversion_follows_rfc() {
+    use cbor::{CborTagEncode, ToCbor, to_json_rfc8949};
+    use rustc_serialize::json::{Json, ToJson};
+
+    fn json<T: Encodable>(v: T) -> Json {
+        ::cbor::to_json_rfc8949(&::readone(&::encode(v)))
+    }
+    let bytes = CborBytes(vec![0xfb, 0xff]);
+    assert_eq!(json(&bytes), Json::String("-_8".to_string()));
+    assert_eq!(json(&CborTagEncode::new(22, &bytes)),
+               Json::String("+/8=".to_string()));
+    assert_eq!(json(&CborTagEncode::new(23, &bytes)),
+               Json::String("fbff".to_string()));
+    assert_eq!(json(&CborTagEncode::new(3, &bytes)),
+               Json::String("~-_8".to_string()));
+    assert_eq!(to_json_rfc8949(&readone(&encode(::std::f64::NAN))),
+               Json::Null);
+
+    // `ToJson` keeps writing byte strings as padded base64.
+    assert_eq!(readone(&encode(&bytes)).to_json(),
+               Json::String("+/8=".to_string()));
+    assert_eq!(readone(&encode(CborTagEncode::new(23, &bytes))).to_json(),
+               Json::String("+/8=".to_string()));
+
+    let mut map = HashMap::new();
+    map.insert("a".to_string(), ());
+    let json = Json::from_str(r#"[1, 300, -1, -300, 1.5, {"a": null}]"#)
+                    .unwrap();
+    assert_eq!(json.to_cbor(),
+               readone(&encode(&(1, 300, -1, -300, 1.5f64, map))));
+}
+
+#[test]
+fn diagnostic_notation() {
+    use cbor::{CanonicalMap, CborSimple, CborTagEncode};
+
+    let map = CanonicalMap::new().entry("b", CborBytes(vec![1, 2]))
+                                 .entry("a", CborBytes(vec![]));
+    let item = readone(&encode(&(
+        -300, 1.0f64, ::std::f32::NEG_INFINITY, "q\"", map,
+        CborTagEncode::new(1, &0.5f32), CborSimple(16), (), vec![0u8; 0],
+    )));
+    assert_eq!(item.to_string(),
+               r#"[-300, 1.0, -Infinity, "q\"", {"a": h'', "b": h'0102'}, "#
+               .to_string() + r#"1(0.5), simple(16), null, []]"#);
+    assert_eq!(format!("{:#}", readone(&encode(&[(1, vec![2])]))),
+               "[\n  [\n    1,\n    [\n      2\n    ]\n  ]\n]");
+}
+
+#[test]
+fn parse_diagnostic_notation() {
+    use cbor::{CborError, CborTagEncode, ReadError};
+
+    let parse = |s: &str| Cbor::from_diagnostic(s).unwrap();
+    let mut map = HashMap::new();
+    map.insert("k".to_string(), CborBytes(vec![1, 2, 0xff]));
+    assert_eq!(
+        parse(r#"[0x1f, -300 / comment /, 1.5, "é\"", 'ab',
+                  {"k": (_ h'01 02', b64'_w')}, 2(h'0100'),
+                  [_ true, null], simple(16)] # trailing comment"#),
+        readone(&encode(&(31, -300, 1.5f64, "é\"", CborBytes(vec![97, 98]),
+                          map, CborTagEncode::new(2, &CborBytes(vec![1, 0])),
+                          (true, ()), cbor::CborSimple(16)))));
+
+    let item = readone(&encode(&(u64::max_value(), -1.0f64, "x")));
+    assert_eq!(parse(&item.to_string()), item);
+
+    // The smallest negative integers only fit in `Cbor::Wide`.
+    let min = vec![0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(parse("-18446744073709551616"), readone(&min));
+    assert_eq!(readone(&min).to_string(), "-18446744073709551616");
+    assert_eq!(parse("-0x8000000000000001"), readone(&[
+        0x3b, 0x80, 0, 0, 0, 0, 0, 0, 0,
+    ]));
+    assert!(Cbor::from_diagnostic("-18446744073709551617").is_err());
+
+    match Cbor::from_diagnostic("[1, {2: 3}]") {
+        Err(CborError::AtOffset {
+            kind: ReadError::Syntax(_), offset: 5,
+        }) => {}
+        v => panic!("expected syntax error, got {:?}", v),
+    }
+
+    let nest = |c, n| ::std::iter::repeat(c).take(n).collect::<String>();
+    let ok = format!("{}1{}", nest("[", 128), nest("]", 128));
+    assert!(Cbor::from_diagnostic(&ok).is_ok());
+    // Nested far deeper than the stack allows.
+    match Cbor::from_diagnostic(&nest("[", 100_000)) {
+        Err(CborError::AtOffset {
+            kind: ReadError::TooDeep { max: 128 }, offset: 128,
+        }) => {}
+        v => panic!("expected TooDeep error, got {:?}", v),
+    }
+}
+
+#[test]
+fn annotate_nested_and_malformed() {
+    assert_eq!(cbor::annotate(&[0xa1, 0x61, b'k', 0x9f, 0x20, 0xff]), "\
+0..1  a1         # map(1)
+1..3     61 6b   # text(1) \"k\"
+3..4     9f      # array(*)
+4..5        20   # negative(-1)
+5..6     ff      # break
+");
+    assert_eq!(cbor::annotate(&[0x82, 0x01]), "\
+0..1  82      # array(2)
+1..2     01   # unsigned(1)
+2             # error: unexpected end of data
+");
+}
+
+#[test]
+fn transcode_json_matches_to_json() {
+
+    let (fb, one) = (CborBytes(vec![0xfb]), CborBytes(vec![1]));
+    let mut map = HashMap::new();
+    map.insert("b".to_string(), CborTagEncode::new(22, &fb));
+    let bytes = encode(&(1, -2, 1.5f64, "é", CborBytes(vec![0xff]), map,
+                         CborTagEncode::new(3, &one)));
+    let mut json = vec![];
+    cbor::transcode_json(&bytes, &mut json).unwrap();
+    assert_eq!(String::from_utf8(json).unwrap(),
+               format!("{}\n", cbor::to_json_rfc8949(&readone(&bytes))));
+
+    // ["ab"] with indefinite lengths, which `Decoder` doesn't support.
+    let bytes = [0x9f, 0x7f, 0x61, b'a', 0x61, b'b', 0xff, 0xff];
+    let mut json = vec![];
+    cbor::transcode_json(&bytes, &mut json).unwrap();
+    assert_eq!(json, b"[\"ab\"]\n");
+
+    let mut json = vec![];
+    assert!(cbor::transcode_json(&[0xa1, 0x01, 0x02], &mut json).is_err());
+}
+
+#[test]
+fn decode_untyped_field_preserves_data_item() {
+    #[derive(RustcDecodable)]
+    struct Msg { kind: String, payload: Cbor }
+
+    let (blob, simple) = (CborBytes(vec![0, 1]), cbor::CborSimple(99));
+    let payload = (CborTagEncode::new(24, &blob), simple);
+    let mut map = HashMap::new();
+    map.insert("kind", readone(&encode("x")));
+    map.insert("payload", readone(&encode(&payload)));
+    let msg: Msg = decode(&encode(&map));
+    assert_eq!(msg.kind, "x");
+    assert_eq!(msg.payload, readone(&encode(&payload)));
+}
+
+#[test]
+fn tag_policy() {
+    use cbor::{CborError, ReadError, TagPolicy, Type};
+
+    let s = "2013-03-21T20:04:00Z".to_string();
+    let bytes = encode(&(CborTagEncode::new(0, &s), 5));
+    let dec = |tags| {
+        Decoder::from_bytes(&bytes[..]).tag_policy(tags)
+                .decode::<(String, u8)>().next().unwrap()
+    };
+    assert_eq!(dec(TagPolicy::Ignore).unwrap(), (s.clone(), 5));
+    match dec(TagPolicy::Error) {
+        Err(CborError::AtPath {
+            kind: ReadError::TypeMismatch {
+                expected: Type::Unicode, got: Type::Tag,
+            },
+            ref path, ..
+        }) if path == "[0]" => {}
+        v => panic!("expected tag mismatch, got {:?}", v),
+    }
+    // Types that handle tags still work.
+    struct Date(u64, String);
+    impl Decodable for Date {
+        fn decode<D: rustc_serialize::Decoder>(d: &mut D)
+                 -> Result<Date, D::Error> {
+            let tag = try!(d.read_u64());
+            Ok(Date(tag, try!(Decodable::decode(d))))
+        }
+    }
+    let (date, _): (Date, u8) = Decoder::from_bytes(&bytes[..])
+        .tag_policy(TagPolicy::Error).decode().next().unwrap().unwrap();
+    assert_eq!((date.0, date.1), (0, s));
+}
+
+#[test]
+fn canonical_map_orders_like_encoded_keys() {
+    use cbor::CanonicalMap;
+
+    let long = |c, n| ::std::iter::repeat(c).take(n).collect::<String>();
+    let (x30, w24) = (long('x', 30), long('w', 24));
+    let keys = ["b", "", "aa", "a", &*x30, "ab", &*w24];
+    let mut map = CanonicalMap::new();
+    for (i, k) in keys.iter().enumerate() {
+        assert_eq!(map.insert(*k, i), None);
+    }
+    assert_eq!(map.insert("a", 10), Some(3));
+    assert_eq!(map.get("a"), Some(&10));
+
+    let encoded: Vec<Vec<u8>> = map.iter().map(|(k, _)| encode(k)).collect();
+    let mut sorted = encoded.clone();
+    sorted.sort();
+    assert_eq!(encoded, sorted);
+    assert_eq!(map.len(), keys.len());
+}
+
+#[test]
+fn map_round_trip_preserves_order() {
+    // {"z": 1, "a": {"y": 2, "b": 3}}
+    let bytes = vec![0xa2, 0x61, b'z', 0x01, 0x61, b'a',
+                     0xa2, 0x61, b'y', 0x02, 0x61, b'b', 0x03];
+    let item = readone(&bytes);
+    assert_eq!(item.to_string(), r#"{"z": 1, "a": {"y": 2, "b": 3}}"#);
+    assert_eq!(encode(&item), bytes);
+
+    let mut map = match item { Cbor::Map(map) => map, _ => unreachable!() };
+    assert!(map.remove("z").is_some());
+    assert_eq!(map.insert("c", Cbor::Null), None);
+    assert_eq!(map.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+    assert_eq!(map.get("c"), Some(&Cbor::Null));
+    assert!(map.insert("a", Cbor::Null).is_some());
+    assert_eq!(map.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+}
+
+#[test]
+fn large_maps_decode_in_linear_time() {
+    // Looking up each key among the ones before it would take billions of
+    // comparisons.
+    let n = 200_000;
+    let map: HashMap<String, u32> =
+        (0..n).map(|i| (i.to_string(), i)).collect();
+    let bytes = encode(&map);
+    let item = readone(&bytes);
+    let map = match item { Cbor::Map(map) => map, _ => unreachable!() };
+    assert_eq!(map.len(), n as usize);
+    assert_eq!(map.get("12345"), Some(&readone(&encode(12345))));
+}
+
+#[test]
+fn faithful_integers_round_trip() {
+    use cbor::CborWide;
+
+    // [5 as uint32, -2 as int16, 300 as uint16, 24 as uint8]
+    let bytes = vec![0x84, 0x1a, 0, 0, 0, 5, 0x39, 0, 1, 0x19, 0x01, 0x2c,
+                     0x18, 24];
+    let item = Decoder::from_bytes(&bytes[..]).faithful_integers(true)
+                       .items().next().unwrap().unwrap();
+    assert_eq!(item.to_string(), "[5_2, -2_1, 300, 24]");
+    assert_eq!(encode(&item), bytes);
+    assert_eq!(Cbor::from_diagnostic("[5_2, -2_1, 300, 24]").unwrap(), item);
+    assert!(Cbor::from_diagnostic("300_0").is_err());
+
+    let v: (u8, i8, u16, u8) = Decoder::from_bytes(&bytes[..])
+        .faithful_integers(true).decode().next().unwrap().unwrap();
+    assert_eq!(v, (5, -2, 300, 24));
+    assert_eq!(readone(&bytes[1..6]),
+               Cbor::Unsigned(cbor::CborUnsigned::UInt32(5)));
+    assert_eq!(CborWide { negative: true, arg: 1, width: 2 }.to_cbor(),
+               readone(&[0x21]));
+}
+
+#[test]
+fn byte_strings_from_and_to_readers() {
+    use std::io;
+    use cbor::{CborError, WriteError};
+
+    let blob: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut enc = Encoder::from_memory();
+    enc.write_bytes_from_reader(blob.len() as u64, &mut &blob[..]).unwrap();
+    enc.encode(&["after"]).unwrap();
+    let bytes = enc.into_bytes();
+    let expected = encode(CborBytes(blob.clone()));
+    assert_eq!(&bytes[..bytes.len() - 6], &expected[..]);
+
+    let mut dec = Decoder::from_bytes(bytes);
+    let mut contents = vec![];
+    {
+        let mut rdr = dec.bytes_reader().unwrap();
+        assert_eq!(rdr.remaining(), blob.len() as u64);
+        io::copy(&mut rdr, &mut contents).unwrap();
+    }
+    assert_eq!(contents, blob);
+    let after: String = dec.decode().next().unwrap().unwrap();
+    assert_eq!(after, "after");
+    assert!(Decoder::from_bytes(encode(1)).bytes_reader().is_err());
+
+    let mut enc = Encoder::from_memory();
+    match enc.write_bytes_from_reader(5, &mut &b"abc"[..]) {
+        Err(CborError::Encode(WriteError::ShortRead {
+            expected: 5, got: 3,
+        })) => {}
+        v => panic!("expected short read, got {:?}", v),
+    }
+
+    // A streamed byte string is a top-level data item like any other.
+    let mut enc = Encoder::from_memory().stringrefs(true)
+                                        .self_describe(true);
+    enc.encode(&[vec!["abc", "abc"]]).unwrap();
+    let start = enc.as_bytes().len();
+    enc.write_bytes_from_reader(3, &mut &b"abc"[..]).unwrap();
+    enc.encode(&[vec!["abc", "abc"]]).unwrap();
+    let bytes = enc.into_bytes();
+    // 55799(256(h'616263'))
+    assert_eq!(&bytes[start..start + 10],
+               &[0xd9, 0xd9, 0xf7, 0xd9, 0x01, 0x00,
+                 0x43, b'a', b'b', b'c'][..]);
+    // The next data item starts its own namespace too.
+    assert_eq!(&bytes[start + 10..], &bytes[..start]);
+}
+
+#[test]
+fn lazy_reader_decodes_entries_on_demand() {
+    use std::io::Cursor;
+    use cbor::{CborError, LazyReader, ReadError};
+
+    // {_ "skip": [_ (_ h'01', h'02'), {"x": 1(2)}], "n": -5,
+    //    "big": h'00...'}
+    let mut bytes = vec![0xbf, 0x64, b's', b'k', b'i', b'p',
+                         0x9f, 0x5f, 0x41, 0x01, 0x41, 0x02, 0xff,
+                         0xa1, 0x61, b'x', 0xc1, 0x02, 0xff,
+                         0x61, b'n', 0x24,
+                         0x63, b'b', b'i', b'g', 0x59, 0x10, 0x00];
+    bytes.extend(::std::iter::repeat(0).take(0x1000));
+    bytes.push(0xff);
+
+    let mut lazy = LazyReader::new(Cursor::new(&bytes[..])).unwrap();
+    assert_eq!(lazy.keys().collect::<Vec<_>>(), vec!["skip", "n", "big"]);
+    assert_eq!(lazy.get::<i32>("n").unwrap(), Some(-5));
+    assert_eq!(lazy.get::<i32>("missing").unwrap(), None);
+    let big: CborBytes = lazy.get("big").unwrap().unwrap();
+    assert_eq!(big.len(), 0x1000);
+
+    match LazyReader::new(Cursor::new(&bytes[..bytes.len() - 1])) {
+        Err(CborError::AtOffset {
+            kind: ReadError::UnexpectedEOF { .. }, ..
+        }) => {}
+        Err(err) => panic!("expected EOF error, got {:?}", err),
+        Ok(_) => panic!("expected EOF error"),
+    }
+
+    // The data ends in the middle of the last value, which is skipped
+    // with `seek`.
+    let end = bytes.len() - 0x800;
+    match LazyReader::new(Cursor::new(&bytes[..end])) {
+        Err(CborError::AtOffset {
+            kind: ReadError::UnexpectedEOF { needed: 0x7ff, offset },
+            offset: at,
+        }) => assert_eq!((offset, at), (end, end)),
+        Err(err) => panic!("expected EOF error, got {:?}", err),
+        Ok(_) => panic!("expected EOF error"),
+    }
+
+    // {"a": "a", [h'...' with a length of 2^64 - 1]}, which must not
+    // overflow the position of the scanner.
+    let bytes = [0xa1, 0x61, b'a', 0x5b,
+                 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    match LazyReader::new(Cursor::new(&bytes[..])) {
+        Err(CborError::AtOffset {
+            kind: ReadError::TooLong { len: 0xffffffffffffffff, .. },
+            offset: 3,
+        }) => {}
+        Err(err) => panic!("expected TooLong error, got {:?}", err),
+        Ok(_) => panic!("expected TooLong error"),
+    }
+    cbor::fuzz::decode_any(&bytes);
+}
+
+#[test]
+fn canonical_encoding_sorts_entries_and_packs_floats() {
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Point { zz: Vec<f64>, a: HashMap<String, u8> }
+
+    let mut a = HashMap::new();
+    for k in &["bb", "c", "ab", "a"] {
+        a.insert(k.to_string(), k.len() as u8);
+    }
+    let p = Point { zz: vec![1.5, 65504.0, 5.960464477539063e-8, 0.1], a: a };
+    let mut enc = Encoder::from_memory().canonical(true).self_describe(true);
+    enc.encode(&[&p]).unwrap();
+    let bytes = enc.into_bytes();
+    assert_eq!(bytes, vec![
+        0xd9, 0xd9, 0xf7, 0xa2,
+        0x61, b'a', 0xa4, 0x61, b'a', 0x01, 0x61, b'c', 0x01,
+                          0x62, b'a', b'b', 0x02, 0x62, b'b', b'b', 0x02,
+        0x62, b'z', b'z', 0x84, 0xf9, 0x3e, 0x00, 0xf9, 0x7b, 0xff,
+                                0xf9, 0x00, 0x01,
+                                0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99,
+                                      0x99, 0x9a,
+    ]);
+
+    let mut dec = Decoder::from_bytes(&bytes[..])
+                          .tag_policy(cbor::TagPolicy::Ignore);
+    let back: Point = dec.decode().next().unwrap().unwrap();
+    assert_eq!(back, p);
+}
+
+#[test]
+fn decoder_limits() {
+    use cbor::{CborError, ReadError, Type};
+
+    fn first_err(dec: &mut Decoder<::std::io::Cursor<Vec<u8>>>)
+                -> ReadError {
+        match dec.items().next().unwrap() {
+            Err(CborError::AtOffset { kind, .. }) => kind,
+            r => panic!("expected error, got {:?}", r),
+        }
+    }
+
+    // A byte string claiming to be 4GB long, which must be rejected
+    // before anything is allocated.
+    let bytes = vec![0x5a, 0xff, 0xff, 0xff, 0xff, 0x00];
+    let mut dec = Decoder::from_bytes(&bytes[..]).max_size(1024);
+    match first_err(&mut dec) {
+        ReadError::TooLong { ty: Type::Bytes, len: 0xffffffff, max: 1024 } => {
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+
+    let bytes = encode(&vec![vec![1, 2, 3], vec![4]]);
+    let mut dec = Decoder::from_bytes(&bytes[..]).max_items(3).max_depth(2);
+    assert_eq!(dec.decode().next().unwrap().ok(), Some(vec![vec![1, 2, 3],
+                                                            vec![4]]));
+    let mut dec = Decoder::from_bytes(&bytes[..]).max_items(2);
+    match first_err(&mut dec) {
+        ReadError::TooLong { ty: Type::Array, len: 3, max: 2 } => {}
+        err => panic!("unexpected error {:?}", err),
+    }
+    let mut dec = Decoder::from_bytes(&bytes[..]).max_depth(1);
+    match first_err(&mut dec) {
+        ReadError::TooDeep { max: 1 } => {}
+        err => panic!("unexpected error {:?}", err),
+    }
+
+    // {"a": 1, "a": 2}
+    let bytes = vec![0xa2, 0x61, b'a', 0x01, 0x61, b'a', 0x02];
+    let mut dec = Decoder::from_bytes(&bytes[..])
+                          .duplicate_keys(cbor::DuplicateKeyPolicy::Error);
+    match dec.items().next().unwrap() {
+        Err(CborError::AtOffset { kind: ReadError::DuplicateKey(k),
+                                  offset: 4 }) => assert_eq!(k, "a"),
+        r => panic!("unexpected {:?}", r),
+    }
+
+    // {"alg": 1, 1: 2}, where 1 names the field alg.
+    let bytes = vec![0xa2, 0x63, b'a', b'l', b'g', 0x01, 0x01, 0x02];
+    let mut dec = Decoder::from_bytes(&bytes[..])
+                          .field_keys(&[("alg", 1)])
+                          .duplicate_keys(cbor::DuplicateKeyPolicy::Error);
+    match dec.items().next().unwrap() {
+        Err(CborError::AtOffset { kind: ReadError::DuplicateKey(k),
+                                  offset: 6 }) => assert_eq!(k, "alg"),
+        r => panic!("unexpected {:?}", r),
+    }
+    // Without the table, the integer key is reported where it starts.
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    match dec.items().next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::TypeMismatch { expected: Type::Unicode,
+                                            got: Type::UInt8 },
+            offset: 6,
+        }) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn deep_nesting_is_limited_by_default() {
+    use cbor::{CborError, Decoded, ReadError, StreamingDecoder};
+
+    // [[[...]]], nested far deeper than the stack allows.
+    let bytes = vec![0x81; 100_000];
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    match dec.items().next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::TooDeep { max: 128 }, offset: 128,
+        }) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    assert!(dec.decode::<Cbor>().next().unwrap().is_err());
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    assert!(dec.skip().is_err());
+
+    let mut stream = StreamingDecoder::new();
+    stream.feed(&bytes).unwrap();
+    stream.feed(&[0x80]).unwrap();
+    match stream.next_item() {
+        Err(CborError::AtOffset { kind: ReadError::TooDeep { .. }, .. }) => {}
+        Ok(Decoded::NeedMore) => panic!("expected TooDeep error"),
+        r => panic!("unexpected {:?}", r),
+    }
+
+    cbor::fuzz::decode_any(&bytes);
+    cbor::fuzz::roundtrip(&bytes);
+
+    // Every reader allows 128 arrays around an integer, but not 129.
+    fn too_deep<T: Debug>(r: cbor::CborResult<T>) {
+        match r {
+            Err(CborError::AtOffset {
+                kind: ReadError::TooDeep { max: 128 }, offset: 128,
+            }) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+    let nest = |c, n| ::std::iter::repeat(c).take(n).collect::<String>();
+    for &(n, ok) in &[(128, true), (129, false)] {
+        let mut bytes = vec![0x81; n];
+        bytes.push(0x01);
+        let diag = format!("{}1{}", nest("[", n), nest("]", n));
+        let mut dec = Decoder::from_bytes(&bytes[..]);
+        let decoded = dec.items().next().unwrap();
+        let skipped = cbor::SliceDecoder::new(&bytes).skip();
+        let json = cbor::transcode_json(&bytes, &mut vec![]);
+        let parsed = Cbor::from_diagnostic(&diag);
+        if ok {
+            assert!(decoded.is_ok() && skipped.is_ok() && json.is_ok()
+                    && parsed.is_ok());
+        } else {
+            too_deep(decoded);
+            too_deep(skipped);
+            too_deep(json);
+            too_deep(parsed);
+        }
+    }
+}
+
+#[test]
+fn decoder_budget_spans_all_items() {
+    use cbor::{Budget, CborError, ReadError};
+
+    // Three arrays of two items each is nine data items.
+    let bytes = encode(&[[1, 2], [3, 4], [5, 6]]);
+    let mut dec = Decoder::from_bytes(&bytes[1..]).max_total_items(8);
+    let mut items = dec.items();
+    assert!(items.next().unwrap().is_ok());
+    assert!(items.next().unwrap().is_ok());
+    match items.next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::BudgetExceeded(Budget::Items(8)), offset: 8,
+        }) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+
+    // The string's length is checked before reading it.
+    let bytes = vec![0x01, 0x7a, 0x7f, 0xff, 0xff, 0xff];
+    let mut dec = Decoder::from_bytes(&bytes[..]).max_total_bytes(1000);
+    let mut items = dec.items();
+    assert!(items.next().unwrap().is_ok());
+    match items.next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::BudgetExceeded(Budget::Bytes(1000)), offset: 6,
+        }) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn arbitrary_items_round_trip() {
+    fn prop(v: Cbor) -> bool {
+        let bytes = encode(&v);
+        let mut dec = Decoder::from_bytes(bytes).faithful_integers(true);
+        assert_eq!(dec.items().next().unwrap().unwrap(), v);
+        true
+    }
+    QuickCheck::new().quickcheck(prop as fn(Cbor) -> bool);
+}
+
+#[test]
+fn arbitrary_bytes_error_or_round_trip() {
+    fn prop(bytes: Vec<u8>) -> bool {
+        let mut dec = Decoder::from_bytes(bytes).faithful_integers(true)
+                                                .max_size(1 << 16)
+                                                .max_items(1 << 16);
+        for item in dec.items() {
+            let v = match item {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+            // Half precision floats are encoded with more precision, so
+            // compare the encodings after one round trip.
+            let bytes = encode(&v);
+            let mut dec = Decoder::from_bytes(&bytes[..])
+                                  .faithful_integers(true);
+            let again = dec.items().next().unwrap().unwrap();
+            assert_eq!(encode(&again), bytes);
+        }
+        true
+    }
+    QuickCheck::new().quickcheck(prop as fn(Vec<u8>) -> bool);
+}
+
+#[test]
+fn fuzz_entry_points() {
+    fn prop(bytes: Vec<u8>) -> bool {
+        cbor::fuzz::decode_any(&bytes);
+        cbor::fuzz::roundtrip(&bytes);
+        true
+    }
+    QuickCheck::new().quickcheck(prop as fn(Vec<u8>) -> bool);
+
+    // Lengths far beyond the end of the data and very deep nesting.
+    let mut deep = vec![0x81; 100000];
+    deep.push(0x00);
+    for bytes in &[
+        vec![0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        vec![0xa1, 0x7a, 0xff, 0xff, 0xff, 0xff],
+        vec![0x9b, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff],
+        deep,
+        encode(&(1.5f32, ::std::f64::NAN, Cbor::Undefined, "\u{1f600}")),
+    ] {
+        cbor::fuzz::decode_any(bytes);
+        cbor::fuzz::roundtrip(bytes);
+    }
+}
+
+#[test]
+fn no_input_panics() {
+    use cbor::DirectDecoder;
+
+    #[derive(RustcDecodable)]
+    #[allow(dead_code)]
+    enum Shape { Dot, Line(u8, u8) }
+
+    let mut inputs = vec![];
+    for a in 0..256 {
+        inputs.push(vec![a as u8]);
+        // The largest possible length or argument after every head.
+        inputs.push(vec![a as u8, 0xff, 0xff, 0xff, 0xff,
+                         0xff, 0xff, 0xff, 0xff]);
+        for b in 0..256 {
+            inputs.push(vec![a as u8, b as u8]);
+        }
+    }
+    for bytes in inputs {
+        cbor::fuzz::decode_any(&bytes);
+        cbor::fuzz::roundtrip(&bytes);
+        let _ = String::decode(&mut DirectDecoder::from_bytes(&bytes[..]));
+        let _ = Shape::decode(&mut DirectDecoder::from_bytes(&bytes[..]));
+        let _ = <Vec<u8>>::decode(&mut DirectDecoder::from_bytes(&bytes[..]));
+    }
+}
+
+#[test]
+fn encode_into_fixed_buffer() {
+    use cbor::{CborError, WriteError};
+
+    let v = (vec![1u32, 1000, 100_000], "abc".to_string(), -5i8);
+    let expected = encode(&v);
+    let mut buf = [0xff; 32];
+    {
+        let mut enc = Encoder::from_slice(&mut buf);
+        enc.encode(&[&v]).unwrap();
+        assert_eq!(enc.bytes_written(), expected.len());
+    }
+    assert_eq!(&buf[..expected.len()], &expected[..]);
+    assert!(buf[expected.len()..].iter().all(|&b| b == 0xff));
+
+    for len in 0..expected.len() {
+        let mut buf = vec![0; len];
+        let mut enc = Encoder::from_slice(&mut buf);
+        match enc.encode(&[&v]) {
+            Err(CborError::Encode(WriteError::BufferTooSmall)) => {}
+            r => panic!("expected a full buffer, got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn slice_decoder_borrows_from_input() {
+    use cbor::{CborError, ReadError, SliceDecoder, Type};
+
+    let bytes = encode(&(("key".to_string(), CborBytes(vec![1, 2])),
+                         vec![-3i64, ::std::i64::MIN], None::<u8>,
+                         Some(1.5f64), true));
+    let mut dec = SliceDecoder::new(&bytes);
+    assert_eq!(dec.read_array().unwrap(), Some(5));
+    assert_eq!(dec.peek_type().unwrap(), Type::Array);
+    dec.skip().unwrap();
+    assert_eq!(dec.read_array().unwrap(), Some(2));
+    assert_eq!(dec.read_i64().unwrap(), -3);
+    assert_eq!(dec.read_i64().unwrap(), ::std::i64::MIN);
+    assert!(dec.read_null().unwrap());
+    assert!(!dec.read_null().unwrap());
+    assert_eq!(dec.read_f64().unwrap(), 1.5);
+    assert!(dec.read_bool().unwrap());
+    assert!(dec.is_empty());
+
+    let mut dec = SliceDecoder::new(&bytes[1..]);
+    dec.read_array().unwrap();
+    let key = dec.read_str().unwrap();
+    assert_eq!(key, "key");
+    assert_eq!(key.as_ptr(), bytes[3..].as_ptr());
+    assert_eq!(dec.read_bytes().unwrap(), &[1, 2][..]);
+    match dec.read_str() {
+        Err(CborError::AtOffset {
+            kind: ReadError::TypeMismatch {
+                expected: Type::Unicode, got: Type::Array,
+            },
+            offset: 8,
+        }) => {}
+        r => panic!("expected a type mismatch, got {:?}", r),
+    }
+
+    // (_ "a" "b") can't be borrowed as one string.
+    let mut dec = SliceDecoder::new(&[0x7f, 0x61, b'a', 0x61, b'b', 0xff]);
+    assert!(dec.clone().read_str().is_err());
+    dec.skip().unwrap();
+    assert!(dec.is_empty());
+}
+
+#[test]
+fn encoded_data_items_in_byte_strings() {
+    use cbor::CborEncoded;
+
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Envelope {
+        protected: CborEncoded<HashMap<String, i32>>,
+        payload: Vec<u8>,
+    }
+
+    let mut map = HashMap::new();
+    map.insert("alg".to_string(), -7);
+    let env = Envelope {
+        protected: CborEncoded(map.clone()),
+        payload: vec![1, 2],
+    };
+    let bytes = encode(&env);
+    assert_eq!(readone(&bytes).to_string(),
+               format!("{{\"protected\": h'{}', \"payload\": h'0102'}}",
+                       encode(&map).iter()
+                                   .map(|b| format!("{:02x}", b))
+                                   .collect::<String>()));
+    assert!(round_trip(env));
+
+    // The byte string must hold exactly one data item.
+    for bad in &[vec![0x40], vec![0x42, 0x01, 0x02], vec![0x41, 0x18]] {
+        let r = Decoder::from_bytes(&bad[..])
+                        .decode::<CborEncoded<u8>>().next().unwrap();
+        assert!(r.is_err(), "{:?} decoded as {:?}", bad, r);
+    }
+}
+
+#[cfg(feature = "cddl")]
+#[test]
+fn cddl_schema_validation() {
+    use cbor::{CborError, ReadError};
+    use cbor::cddl::Schema;
+
+    let schema = Schema::parse(r#"
+        ; A batch of readings from one sensor.
+        batch = {
+            sensor: tstr,
+            ? unit: "C" / "F",
+            readings: [+ reading],
+            * tstr => any,          ; extensions
+        }
+        reading = [time: #6.1(uint), value: float / null, sample]
+        sample = (level: 0...3, ? flags: [0*4 bool])
+    "#).unwrap();
+
+    let check = |diag: &str| {
+        match schema.validate(&diag.parse().unwrap()) {
+            Ok(()) => None,
+            Err(CborError::AtPath {
+                kind: ReadError::SchemaMismatch(msg), path, ..
+            }) => Some((path, msg)),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    };
+    assert_eq!(check(r#"{"sensor": "a", "readings": [[1(5), 1.5, 0]],
+                         "x-note": h'00'}"#), None);
+    assert_eq!(check(r#"{"sensor": "a", "unit": "F",
+                         "readings": [[1(5), null, 2, [true]]]}"#), None);
+
+    let bad = [
+        (r#"{"readings": [[1(5), 1.5, 0]]}"#,
+         "", "missing entry \"sensor\""),
+        (r#"{"sensor": "a", "unit": "K", "readings": [[1(5), 1.5, 0]]}"#,
+         r#"["unit"]"#, "expected \"C\" / \"F\", found \"K\""),
+        (r#"{"sensor": "a", "readings": []}"#,
+         r#"["readings"]"#, "expected reading, found the end of the array"),
+        (r#"{"sensor": "a", "readings": [[1(5), 1.5, 0], [5, 1.5, 0]]}"#,
+         r#"["readings"][1][0]"#, "expected #6.1(uint), found 5"),
+        (r#"{"sensor": "a", "readings": [[1(5), 1.5, 3]]}"#,
+         r#"["readings"][0][2]"#, "expected 0...3, found 3"),
+        (r#"{"sensor": "a", "readings": [[1(5), 1.5, 0, [], 1]]}"#,
+         r#"["readings"][0][4]"#, "unexpected item 1"),
+        (r#"{"sensor": "a", "readings": [[1(5), 1.5, 0,
+                                          [true, 1]]]}"#,
+         r#"["readings"][0][3][1]"#, "expected bool, found 1"),
+    ];
+    for &(diag, path, msg) in &bad {
+        assert_eq!(check(diag), Some((path.to_string(), msg.to_string())));
+    }
+
+    // Every top-level data item in a byte stream is checked.
+    let mut bytes = encode(&{
+        let mut m = HashMap::new();
+        m.insert("sensor", vec![1]);
+        m
+    });
+    match schema.validate_bytes(&bytes) {
+        Err(CborError::AtPath { offset: 0, ref path, .. })
+            if path == r#"["sensor"]"# => {}
+        r => panic!("expected a mismatch, got {:?}", r),
+    }
+    let ok = readone(&bytes);
+    bytes.clear();
+    let good: Cbor = r#"{"sensor": "a", "readings": [[1(5), 1.5, 0]]}"#
+        .parse().unwrap();
+    bytes.extend(encode(&good));
+    let len = bytes.len();
+    bytes.extend(encode(&ok));
+    match schema.validate_bytes(&bytes) {
+        Err(CborError::AtPath { offset, .. }) => assert_eq!(offset, len),
+        r => panic!("expected a mismatch, got {:?}", r),
+    }
+
+    for bad in &["", "a", "a = ", "a = b", "a = int .size 2", "a = 1 // 2",
+                 "a = int\na = tstr", "a = [* (int, tstr)", "a = #7.1"] {
+        match Schema::parse(bad) {
+            Err(CborError::AtOffset { kind: ReadError::Syntax(_), .. }) => {}
+            r => panic!("{:?} parsed as {:?}", bad, r),
+        }
+    }
+}
+
+#[test]
+fn validate_well_formedness() {
+    use cbor::{CborError, ReadError};
+
+    fn prop(v: (Vec<String>, HashMap<String, Vec<i64>>, Option<f64>))
+           -> bool {
+        let bytes = encode(&v);
+        cbor::validate(&bytes).unwrap();
+        (0..bytes.len()).all(|n| cbor::validate(&bytes[..n]).is_err())
+    }
+    qc_sized(prop as fn(_) -> bool, 10);
+
+    // 1(_ h'01' h'02'), {1: [_ ]} and "é" in two chunks are all fine.
+    for ok in &[&[0xc1, 0x5f, 0x41, 0x01, 0x41, 0x02, 0xff][..],
+                &[0xa1, 0x01, 0x9f, 0xff][..],
+                &[0x7f, 0x61, b'a', 0x62, 0xc3, 0xa9, 0xff][..]] {
+        cbor::validate(ok).unwrap();
+    }
+    let bad: &[(&[u8], usize)] = &[
+        (&[], 0),
+        // A break outside of an indefinite length item.
+        (&[0x81, 0xff], 1),
+        // A byte string chunk in a Unicode string.
+        (&[0x7f, 0x41, b'a', 0xff], 1),
+        // A nested indefinite length chunk.
+        (&[0x5f, 0x5f, 0xff, 0xff], 1),
+        // "é" split between chunks.
+        (&[0x7f, 0x61, 0xc3, 0x61, 0xa9, 0xff], 1),
+        // Reserved additional information.
+        (&[0x82, 0x01, 0x1c], 2),
+        (&[0xf8, 0x10], 0),
+        // A map with a key and no value.
+        (&[0xa1, 0x01], 2),
+    ];
+    for &(bytes, offset) in bad {
+        match cbor::validate(bytes) {
+            Err(CborError::AtOffset { offset: got, .. }) if got == offset => {}
+            r => panic!("{:?}: expected an error at {}, got {:?}",
+                        bytes, offset, r),
+        }
+    }
+    match cbor::validate(&[0x01, 0x02, 0x03]) {
+        Err(CborError::AtOffset {
+            kind: ReadError::TrailingBytes { remaining: 2 }, offset: 1,
+        }) => {}
+        r => panic!("expected trailing bytes, got {:?}", r),
+    }
+}
+
+#[test]
+fn decode_exactly_one_item() {
+    use cbor::{CborError, ReadError};
+
+    let mut bytes = encode(&("a".to_string(), vec![1u16, 300]));
+    let (s, v): (String, Vec<u16>) = cbor::from_slice_exact(&bytes).unwrap();
+    assert_eq!((&*s, &*v), ("a", &[1, 300][..]));
+
+    let len = bytes.len();
+    bytes.extend(encode(&"next"));
+    match cbor::from_slice_exact::<(String, Vec<u16>)>(&bytes) {
+        Err(CborError::AtOffset {
+            kind: ReadError::TrailingBytes { remaining: 5 }, offset,
+        }) => assert_eq!(offset, len),
+        r => panic!("expected trailing bytes, got {:?}", r),
+    }
+    assert!(cbor::from_slice_exact::<u8>(&[]).is_err());
+
+    // Split concatenated messages.
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    let mut ends = vec![];
+    while let Some(item) = dec.items().next() {
+        item.unwrap();
+        ends.push(dec.bytes_consumed());
+    }
+    assert_eq!(ends, vec![len, bytes.len()]);
+}
+
+#[test]
+fn readers_report_bytes_consumed() {
+    use cbor::{Decoded, DirectDecoder, StreamingDecoder};
+
+    // Some(1), None, "abc"
+    let bytes = vec![0x01, 0xf6, 0x63, b'a', b'b', b'c'];
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert_eq!(Option::<u8>::decode(&mut dec).unwrap(), Some(1));
+    assert_eq!(dec.bytes_consumed(), 1);
+    assert_eq!(Option::<u8>::decode(&mut dec).unwrap(), None);
+    assert_eq!(dec.bytes_consumed(), 2);
+    assert_eq!(String::decode(&mut dec).unwrap(), "abc");
+    assert_eq!(dec.bytes_consumed(), 6);
+
+    let mut stream = StreamingDecoder::new();
+    stream.feed(&bytes[..4]).unwrap();
+    for &end in &[1, 2] {
+        assert!(match stream.next_item().unwrap() {
+            Decoded::Item(_) => true,
+            Decoded::NeedMore => false,
+        });
+        assert_eq!(stream.bytes_consumed(), end);
+    }
+    assert_eq!(stream.next_item().unwrap(), Decoded::NeedMore);
+    assert_eq!(stream.bytes_consumed(), 2);
+    stream.feed(&bytes[4..]).unwrap();
+    stream.next_item().unwrap();
+    assert_eq!(stream.bytes_consumed(), 6);
+}
+
+#[test]
+fn decode_from_chunks() {
+    fn prop(v: Vec<(String, Vec<u8>)>, cuts: Vec<usize>) -> bool {
+        let bytes = encode(&v);
+        let mut cuts: Vec<usize> =
+            cuts.into_iter().map(|c| c % (bytes.len() + 1)).collect();
+        cuts.push(0);
+        cuts.push(bytes.len());
+        cuts.sort();
+        let chunks: Vec<&[u8]> =
+            cuts.windows(2).map(|w| &bytes[w[0]..w[1]]).collect();
+        let mut dec = Decoder::from_chunks(&chunks);
+        let got: Vec<(String, Vec<u8>)> =
+            dec.decode().next().unwrap().unwrap();
+        assert_eq!(got, v);
+        assert_eq!(dec.bytes_consumed(), bytes.len());
+        dec.decode::<u8>().next().is_none()
+    }
+    qc_sized(prop as fn(_, _) -> bool, 10);
+}
+
+#[test]
+fn preferred_serialization_uses_shortest_floats() {
+    fn preferred<T: Encodable>(v: T) -> Vec<u8> {
+        let mut enc = Encoder::from_memory().preferred(true);
+        enc.encode(&[v]).unwrap();
+        enc.into_bytes()
+    }
+    // Every f32 is also an f64, and both must be written the same way.
+    fn prop(v: f32) -> bool {
+        let bytes = preferred(v as f64);
+        assert_eq!(bytes, preferred(v));
+        assert!(bytes.len() <= 5);
+        let back: f64 = decode(&bytes);
+        back == v as f64 || (v.is_nan() && back.is_nan())
+    }
+    qc_sized(prop as fn(f32) -> bool, 1000);
+
+    // Unlike canonical encoding, map entries stay in their order.
+    let mut map = ::std::collections::BTreeMap::new();
+    map.insert("aa".to_string(), 1.5f64);
+    map.insert("b".to_string(), 0.1);
+    assert_eq!(preferred(&map), vec![
+        0xa2, 0x62, b'a', b'a', 0xf9, 0x3e, 0x00,
+              0x61, b'b', 0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a,
+    ]);
+}
+
+#[test]
+fn canonical_nans() {
+    use cbor::{CborError, ReadError};
+
+    fn prop(bits: u64) -> bool {
+        // Every NaN has all exponent bits set and a nonzero mantissa.
+        let nan: f64 = unsafe {
+            ::std::mem::transmute(bits | 0x7ff0_0000_0000_0001)
+        };
+        for &canonical in &[false, true] {
+            let mut enc = Encoder::from_memory().canonical_nans(canonical);
+            enc.encode(&[(nan, nan as f32 as f64)]).unwrap();
+            enc.encode(&[nan as f32]).unwrap();
+            let mut dec = Decoder::from_bytes(enc.into_bytes())
+                                  .strict_nans(true);
+            match dec.items().next().unwrap() {
+                Ok(v) => assert!(canonical, "accepted {:?}", v),
+                Err(CborError::AtOffset {
+                    kind: ReadError::NonCanonicalNan { offset: 1 }, ..
+                }) => assert!(!canonical),
+                Err(err) => panic!("unexpected {:?}", err),
+            }
+            if canonical {
+                let v: f32 = dec.decode().next().unwrap().unwrap();
+                assert!(v.is_nan());
+            }
+        }
+        true
+    }
+    qc_sized(prop as fn(u64) -> bool, ::std::u64::MAX);
+}
+
+#[test]
+fn strict_minimal_rejects_overlong_arguments() {
+    use cbor::{CborError, ReadError};
+
+    // Each major type with an argument: integers, lengths and tags.
+    fn prop(major: u8, n: u16) -> bool {
+        let major = [0, 1, 2, 3, 4, 5, 6][major as usize % 7];
+        let n = n as u64 % 300;
+        let mut bytes = vec![(major << 5) | 27];
+        for i in (0..8).rev() {
+            bytes.push((n >> (i * 8)) as u8);
+        }
+        match major {
+            2 | 3 => bytes.extend(::std::iter::repeat(b'a').take(n as usize)),
+            4 => bytes.extend(::std::iter::repeat(0).take(n as usize)),
+            // Map keys must be strings, so this is `"": 0` repeated.
+            5 => for _ in 0..n {
+                bytes.extend(&[0x60, 0x00]);
+            },
+            6 => bytes.push(0),
+            _ => {}
+        }
+        let mut dec = Decoder::from_bytes(bytes.clone());
+        assert!(dec.items().next().unwrap().is_ok());
+        let mut dec = Decoder::from_bytes(bytes).strict_minimal(true);
+        match dec.items().next().unwrap() {
+            Err(CborError::AtOffset {
+                kind: ReadError::NonMinimalEncoding { offset: 0 }, ..
+            }) => true,
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+    qc_sized(prop as fn(u8, u16) -> bool, 300);
+
+    let bytes = encode(&(10u64, -300i64, "a", ::std::u64::MAX));
+    let mut dec = Decoder::from_bytes(bytes).strict_minimal(true);
+    assert!(dec.items().next().unwrap().is_ok());
+}
+
+#[test]
+fn dag_cbor_profile() {
+    use std::collections::HashMap;
+    use cbor::{Cid, CborError, ReadError, WriteError};
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Block { links: Vec<Cid>, size: f32, meta: HashMap<String, i64> }
+
+    let mut meta = HashMap::new();
+    for k in &["bb", "c", "a"] {
+        meta.insert(k.to_string(), -(k.len() as i64));
+    }
+    let block = Block {
+        links: vec![Cid(vec![0x01, 0x71]), Cid(vec![])],
+        size: 0.5,
+        meta: meta,
+    };
+    let mut enc = Encoder::from_memory().dag_cbor(true);
+    enc.encode(&[&block]).unwrap();
+    let bytes = enc.into_bytes();
+    assert_eq!(bytes, vec![
+        0xa3, 0x64, b'm', b'e', b't', b'a',
+              0xa3, 0x61, b'a', 0x20, 0x61, b'c', 0x20, 0x62, b'b', b'b', 0x21,
+              0x64, b's', b'i', b'z', b'e',
+              0xfb, 0x3f, 0xe0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+              0x65, b'l', b'i', b'n', b'k', b's',
+              0x82, 0xd8, 0x2a, 0x43, 0x00, 0x01, 0x71, 0xd8, 0x2a, 0x41, 0x00,
+    ]);
+    let mut dec = Decoder::from_bytes(&bytes[..]).dag_cbor(true);
+    assert_eq!(dec.decode::<Block>().next().unwrap().unwrap(), block);
+
+    for &(ref v, what) in &[(Cbor::Undefined, "undefined"),
+                            (Cbor::Tag(cbor::CborTag {
+                                tag: 1, data: Box::new(Cbor::Null),
+                            }), "tag 1")] {
+        let mut enc = Encoder::from_memory().dag_cbor(true);
+        match enc.encode(&[v]) {
+            Err(CborError::Encode(WriteError::DagCbor(ref s))) => {
+                assert_eq!(s, what)
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    // undefined, 1.5 as a single precision float, tag 0, 10 in two bytes
+    // and a repeated key.
+    let invalid: &[(&[u8], usize)] = &[
+        (&[0x81, 0xf7], 1),
+        (&[0xfa, 0x3f, 0xc0, 0x00, 0x00], 0),
+        (&[0x82, 0x01, 0xc0, 0x60], 2),
+        (&[0x19, 0x00, 0x0a], 0),
+        (&[0xa2, 0x61, b'a', 0x01, 0x61, b'a', 0x02], 4),
+    ];
+    for &(bytes, offset) in invalid {
+        let mut dec = Decoder::from_bytes(bytes);
+        assert!(dec.items().next().unwrap().is_ok());
+        let mut dec = Decoder::from_bytes(bytes).dag_cbor(true);
+        match dec.items().next().unwrap() {
+            Err(CborError::AtOffset { kind: ReadError::DagCbor(_), offset: o })
+            | Err(CborError::AtOffset {
+                kind: ReadError::NonMinimalEncoding { .. }, offset: o,
+            }) => assert_eq!(o, offset),
+            r => panic!("unexpected {:?} for {:?}", r, bytes),
+        }
+    }
+}
+
+#[test]
+fn packed_cbor_round_trips() {
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Reading { sensor: String, unit: String, value: i32 }
+
+    fn prop(v: Vec<(String, Vec<u8>, i32)>) -> bool {
+        let readings: Vec<Reading> = v.iter().map(|&(ref s, _, n)| Reading {
+            sensor: s.clone(), unit: "celsius".to_string(), value: n,
+        }).collect();
+        let bytes = encode(&(&readings, &v));
+        let packed = cbor::pack(&bytes).unwrap();
+        assert!(packed.len() <= bytes.len());
+        assert_eq!(cbor::unpack(&packed).unwrap(), bytes);
+        true
+    }
+    qc_sized(prop as fn(_) -> bool, 40);
+
+    // Enough distinct repeated strings to need references with tag 6.
+    let keys: Vec<String> = (0..40).map(|i| format!("key number {}", i))
+                                    .collect();
+    let v: Vec<&[String]> = vec![&keys, &keys, &keys];
+    let bytes = encode(&v);
+    let packed = cbor::pack(&bytes).unwrap();
+    assert!(packed.len() < bytes.len() / 2);
+    let back: Vec<Vec<String>> = decode(&cbor::unpack(&packed).unwrap());
+    assert_eq!(back, vec![keys.clone(), keys.clone(), keys]);
+
+    // Simple values 0 to 15 would be read as references.
+    assert!(cbor::pack(&[0x82, 0xe0, 0xe0]).is_err());
+    // A reference to a shared item that doesn't exist.
+    assert!(cbor::unpack(&[0xd8, 0x71, 0x83, 0x80, 0x80, 0xe0]).is_err());
+    // Data that isn't packed is left alone.
+    assert_eq!(cbor::unpack(&[0x82, 0xe0, 0xe0]).unwrap(),
+               vec![0x82, 0xe0, 0xe0]);
+}
+
+#[test]
+fn stringrefs_round_trip() {
+    #[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+    struct Reading { sensor: String, data: CborBytes, value: i32 }
+
+    fn prop(v: Vec<(String, Vec<u8>, i32)>) -> bool {
+        // Repeat everything, so that there's something to refer to.
+        let readings: Vec<Reading> = v.iter().chain(v.iter())
+                                      .map(|&(ref s, ref d, n)| Reading {
+            sensor: s.clone(), data: CborBytes(d.clone()), value: n,
+        }).collect();
+        let mut enc = Encoder::from_memory().stringrefs(true);
+        enc.encode(&[&readings, &readings]).unwrap();
+        let bytes = enc.into_bytes();
+        if !readings.is_empty() {
+            assert!(bytes.len() < encode(&[&readings, &readings]).len());
+        }
+
+        let mut dec = Decoder::from_bytes(&bytes[..]).stringrefs(true);
+        let back: Vec<Vec<Reading>> =
+            dec.decode().collect::<Result<_, _>>().unwrap();
+        assert_eq!(back, vec![readings.clone(), readings]);
+
+        // Strings are resolved in abstract syntax, too.
+        let mut dec = Decoder::from_bytes(&bytes[..]).stringrefs(true);
+        let items = dec.items().collect::<Result<Vec<_>, _>>().unwrap();
+        let mut enc = Encoder::from_memory();
+        enc.encode(&back).unwrap();
+        let mut dec = Decoder::from_bytes(enc.into_bytes());
+        items == dec.items().collect::<Result<Vec<_>, _>>().unwrap()
+    }
+    qc_sized(prop as fn(_) -> bool, 20);
+
+    // Canonical encoding sorts map entries, so stringrefs aren't used.
+    let mut enc = Encoder::from_memory().stringrefs(true).canonical(true);
+    enc.encode(&[vec!["hello", "hello"]]).unwrap();
+    assert_eq!(enc.as_bytes(), &encode(&vec!["hello", "hello"])[..]);
+}
+
+#[test]
+fn shared_values_are_written_once() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use cbor::CborShared;
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Node { name: String, next: Option<CborShared<Rc<Node>>> }
+
+    let leaf = Rc::new(Node { name: "leaf".to_string(), next: None });
+    let nodes: Vec<_> = (0..3).map(|i| Node {
+        name: i.to_string(), next: Some(CborShared(leaf.clone())),
+    }).collect();
+    let bytes = encode(&nodes);
+    // The leaf is written once, followed by two references to it.
+    let leaf_bytes = encode(&*leaf);
+    assert_eq!(bytes.windows(leaf_bytes.len())
+                    .filter(|w| *w == &leaf_bytes[..]).count(), 1);
+    assert_eq!(bytes.windows(2).filter(|w| *w == &[0xd8, 0x1d]).count(), 2);
+
+    let mut dec = Decoder::from_bytes(&bytes[..]).shared_values(true);
+    let back: Vec<Node> = dec.decode().next().unwrap().unwrap();
+    assert_eq!(back, nodes);
+
+    // A cycle is written as a reference to the value that contains it...
+    #[derive(RustcEncodable)]
+    struct Link { next: Option<CborShared<Rc<RefCell<Link>>>> }
+    let link = Rc::new(RefCell::new(Link { next: None }));
+    link.borrow_mut().next = Some(CborShared(link.clone()));
+    let bytes = encode(CborShared(link.clone()));
+    link.borrow_mut().next = None;
+    // 28({"next": 28({"next": 29(0)})})
+    assert_eq!(bytes, vec![0xd8, 0x1c, 0xa1, 0x64, b'n', b'e', b'x', b't',
+                           0xd8, 0x1d, 0x00]);
+    // ... which can't be read back as abstract syntax.
+    let mut dec = Decoder::from_bytes(&bytes[..]).shared_values(true);
+    assert!(dec.items().next().unwrap().is_err());
+}
+
+#[test]
+fn smart_pointers_are_transparent() {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Plain { a: String, b: Vec<u32>, c: u8, d: bool }
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Wrapped {
+        a: Rc<String>,
+        b: Arc<Vec<u32>>,
+        c: Cell<u8>,
+        d: RefCell<bool>,
+    }
+
+    let plain = Plain { a: "x".to_string(), b: vec![1, 2], c: 3, d: true };
+    let wrapped = Wrapped {
+        a: Rc::new("x".to_string()),
+        b: Arc::new(vec![1, 2]),
+        c: Cell::new(3),
+        d: RefCell::new(true),
+    };
+    // The pointers don't show up in the data at all.
+    assert_eq!(encode(&wrapped), encode(&plain));
+    assert!(round_trip(wrapped));
+}
+
+#[test]
+fn integers_128_bits() {
+    use cbor::{CborI128, CborU128, CborWide};
+
+    let max = ::std::u64::MAX;
+    let unsigned = vec![(0, 0), (0, 23), (0, max), (1, 0), (max, max)];
+    for &(hi, lo) in &unsigned {
+        assert!(round_trip(CborU128 { hi: hi, lo: lo }));
+        if hi <= ::std::i64::MAX as u64 {
+            assert!(round_trip(CborI128 { hi: hi as i64, lo: lo }));
+        }
+    }
+    // -1, i64::MIN, i64::MIN - 1, -2^64, -2^64 - 1 and the 128 bit minimum.
+    let signed = vec![(-1, max), (-1, 1 << 63), (-1, (1 << 63) - 1),
+                      (-1, 0), (-2, max), (::std::i64::MIN, 0)];
+    for &(hi, lo) in &signed {
+        assert!(round_trip(CborI128 { hi: hi, lo: lo }));
+    }
+    assert_eq!(CborI128::from_i64(::std::i64::MIN),
+               CborI128 { hi: -1, lo: 1 << 63 });
+
+    // Down to -2^64, integers are plain negative integers.
+    let min_int = vec![0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(encode(CborI128 { hi: -1, lo: 0 }), min_int);
+    assert_eq!(readone(&min_int), Cbor::Wide(CborWide {
+        negative: true,
+        arg: max,
+        width: 8,
+    }));
+    assert_eq!(encode(readone(&min_int)), min_int);
+    assert_eq!(&encode(CborI128 { hi: -2, lo: max })[..3], &[0xc3, 0x49, 1]);
+    let mut dec = Decoder::from_bytes(min_int);
+    assert!(dec.decode::<i64>().next().unwrap().is_err());
+
+    // Plain integers are read as well, whatever width they were written
+    // with.
+    assert_eq!(decode::<CborU128>(&encode(300u16)), CborU128::from_u64(300));
+    assert_eq!(decode::<CborI128>(&encode(-300i16)), CborI128::from_i64(-300));
+    assert_eq!(encode(CborU128::from_u64(300)), encode(300u16));
+
+    // 3(h'ffffffffffffffffffffffffffffffff') is one less than the 128 bit
+    // minimum.
+    let mut too_small = vec![0xc3, 0x50];
+    too_small.extend(vec![0xff; 16]);
+    let mut dec = Decoder::from_bytes(too_small);
+    assert!(dec.decode::<CborI128>().next().unwrap().is_err());
+    let mut dec = Decoder::from_bytes(encode(-1));
+    assert!(dec.decode::<CborU128>().next().unwrap().is_err());
+}
+
+#[test]
+fn integer_field_keys() {
+    use cbor::{CborError, CborMap, WriteError};
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Claims { iss: String, exp: u64, extra: bool }
+    let keys = [("iss", 1), ("exp", 4), ("nbf", -5)];
+
+    let claims = Claims { iss: "me".to_string(), exp: 99, extra: true };
+    let mut enc = Encoder::from_memory().field_keys(&keys);
+    enc.encode(&[&claims]).unwrap();
+    let bytes = enc.into_bytes();
+    assert_eq!(bytes, vec![0xa3, 0x01, 0x62, b'm', b'e', 0x04, 0x18, 0x63,
+                           0x65, b'e', b'x', b't', b'r', b'a', 0xf5]);
+
+    let mut dec = Decoder::from_bytes(&bytes[..]).field_keys(&keys);
+    let back: Claims = dec.decode().next().unwrap().unwrap();
+    assert_eq!(back, claims);
+    // Without the table, integer keys can't be read.
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    assert!(dec.decode::<Claims>().next().unwrap().is_err());
+
+    // Encoded integer keys sort before strings in canonical order.
+    #[derive(RustcEncodable)]
+    struct Reversed { extra: bool, exp: u64, iss: String }
+    let reversed = Reversed { extra: true, exp: 99, iss: "me".to_string() };
+    let mut enc = Encoder::from_memory().field_keys(&keys).canonical(true);
+    enc.encode(&[&reversed]).unwrap();
+    assert_eq!(enc.as_bytes(), &bytes[..]);
+
+    // Keys of other maps aren't changed.
+    let mut map = CborMap::new();
+    map.insert("iss".to_string(), Cbor::Null);
+    let mut enc = Encoder::from_memory().field_keys(&keys);
+    enc.encode(&[&map]).unwrap();
+    assert_eq!(enc.as_bytes(), &encode(&map)[..]);
+
+    // DAG-CBOR doesn't allow integer keys.
+    let mut enc = Encoder::from_memory().field_keys(&keys).dag_cbor(true);
+    match enc.encode(&[&claims]) {
+        Err(CborError::Encode(WriteError::DagCbor(ref what)))
+            if what == "integer map key 1 (for field \"iss\")" => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn flattened_maps() {
+    use cbor::{CborError, CborFlatten, WriteError};
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Header { kind: String, version: u8 }
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Body { data: Vec<u32> }
+
+    let mut extra = HashMap::new();
+    extra.insert("note".to_string(), true);
+    let v = CborFlatten(Header { kind: "k".to_string(), version: 2 },
+                        CborFlatten(Body { data: vec![1, 2] }, extra));
+    let bytes = encode(&v);
+    let items: Vec<Cbor> = Decoder::from_bytes(&bytes[..]).items()
+                                   .collect::<Result<_, _>>().unwrap();
+    assert_eq!(items, vec![Cbor::from_diagnostic(
+        r#"{"kind": "k", "version": 2, "data": [1, 2], "note": true}"#,
+    ).unwrap()]);
+    assert!(round_trip(CborFlatten(Header { kind: "k".to_string(),
+                                            version: 2 },
+                                   Body { data: vec![1, 2] })));
+
+    // The merged map is sorted as a whole in canonical mode.
+    let v = CborFlatten(Body { data: vec![] },
+                        Header { kind: "k".to_string(), version: 2 });
+    let mut enc = Encoder::from_memory().canonical(true);
+    enc.encode(&[&v]).unwrap();
+    let expected = Cbor::from_diagnostic(
+        r#"{"data": h'', "kind": "k", "version": 2}"#).unwrap();
+    let mut canonical = Encoder::from_memory().canonical(true);
+    canonical.encode(&[&expected]).unwrap();
+    assert_eq!(enc.as_bytes(), canonical.as_bytes());
+
+    // Only maps can be merged.
+    let mut enc = Encoder::from_memory();
+    match enc.encode(&[CborFlatten(Body { data: vec![] }, 5)]) {
+        Err(CborError::Encode(WriteError::InvalidFlatten)) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn indefinite_lengths() {
+    use cbor::{CborFlatten, UnknownLengthPolicy};
+
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Id { id: u32 }
+    #[derive(RustcEncodable, RustcDecodable, Debug, PartialEq)]
+    struct Tags { tags: Vec<String> }
+
+    let v = CborFlatten(Id { id: 7 }, Tags { tags: vec!["x".to_string()] });
+    let mut enc = Encoder::from_memory()
+                          .unknown_lengths(UnknownLengthPolicy::Indefinite);
+    enc.encode(&[&v]).unwrap();
+    let bytes = enc.into_bytes();
+    assert_eq!(bytes[0], 0xbf);
+    assert_eq!(bytes[bytes.len() - 1], 0xff);
+    assert_eq!(decode::<CborFlatten<Id, Tags>>(&bytes), v);
+    // Canonical mode never uses indefinite lengths.
+    let mut enc = Encoder::from_memory().canonical(true)
+                          .unknown_lengths(UnknownLengthPolicy::Indefinite);
+    enc.encode(&[&v]).unwrap();
+    assert_eq!(enc.as_bytes()[0], 0xa2);
+
+    // [_ 1, {_ "a": [_ ]}]
+    let bytes = vec![0x9f, 0x01, 0xbf, 0x61, b'a', 0x9f, 0xff, 0xff, 0xff];
+    let v: (u8, HashMap<String, Vec<u8>>) = decode(&bytes);
+    assert_eq!(v.0, 1);
+    assert_eq!(v.1["a"], vec![]);
+    // A missing break is an error, and so are too many items.
+    let mut dec = Decoder::from_bytes(&bytes[..bytes.len() - 1]);
+    assert!(dec.items().next().unwrap().is_err());
+    let mut dec = Decoder::from_bytes(vec![0x9f, 0x01, 0x02, 0xff])
+                          .max_items(1);
+    assert!(dec.items().next().unwrap().is_err());
+}
+
+#[test]
+fn encode_from_iterators() {
+    use cbor::{CborError, UnknownLengthPolicy, WriteError};
+
+    let rows: Vec<(u32, String)> =
+        (0..100).map(|n| (n, n.to_string())).collect();
+    let expected = encode(&rows);
+    for &len in &[Some(rows.len()), None] {
+        let mut enc = Encoder::from_memory();
+        enc.encode_seq_from_iter(len, rows.iter()).unwrap();
+        assert_eq!(enc.as_bytes(), &expected[..]);
+    }
+    let policy = UnknownLengthPolicy::Indefinite;
+    let mut enc = Encoder::from_memory().unknown_lengths(policy);
+    enc.encode_seq_from_iter(None, rows.iter()).unwrap();
+    enc.encode_map_from_iter(None, rows.iter().map(|r| (&r.1, r.0)))
+       .unwrap();
+    let mut dec = Decoder::from_bytes(enc.as_bytes());
+    let back: Vec<(u32, String)> = dec.decode().next().unwrap().unwrap();
+    assert_eq!(back, rows);
+    let map: HashMap<String, u32> = dec.decode().next().unwrap().unwrap();
+    assert_eq!(map.len(), rows.len());
+    assert_eq!(map["42"], 42);
+
+    // Map entries are still sorted in canonical mode.
+    let mut enc = Encoder::from_memory().canonical(true);
+    enc.encode_map_from_iter(None, vec![("b", 1), ("a", 2)]).unwrap();
+    assert_eq!(enc.as_bytes(),
+               &[0xa2, 0x61, b'a', 0x02, 0x61, b'b', 0x01][..]);
+
+    let mut enc = Encoder::from_memory();
+    match enc.encode_seq_from_iter(Some(3), vec![1, 2]) {
+        Err(CborError::Encode(WriteError::LengthMismatch {
+            expected: 3, got: 2,
+        })) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn canonical_encoding_sorts_out_of_order_producers() {
+    use rustc_serialize::Encoder as RustcEncoder;
+
+    // Writes its entries in reverse order of their keys, with a nested
+    // map that is out of order as well.
+    struct Reversed;
+    impl Encodable for Reversed {
+        fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+            let keys = ["zz", "m", "b", "aaa"];
+            e.emit_map(keys.len(), |e| {
+                for (i, k) in keys.iter().enumerate() {
+                    try!(e.emit_map_elt_key(i, |e| k.encode(e)));
+                    try!(e.emit_map_elt_val(i, |e| {
+                        if *k == "m" {
+                            vec![("y", 1), ("x", 2)].into_iter()
+                                .collect::<HashMap<_, _>>().encode(e)
+                        } else {
+                            i.encode(e)
+                        }
+                    }));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    let mut enc = Encoder::from_memory().canonical(true);
+    enc.encode(&[Reversed]).unwrap();
+    // Shorter keys sort first, since their heads are smaller.
+    let expected = Cbor::from_diagnostic(
+        r#"{"b": 2, "m": {"x": 2, "y": 1}, "zz": 0, "aaa": 3}"#).unwrap();
+    let mut dec = Decoder::from_bytes(enc.as_bytes());
+    let items = dec.items().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(items, vec![expected]);
+    assert_eq!(enc.as_bytes(), &[
+        0xa4, 0x61, b'b', 0x02,
+              0x61, b'm', 0xa2, 0x61, b'x', 0x02, 0x61, b'y', 0x01,
+              0x62, b'z', b'z', 0x00,
+              0x63, b'a', b'a', b'a', 0x03,
+    ][..]);
+}
+
+#[test]
+fn framed_messages() {
+    use cbor::{CborError, ReadError};
+    use cbor::framed::{self, Framing};
+
+    let long: String = ::std::iter::repeat('b').take(30).collect();
+    let msgs = vec![vec![], vec!["a".to_string(); 10], vec![long]];
+    for &framing in &[Framing::ByteString, Framing::Length32] {
+        let mut stream = vec![];
+        for msg in &msgs {
+            framed::write_message(&mut stream, framing, msg).unwrap();
+        }
+        let mut rdr = &stream[..];
+        let mut got: Vec<Vec<String>> = vec![];
+        while let Some(msg) = framed::read_message(&mut rdr, framing, 64)
+                                     .unwrap() {
+            got.push(msg);
+        }
+        assert_eq!(got, msgs);
+
+        let mut rdr = &stream[..stream.len() - 1];
+        framed::read_frame(&mut rdr, framing, 64).unwrap().unwrap();
+        framed::read_frame(&mut rdr, framing, 64).unwrap().unwrap();
+        assert!(framed::read_frame(&mut rdr, framing, 64).is_err());
+
+        // The second message is 21 bytes, and the third is 33.
+        let mut rdr = &stream[..];
+        framed::read_frame(&mut rdr, framing, 1).unwrap().unwrap();
+        framed::read_frame(&mut rdr, framing, 21).unwrap().unwrap();
+        match framed::read_frame(&mut rdr, framing, 32) {
+            Err(CborError::AtOffset {
+                kind: ReadError::TooLong { len: 33, max: 32, .. }, offset: 0,
+            }) => {}
+            r => panic!("expected TooLong error, got {:?}", r),
+        }
+        if framing == Framing::ByteString {
+            // Generic tools see a sequence of byte strings.
+            let mut dec = Decoder::from_bytes(&stream[..]);
+            let frames: Vec<CborBytes> =
+                dec.decode().collect::<Result<_, _>>().unwrap();
+            assert_eq!(frames.len(), 3);
+            let inner: Vec<String> =
+                cbor::from_slice_exact(&frames[1].0).unwrap();
+            assert_eq!(inner, msgs[1]);
+        }
+    }
+}
+
+#[test]
+fn framed_messages_with_transforms() {
+    use cbor::{CborError, CborResult, ReadError};
+    use cbor::framed::{self, Framing, PayloadTransform};
+
+    // Prefixes each message with a sequence number, which is checked on
+    // the way back, like the nonce of a cipher.
+    struct Sequenced(u8);
+
+    impl PayloadTransform for Sequenced {
+        fn seal(&mut self, mut msg: Vec<u8>) -> CborResult<Vec<u8>> {
+            msg.insert(0, self.0);
+            self.0 += 1;
+            Ok(msg)
+        }
+
+        fn open(&mut self, mut msg: Vec<u8>) -> CborResult<Vec<u8>> {
+            if msg.is_empty() || msg.remove(0) != self.0 {
+                let err = ReadError::Other("out of sequence".to_string());
+                return Err(CborError::Decode(err));
+            }
+            self.0 += 1;
+            Ok(msg)
+        }
+    }
+
+    for &framing in &[Framing::ByteString, Framing::Length32] {
+        let mut stream = vec![];
+        let mut seal = Sequenced(0);
+        for msg in &["a", "b", "c"] {
+            framed::write_message_with(&mut stream, framing, &mut seal, msg)
+                .unwrap();
+        }
+        let mut rdr = &stream[..];
+        let mut open = Sequenced(0);
+        let mut got: Vec<String> = vec![];
+        while let Some(msg) = framed::read_message_with(&mut rdr, framing, 64,
+                                                        &mut open).unwrap() {
+            got.push(msg);
+        }
+        assert_eq!(got, vec!["a", "b", "c"]);
+
+        // Skipping a message is caught by the transform.
+        let mut rdr = &stream[..];
+        framed::read_frame(&mut rdr, framing, 64).unwrap();
+        let r: CborResult<Option<String>> = framed::read_message_with(
+            &mut rdr, framing, 64, &mut Sequenced(0));
+        assert!(r.is_err());
+    }
+}
+
+#[test]
+fn mime_types() {
+    assert_eq!(cbor::MIME_TYPE, "application/cbor");
+    assert_eq!(cbor::MIME_TYPE_SEQ, "application/cbor-seq");
+    for ty in &["application/cbor", " application/cbor ;q=1",
+                "APPLICATION/Cbor", "application/cwt+cbor"] {
+        assert!(cbor::is_cbor_media_type(ty), "{}", ty);
+    }
+    for ty in &["", "application/json", "application/cbor-seq",
+                "application/+cbor", "cbor", "text/cbor-ish"] {
+        assert!(!cbor::is_cbor_media_type(ty), "{}", ty);
+    }
+}
+
+#[test]
+fn any_values_as_map_keys() {
+    use std::collections::{BTreeMap, HashSet};
+    use cbor::{CborFloat, CborUnsigned};
+
+    let nan = Cbor::Float(CborFloat::Float64(::std::f64::NAN));
+    assert_eq!(nan, nan.clone());
+    assert!(Cbor::Float(CborFloat::Float64(-0.0))
+            < Cbor::Float(CborFloat::Float64(0.0)));
+    let set: HashSet<Cbor> = vec![nan.clone(), nan.clone()].into_iter()
+                                                           .collect();
+    assert_eq!(set.len(), 1);
+
+    let one = Cbor::Unsigned(CborUnsigned::UInt8(1));
+    let mut map = BTreeMap::new();
+    map.insert(one.clone(), Cbor::Unicode("one".to_string()));
+    map.insert(nan.clone(), Cbor::Null);
+    map.insert(Cbor::Array(vec![one.clone()]), Cbor::Bool(true));
+    map.insert(Cbor::Unicode("k".to_string()), one.clone());
+    let bytes = cbor::encode_any_map(&map).unwrap();
+    assert_eq!(bytes[0], 0xa4);
+    assert_eq!(cbor::decode_any_map(&bytes).unwrap(), map);
+    assert_eq!(cbor::encode_any_map(&cbor::decode_any_map(&bytes).unwrap())
+               .unwrap(), bytes);
+
+    // The usual decoder still wants string keys.
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    assert!(dec.items().next().unwrap().is_err());
+    assert!(cbor::decode_any_map(&[0x81, 0x01]).is_err());
+    assert!(cbor::decode_any_map(&[0xa0, 0x01]).is_err());
+}
+
+#[test]
+fn value_accessors() {
+    let item = Cbor::from_diagnostic(
+        r#"{"a": {"b/c": [1, -2, 3.5, true, null, h'00', "x"]}, "~": 0}"#)
+        .unwrap();
+    let list = item.pointer("/a/b~1c").unwrap();
+    assert_eq!(list.as_array().map(|v| v.len()), Some(7));
+    assert_eq!(list[0].as_u64(), Some(1));
+    assert_eq!(list[0].as_i64(), Some(1));
+    assert_eq!(list[1].as_u64(), None);
+    assert_eq!(list[1].as_i64(), Some(-2));
+    assert_eq!(list[2].as_f64(), Some(3.5));
+    assert_eq!(list[3].as_bool(), Some(true));
+    assert!(list[4].is_null());
+    assert_eq!(list[5].get_bytes(), Some(&[0][..]));
+    assert_eq!(list[6].get_text(), Some("x"));
+    assert_eq!(item.pointer("/~0").and_then(|v| v.as_u64()), Some(0));
+    assert_eq!(item.pointer(""), Some(&item));
+    assert_eq!(item.pointer("a"), None);
+    assert_eq!(item.pointer("/a/b~1c/7"), None);
+    assert_eq!(item.pointer("/a/b~1c/x"), None);
+    assert_eq!(item.get("a").and_then(|v| v.get_at(0)), None);
+    assert!(item.as_map().unwrap().contains_key("~"));
+
+    // Indexing anything that isn't there gives null.
+    assert_eq!(item["a"]["b/c"][6].get_text(), Some("x"));
+    assert!(item["a"]["b/c"][7].is_null());
+    assert!(item["a"]["x"]["y"].is_null());
+    assert!(item["a"][0].is_null());
+    assert!(item["~"]["a"].is_null());
+}
+
+#[test]
+#[should_panic(expected = "cannot look up key \"b\"")]
+fn index_mut_missing_key() {
+    let mut item = cbor!({"a" => 1});
+    item["a"] = cbor!(2);
+    item["b"] = cbor!(3);
+}
+
+#[test]
+fn build_and_mutate_values() {
+    let mut item = cbor!({
+        "a" => 1,
+        "b" => [1, -2, tag(0, "2024-01-01T00:00:00Z"), [], {}],
+        "c" => { "d" => null, "e" => Some(1.5) },
+    });
+    assert_eq!(item, Cbor::from_diagnostic(
+        r#"{"a": 1, "b": [1, -2, 0("2024-01-01T00:00:00Z"), [], {}],
+            "c": {"d": null, "e": 1.5}}"#).unwrap());
+
+    item["a"] = cbor!("one");
+    item["b"].push(cbor!(u64::max_value()));
+    item["b"][3].push(cbor!(true));
+    assert_eq!(item.insert("f", cbor!([undefined])), None);
+    *item.pointer_mut("/c/d").unwrap() = cbor!(vec!["x", "y"]);
+    item.as_map_mut().unwrap().remove("c");
+    assert_eq!(item.to_string(),
+               r#"{"a": "one", "b": [1, -2, 0("2024-01-01T00:00:00Z"), "#
+               .to_string()
+               + r#"[true], {}, 18446744073709551615], "f": [undefined]}"#);
+}
+
+#[test]
+fn get_single_field() {
+    // {"big": [0, 0, ...], 1: 2, "method": "a", "method": "b"}, with an
+    // indefinite length.
+    let mut bytes = vec![0xbf, 0x63, b'b', b'i', b'g', 0x99, 0x03, 0xe8];
+    bytes.extend(::std::iter::repeat(0).take(1000));
+    bytes.extend(vec![0x01, 0x02,
+                      0x66, b'm', b'e', b't', b'h', b'o', b'd', 0x61, b'a',
+                      0x66, b'm', b'e', b't', b'h', b'o', b'd', 0x61, b'b',
+                      0xff]);
+    let method: Option<String> = cbor::get_field(&bytes, "method").unwrap();
+    assert_eq!(method, Some("b".to_string()));
+    let big: Option<Vec<u8>> = cbor::get_field(&bytes, "big").unwrap();
+    assert_eq!(big.map(|v| v.len()), Some(1000));
+    assert_eq!(cbor::get_field::<u8>(&bytes, "nope").unwrap(), None);
+
+    match cbor::get_field::<u8>(&bytes, "method") {
+        Err(cbor::CborError::AtPath { offset, ref path, .. }) => {
+            assert_eq!(offset, bytes.len() - 3);
+            assert_eq!(path, "method");
+        }
+        r => panic!("unexpected {:?}", r),
+    }
+    assert!(cbor::get_field::<u8>(&[0x80], "a").is_err());
+    assert!(cbor::get_field::<u8>(&bytes[..bytes.len() - 1], "a").is_err());
+}
+
+#[test]
+fn projections() {
+    use cbor::Projection;
+
+    let item = cbor!({
+        "a" => [{ "x" => 1, "y" => 2 }, { "x" => 3 }, 4],
+        "b" => { "c" => "d", "e/f" => null },
+        "g" => 5,
+    });
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[&item]).unwrap();
+    let bytes = enc.as_bytes();
+
+    let project = |proj: Projection| proj.project(bytes).unwrap();
+    assert_eq!(project(Projection::new()), Cbor::Null);
+    assert_eq!(project(Projection::new().path("")), item);
+    assert_eq!(project(Projection::new().path("/a/*/x").path("g")),
+               cbor!({ "a" => [{ "x" => 1 }, { "x" => 3 }], "g" => 5 }));
+    assert_eq!(project(Projection::new().path("/a/1").path("/b/e~1f")),
+               cbor!({ "a" => [{ "x" => 3 }], "b" => { "e/f" => null } }));
+    assert_eq!(project(Projection::new().path("/b").path("/b/c")),
+               cbor!({ "b" => { "c" => "d", "e/f" => null } }));
+    assert_eq!(project(Projection::new().path("/g/h").path("/nope")),
+               cbor!({}));
+
+    let g: HashMap<String, u8> =
+        Projection::new().path("g").decode(bytes).unwrap();
+    assert_eq!(g["g"], 5);
+    assert!(Projection::new().path("g").project(&bytes[1..]).is_err());
+}
+
+#[test]
+fn skip_without_decoding() {
+    // [_ "ab", h'0102'], {1: 0(2)}, 1.5, "x" with a huge length
+    let bytes = vec![0x9f, 0x7f, 0x61, b'a', 0x61, b'b', 0xff,
+                     0x42, 0x01, 0x02, 0xff,
+                     0xa1, 0x01, 0xc0, 0x02,
+                     0xf9, 0x3e, 0x00,
+                     0x7b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    let mut dec = Decoder::from_bytes(&bytes[..]);
+    assert!(dec.skip().unwrap());
+    assert_eq!(dec.bytes_consumed(), 11);
+    assert!(dec.skip().unwrap());
+    let v: f64 = dec.decode().next().unwrap().unwrap();
+    assert_eq!(v, 1.5);
+    assert!(dec.skip().is_err());
+
+    let mut dec = Decoder::from_bytes(vec![0x81, 0x81, 0x80]).max_depth(1);
+    assert!(dec.skip().is_err());
+    assert!(Decoder::from_bytes(vec![0xff]).skip().is_err());
+    assert!(Decoder::from_bytes(vec![0x1f]).skip().is_err());
+    assert!(Decoder::from_bytes(vec![0x82, 0x01]).skip().is_err());
+}
+
+#[test]
+fn reuse_decoder_for_many_messages() {
+    let mut dec = Decoder::from_bytes(vec![]).max_total_items(3);
+    for i in 0..10u8 {
+        let mut enc = Encoder::from_memory();
+        enc.encode(&[(i, "x")]).unwrap();
+        dec.reset(enc.as_bytes());
+        let v: (u8, String) = dec.decode().next().unwrap().unwrap();
+        assert_eq!(v, (i, "x".to_string()));
+        assert!(dec.decode::<u8>().next().is_none());
+    }
+    // The budget is per message, and errors have offsets in the message.
+    dec.reset(&[0x01, 0x83, 0x01, 0x02, 0x03]);
+    assert!(dec.skip().unwrap());
+    match dec.items().next() {
+        Some(Err(cbor::CborError::AtOffset { offset, .. })) => {
+            assert!(offset < 5)
+        }
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn decode_from_slice() {
+    let data = vec![("a".to_string(), vec![1u64, 1000]),
+                    ("bc".to_string(), vec![])];
+    let bytes = encode(&data);
+    let mut dec = Decoder::from_slice(&bytes);
+    let v: Vec<(String, Vec<u64>)> = dec.decode().next().unwrap().unwrap();
+    assert_eq!(v, data);
+    assert_eq!(dec.bytes_consumed(), bytes.len());
+    assert!(dec.items().next().is_none());
+
+    // Errors have the same offsets as with `from_bytes`.
+    let short = &bytes[..bytes.len() - 1];
+    let err = Decoder::from_slice(short).items().next().unwrap();
+    let expected = Decoder::from_bytes(short).items().next().unwrap();
+    assert_eq!(format!("{:?}", err), format!("{:?}", expected));
+
+    // Readers are read from directly, since the decoder has a buffer.
+    let mut dec: Decoder<&[u8]> = Decoder::from_reader(&bytes[..]);
+    let v: Vec<(String, Vec<u64>)> = dec.decode().next().unwrap().unwrap();
+    assert_eq!(v, data);
+    assert_eq!(dec.bytes_consumed(), bytes.len());
+}
+
+#[test]
+fn encoder_into_inner() {
+    use std::io::BufWriter;
+
+    let mut enc = Encoder::from_writer(vec![]);
+    enc.encode(&["a", "b"]).unwrap();
+    let out = enc.into_inner().unwrap().into_inner().unwrap();
+    let mut expected = encode("a");
+    expected.extend(encode("b"));
+    assert_eq!(out, expected);
+
+    let mut enc = Encoder::from_writer_unbuffered(BufWriter::new(vec![]));
+    enc.encode(&[vec![1u8, 2]]).unwrap();
+    let out = enc.into_inner().unwrap().into_inner().unwrap();
+    assert_eq!(out, encode(vec![1u8, 2]));
+}
+
+#[test]
+fn out_of_order_encodable_is_an_error() {
+    use cbor::{CborError, WriteError};
+    use rustc_serialize::Encoder as RustcEncoder;
+
+    struct LoneValue;
+
+    impl Encodable for LoneValue {
+        fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+            e.emit_map_elt_val(0, |e| e.emit_u8(1))
+        }
+    }
+
+    let mut enc = Encoder::from_memory().canonical(true);
+    match enc.encode(&[LoneValue]) {
+        Err(CborError::Encode(WriteError::OutOfOrder(_))) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // Without sorting, the value is just written.
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[LoneValue]).unwrap();
+    assert_eq!(enc.into_bytes(), vec![1]);
+}
+
+#[test]
+fn pathological_encodables_are_errors() {
+    use cbor::{CborError, Type, WriteError};
+    use rustc_serialize::Encoder as RustcEncoder;
+
+    // A sequence and a map that give the wrong length.
+    struct ShortSeq;
+    struct LongMap;
+
+    impl Encodable for ShortSeq {
+        fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+            e.emit_seq(3, |e| e.emit_seq_elt(0, |e| e.emit_u8(1)))
+        }
+    }
+
+    impl Encodable for LongMap {
+        fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+            e.emit_map(0, |e| {
+                try!(e.emit_map_elt_key(0, |e| e.emit_str("a")));
+                e.emit_map_elt_val(0, |e| e.emit_u8(1))
+            })
+        }
+    }
+
+    for &canonical in &[false, true] {
+        let mut enc = Encoder::from_memory().canonical(canonical);
+        match enc.encode(&[ShortSeq]) {
+            Err(CborError::Encode(WriteError::LengthMismatch {
+                expected: 3, got: 1,
+            })) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let mut enc = Encoder::from_memory().canonical(canonical);
+        match enc.encode(&[(1, LongMap)]) {
+            Err(CborError::EncodeAtPath {
+                kind: WriteError::LengthMismatch { expected: 0, got: 1 },
+                ref path,
+            }) if path == "[1]" => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    let v = Cbor::Array(vec![Cbor::Null, Cbor::Break]);
+    match Encoder::from_memory().encode(&[v]) {
+        Err(CborError::EncodeAtPath {
+            kind: WriteError::UnsupportedType(Type::Break),
+            ref path,
+        }) if path == "[1]" => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn reuse_encoder_buffers() {
+    let mut enc = Encoder::with_capacity(4).dag_cbor(true);
+    assert!(enc.encode(&[vec![1.0, ::std::f64::NAN]]).is_err());
+    enc.clear();
+    enc.encode(&[vec![1u8, 2]]).unwrap();
+    assert_eq!(enc.as_bytes(), &encode(vec![1u8, 2])[..]);
+
+    // Nothing from the data item that failed is left for the next one.
+    let new = || Encoder::from_memory().stringrefs(true).self_describe(true);
+    let bad = (vec!["abc", "abc"], Cbor::Array(vec![Cbor::Null, Cbor::Break]));
+    let good = (vec!["abc", "abc"], vec![1u8, 2]);
+    let mut enc = new();
+    assert!(enc.encode(&[&bad]).is_err());
+    enc.clear();
+    enc.encode(&[&good]).unwrap();
+    let mut fresh = new();
+    fresh.encode(&[&good]).unwrap();
+    assert_eq!(enc.as_bytes(), fresh.as_bytes());
+
+    let mut buf = vec![];
+    for n in 0..3u8 {
+        Encoder::from_vec(&mut buf).encode(&[n]).unwrap();
+    }
+    assert_eq!(buf, vec![0, 1, 2]);
+}
+
+#[test]
+fn predict_encoded_size() {
+    let mut map = HashMap::new();
+    map.insert("k".to_string(), vec![Some(1.5f64), None]);
+    let v = (map, CborBytes(vec![0; 300]), -100_000i64, "é".to_string());
+    assert_eq!(cbor::encoded_size(&v).unwrap(), encode(&v).len());
+    assert_eq!(cbor::encoded_size(&()).unwrap(), 1);
+}
+
+#[test]
+fn decode_all_with_recovery() {
+    let mut bytes = vec![];
+    for i in 0..4u32 {
+        bytes.extend(encode(&(i, format!("record {}", i))));
+    }
+    // A well formed item of the wrong type is skipped in either mode.
+    let mut wrong = encode(&"not a record");
+    wrong.extend(bytes.iter().cloned());
+    let all = cbor::decode_all::<(u32, String)>(&wrong, false);
+    assert_eq!(all.len(), 5);
+    assert!(all[0].is_err() && all[1..].iter().all(|r| r.is_ok()));
+
+    // Break the second record with a reserved head.
+    let second = encode(&(0u32, "record 0")).len();
+    bytes[second] = 0x1c;
+    let all = cbor::decode_all::<(u32, String)>(&bytes, false);
+    assert_eq!(all.len(), 2);
+    match all[1] {
+        Err(cbor::CborError::AtOffset { offset, .. }) => {
+            assert_eq!(offset, second)
+        }
+        ref r => panic!("unexpected {:?}", r),
+    }
+    let ok = cbor::decode_all::<(u32, String)>(&bytes, true)
+        .into_iter().filter_map(|r| r.ok()).map(|r| r.0).collect::<Vec<_>>();
+    assert_eq!(ok, vec![0, 2, 3]);
+
+    // Truncation is an error too.
+    let all = cbor::decode_all::<(u32, String)>(&bytes[..3], true);
+    assert_eq!(all.len(), 1);
+    assert!(all[0].is_err());
+}
+
+#[test]
+fn short_containers_report_missing_items() {
+    use cbor::{CborError, ReadError, Type};
+
+    // {"a": 1, <break>
+    let bytes = [0xa2, 0x61, b'a', 0x01, 0xff];
+    match Decoder::from_bytes(&bytes[..]).items().next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::MissingItems {
+                ty: Type::Map, expected: 2, got: 1, eof: false,
+            },
+            offset: 4,
+        }) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+    // Running out in the middle of an item is still an unexpected EOF.
+    let bytes = encode(&vec![1u32, 1000]);
+    match Decoder::from_bytes(&bytes[..3]).items().next().unwrap() {
+        Err(CborError::AtOffset {
+            kind: ReadError::UnexpectedEOF { .. }, ..
+        }) => {}
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn invalid_map_key_has_key_and_path() {
+    use cbor::{CborError, Type, WriteError};
+
+    #[derive(RustcEncodable)]
+    struct Limits { by_id: HashMap<u32, u32> }
+    #[derive(RustcEncodable)]
+    struct Config { limits: Vec<Limits> }
+
+    let mut by_id = HashMap::new();
+    by_id.insert(7, 100);
+    let mut outer = HashMap::new();
+    outer.insert("config", Config { limits: vec![Limits { by_id: by_id }] });
+    for &canonical in &[false, true] {
+        let mut enc = Encoder::from_memory().canonical(canonical);
+        match enc.encode(&[&outer]) {
+            Err(CborError::Encode(WriteError::InvalidMapKey {
+                got: Some(Type::UInt32), ref key, ref path,
+            })) => {
+                assert_eq!(key.as_ref().unwrap(), "7");
+                assert_eq!(path, r#"["config"].limits[0].by_id"#);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn errors_have_paths() {
+    use cbor::{CborError, ReadError, WriteError};
+
+    #[derive(RustcEncodable, RustcDecodable, Debug)]
+    struct Payload { id: u64 }
+    #[derive(RustcEncodable, RustcDecodable, Debug)]
+    struct Message { payload: Payload }
+
+    let v = Cbor::from_diagnostic(r#"{"messages": [
+        {"payload": {"id": 1}}, {"payload": {"id": 2}},
+        {"payload": {"id": "three"}}
+    ]}"#).unwrap();
+    let bytes = encode(&v);
+    let at = bytes.windows(6).position(|w| w == b"\x65three").unwrap();
+    let r = cbor::from_slice_exact::<HashMap<String, Vec<Message>>>(&bytes);
+    match r {
+        Err(CborError::AtPath {
+            kind: ReadError::TypeMismatch { .. }, offset, ref path,
+        }) => {
+            assert_eq!(offset, at);
+            assert_eq!(path, r#"["messages"][2].payload.id"#);
+        }
+        r => panic!("unexpected {:?}", r),
+    }
+
+    let msgs = vec![(1u8, vec![1.0, ::std::f64::NAN])];
+    let mut enc = Encoder::from_memory().dag_cbor(true);
+    match enc.encode(&[&msgs]) {
+        Err(CborError::EncodeAtPath {
+            kind: WriteError::DagCbor(_), ref path,
+        }) => assert_eq!(path, "[0][1][1]"),
+        r => panic!("unexpected {:?}", r),
+    }
+}
+
+#[test]
+fn chars_are_text() {
+    use std::collections::HashMap;
+    use cbor::DirectDecoder;
+
+    assert_eq!(encode('☃'), encode("☃"));
+    assert_eq!(decode::<char>(&encode("☃")), '☃');
+    let mut dec = DirectDecoder::from_bytes(encode(('é', 'x')));
+    let v: (char, char) = Decodable::decode(&mut dec).unwrap();
+    assert_eq!(v, ('é', 'x'));
+
+    // Chars can be map keys now.
+    let mut map = HashMap::new();
+    map.insert('k', 1);
+    assert_eq!(decode::<HashMap<char, u8>>(&encode(&map)), map);
+
+    // Scalar values are still read.
+    let mut enc = Encoder::from_memory().chars_as_integers(true);
+    enc.encode(&['☃']).unwrap();
+    assert_eq!(enc.as_bytes(), &encode(0x2603u32)[..]);
+    assert_eq!(decode::<char>(enc.as_bytes()), '☃');
+    let mut dec = DirectDecoder::from_bytes(enc.as_bytes());
+    let v: char = Decodable::decode(&mut dec).unwrap();
+    assert_eq!(v, '☃');
+
+    let r: Result<char, _> = Decoder::from_bytes(encode("ab"))
+        .decode().next().unwrap();
+    assert!(r.is_err());
+}
+
+#[test]
+fn newtypes_are_transparent() {
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Meters(f64);
+    #[derive(Debug, PartialEq, Eq, Hash, RustcDecodable, RustcEncodable)]
+    struct UserId(String);
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Named { id: UserId }
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Wrapper(Named);
+
+    assert_eq!(encode(Meters(1.5)), encode(1.5));
+    assert_eq!(decode::<Meters>(&encode(1.5)), Meters(1.5));
+    let v = Wrapper(Named { id: UserId("u1".to_string()) });
+    let bytes = encode(&v);
+    assert_eq!(readone(&bytes).to_string(), r#"{"id": "u1"}"#);
+    assert_eq!(decode::<Wrapper>(&bytes), v);
+
+    // Newtypes of strings can be map keys.
+    let mut map = HashMap::new();
+    map.insert(UserId("u2".to_string()), 2);
+    assert_eq!(encode(&map), encode(&vec![("u2", 2)].into_iter()
+        .collect::<HashMap<_, _>>()));
+    assert_eq!(decode::<HashMap<UserId, u8>>(&encode(&map)), map);
+
+    // Both encodings are decoded.
+    let mut enc = Encoder::from_memory().transparent_newtypes(false);
+    enc.encode(&[&v]).unwrap();
+    assert_eq!(readone(enc.as_bytes()).to_string(),
+               r#"{"_field0": {"id": {"_field0": "u1"}}}"#);
+    assert_eq!(decode::<Wrapper>(enc.as_bytes()), v);
+}
+
+#[test]
+fn nested_options_round_trip() {
+    use cbor::{DirectDecoder, OptionFormat};
+
+    let vs: Vec<Option<Option<()>>> = vec![None, Some(None), Some(Some(()))];
+    // By default, they are all `null`.
+    assert_eq!(decode::<Vec<Option<Option<()>>>>(&encode(&vs)),
+               vec![None, None, None]);
+
+    let format = OptionFormat::Array;
+    let mut enc = Encoder::from_memory().option_format(format);
+    enc.encode(&[&vs]).unwrap();
+    assert_eq!(readone(enc.as_bytes()).to_string(),
+               "[null, [null], [[null]]]");
+    let mut dec = Decoder::from_bytes(enc.as_bytes()).option_format(format);
+    let got: Vec<Option<Option<()>>> = dec.decode().next().unwrap().unwrap();
+    assert_eq!(got, vs);
+    let mut dec = DirectDecoder::from_bytes(enc.as_bytes())
+        .option_format(format);
+    let got: Vec<Option<Option<()>>> = Decodable::decode(&mut dec).unwrap();
+    assert_eq!(got, vs);
+
+    // A bare value isn't an `Option` in this format.
+    let r: Result<Option<u8>, _> = Decoder::from_bytes(encode(1))
+        .option_format(format).decode().next().unwrap();
+    assert!(r.is_err());
+    let mut dec = DirectDecoder::from_bytes(encode(vec![1, 2]))
+        .option_format(format);
+    assert!(Option::<u8>::decode(&mut dec).is_err());
+}
+
+#[test]
+fn options_of_data_items_round_trip() {
+    let bytes = Cbor::Bytes(CborBytes(vec![1, 2]));
+    let tagged = cbor!(tag(32, "http://x"));
+    for v in vec![Some(bytes), Some(tagged), Some(Cbor::Null), None] {
+        // The field after the `Option` is still read from the right item.
+        let v = (v, 5u8);
+        let got = decode::<(Option<Cbor>, u8)>(&encode(&v));
+        match v.0 {
+            Some(Cbor::Null) => assert_eq!(got, (None, 5)),
+            _ => assert_eq!(got, v),
+        }
+    }
+}
+
+#[test]
+fn unit_structs_and_variants() {
+    use cbor::DirectDecoder;
+
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Unit;
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    enum Color { Red, Green }
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Paint { unit: Unit, color: Color }
+
+    assert_eq!(encode(Unit), encode(()));
+    assert_eq!(encode(Color::Green), encode("Green"));
+    let v = Paint { unit: Unit, color: Color::Red };
+    let bytes = encode(&v);
+    assert_eq!(decode::<Paint>(&bytes), v);
+    let mut enc = Encoder::from_memory().canonical(true);
+    enc.encode(&[&v]).unwrap();
+    assert_eq!(readone(enc.as_bytes()).to_string(),
+               r#"{"unit": null, "color": "Red"}"#);
+
+    let mut dec = DirectDecoder::from_bytes(encode((Unit, Color::Green)));
+    let got: (Unit, Color) = Decodable::decode(&mut dec).unwrap();
+    assert_eq!(got, (Unit, Color::Green));
+
+    // Unit structs written as empty maps are still read.
+    let empty: HashMap<String, u8> = HashMap::new();
+    assert_eq!(decode::<Unit>(&encode(&empty)), Unit);
+    let r: Result<Color, _> = Decoder::from_bytes(encode("Blue"))
+        .decode().next().unwrap();
+    assert!(r.is_err());
+}
+
+#[test]
+fn direct_decoder_reads_tuple_structs() {
+    use cbor::DirectDecoder;
+
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct DataName(Vec<u8>);
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Pair(u8, String);
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Many(DataName, Pair, Option<i8>, bool);
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Named { name: DataName }
+
+    fn direct<T: Decodable>(bytes: &[u8]) -> T {
+        Decodable::decode(&mut DirectDecoder::from_bytes(bytes)).unwrap()
+    }
+
+    let one = DataName(vec![1, 2]);
+    assert_eq!(direct::<DataName>(&encode(&one)), one);
+    let two = Pair(5, "five".to_string());
+    assert_eq!(direct::<Pair>(&encode(&two)), two);
+    let many = Many(DataName(vec![]), Pair(0, "".to_string()), Some(-1), true);
+    assert_eq!(direct::<Many>(&encode(&many)), many);
+    let named = Named { name: DataName(vec![3]) };
+    assert_eq!(direct::<Named>(&encode(&named)), named);
+    assert_eq!(direct::<Vec<DataName>>(&encode(vec![&one, &one])),
+               vec![DataName(vec![1, 2]), DataName(vec![1, 2])]);
+
+    // Fields out of order can't be read, nor can missing ones.
+    #[derive(RustcEncodable)]
+    struct Swapped { _field1: String, _field0: u8 }
+    let swapped = Swapped { _field1: "five".to_string(), _field0: 5 };
+    assert_eq!(decode::<Pair>(&encode(&swapped)), two);
+    let r = Pair::decode(&mut DirectDecoder::from_bytes(encode(&swapped)));
+    assert!(r.is_err());
+    let r = Many::decode(&mut DirectDecoder::from_bytes(encode(&two)));
+    assert!(r.is_err());
+}
+
+#[test]
+fn fixed_size_arrays() {
+    use cbor::DirectDecoder;
+
+    let hash = [0xabu8; 32];
+    let bytes = encode(&hash);
+    // A byte string of 32 bytes.
+    assert_eq!(&bytes[..2], &[0x58, 32][..]);
+    assert_eq!(bytes, encode(hash.to_vec()));
+    assert_eq!(decode::<[u8; 32]>(&bytes), hash);
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert_eq!(<[u8; 32]>::decode(&mut dec).unwrap(), hash);
+
+    let points = [(1i8, -1i8), (2, -2), (3, -3)];
+    assert_eq!(decode::<[(i8, i8); 3]>(&encode(&points)), points);
+    assert_eq!(decode::<[String; 0]>(&encode(Vec::<String>::new())).len(), 0);
+
+    // The length has to match.
+    let r: Result<[u8; 4], _> = Decoder::from_bytes(encode(vec![1u8, 2, 3]))
+        .decode().next().unwrap();
+    assert!(r.is_err());
+    let mut dec = DirectDecoder::from_bytes(encode(vec![1u8, 2, 3]));
+    assert!(<[u8; 2]>::decode(&mut dec).is_err());
+}
+
+#[test]
+fn byte_sequences_are_byte_strings() {
+    use cbor::{BytesMode, DirectDecoder};
+
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Blob { hash: [u8; 4], data: Vec<u8>, small: Vec<u16> }
+
+    let blob = Blob {
+        hash: [0, 23, 24, 255],
+        data: vec![],
+        small: vec![1, 2],
+    };
+    let bytes = encode(&blob);
+    assert_eq!(readone(&bytes).to_string(),
+               r#"{"hash": h'001718ff', "data": h'', "small": [1, 2]}"#);
+    assert_eq!(decode::<Blob>(&bytes), blob);
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert_eq!(Blob::decode(&mut dec).unwrap(), blob);
+
+    // An empty sequence of any type is an empty byte string.
+    assert_eq!(encode(Vec::<u8>::new()), vec![0x40]);
+    assert_eq!(encode(Vec::<String>::new()), vec![0x40]);
+    assert_eq!(decode::<Vec<String>>(&[0x40]), Vec::<String>::new());
+
+    let mut enc = Encoder::from_memory().bytes_mode(BytesMode::Array);
+    enc.encode(&[&blob]).unwrap();
+    assert_eq!(readone(enc.as_bytes()).to_string(),
+               r#"{"hash": [0, 23, 24, 255], "data": [], "small": [1, 2]}"#);
+    assert_eq!(decode::<Blob>(enc.as_bytes()), blob);
+
+    // Both forms are read by DirectDecoder too.
+    for bytes in &[encode(vec![1u8, 200]), encode((1u8, 200u8))] {
+        let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+        assert_eq!(<Vec<u8>>::decode(&mut dec).unwrap(), vec![1, 200]);
+    }
+    let mut dec = DirectDecoder::from_bytes(encode(vec![1u8, 200]));
+    assert!(<Vec<String>>::decode(&mut dec).is_err());
+
+    // Sequences with anything but u8s are arrays, and so are the AST's.
+    let v = (vec![Some(1u8), None], vec![vec![1u8], vec![]]);
+    assert_eq!(readone(&encode(&v)).to_string(), "[[1, null], [h'01', h'']]");
+    assert_eq!(decode::<(Vec<Option<u8>>, Vec<Vec<u8>>)>(&encode(&v)), v);
+    // Values that only hold a u8 aren't u8s, even if they're encoded the
+    // same way.
+    #[derive(Debug, PartialEq, RustcDecodable, RustcEncodable)]
+    struct Byte(u8);
+    let v = (vec![Some(1u8), Some(2)], vec![Byte(3)]);
+    assert_eq!(readone(&encode(&v)).to_string(), "[[1, 2], [3]]");
+    assert_eq!(decode::<(Vec<Option<u8>>, Vec<Byte>)>(&encode(&v)), v);
+    let item = Cbor::from_diagnostic("[1, 2]").unwrap();
+    assert_eq!(readone(&encode(&item)), item);
+}
+
+#[test]
+fn byte_strings_from_other_encoders() {
+    use cbor::DirectDecoder;
+
+    #[derive(Debug, PartialEq, RustcDecodable)]
+    struct Digest([u8; 2]);
+    #[derive(Debug, PartialEq, RustcDecodable)]
+    struct Signed { sig: Vec<u8>, key: Digest, extra: Vec<Vec<u8>> }
+
+    // {"sig": h'010203', "key": h'0a0b', "extra": [h'', h'ff']}, the way
+    // other encoders write byte vectors.
+    let bytes = [0xa3, 0x63, b's', b'i', b'g', 0x43, 0x01, 0x02, 0x03,
+                 0x63, b'k', b'e', b'y', 0x42, 0x0a, 0x0b,
+                 0x65, b'e', b'x', b't', b'r', b'a', 0x82, 0x40, 0x41, 0xff];
+    let want = Signed {
+        sig: vec![1, 2, 3],
+        key: Digest([10, 11]),
+        extra: vec![vec![], vec![255]],
+    };
+    assert_eq!(decode::<Signed>(&bytes), want);
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert_eq!(Signed::decode(&mut dec).unwrap(), want);
+
+    // The length of a byte string still has to fit a fixed-size array.
+    let r: Result<[u8; 2], _> = Decoder::from_bytes(vec![0x43, 1, 2, 3])
+        .decode().next().unwrap();
+    assert!(r.is_err());
+    let mut dec = DirectDecoder::from_bytes(vec![0x41, 1]);
+    assert!(<[u8; 2]>::decode(&mut dec).is_err());
+}
+
+#[test]
+fn strings_and_bytes_coerced() {
+    use cbor::DirectDecoder;
+
+    #[derive(Debug, PartialEq, RustcDecodable)]
+    struct Msg { name: String, data: Vec<u8> }
+
+    // {"name": h'6869', "data": "ok"}, with the two string types swapped.
+    let bytes = [0xa2, 0x64, b'n', b'a', b'm', b'e', 0x42, b'h', b'i',
+                 0x64, b'd', b'a', b't', b'a', 0x62, b'o', b'k'];
+    let want = Msg { name: "hi".to_string(), data: b"ok".to_vec() };
+
+    let r: Result<Msg, _> = Decoder::from_bytes(&bytes[..])
+        .decode().next().unwrap();
+    assert!(r.is_err());
+    let msg: Msg = Decoder::from_bytes(&bytes[..]).coerce_strings(true)
+        .decode().next().unwrap().unwrap();
+    assert_eq!(msg, want);
+
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]);
+    assert!(Msg::decode(&mut dec).is_err());
+    let mut dec = DirectDecoder::from_bytes(&bytes[..]).coerce_strings(true);
+    assert_eq!(Msg::decode(&mut dec).unwrap(), want);
+
+    // Bytes read as a string still have to be UTF-8.
+    let r: Result<String, _> = Decoder::from_bytes(vec![0x41, 0xff])
+        .coerce_strings(true).decode().next().unwrap();
+    assert!(r.is_err());
+    let mut dec = DirectDecoder::from_bytes(vec![0x41, 0xff])
+        .coerce_strings(true);
+    assert!(String::decode(&mut dec).is_err());
+}
+
+#[test]
+fn numbers_coerced_from_strings() {
+    use cbor::Narrowing;
+
+    #[derive(Debug, PartialEq, RustcDecodable)]
+    struct Row { id: u64, delta: i32, score: f64, ratio: f32 }
+
+    let bytes = encode(&[
+        ("id", "18446744073709551615"), ("delta", "-7"),
+        ("score", "2.5"), ("ratio", "3"),
+    ].iter().cloned().collect::<HashMap<_, _>>());
+    let want = Row { id: ::std::u64::MAX, delta: -7, score: 2.5, ratio: 3.0 };
+
+    let r: Result<Row, _> = Decoder::from_bytes(&bytes[..])
+        .decode().next().unwrap();
+    assert!(r.is_err());
+    let row: Row = Decoder::from_bytes(&bytes[..]).coerce_numbers(true)
+        .decode().next().unwrap().unwrap();
+    assert_eq!(row, want);
+
+    // Numbers in strings still have to fit, and integers can't have
+    // fractions.
+    let coerced = |v: &str| Decoder::from_bytes(encode(v))
+        .coerce_numbers(true).decode::<i8>().next().unwrap();
+    assert_eq!(coerced("-128").unwrap(), -128);
+    assert!(coerced("128").is_err());
+    assert!(coerced("1.5").is_err());
+    assert!(coerced("one").is_err());
+    let r: Result<u8, _> = Decoder::from_bytes(encode("-1"))
+        .coerce_numbers(true).narrowing(Narrowing::Saturate)
+        .decode().next().unwrap();
+    assert!(r.is_err());
+}
+
+#[test]
+fn decode_with_seeds() {
+    use std::marker::PhantomData;
+    use rustc_serialize::Decoder as RustcDecoder;
+    use cbor::{DecodeSeed, DirectDecoder};
+
+    // Interns strings, so that each one is decoded as its index.
+    struct Intern<'a>(&'a mut Vec<String>);
+
+    impl<'a> DecodeSeed for Intern<'a> {
+        type Value = usize;
+
+        fn decode<D: RustcDecoder>(self, d: &mut D)
+                                  -> Result<usize, D::Error> {
+            let s = try!(d.read_str());
+            match self.0.iter().position(|t| *t == s) {
+                Some(i) => Ok(i),
+                None => {
+                    self.0.push(s);
+                    Ok(self.0.len() - 1)
+                }
+            }
+        }
+    }
+
+    let mut enc = Encoder::from_memory();
+    enc.encode(&["a", "b", "a"]).unwrap();
+    let mut table = vec![];
+    let mut dec = Decoder::from_bytes(enc.as_bytes());
+    let mut ids = vec![];
+    while let Some(id) = dec.decode_seed(Intern(&mut table)) {
+        ids.push(id.unwrap());
+    }
+    assert_eq!(ids, vec![0, 1, 0]);
+    assert_eq!(table, vec!["a".to_string(), "b".to_string()]);
+
+    let mut dec = DirectDecoder::from_bytes(enc.as_bytes());
+    assert_eq!(Intern(&mut table).decode(&mut dec).unwrap(), 0);
+
+    // A vector is refilled in place, and errors have the usual offsets.
+    let mut enc = Encoder::from_memory();
+    enc.encode(&[vec![1u16, 2, 3], vec![4]]).unwrap();
+    enc.encode(&[vec!["x"]]).unwrap();
+    let mut dec = Decoder::from_bytes(enc.as_bytes());
+    let mut buf: Vec<u16> = vec![];
+    dec.decode_seed(&mut buf).unwrap().unwrap();
+    assert_eq!(buf, vec![1, 2, 3]);
+    let ptr = buf.as_ptr();
+    dec.decode_seed(&mut buf).unwrap().unwrap();
+    assert_eq!(buf, vec![4]);
+    assert_eq!(buf.as_ptr(), ptr);
+    let err = dec.decode_seed(&mut buf).unwrap().unwrap_err();
+    assert!(err.to_string().contains("[0]"));
+    assert!(dec.decode_seed(PhantomData::<u8>).is_none());
+}
+
+#[test]
+fn checksummed_envelopes() {
+    use cbor::{CborError, ReadError, checksum};
+    use cbor::checksum::Checksum;
+
+    // The CRC is the standard CRC-32 of the encoded data item.
+    assert_eq!(checksum::crc32(b"123456789"), 0xcbf43926);
+    let mut log = vec![];
+    checksum::write_item(&mut log, b"123456789").unwrap();
+    assert_eq!(&log[log.len() - 4..], &[0xcb, 0xf4, 0x39, 0x26][..]);
+
+    // And the xxHash is the standard XXH32, with a seed of 0.
+    assert_eq!(checksum::xxh32(b""), 0x02cc5d05);
+    assert_eq!(checksum::xxh32(b"abc"), 0x32d153ff);
+    assert_eq!(checksum::xxh32(b"Nobody inspects the spammish repetition"),
+               0xe2293b2f);
+    let mut log = vec![];
+    checksum::write_item_with(&mut log, Checksum::XxHash32, b"abc").unwrap();
+    assert_eq!(&log[..6], &[0xda, 0x00, 0x58, 0x58, 0x48, 0x82][..]);
+    assert_eq!(&log[log.len() - 4..], &[0x32, 0xd1, 0x53, 0xff][..]);
+
+    let mut log = vec![];
+    checksum::write_message(&mut log, &vec![1u32, 2]).unwrap();
+    checksum::write_message(&mut log, &"hello").unwrap();
+    assert_eq!(readone(&log).to_string(),
+               format!("{}([h'820102', h'{}'])", checksum::TAG, "eae54691"));
+    let mut rdr = &log[..];
+    let v: Option<Vec<u32>> = checksum::read_message(&mut rdr).unwrap();
+    assert_eq!(v, Some(vec![1, 2]));
+    let s: Option<String> = checksum::read_message(&mut rdr).unwrap();
+    assert_eq!(s, Some("hello".to_string()));
+    let end: Option<String> = checksum::read_message(&mut rdr).unwrap();
+    assert_eq!(end, None);
+
+    // Corrupt the second data item.
+    let second = 15;
+    log[second + 8] = b'j';
+    let mut rdr = &log[second..];
+    match checksum::read_item(&mut rdr) {
+        Err(CborError::AtOffset {
+            kind: ReadError::ChecksumMismatch { .. }, offset,
+        }) => assert_eq!(offset, 13),
+        r => panic!("expected a checksum mismatch, but got {:?}", r),
+    }
+
+    // Truncated envelopes and other data items are errors too.
+    let mut rdr = &log[..10];
+    assert!(checksum::read_item(&mut rdr).is_err());
+    let mut rdr = &[0x82, 0x01, 0x02][..];
+    assert!(checksum::read_item(&mut rdr).is_err());
+
+    // Readers accept either checksum.
+    let mut log = vec![];
+    checksum::write_message(&mut log, &1u8).unwrap();
+    checksum::write_message_with(&mut log, Checksum::XxHash32, &"hello")
+        .unwrap();
+    let second = log.len() - 18;
+    assert_eq!(readone(&log[second..]).to_string(),
+               format!("{}([h'6568656c6c6f', h'{:08x}'])",
+                       checksum::TAG_XXH32, checksum::xxh32(b"ehello")));
+    let mut rdr = &log[..];
+    let n: Option<u8> = checksum::read_message(&mut rdr).unwrap();
+    assert_eq!(n, Some(1));
+    let s: Option<String> = checksum::read_message(&mut rdr).unwrap();
+    assert_eq!(s, Some("hello".to_string()));
+
+    log[second + 8] = b'j';
+    let mut rdr = &log[second..];
+    match checksum::read_item(&mut rdr) {
+        Err(CborError::AtOffset {
+            kind: ReadError::ChecksumMismatch { .. }, offset: 13,
+        }) => {}
+        r => panic!("expected a checksum mismatch, but got {:?}", r),
+    }
+}
+
+#[test]
+fn versioned_values() {
+    use cbor::{CborError, ReadError, Versioned, Versions};
+
+    #[derive(RustcEncodable, RustcDecodable)]
+    struct PointV1(i32, i32);
+    #[derive(RustcEncodable, RustcDecodable)]
+    struct PointV2 { x: i32, y: i32 }
+    #[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+    struct Point { x: i32, y: i32, z: i32 }
+
+    let versions = Versions::new()
+        .register(1, |p: PointV1| Point { x: p.0, y: p.1, z: 0 })
+        .register(2, |p: PointV2| Point { x: p.x, y: p.y, z: 0 })
+        .current(3);
+
+    let v1 = encode(Versioned { version: 1, value: PointV1(1, 2) });
+    assert_eq!(readone(&v1).to_string(), "[1, [1, 2]]");
+    assert_eq!(versions.decode(&v1).unwrap(), Point { x: 1, y: 2, z: 0 });
+    let v2 = encode(Versioned { version: 2, value: PointV2 { x: 3, y: 4 } });
+    assert_eq!(versions.decode(&v2).unwrap(), Point { x: 3, y: 4, z: 0 });
+    let p = Point { x: 5, y: 6, z: 7 };
+    let v3 = encode(Versioned { version: 3, value: &p });
+    assert_eq!(versions.decode(&v3).unwrap(), p);
+
+    // A value that doesn't match the type of its version is an error, and
+    // so is a version that isn't registered.
+    let bad = encode(Versioned { version: 1, value: PointV2 { x: 0, y: 0 } });
+    assert!(versions.decode(&bad).is_err());
+    let v4 = encode(Versioned { version: 4, value: &p });
+    match versions.decode(&v4) {
+        Err(CborError::Decode(ReadError::UnknownVersion(4))) => {}
+        r => panic!("expected an unknown version, but got {:?}", r),
+    }
+
+    // Versions can also be read from values that are already decoded.
+    let v: Versioned<cbor::Cbor> = decode(&v2);
+    assert_eq!(v.version, 2);
+    assert_eq!(versions.upgrade(v).unwrap(), Point { x: 3, y: 4, z: 0 });
+}
+
+#[test]
+fn structs_with_skipped_fields() {
+    use cbor::{CborError, WriteError};
+    use rustc_serialize::Encoder as RustcEncoder;
+
+    // Skips `nick` when it's `None`, which shrinks the map.
+    #[derive(Debug, PartialEq, RustcDecodable)]
+    struct User { name: String, nick: Option<String>, age: u8 }
+
+    impl Encodable for User {
+        fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+            let len = if self.nick.is_some() { 3 } else { 2 };
+            e.emit_struct("User", len, |e| {
+                try!(e.emit_struct_field("name", 0, |e| self.name.encode(e)));
+                if let Some(ref nick) = self.nick {
+                    try!(e.emit_struct_field("nick", 1, |e| nick.encode(e)));
+                }
+                e.emit_struct_field("age", 2, |e| self.age.encode(e))
+            })
+        }
+    }
+
+    let users = vec![
+        User { name: "a".to_string(), nick: None, age: 1 },
+        User { name: "b".to_string(), nick: Some("c".to_string()), age: 2 },
+    ];
+    for &canonical in &[false, true] {
+        let mut enc = Encoder::from_memory().canonical(canonical);
+        enc.encode(&[&users]).unwrap();
+        let first = if canonical {
+            r#"{"age": 1, "name": "a"}"#
+        } else {
+            r#"{"name": "a", "age": 1}"#
+        };
+        assert_eq!(readone(enc.as_bytes())[0].to_string(), first);
+        // A missing `Option` is decoded as `None`.
+        assert_eq!(decode::<Vec<User>>(enc.as_bytes()), users);
+    }
+
+    // A length that doesn't match the fields written is an error instead
+    // of a map with the wrong number of entries.
+    struct Liar;
+
+    impl Encodable for Liar {
+        fn encode<E: RustcEncoder>(&self, e: &mut E) -> Result<(), E::Error> {
+            e.emit_struct("Liar", 3, |e| {
+                try!(e.emit_struct_field("a", 0, |e| 1u8.encode(e)));
+                e.emit_struct_field("b", 1, |e| 2u8.encode(e))
+            })
+        }
+    }
+
+    for &canonical in &[false, true] {
+        let mut enc = Encoder::from_memory().canonical(canonical);
+        match enc.encode(&[vec![Liar]]) {
+            Err(CborError::Encode(WriteError::LengthMismatch {
+                expected: 3, got: 2,
+            })) => {}
+            r => panic!("expected a length mismatch, but got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn cose_messages() {
+    use cbor::{CborError, CborResult, ReadError, ToCbor};
+    use cbor::cose::{
+        self, Cipher, CoseEncrypt0, CoseMac0, CoseSign, CoseSign1, HeaderMap,
+        Headers, Mac, Signer, Verifier,
+    };
+
+    // Stand-ins for real algorithms, which only need to be deterministic.
+    struct Sum(u8);
+
+    impl Signer for Sum {
+        fn sign(&self, data: &[u8]) -> CborResult<Vec<u8>> {
+            Ok(vec![data.iter().fold(self.0, |a, &b| a.wrapping_add(b))])
+        }
+    }
+
+    impl Verifier for Sum {
+        fn verify(&self, data: &[u8], sig: &[u8]) -> CborResult<()> {
+            if try!(self.sign(data)) == sig {
+                Ok(())
+            } else {
+                Err(CborError::Decode(ReadError::Other("bad".to_string())))
+            }
+        }
+    }
+
+    impl Mac for Sum {
+        fn tag(&self, data: &[u8]) -> CborResult<Vec<u8>> {
+            self.sign(data)
+        }
+    }
+
+    impl Cipher for Sum {
+        fn encrypt(&self, _: &Headers, aad: &[u8], plaintext: &[u8])
+                  -> CborResult<Vec<u8>> {
+            let mut out: Vec<u8> = plaintext.iter().map(|b| *b ^ self.0)
+                                            .collect();
+            out.extend(try!(self.sign(aad)));
+            Ok(out)
+        }
+
+        fn decrypt(&self, _: &Headers, aad: &[u8], ciphertext: &[u8])
+                  -> CborResult<Vec<u8>> {
+            let (text, tag) = ciphertext.split_at(ciphertext.len() - 1);
+            try!(self.verify(aad, tag));
+            Ok(text.iter().map(|b| *b ^ self.0).collect())
+        }
+    }
+
+    let mut protected = HeaderMap::new();
+    protected.insert(cose::ALG.to_cbor(), (-7i64).to_cbor());
+    let mut unprotected = HeaderMap::new();
+    unprotected.insert(cose::KID.to_cbor(), "k".to_cbor());
+    let headers = Headers::new(&protected, unprotected).unwrap();
+
+    // The signature covers the Sig_structure of the message.
+    let msg = CoseSign1::sign(headers.clone(), b"hi".to_vec(), b"",
+                              &Sum(0)).unwrap();
+    let data = cose::sig_structure("Signature1", &[0xa1, 0x01, 0x26], None,
+                                   b"", b"hi").unwrap();
+    assert_eq!(readone(&data).to_string(),
+               r#"["Signature1", h'a10126', h'', h'6869']"#);
+    assert_eq!(msg.signature, Sum(0).sign(&data).unwrap());
+    let bytes = msg.to_bytes().unwrap();
+    assert_eq!(&bytes[..10], &[0xd2, 0x84, 0x43, 0xa1, 0x01, 0x26,
+                               0xa1, 0x04, 0x61, b'k'][..]);
+    let back = CoseSign1::from_bytes(&bytes).unwrap();
+    assert_eq!(back, msg);
+    assert!(back.verify(b"", &Sum(0)).is_ok());
+    assert!(back.verify(b"aad", &Sum(0)).is_err());
+    assert!(back.verify(b"", &Sum(1)).is_err());
+    // The tag may be left out, but a different one is an error.
+    assert_eq!(CoseSign1::from_bytes(&bytes[1..]).unwrap(), msg);
+    assert!(CoseMac0::from_bytes(&bytes).is_err());
+
+    // A detached payload is null, and is given when verifying.
+    let mut detached = msg.clone();
+    detached.payload = None;
+    let back = CoseSign1::from_bytes(&detached.to_bytes().unwrap()).unwrap();
+    assert!(back.verify(b"", &Sum(0)).is_err());
+    assert!(back.verify_detached(b"hi", b"", &Sum(0)).is_ok());
+
+    // Each signature of a COSE_Sign covers its own protected headers.
+    let mut msg = CoseSign::new(headers.clone(), b"hi".to_vec());
+    msg.add_signature(headers.clone(), b"", &Sum(1)).unwrap();
+    msg.add_signature(Headers::new(&HeaderMap::new(), HeaderMap::new())
+                          .unwrap(), b"", &Sum(2)).unwrap();
+    let bytes = msg.to_bytes().unwrap();
+    assert_eq!(&bytes[..3], &[0xd8, 98, 0x84][..]);
+    let back = CoseSign::from_bytes(&bytes).unwrap();
+    assert_eq!(back, msg);
+    assert!(back.verify(0, b"", &Sum(1)).is_ok());
+    assert!(back.verify(1, b"", &Sum(2)).is_ok());
+    assert!(back.verify(1, b"", &Sum(1)).is_err());
+
+    let msg = CoseMac0::create(headers.clone(), b"hi".to_vec(), b"",
+                               &Sum(3)).unwrap();
+    let mut back = CoseMac0::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+    assert!(back.verify(b"", &Sum(3)).is_ok());
+    back.payload = Some(b"ho".to_vec());
+    assert!(back.verify(b"", &Sum(3)).is_err());
+
+    let msg = CoseEncrypt0::encrypt(headers.clone(), b"secret", b"",
+                                    &Sum(4)).unwrap();
+    let bytes = msg.to_bytes().unwrap();
+    assert!(!bytes.windows(6).any(|w| w == b"secret"));
+    let back = CoseEncrypt0::from_bytes(&bytes).unwrap();
+    assert_eq!(back.decrypt(b"", &Sum(4)).unwrap(), b"secret".to_vec());
+    assert!(back.decrypt(b"aad", &Sum(4)).is_err());
+
+    // Malformed messages are errors, with the offset of the problem.
+    match CoseSign1::from_bytes(&[0xd2, 0x84, 0x41, 0x01]) {
+        Err(CborError::AtOffset { offset: 3, .. }) => {}
+        r => panic!("expected an error at offset 3, but got {:?}", r),
+    }
+    assert!(CoseSign1::from_bytes(&[0xd2, 0x83, 0x40, 0xa0, 0xf6]).is_err());
+}
+
+#[test]
+fn cwt_claims() {
+    use rustc_serialize::hex::FromHex;
+    use cbor::{CborError, ReadError, ToCbor};
+    use cbor::cwt::CwtClaims;
+
+    // The claims set of RFC 8392, appendix A.1.
+    let bytes = "a70175636f61703a2f2f61732e6578616d706c652e636f6d0265657269\
+                 6b77037818636f61703a2f2f6c696768742e6578616d706c652e636f6d\
+                 041a5612aeb0051a5610d9f0061a5610d9f007420b71"
+                     .from_hex().unwrap();
+    let claims = CwtClaims::from_bytes(&bytes).unwrap();
+    assert_eq!(claims, CwtClaims {
+        iss: Some("coap://as.example.com".to_string()),
+        sub: Some("erikw".to_string()),
+        aud: Some("coap://light.example.com".to_string()),
+        exp: Some(1444064944),
+        nbf: Some(1443944944),
+        iat: Some(1443944944),
+        cti: Some(vec![0x0b, 0x71]),
+        extra: Default::default(),
+    });
+    assert_eq!(claims.to_bytes().unwrap(), bytes);
+
+    // Other claims are kept, and times may have tag 1.
+    let mut claims = CwtClaims::new();
+    claims.extra.insert((-70000i64).to_cbor(), "x".to_cbor());
+    claims.extra.insert("scope".to_cbor(), "read".to_cbor());
+    claims.extra.insert(4i64.to_cbor(), "ignored".to_cbor());
+    claims.exp = Some(1);
+    let back = CwtClaims::from_bytes(&claims.to_bytes().unwrap()).unwrap();
+    assert_eq!(back.exp, Some(1));
+    assert_eq!(back.extra.len(), 2);
+    assert_eq!(CwtClaims::from_bytes(&[0xa1, 0x06, 0xc1, 0x02]).unwrap().iat,
+               Some(2));
+    // Fractional seconds are truncated.
+    let bytes = "a104fb41d584abac200000".from_hex().unwrap();
+    assert_eq!(CwtClaims::from_bytes(&bytes).unwrap().exp, Some(1444064944));
+    let bytes = "a105c1f9be00".from_hex().unwrap(); // {5: 1(-1.5)}
+    assert_eq!(CwtClaims::from_bytes(&bytes).unwrap().nbf, Some(-1));
+    let bytes = "a106f97e00".from_hex().unwrap(); // {6: NaN}
+    assert!(CwtClaims::from_bytes(&bytes).is_err());
+
+    match CwtClaims::from_bytes(&[0xa1, 0x01, 0x02]) {
+        Err(CborError::Decode(ReadError::TypeMismatch { .. })) => {}
+        r => panic!("expected a type mismatch, but got {:?}", r),
+    }
+}